@@ -0,0 +1,560 @@
+//! # Address
+//!
+//! An address is derived from the Ed25519 public key of its owner: the first 20 bytes of the `Sha256` hash of the key,
+//! encoded with bech32 after the `ADDRESS_PREFIX`. For example, `fcc1...`.
+//!
+//! The bech32 checksum catches any single typo, so a transfer to a mistyped address is rejected, instead of creating a new Account.
+//!
+//! The address of a multisig account is derived from its policy instead, and encoded after the `MULTISIG_PREFIX`, `fccm1...`.
+//!
+//! Free-form names such as `"Camper"`, used by the course fixtures, are legacy addresses. Whether a chain accepts them is
+//! the `legacy_addresses` flag of its `GenesisConfig`, committed by its hash, so every build agrees on which chains are valid.
+//! `validate_chain_address` and `validate_block_addresses` follow it.
+//!
+//! **Note:** With the `legacy-addresses` feature, which is enabled by default, `Address::parse` also accepts free-form
+//! names, outside of any chain: for example, when an address is deserialised.
+//! Use `#[serde(deserialize_with = "address::deserialize")]` on the `address` fields of `Account` and `Transaction`
+//! to reject invalid addresses when they are deserialised.
+
+use crate::{body::FullBlock, genesis::GenesisConfig, history::transaction_addresses};
+use ed25519_dalek::VerifyingKey;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+use wasm_bindgen::prelude::*;
+
+/// The human-readable part of every address of a key.
+pub const ADDRESS_PREFIX: &str = "fcc";
+
+/// The human-readable part of every address of a multisig account.
+pub const MULTISIG_PREFIX: &str = "fccm";
+
+/// The number of bytes of the public key hash held by an address.
+pub const PAYLOAD_LENGTH: usize = 20;
+
+/// The characters of the bech32 alphabet, in order of value.
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The number of characters of the bech32 checksum.
+const CHECKSUM_LENGTH: usize = 6;
+
+/// The maximum length of a bech32 string.
+const MAX_LENGTH: usize = 90;
+
+/// A valid address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(String);
+
+impl Address {
+    /// Returns the address of the owner of the public `key`.
+    pub fn from_public_key(key: &VerifyingKey) -> Self {
+        Self::from_hash(&Sha256::digest(key.as_bytes()))
+    }
+
+    /// Returns the address holding the first `PAYLOAD_LENGTH` bytes of the `hash` of a public key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hash is shorter than `PAYLOAD_LENGTH`.
+    pub fn from_hash(hash: &[u8]) -> Self {
+        Self(encode(ADDRESS_PREFIX, &hash[..PAYLOAD_LENGTH]))
+    }
+
+    /// Returns the multisig address holding the first `PAYLOAD_LENGTH` bytes of the `hash` of a multisig policy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hash is shorter than `PAYLOAD_LENGTH`.
+    pub fn from_policy_hash(hash: &[u8]) -> Self {
+        Self(encode(MULTISIG_PREFIX, &hash[..PAYLOAD_LENGTH]))
+    }
+
+    /// Parses the `address`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address is not a lowercase bech32 string with the `ADDRESS_PREFIX`, or `MULTISIG_PREFIX`,
+    /// a `PAYLOAD_LENGTH` payload, and a correct checksum. Without the `legacy-addresses` feature, free-form names are
+    /// also an error.
+    pub fn parse(address: &str) -> Result<Self, String> {
+        Self::parse_with(address, cfg!(feature = "legacy-addresses"))
+    }
+
+    /// Parses the `address`, accepting free-form names only if `legacy_names` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `Address::parse`, whatever the features of the build.
+    pub fn parse_with(address: &str, legacy_names: bool) -> Result<Self, String> {
+        let decoded = decode(address);
+        let foreign_prefix = |prefix: &str| {
+            format!(
+                "Address '{}' has the prefix '{}', not '{}'",
+                address, prefix, ADDRESS_PREFIX
+            )
+        };
+        let lowercase = address.to_lowercase();
+        if ![ADDRESS_PREFIX, MULTISIG_PREFIX]
+            .iter()
+            .any(|prefix| lowercase.starts_with(&format!("{}1", prefix)))
+        {
+            return match decoded {
+                // A bech32 string of another network is never a legacy name
+                Ok((prefix, _)) => Err(foreign_prefix(&prefix)),
+                Err(_) if legacy_names && is_legacy_name(address) => Ok(Self(address.to_string())),
+                Err(_) => Err(format!("'{}' is not a valid address", address)),
+            };
+        }
+        if lowercase != address {
+            return Err(format!("Address '{}' is not lowercase", address));
+        }
+        let (prefix, words) = decoded?;
+        if prefix != ADDRESS_PREFIX && prefix != MULTISIG_PREFIX {
+            return Err(foreign_prefix(&prefix));
+        }
+        match convert_bits(&words, 5, 8, false) {
+            Some(payload) if payload.len() == PAYLOAD_LENGTH => Ok(Self(address.to_string())),
+            _ => Err(format!("Address '{}' has an invalid payload", address)),
+        }
+    }
+
+    /// Returns `true` if the address is a free-form name, rather than derived from a public key, or a multisig policy.
+    pub fn is_legacy(&self) -> bool {
+        !self.0.starts_with(&format!("{}1", ADDRESS_PREFIX)) && !self.is_multisig()
+    }
+
+    /// Returns `true` if the address is derived from a multisig policy.
+    pub fn is_multisig(&self) -> bool {
+        self.0.starts_with(&format!("{}1", MULTISIG_PREFIX))
+    }
+
+    /// Returns `true` if the address is derived from the public `key`.
+    ///
+    /// **Note:** Legacy addresses are not derived from any key.
+    pub fn is_owned_by(&self, key: &VerifyingKey) -> bool {
+        *self == Self::from_public_key(key)
+    }
+
+    /// Returns the address as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        Self::parse(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        Self::parse(&address).map_err(de::Error::custom)
+    }
+}
+
+/// Deserialises a `String` field, only if it is a valid address.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// pub struct Transaction {
+///     pub event: Events,
+///     #[serde(deserialize_with = "address::deserialize")]
+///     pub address: String,
+/// }
+/// ```
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Address::deserialize(deserializer).map(String::from)
+}
+
+/// Checks the `address` is valid.
+///
+/// # Errors
+///
+/// Returns the error of `Address::parse`.
+pub fn validate_address(address: &str) -> Result<(), String> {
+    Address::parse(address).map(|_| ())
+}
+
+/// Checks the `address` is valid on the chain configured by the `config`: free-form names are only valid if it has
+/// `legacy_addresses`.
+///
+/// # Errors
+///
+/// Returns the error of `Address::parse_with`.
+pub fn validate_chain_address(address: &str, config: &GenesisConfig) -> Result<(), String> {
+    Address::parse_with(address, config.legacy_addresses).map(|_| ())
+}
+
+/// Checks every address of the `full_block` is valid on the chain configured by the `config`: its Accounts, the senders
+/// and counterparties of its transactions, its next miner, and its next validators.
+///
+/// # Errors
+///
+/// Returns an error naming the block, and the invalid address.
+pub fn validate_block_addresses(
+    full_block: &FullBlock,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let block = &full_block.block;
+    let addresses = block
+        .data
+        .iter()
+        .map(|account| account.address.as_str())
+        .chain(
+            full_block
+                .body
+                .transactions
+                .iter()
+                .flat_map(transaction_addresses),
+        )
+        .chain(std::iter::once(block.next_miner.as_str()))
+        .chain(block.next_validators.iter().map(String::as_str));
+    for address in addresses {
+        validate_chain_address(address, config)
+            .map_err(|e| format!("Block {}: {}", block.id, e))?;
+    }
+    Ok(())
+}
+
+/// Returns `true` if the `name` is a free-form name, as allowed for legacy addresses:
+/// a non-empty string of ASCII letters, digits, `_`, and `-`.
+fn is_legacy_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The bech32 checksum of the 5 bit `values`.
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Expands the human-readable part into the values the checksum covers.
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let bytes = prefix.as_bytes();
+    let mut values: Vec<u8> = bytes.iter().map(|b| b >> 5).collect();
+    values.push(0);
+    values.extend(bytes.iter().map(|b| b & 31));
+    values
+}
+
+/// Regroups the `data` from groups of `from` bits into groups of `to` bits.
+///
+/// Returns `None` if a value does not fit in `from` bits, or, without `pad`, if the data leaves non-zero padding.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let max = (1u32 << to) - 1;
+    let mut output = vec![];
+    for value in data {
+        let value = *value as u32;
+        if value >> from != 0 {
+            return None;
+        }
+        accumulator = (accumulator << from) | value;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            output.push(((accumulator >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            output.push(((accumulator << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (accumulator << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(output)
+}
+
+/// Encodes the `payload` bytes as a bech32 string, after the `prefix`.
+fn encode(prefix: &str, payload: &[u8]) -> String {
+    let words = convert_bits(payload, 8, 5, true).expect("bytes fit in 8 bits");
+    let checksum = polymod(
+        expand_prefix(prefix)
+            .into_iter()
+            .chain(words.iter().copied())
+            .chain([0; CHECKSUM_LENGTH]),
+    ) ^ 1;
+    let checksum = (0..CHECKSUM_LENGTH).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8);
+    let mut output = format!("{}1", prefix);
+    output.extend(
+        words
+            .iter()
+            .copied()
+            .chain(checksum)
+            .map(|word| CHARSET[word as usize] as char),
+    );
+    output
+}
+
+/// Decodes a bech32 string into its lowercase human-readable part, and its 5 bit data values, without the checksum.
+///
+/// # Errors
+///
+/// Returns an error if the string is not bech32, or its checksum is not correct.
+fn decode(address: &str) -> Result<(String, Vec<u8>), String> {
+    let not_bech32 = || format!("Address '{}' is not bech32", address);
+    let lowercase = address.to_lowercase();
+    if address.len() > MAX_LENGTH
+        || (lowercase != address && address.to_uppercase() != address)
+        || address.bytes().any(|b| !(33..=126).contains(&b))
+    {
+        return Err(not_bech32());
+    }
+    let separator = lowercase.rfind('1').ok_or_else(not_bech32)?;
+    if separator == 0 || separator + 1 + CHECKSUM_LENGTH > lowercase.len() {
+        return Err(not_bech32());
+    }
+    let (prefix, data) = (&lowercase[..separator], &lowercase[separator + 1..]);
+    let values = data
+        .bytes()
+        .map(|b| CHARSET.iter().position(|c| *c == b).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(not_bech32)?;
+    if polymod(
+        expand_prefix(prefix)
+            .into_iter()
+            .chain(values.iter().copied()),
+    ) != 1
+    {
+        return Err(format!("Address '{}' has an invalid checksum", address));
+    }
+    Ok((
+        prefix.to_string(),
+        values[..values.len() - CHECKSUM_LENGTH].to_vec(),
+    ))
+}
+
+/// Returns the address of the owner of the hex encoded Ed25519 `public_key` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const address = get_address(publicKey);
+/// ```
+///
+/// # Errors
+///
+/// If `public_key` argument is not a hex encoded Ed25519 public key, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_address(public_key: String) -> Result<String, JsError> {
+    let key = hex::decode(public_key)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or_else(|| JsError::new("Public key is not an Ed25519 public key"))?;
+    Ok(Address::from_public_key(&key).into())
+}
+
+/// Returns `true` if the `address` argument is a valid address.
+///
+/// # Examples
+///
+/// ```js
+/// if (!is_valid_address(recipient)) {
+///   throw new Error("Check the address of the recipient");
+/// }
+/// ```
+#[wasm_bindgen]
+pub fn is_valid_address(address: String) -> bool {
+    validate_address(&address).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use ed25519_dalek::SigningKey;
+    #[test]
+    fn bech32_test_vectors() {
+        // Valid strings of BIP-173
+        for valid in [
+            "A12UEL5L",
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ] {
+            assert!(decode(valid).is_ok(), "{} is valid", valid);
+        }
+        // Invalid strings of BIP-173
+        for invalid in [
+            "\u{20}1nwldj5",
+            "an84characterslonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1569pvx",
+            "pzry9x0s0muk",
+            "1pzry9x0s0muk",
+            "x1b4n0q5v",
+            "li1dgmt3",
+            "A1G7SGD8",
+            "10a06t8",
+            "1qzzfhee",
+            "a12UEL5L",
+        ] {
+            assert!(decode(invalid).is_err(), "{} is invalid", invalid);
+        }
+        let (prefix, words) = decode("abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw").unwrap();
+        assert_eq!(prefix, "abcdef");
+        assert_eq!(words, (0..32).collect::<Vec<u8>>());
+    }
+    #[test]
+    fn address_round_trips_public_key() {
+        let key = _fixture_key();
+        let address = Address::from_public_key(&key);
+        assert!(address.as_str().starts_with("fcc1"));
+        assert_eq!(address.as_str().len(), 4 + 32 + CHECKSUM_LENGTH);
+        assert!(!address.is_legacy());
+        assert!(address.is_owned_by(&key));
+        assert_eq!(Address::parse(address.as_str()), Ok(address.clone()));
+
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+    }
+    #[test]
+    fn multisig_addresses_have_their_own_prefix() {
+        let key = _fixture_key();
+        let address = Address::from_policy_hash(&Sha256::digest(key.as_bytes()));
+        assert!(address.as_str().starts_with("fccm1"));
+        assert!(address.is_multisig());
+        assert!(!address.is_legacy());
+        assert!(!address.is_owned_by(&key));
+        assert_eq!(Address::parse(address.as_str()), Ok(address.clone()));
+        assert!(!Address::from_public_key(&key).is_multisig());
+    }
+    #[test]
+    fn typos_are_rejected() {
+        let address = String::from(Address::from_public_key(&_fixture_key()));
+        // Swap one character of the payload for another of the alphabet
+        let mut typo = address.clone().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert_eq!(
+            validate_address(&typo),
+            Err(format!("Address '{}' has an invalid checksum", typo))
+        );
+        assert_eq!(
+            validate_address(&address.to_uppercase()),
+            Err(format!(
+                "Address '{}' is not lowercase",
+                address.to_uppercase()
+            ))
+        );
+        assert!(validate_address(&address[..address.len() - 1]).is_err());
+        assert!(serde_json::from_str::<Address>(&format!("\"{}\"", typo)).is_err());
+        // A valid bech32 string, with a payload of the wrong length
+        let short = encode(ADDRESS_PREFIX, &[1, 2, 3]);
+        assert_eq!(
+            validate_address(&short),
+            Err(format!("Address '{}' has an invalid payload", short))
+        );
+    }
+    #[test]
+    fn foreign_prefixes_are_rejected() {
+        let payload = &Sha256::digest(_fixture_key().as_bytes())[..PAYLOAD_LENGTH];
+        // The prefix is everything before the last separator, so this is not an `ADDRESS_PREFIX` address
+        let extended = encode("fcc1x", payload);
+        assert!(extended.starts_with("fcc1"));
+        assert_eq!(
+            validate_address(&extended),
+            Err(format!(
+                "Address '{}' has the prefix 'fcc1x', not 'fcc'",
+                extended
+            ))
+        );
+        let bitcoin = encode("bc", payload);
+        assert_eq!(
+            validate_address(&bitcoin),
+            Err(format!(
+                "Address '{}' has the prefix 'bc', not 'fcc'",
+                bitcoin
+            ))
+        );
+    }
+    #[test]
+    fn legacy_names_follow_the_feature() {
+        let legacy = cfg!(feature = "legacy-addresses");
+        assert_eq!(validate_address("Camper").is_ok(), legacy);
+        assert_eq!(validate_address("node_1").is_ok(), legacy);
+        assert!(validate_address("").is_err());
+        assert!(validate_address("Tom Mondays").is_err());
+        if legacy {
+            assert!(Address::parse("Camper").unwrap().is_legacy());
+        }
+    }
+    #[test]
+    fn legacy_names_follow_the_chain() {
+        let key = String::from(Address::from_public_key(&_fixture_key()));
+        let mut config = GenesisConfig::default();
+        assert!(config.legacy_addresses);
+        assert!(validate_chain_address("Camper", &config).is_ok());
+        assert!(validate_chain_address("Tom Mondays", &config).is_err());
+        config.legacy_addresses = false;
+        assert_eq!(
+            validate_chain_address("Camper", &config),
+            Err("'Camper' is not a valid address".to_string())
+        );
+        assert!(validate_chain_address(&key, &config).is_ok());
+
+        let mut full_block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 0,
+            data: vec![],
+            nonce: 0,
+            next_miner: key.clone(),
+            next_validators: vec![key],
+        });
+        assert_eq!(validate_block_addresses(&full_block, &config), Ok(()));
+        full_block.block.next_validators.push("Camper".to_string());
+        assert_eq!(
+            validate_block_addresses(&full_block, &config),
+            Err("Block 1: 'Camper' is not a valid address".to_string())
+        );
+        config.legacy_addresses = true;
+        assert_eq!(validate_block_addresses(&full_block, &config), Ok(()));
+    }
+
+    fn _fixture_key() -> VerifyingKey {
+        SigningKey::from_bytes(&[7; 32]).verifying_key()
+    }
+}
@@ -0,0 +1,379 @@
+//! # Assembly
+//!
+//! The hash of a block commits to its body, so every field of the body must be set before the block is mined, or sealed
+//! by its producer. `assemble_block` builds the next block of a chain from the transactions offered for it:
+//! 1. The offered transactions are split by their time locks, with `timelock::schedule_transactions`, and the `ready` ones
+//!    are selected with `gas::select_transactions_after`.
+//! 2. The signatures and time locks of the selected transactions, and the witnesses of those of multisig accounts, are moved
+//!    to the body, under the index of their transaction, and the transactions are replayed once, on the state after the chain.
+//!
+//! The fields of the body are then filled in order, each from the replay: the protocol `version`, the `contracts_hash`,
+//! the `receipts`, the `transactions_root`, the `gas_used`, the `data` of the block, holding the Accounts its transactions
+//! change, and, last, the `snapshot_hash` of the state after the block. Blocks mined by proof of work also commit to the
+//! `difficulty` governed by the chain.
+
+use crate::{
+    account::Account,
+    block::Block,
+    body::{headers, FullBlock},
+    consensus::ConsensusMode,
+    difficulty::governed_difficulty_at,
+    gas::{select_transactions_after, Rejection},
+    genesis::GenesisConfig,
+    ledger::changed_accounts,
+    merkle::merkle_root,
+    miner::next_block,
+    multisig::MultisigWitness,
+    protocol::{version_at, RuleSet},
+    receipt::{DetailedReceipt, Receipt},
+    snapshot::{is_snapshot_block, StateSnapshot},
+    state::ChainState,
+    timelock::{schedule_transactions, split_time_locks, ScheduledTransaction},
+    timestamp::{Clock, SystemClock},
+    wallet::TransactionSignature,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// The next block of a chain, ready to be mined or sealed, and what became of each transaction offered for it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AssembledBlock {
+    /// The block, whose body is committed to by its hash.
+    pub full_block: FullBlock,
+    /// The receipt of every mined transaction, in order, with its error message.
+    pub receipts: Vec<DetailedReceipt>,
+    /// The transactions which are not valid yet, to keep for a later block.
+    pub pending: Vec<ScheduledTransaction>,
+    /// The transactions which do not fit in the block, to keep for a later block.
+    pub deferred: Vec<ScheduledTransaction>,
+    /// The transactions past their deadline, to drop.
+    pub expired: Vec<ScheduledTransaction>,
+    /// The transactions which cannot be mined, to drop.
+    pub rejected: Vec<Rejection>,
+}
+
+/// Assembles the `block` following the `chain` from the offered `transactions`.
+///
+/// From the protocol version with spending checks, the `data` of the block is replaced by the Accounts its transactions
+/// change. Before it, as in the genesis block, the `data` is kept as given.
+///
+/// Transactions of addresses of keys are only selected with a valid signature, and those of multisig accounts with a
+/// witness meeting the threshold of the account.
+///
+/// **Note:** The `hash` and `nonce` of the `block` are left unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the `chain` is invalid, the protocol version of the block is not supported, or the selected
+/// transactions cannot be applied.
+pub fn assemble_block(
+    chain: &[FullBlock],
+    block: Block,
+    transactions: Vec<ScheduledTransaction>,
+    config: &GenesisConfig,
+) -> Result<AssembledBlock, String> {
+    let state = ChainState::from_chain(chain, config)?;
+    let block_id = block.id;
+    let version = version_at(block_id, config);
+    let rules = RuleSet::of_version(version)
+        .ok_or_else(|| format!("Protocol version {} is not supported", version))?;
+    let schedule = schedule_transactions(transactions, block_id, block.timestamp);
+    let mut rejected = vec![];
+    let mut ready = vec![];
+    for scheduled in schedule.ready {
+        let is_locked = scheduled.valid_after.is_some() || scheduled.valid_until.is_some();
+        let error = if is_locked && !rules.time_locks {
+            format!("Version {} does not support time locks", version)
+        } else if scheduled.witness.is_some() && !rules.multisig {
+            format!("Version {} does not support multisig witnesses", version)
+        } else if scheduled.signature.is_some() && !rules.signatures {
+            format!(
+                "Version {} does not support transaction signatures",
+                version
+            )
+        } else {
+            ready.push(scheduled);
+            continue;
+        };
+        rejected.push(Rejection {
+            transaction: scheduled,
+            error,
+        });
+    }
+    let mut selection = select_transactions_after(ready, &state, block_id, config)?;
+    rejected.extend(selection.rejected);
+    let multisig = selection
+        .included
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(index, scheduled)| {
+            let witness = scheduled.witness.take()?;
+            Some(MultisigWitness { index, ..witness })
+        })
+        .collect();
+    let transaction_signatures = selection
+        .included
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(index, scheduled)| {
+            let signature = scheduled.signature.take()?;
+            Some(TransactionSignature { index, ..signature })
+        })
+        .collect();
+    let (transactions, time_locks) = split_time_locks(selection.included);
+
+    let mut full_block = FullBlock::from(block);
+    full_block.body.transactions = transactions;
+    full_block.body.multisig = multisig;
+    full_block.body.transaction_signatures = transaction_signatures;
+    full_block.body.time_locks = time_locks;
+    let (mut next, detailed) = state.replay_transactions(&full_block, config)?;
+    let receipts: Vec<Receipt> = detailed
+        .iter()
+        .map(|detailed| detailed.receipt.clone())
+        .collect();
+
+    let contracts_hash = next.contracts.commitment(&full_block);
+    let body = &mut full_block.body;
+    body.version = version;
+    body.contracts_hash = contracts_hash;
+    if rules.receipts {
+        body.receipts = receipts.clone();
+    }
+    if rules.transactions_root {
+        let ids: Vec<_> = receipts.iter().map(|receipt| receipt.tx_hash).collect();
+        body.transactions_root = merkle_root(&ids);
+    }
+    body.gas_used = receipts
+        .iter()
+        .fold(0u64, |gas, receipt| gas.saturating_add(receipt.gas_used));
+    if rules.spending {
+        full_block.block.data = changed_accounts(&full_block, &receipts, &next.accounts);
+    } else {
+        for account in &full_block.block.data {
+            next.accounts
+                .insert(account.address.clone(), account.clone());
+        }
+    }
+    if rules.difficulty && config.consensus == ConsensusMode::ProofOfWork {
+        full_block.body.difficulty =
+            governed_difficulty_at(&headers(chain), block_id, &state.governance, config);
+    }
+    if is_snapshot_block(block_id, config) {
+        full_block.body.snapshot_hash = Some(StateSnapshot::of_state(block_id, &next).hash());
+    }
+    Ok(AssembledBlock {
+        full_block,
+        receipts: detailed,
+        pending: schedule.pending,
+        deferred: selection.deferred,
+        expired: schedule.expired,
+        rejected,
+    })
+}
+
+/// Assembles the next block of the `chain` argument, holding the `data` argument, from the offered `transactions` argument,
+/// with the current time as its timestamp.
+///
+/// # Examples
+///
+/// ```js
+/// const { full_block, receipts, pending, deferred, rejected } = assemble_next_block(chain, [], network, transactions, config);
+/// nodeState.transactions = [...pending, ...deferred];
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the block cannot be assembled, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn assemble_next_block(
+    chain: JsValue,
+    data: JsValue,
+    network: JsValue,
+    transactions: JsValue,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let data: Vec<Account> = data.into_serde()?;
+    let network: Vec<String> = network.into_serde()?;
+    let transactions: Vec<ScheduledTransaction> = transactions.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let mut block = next_block(&headers(&chain), data, network);
+    block.timestamp = SystemClock.now();
+    let assembled =
+        assemble_block(&chain, block, transactions, &config).map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&assembled)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait, hash::BlockHash, multisig::MultisigPolicy, timelock::TimeLock,
+        Events, Transaction,
+    };
+    use ed25519_dalek::SigningKey;
+    #[test]
+    fn offered_transactions_are_sorted() {
+        let config = GenesisConfig::default();
+        let chain = _fixture_chain(&config);
+        let transfer = |address: &str, amount| Transaction {
+            event: Events::Transfer("Camper".to_string(), amount),
+            address: address.to_string(),
+        };
+        let locked = |valid_after, valid_until| ScheduledTransaction {
+            valid_after,
+            valid_until,
+            ..transfer("Tom", 1).into()
+        };
+        let signer = Transaction {
+            event: Events::AddSigner("ab".to_string(), 1),
+            address: "Tom".to_string(),
+        };
+        let transactions = vec![
+            transfer("Tom", 5).into(),
+            locked(Some(TimeLock::Block(5)), None),
+            locked(None, Some(TimeLock::Block(0))),
+            locked(None, Some(TimeLock::Block(10))),
+            transfer("Camper", 100).into(),
+            signer.into(),
+        ];
+        let assembled = assemble_block(&chain, _fixture_block(1), transactions, &config).unwrap();
+        assert_eq!(assembled.pending.len(), 1);
+        assert_eq!(assembled.expired.len(), 1);
+        assert_eq!(
+            assembled
+                .rejected
+                .iter()
+                .map(|rejection| rejection.error.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "'Camper' cannot transfer 100 tokens",
+                "'Tom' is not a multisig account"
+            ]
+        );
+
+        let full_block = &assembled.full_block;
+        assert_eq!(full_block.body.transactions.len(), 2);
+        assert_eq!(full_block.body.time_locks.len(), 1);
+        assert_eq!(full_block.body.time_locks[0].index, 1);
+        assert_eq!(full_block.body.gas_used, 2000);
+        assert_eq!(assembled.receipts.len(), 2);
+        // Tom pays 2 fees and transfers 6 tokens to Camper
+        let tokens: Vec<_> = full_block
+            .block
+            .data
+            .iter()
+            .map(|account| (account.address.as_str(), account.tokens))
+            .collect();
+        assert_eq!(tokens, vec![("Tom", 22), ("Camper", 26)]);
+        let chain = [chain, vec![assembled.full_block]].concat();
+        assert!(ChainState::from_chain(&chain, &config).is_ok());
+    }
+    #[test]
+    fn witnesses_are_assembled_with_their_transactions() {
+        let config = GenesisConfig::default();
+        let keys: Vec<SigningKey> = (1..=3)
+            .map(|seed| SigningKey::from_bytes(&[seed; 32]))
+            .collect();
+        let signers = keys
+            .iter()
+            .map(|key| hex::encode(key.verifying_key().as_bytes()))
+            .collect();
+        let policy = MultisigPolicy::new(2, signers).unwrap();
+        let treasury = String::from(policy.address());
+        let mut genesis = _fixture_block(0);
+        genesis.data = vec![Account::new("Tom"), Account::new(&treasury)];
+        let chain = vec![
+            assemble_block(&[], genesis, vec![], &config)
+                .unwrap()
+                .full_block,
+        ];
+
+        let transfer = Transaction {
+            event: Events::Transfer("Tom".to_string(), 5),
+            address: treasury.clone(),
+        };
+        let mut witness = MultisigWitness::new(7, 0, policy);
+        for key in &keys[..2] {
+            witness
+                .sign(&transfer.clone().into(), &config, key)
+                .unwrap();
+        }
+        let update = Transaction {
+            event: Events::UpdateChain,
+            address: "Tom".to_string(),
+        };
+        let witnessed = ScheduledTransaction {
+            witness: Some(witness),
+            ..transfer.clone().into()
+        };
+        let transactions = vec![update.into(), witnessed, transfer.into()];
+        let assembled = assemble_block(&chain, _fixture_block(1), transactions, &config).unwrap();
+        // Only the witnessed transfer is mined, so the unwitnessed one is not signed
+        assert_eq!(assembled.rejected.len(), 1);
+        assert_eq!(
+            assembled.rejected[0].error,
+            format!(
+                "Transaction 2 of block 1 is not signed by the signers of '{}'",
+                treasury
+            )
+        );
+        let body = &assembled.full_block.body;
+        assert_eq!(body.transactions.len(), 2);
+        assert_eq!(body.multisig.len(), 1);
+        assert_eq!(body.multisig[0].index, 1);
+        let chain = [chain, vec![assembled.full_block]].concat();
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert_eq!(state.accounts["Tom"].tokens, 24);
+        assert!(state.multisig.policy(&treasury).is_some());
+    }
+    #[test]
+    fn snapshot_is_of_the_assembled_state() {
+        let config = GenesisConfig {
+            snapshot_interval: 1,
+            ..Default::default()
+        };
+        let mut chain = _fixture_chain(&config);
+        let update = Transaction {
+            event: Events::UpdateChain,
+            address: "Tom".to_string(),
+        };
+        let assembled =
+            assemble_block(&chain, _fixture_block(1), vec![update.into()], &config).unwrap();
+        chain.push(assembled.full_block);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert_eq!(state.accounts["Tom"].tokens, 29);
+        assert_eq!(
+            chain[1].body.snapshot_hash,
+            Some(StateSnapshot::of_state(1, &state).hash())
+        );
+    }
+
+    /// The block `id`, with a placeholder hash.
+    fn _fixture_block(id: u64) -> Block {
+        Block {
+            id,
+            hash: BlockHash::default().to_hex(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![],
+            nonce: 0,
+            next_miner: "Tom".to_string(),
+            next_validators: vec![],
+        }
+    }
+    /// A genesis block, where Tom holds 30 tokens, of which 10 are staked, and Camper 20.
+    fn _fixture_chain(config: &GenesisConfig) -> Vec<FullBlock> {
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let mut genesis = _fixture_block(0);
+        genesis.data = vec![tom, Account::new("Camper")];
+        vec![
+            assemble_block(&[], genesis, vec![], config)
+                .unwrap()
+                .full_block,
+        ]
+    }
+}
@@ -0,0 +1,171 @@
+//! # Body
+//!
+//! The body of a block holds the data added to blocks since the `Block` type was defined.
+//!
+//! A `FullBlock` serialises as a `Block`, with the fields of its `BlockBody` alongside. So, a chain of full blocks
+//! can still be read as a `Chain`, and a `Chain` can be read as full blocks with empty bodies.
+
+use crate::{
+    block::Block, chain::Chain, hash::BlockHash, multisig::MultisigWitness, receipt::Receipt,
+    timelock::TransactionTimeLocks, wallet::TransactionSignature, Transaction,
+};
+use serde::{Deserialize, Serialize};
+
+/// The signature of the Account which produced a block, or of a signer of a multisig transaction.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    /// The hex encoded Ed25519 public key of the signer.
+    pub public_key: String,
+    /// The hex encoded Ed25519 signature of the block digest, or of the transaction hash.
+    pub signature: String,
+}
+
+/// The fields of a block which are not part of `Block`.
+///
+/// **Note:** The `transactions`, `snapshot_hash`, `multisig` witnesses, `contracts_hash`, `gas_used`, `version`, `receipts`, `transactions_root`, `time_locks`, `transaction_signatures`, and `difficulty` are committed to by the hex hash of the block. The `slot` and `missed_slots` are not hashed,
+/// as they follow from the `timestamp`s of the block and its parent. Validation checks they match.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BlockBody {
+    /// The signature of the producer, for blocks produced by stake.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<BlockSignature>,
+    /// The slot the block was produced in, for blocks produced by stake.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// The number of slots since the previous block in which no block was produced.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub missed_slots: u64,
+    /// The transactions mined into the block, whose resulting `Account`s are the `data` of the block.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<Transaction>,
+    /// The hash of the `StateSnapshot` taken after the block, for blocks at a snapshot interval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_hash: Option<BlockHash>,
+    /// The witnesses of the transactions from multisig accounts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub multisig: Vec<MultisigWitness>,
+    /// The hash of the `ContractState` after the block, for blocks with contract transactions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contracts_hash: Option<BlockHash>,
+    /// The gas used by the transactions of the block.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub gas_used: u64,
+    /// The protocol version whose rules the block follows.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u32,
+    /// The receipts of the transactions, for blocks following a protocol version with receipts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub receipts: Vec<Receipt>,
+    /// The Merkle root of the hashes of the transactions, for blocks following a protocol version with transaction roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transactions_root: Option<BlockHash>,
+    /// The time locks of the time locked transactions, by the index of the transaction.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub time_locks: Vec<TransactionTimeLocks>,
+    /// The signatures of the transactions from addresses of keys, by the index of the transaction.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transaction_signatures: Vec<TransactionSignature>,
+    /// The difficulty the hash of the block meets, for blocks mined following a protocol version with committed difficulties.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub difficulty: u32,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A `Block`, together with its `BlockBody`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FullBlock {
+    #[serde(flatten)]
+    pub block: Block,
+    #[serde(flatten)]
+    pub body: BlockBody,
+}
+
+impl From<Block> for FullBlock {
+    fn from(block: Block) -> Self {
+        Self {
+            block,
+            body: BlockBody::default(),
+        }
+    }
+}
+
+/// A chain of `FullBlock`s.
+pub type FullChain = Vec<FullBlock>;
+
+/// Returns the `Block`s of a `FullChain`, without their bodies.
+pub fn headers(chain: &[FullBlock]) -> Chain {
+    chain
+        .iter()
+        .map(|full_block| full_block.block.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, AccountTrait};
+    #[test]
+    fn block_json_reads_as_full_block_with_empty_body() {
+        let block = _fixture_block();
+        let json = serde_json::to_string(&block).unwrap();
+        let full_block: FullBlock = serde_json::from_str(&json).unwrap();
+        assert_eq!(full_block.block, block);
+        assert_eq!(full_block.body, BlockBody::default());
+        // An empty body adds no fields
+        assert_eq!(serde_json::to_string(&full_block).unwrap(), json);
+    }
+    #[test]
+    fn full_block_json_reads_as_block() {
+        let full_block = FullBlock {
+            block: _fixture_block(),
+            body: BlockBody {
+                signature: Some(BlockSignature {
+                    public_key: "ab".to_string(),
+                    signature: "cd".to_string(),
+                }),
+                slot: Some(3),
+                missed_slots: 1,
+                transactions: vec![],
+                snapshot_hash: None,
+                multisig: vec![],
+                contracts_hash: None,
+                gas_used: 0,
+                version: 0,
+                receipts: vec![],
+                transactions_root: None,
+                time_locks: vec![],
+                transaction_signatures: vec![],
+                difficulty: 0,
+            },
+        };
+        let json = serde_json::to_value(&full_block).unwrap();
+        assert_eq!(json["signature"]["public_key"], "ab");
+        assert_eq!(json["missed_slots"], 1);
+        assert_eq!(json["next_miner"], "Mrugesh");
+        let block: Block = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(block, full_block.block);
+        let round_trip: FullBlock = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, full_block);
+    }
+    #[test]
+    fn headers_drop_bodies() {
+        let chain: FullChain = vec![FullBlock::from(_fixture_block())];
+        assert_eq!(headers(&chain), vec![_fixture_block()]);
+    }
+
+    fn _fixture_block() -> Block {
+        Block {
+            id: 1,
+            hash: "0x00".to_string(),
+            previous_hash: "0110".to_string(),
+            timestamp: 1650301013,
+            data: vec![Account::new("Ahmad")],
+            nonce: 172,
+            next_miner: "Mrugesh".to_string(),
+            next_validators: vec!["Mrugesh".to_string()],
+        }
+    }
+}
@@ -0,0 +1,1111 @@
+//! # Codec
+//!
+//! The canonical binary encoding of blocks and transactions, used to calculate their hashes.
+//!
+//! Unlike the `serde_json::Value` hashed by `calculate_hash`, the encoding does not depend on key ordering or number formatting:
+//! - Every encoding starts with the `FORMAT_VERSION` byte
+//! - Integers are fixed width, big endian
+//! - Strings, byte arrays, and sequences are prefixed with their length as a `u32`
+//! - Structs and tuples are their fields in order, with no names
+//! - Enum variants are their name as a string, followed by their fields
+//! - `Option`s are a `0` byte for `None`, or a `1` byte followed by the value
+//!
+//! Maps and floats have no canonical encoding, and are rejected.
+//!
+//! The encoding is not self-describing, so `decode` needs to know the type being decoded. Types skipping fields when
+//! serialising, such as `BlockBody`, must be converted to a type with a fixed list of fields first.
+//!
+//! **Note:** `encode_block` lists the fields of `Account` and `Block` explicitly, so adding a field to either does not change
+//! existing hashes until the field is added to the encoding, alongside a new `FORMAT_VERSION`.
+//!
+//! The committed fields of a `BlockBody` are appended to the block encoding by `encode_body`. Each non-empty field is written
+//! as a tag byte followed by its value, so a block with an empty body hashes the same as its `Block`.
+
+use crate::{
+    account::Account,
+    block::Block,
+    body::{BlockBody, FullBlock},
+    genesis::GenesisConfig,
+    snapshot::StateSnapshot,
+    timelock::TransactionTimeLocks,
+    Transaction,
+};
+use serde::{
+    de::{self, DeserializeOwned, IntoDeserializer, Visitor},
+    ser, Serialize,
+};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// The version of the encoding, written as the first byte of every encoded block and transaction.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// An error returned when a value has no canonical encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError(pub String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl ser::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CodecError(msg.to_string())
+    }
+}
+
+impl de::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CodecError(msg.to_string())
+    }
+}
+
+/// Encodes any serialisable `value`, without a version byte.
+///
+/// # Errors
+///
+/// Returns an error if the value contains a map, a float, or a sequence of unknown length.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let mut serializer = CanonicalSerializer { output: vec![] };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+/// Decodes a value of type `T` from its canonical encoding, without a version byte.
+///
+/// # Errors
+///
+/// Returns an error if the `bytes` are not the encoding of a `T`, or have bytes left over.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+    let mut deserializer = CanonicalDeserializer { input: bytes };
+    let value = T::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(CodecError(format!(
+            "{} trailing bytes",
+            deserializer.input.len()
+        )));
+    }
+    Ok(value)
+}
+
+/// Encodes the fields of an `Account`.
+fn account_fields(account: &Account) -> (&str, u64, u64) {
+    (&account.address, account.staked, account.tokens)
+}
+
+/// Encodes every field of the `block`, except its `hash`, prefixed with the `FORMAT_VERSION`.
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    let data: Vec<_> = block.data.iter().map(account_fields).collect();
+    let fields = (
+        block.id,
+        &block.previous_hash,
+        block.timestamp,
+        data,
+        block.nonce,
+        &block.next_miner,
+        &block.next_validators,
+    );
+    let mut output = vec![FORMAT_VERSION];
+    output.extend(encode(&fields).expect("block fields to be encodable"));
+    output
+}
+
+/// The tag of the `transactions` of a `BlockBody`.
+pub const TRANSACTIONS_TAG: u8 = 1;
+/// The tag of the `snapshot_hash` of a `BlockBody`.
+pub const SNAPSHOT_HASH_TAG: u8 = 2;
+/// The tag of the `multisig` witnesses of a `BlockBody`.
+pub const MULTISIG_TAG: u8 = 3;
+/// The tag of the `contracts_hash` of a `BlockBody`.
+pub const CONTRACTS_HASH_TAG: u8 = 4;
+/// The tag of the `gas_used` of a `BlockBody`.
+pub const GAS_USED_TAG: u8 = 5;
+/// The tag of the protocol `version` of a `BlockBody`.
+pub const VERSION_TAG: u8 = 6;
+/// The tag of the `receipts` of a `BlockBody`.
+pub const RECEIPTS_TAG: u8 = 7;
+/// The tag of the `transactions_root` of a `BlockBody`.
+pub const TRANSACTIONS_ROOT_TAG: u8 = 8;
+/// The tag of the `time_locks` of a `BlockBody`.
+pub const TIME_LOCKS_TAG: u8 = 9;
+/// The tag of the `transaction_signatures` of a `BlockBody`.
+pub const TRANSACTION_SIGNATURES_TAG: u8 = 10;
+/// The tag of the `difficulty` of a `BlockBody`.
+pub const DIFFICULTY_TAG: u8 = 11;
+
+/// Encodes the fields of the `body` committed to by the block hash, without a version byte.
+///
+/// **Note:** The `signature`, `slot`, and `missed_slots` are not committed. Empty fields are not written.
+///
+/// # Errors
+///
+/// Returns an error if a transaction, or witness, has no canonical encoding.
+pub fn encode_body(body: &BlockBody) -> Result<Vec<u8>, CodecError> {
+    let mut output = vec![];
+    if !body.transactions.is_empty() {
+        let transactions: Vec<_> = body
+            .transactions
+            .iter()
+            .map(|transaction| (&transaction.address, &transaction.event))
+            .collect();
+        output.push(TRANSACTIONS_TAG);
+        output.extend(encode(&transactions)?);
+    }
+    if let Some(snapshot_hash) = &body.snapshot_hash {
+        output.push(SNAPSHOT_HASH_TAG);
+        output.extend(snapshot_hash.0);
+    }
+    if !body.multisig.is_empty() {
+        output.push(MULTISIG_TAG);
+        output.extend(encode(&body.multisig)?);
+    }
+    if let Some(contracts_hash) = &body.contracts_hash {
+        output.push(CONTRACTS_HASH_TAG);
+        output.extend(contracts_hash.0);
+    }
+    if body.gas_used > 0 {
+        output.push(GAS_USED_TAG);
+        output.extend(encode(&body.gas_used)?);
+    }
+    if body.version > 0 {
+        output.push(VERSION_TAG);
+        output.extend(encode(&body.version)?);
+    }
+    if !body.receipts.is_empty() {
+        output.push(RECEIPTS_TAG);
+        output.extend(encode(&body.receipts)?);
+    }
+    if let Some(transactions_root) = &body.transactions_root {
+        output.push(TRANSACTIONS_ROOT_TAG);
+        output.extend(transactions_root.0);
+    }
+    if !body.time_locks.is_empty() {
+        output.push(TIME_LOCKS_TAG);
+        output.extend(encode(&body.time_locks)?);
+    }
+    if !body.transaction_signatures.is_empty() {
+        output.push(TRANSACTION_SIGNATURES_TAG);
+        output.extend(encode(&body.transaction_signatures)?);
+    }
+    if body.difficulty > 0 {
+        output.push(DIFFICULTY_TAG);
+        output.extend(encode(&body.difficulty)?);
+    }
+    Ok(output)
+}
+
+/// The version of the list of genesis parameter tags, written after the `FORMAT_VERSION` by `encode_genesis_config`.
+pub const GENESIS_VERSION: u8 = 4;
+
+/// Encodes the parameters of the `config` every Account must agree on, prefixed with the `FORMAT_VERSION` and
+/// `GENESIS_VERSION`.
+///
+/// Every parameter is written, whatever its value, as a tag byte followed by its value, in the order of the tags. So the
+/// encoding does not depend on `GenesisConfig::default()`. A new parameter takes the next tag, and bumps the
+/// `GENESIS_VERSION`. Tags are never reused.
+///
+/// The `upgrades` schedule is encoded too, so nodes scheduling different activation heights do not share a genesis hash.
+pub fn encode_genesis_config(config: &GenesisConfig) -> Result<Vec<u8>, CodecError> {
+    let producer_keys: Vec<_> = config.producer_keys.iter().collect();
+    let allocations: Vec<_> = config.allocations.iter().map(account_fields).collect();
+    let vesting: Vec<_> = config
+        .vesting
+        .iter()
+        .map(|(address, s)| (address, (s.amount, s.start, s.cliff, s.duration)))
+        .collect();
+    let upgrades: Vec<_> = config
+        .upgrades
+        .iter()
+        .map(|upgrade| (upgrade.version, upgrade.height))
+        .collect();
+    let parameters = [
+        (1, encode(&config.consensus)?),
+        (2, encode(&config.difficulty_prefix)?),
+        (3, encode(&config.target_block_time)?),
+        (4, encode(&config.retarget_interval)?),
+        (5, encode(&config.slot_duration)?),
+        (6, encode(&config.epoch_length)?),
+        (7, encode(&(config.median_time_span as u64))?),
+        (8, encode(&config.max_clock_drift)?),
+        (9, encode(&config.snapshot_interval)?),
+        (10, encode(&producer_keys)?),
+        (11, encode(&config.starting_tokens)?),
+        (12, encode(&config.rack_price)?),
+        (13, encode(&config.default_miner)?),
+        (14, encode(&(config.validator_count as u64))?),
+        (15, encode(&allocations)?),
+        (16, encode(&config.contract_gas_limit)?),
+        (17, encode(&config.block_gas_limit)?),
+        (18, encode(&config.gas_per_token)?),
+        (19, encode(&config.voting_period)?),
+        (20, encode(&config.quorum_percent)?),
+        (21, encode(&config.execution_period)?),
+        (22, encode(&config.max_reward)?),
+        (23, encode(&vesting)?),
+        (24, encode(&upgrades)?),
+        (25, encode(&config.legacy_addresses)?),
+    ];
+    let mut output = vec![FORMAT_VERSION, GENESIS_VERSION];
+    for (tag, value) in parameters {
+        output.push(tag);
+        output.extend(value);
+    }
+    Ok(output)
+}
+
+/// The version of the snapshot encoding, written after the `FORMAT_VERSION` by `hash_snapshot`.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Returns the `Sha256` digest of the canonical encoding of the `snapshot`, prefixed with the `FORMAT_VERSION` and
+/// `SNAPSHOT_VERSION`.
+///
+/// The id of the block it was taken at is followed by its accounts, sequences, multisig policies, the hash of its contracts,
+/// its proposals, and its parameter changes. Maps are written as sequences of pairs, ordered by key.
+pub fn hash_snapshot(snapshot: &StateSnapshot) -> Vec<u8> {
+    let accounts: Vec<_> = snapshot.accounts.iter().map(account_fields).collect();
+    let sequences: Vec<_> = snapshot.sequences.iter().collect();
+    let policies: Vec<_> = snapshot.multisig.policies().iter().collect();
+    let proposals: Vec<_> = snapshot
+        .governance
+        .proposals
+        .iter()
+        .map(|proposal| {
+            let votes: Vec<_> = proposal.votes.iter().collect();
+            (
+                proposal.id,
+                &proposal.proposer,
+                proposal.parameter,
+                proposal.value,
+                proposal.proposed_at,
+                votes,
+                proposal.executed,
+            )
+        })
+        .collect();
+    let mut output = vec![FORMAT_VERSION, SNAPSHOT_VERSION];
+    let fields = (
+        snapshot.block_id,
+        accounts,
+        sequences,
+        policies,
+        snapshot.contracts.hash().0,
+        proposals,
+        &snapshot.governance.changes,
+    );
+    output.extend(encode(&fields).expect("snapshot fields to be encodable"));
+    Sha256::digest(output).to_vec()
+}
+
+/// Returns the `Sha256` digest of the canonical encoding of the `block`.
+pub fn hash_block(block: &Block) -> Vec<u8> {
+    Sha256::digest(encode_block(block)).to_vec()
+}
+
+/// Returns the `Sha256` digest of the canonical encoding of the `full_block`: its `Block`, followed by its body.
+///
+/// # Errors
+///
+/// Returns an error if a transaction of the body has no canonical encoding.
+pub fn hash_full_block(full_block: &FullBlock) -> Result<Vec<u8>, CodecError> {
+    let mut output = encode_block(&full_block.block);
+    output.extend(encode_body(&full_block.body)?);
+    Ok(Sha256::digest(output).to_vec())
+}
+
+/// Encodes the identity of the `transaction`: the `genesis_hash` of its chain, its address, its `sequence` among the
+/// transactions of the address, and its event, prefixed with the `FORMAT_VERSION`.
+///
+/// If either of the `time_locks` is set, they follow, after the `TIME_LOCKS_TAG`, so a signature of the ID also signs them.
+/// Their `index` is not part of the identity.
+///
+/// # Errors
+///
+/// Returns an error if the `event` of the transaction has no canonical encoding.
+pub fn encode_transaction_id(
+    genesis_hash: &str,
+    sequence: u64,
+    transaction: &Transaction,
+    time_locks: &TransactionTimeLocks,
+) -> Result<Vec<u8>, CodecError> {
+    let mut output = vec![FORMAT_VERSION];
+    output.extend(encode(&(
+        genesis_hash,
+        &transaction.address,
+        sequence,
+        &transaction.event,
+    ))?);
+    if !time_locks.is_empty() {
+        output.push(TIME_LOCKS_TAG);
+        output.extend(encode(&(time_locks.valid_after, time_locks.valid_until))?);
+    }
+    Ok(output)
+}
+
+/// Returns the `Sha256` digest of `encode_transaction_id`, the ID of the transaction. See `sequence::transaction_id`.
+pub fn hash_transaction_id(
+    genesis_hash: &str,
+    sequence: u64,
+    transaction: &Transaction,
+    time_locks: &TransactionTimeLocks,
+) -> Result<Vec<u8>, CodecError> {
+    let output = encode_transaction_id(genesis_hash, sequence, transaction, time_locks)?;
+    Ok(Sha256::digest(output).to_vec())
+}
+
+/// A `serde::Serializer` writing the canonical encoding.
+struct CanonicalSerializer {
+    output: Vec<u8>,
+}
+
+impl CanonicalSerializer {
+    fn write_len(&mut self, len: usize) -> Result<(), CodecError> {
+        let len = u32::try_from(len).map_err(|_| CodecError("Length exceeds u32".to_string()))?;
+        self.output.extend(len.to_be_bytes());
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        self.write_len(bytes.len())?;
+        self.output.extend(bytes);
+        Ok(())
+    }
+}
+
+impl ser::Serializer for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = ser::Impossible<(), CodecError>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), CodecError> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), CodecError> {
+        self.output.push(v);
+        Ok(())
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), CodecError> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+    fn serialize_f32(self, _v: f32) -> Result<(), CodecError> {
+        Err(CodecError("Floats have no canonical encoding".to_string()))
+    }
+    fn serialize_f64(self, _v: f64) -> Result<(), CodecError> {
+        Err(CodecError("Floats have no canonical encoding".to_string()))
+    }
+    fn serialize_char(self, v: char) -> Result<(), CodecError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+    fn serialize_str(self, v: &str) -> Result<(), CodecError> {
+        self.write_bytes(v.as_bytes())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), CodecError> {
+        self.write_bytes(v)
+    }
+    fn serialize_none(self) -> Result<(), CodecError> {
+        self.output.push(0);
+        Ok(())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), CodecError> {
+        self.output.push(1);
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), CodecError> {
+        self.serialize_str(variant)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        self.serialize_str(variant)?;
+        value.serialize(self)
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, CodecError> {
+        let len = len.ok_or_else(|| CodecError("Sequence length must be known".to_string()))?;
+        self.write_len(len)?;
+        Ok(self)
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, CodecError> {
+        self.serialize_str(variant)?;
+        Ok(self)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, CodecError> {
+        Err(CodecError("Maps have no canonical encoding".to_string()))
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, CodecError> {
+        self.serialize_str(variant)?;
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut CanonicalSerializer {
+    type Ok = ();
+    type Error = CodecError;
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+    fn end(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+/// A `serde::Deserializer` reading the canonical encoding.
+struct CanonicalDeserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> CanonicalDeserializer<'de> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        if self.input.len() < N {
+            return Err(CodecError("Unexpected end of input".to_string()));
+        }
+        let (bytes, rest) = self.input.split_at(N);
+        self.input = rest;
+        Ok(bytes.try_into().expect("slice to be N bytes"))
+    }
+
+    fn read_len(&mut self) -> Result<usize, CodecError> {
+        Ok(u32::from_be_bytes(self.read()?) as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'de [u8], CodecError> {
+        let len = self.read_len()?;
+        if self.input.len() < len {
+            return Err(CodecError("Unexpected end of input".to_string()));
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_str(&mut self) -> Result<&'de str, CodecError> {
+        std::str::from_utf8(self.read_bytes()?).map_err(|e| CodecError(e.to_string()))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut CanonicalDeserializer<'de> {
+    type Error = CodecError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, CodecError> {
+        Err(CodecError(
+            "The canonical encoding is not self-describing".to_string(),
+        ))
+    }
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.read::<1>()?[0] {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            byte => Err(CodecError(format!("Invalid bool {}", byte))),
+        }
+    }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i8(i8::from_be_bytes(self.read()?))
+    }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i16(i16::from_be_bytes(self.read()?))
+    }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i32(i32::from_be_bytes(self.read()?))
+    }
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i64(i64::from_be_bytes(self.read()?))
+    }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u8(self.read::<1>()?[0])
+    }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u16(u16::from_be_bytes(self.read()?))
+    }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u32(u32::from_be_bytes(self.read()?))
+    }
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u64(u64::from_be_bytes(self.read()?))
+    }
+    fn deserialize_f32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, CodecError> {
+        Err(CodecError("Floats have no canonical encoding".to_string()))
+    }
+    fn deserialize_f64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, CodecError> {
+        Err(CodecError("Floats have no canonical encoding".to_string()))
+    }
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let mut chars = self.read_str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(CodecError("Invalid char".to_string())),
+        }
+    }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_borrowed_str(self.read_str()?)
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_str(visitor)
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_borrowed_bytes(self.read_bytes()?)
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_bytes(visitor)
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.read::<1>()?[0] {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            byte => Err(CodecError(format!("Invalid option tag {}", byte))),
+        }
+    }
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_unit()
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let len = self.read_len()?;
+        visitor.visit_seq(Elements {
+            deserializer: self,
+            remaining: len,
+        })
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_seq(Elements {
+            deserializer: self,
+            remaining: len,
+        })
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_tuple(len, visitor)
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, CodecError> {
+        Err(CodecError("Maps have no canonical encoding".to_string()))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_enum(self)
+    }
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_str(visitor)
+    }
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_any(visitor)
+    }
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The elements of a sequence, tuple, or struct.
+struct Elements<'a, 'de> {
+    deserializer: &'a mut CanonicalDeserializer<'de>,
+    remaining: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = CodecError;
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, CodecError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut CanonicalDeserializer<'de> {
+    type Error = CodecError;
+    type Variant = Self;
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), CodecError> {
+        let variant: de::value::StrDeserializer<'_, CodecError> =
+            self.read_str()?.into_deserializer();
+        Ok((seed.deserialize(variant)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut CanonicalDeserializer<'de> {
+    type Error = CodecError;
+    fn unit_variant(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, CodecError> {
+        seed.deserialize(self)
+    }
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait,
+        timelock::{TimeLock, TransactionTimeLocks},
+        Events,
+    };
+    use std::collections::HashMap;
+    #[test]
+    fn integers_are_fixed_width_big_endian() {
+        assert_eq!(encode(&1u64).unwrap(), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(encode(&258u32).unwrap(), vec![0, 0, 1, 2]);
+        assert_eq!(encode(&7u8).unwrap(), vec![7]);
+        assert_eq!(encode(&true).unwrap(), vec![1]);
+    }
+    #[test]
+    fn strings_and_sequences_are_length_prefixed() {
+        assert_eq!(encode("ab").unwrap(), vec![0, 0, 0, 2, b'a', b'b']);
+        assert_eq!(
+            encode(&vec!["a", ""]).unwrap(),
+            vec![0, 0, 0, 2, 0, 0, 0, 1, b'a', 0, 0, 0, 0]
+        );
+        // Moving a character between strings changes the encoding
+        assert_ne!(encode(&("ab", "c")).unwrap(), encode(&("a", "bc")).unwrap());
+    }
+    #[test]
+    fn options_are_tagged() {
+        assert_eq!(encode(&None::<u8>).unwrap(), vec![0]);
+        assert_eq!(encode(&Some(5u8)).unwrap(), vec![1, 5]);
+    }
+    #[test]
+    fn enum_variants_are_encoded_by_name() {
+        #[derive(Serialize)]
+        enum Example {
+            Unit,
+            Pair(String, u64),
+        }
+        assert_eq!(
+            encode(&Example::Unit).unwrap(),
+            vec![0, 0, 0, 4, b'U', b'n', b'i', b't']
+        );
+        assert_eq!(
+            encode(&Example::Pair("T".to_string(), 1)).unwrap(),
+            vec![0, 0, 0, 4, b'P', b'a', b'i', b'r', 0, 0, 0, 1, b'T', 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+    }
+    #[test]
+    fn maps_and_floats_are_rejected() {
+        let map: HashMap<String, u64> = HashMap::new();
+        assert!(encode(&map).is_err());
+        assert!(encode(&1.5f64).is_err());
+    }
+    #[test]
+    fn encode_block_starts_with_version() {
+        let block = _fixture_block();
+        let encoded = encode_block(&block);
+        assert_eq!(encoded[0], FORMAT_VERSION);
+        // id
+        assert_eq!(encoded[1..9], [0, 0, 0, 0, 0, 0, 0, 1]);
+        // previous_hash
+        assert_eq!(encoded[9..13], [0, 0, 0, 4]);
+        assert_eq!(&encoded[13..17], b"0110");
+    }
+    #[test]
+    fn encode_block_ignores_hash_field() {
+        let mut block = _fixture_block();
+        let encoded = encode_block(&block);
+        block.hash = "0x1234".to_string();
+        assert_eq!(encode_block(&block), encoded);
+        block.nonce += 1;
+        assert_ne!(encode_block(&block), encoded);
+    }
+    #[test]
+    fn hash_block_test_vector() {
+        let block = _fixture_block();
+        assert_eq!(
+            hex::encode(encode_block(&block)),
+            concat!(
+                "01",
+                "0000000000000001",
+                "0000000430313130",
+                "00000000625d9855",
+                "00000001",
+                "0000000541686d6164",
+                "0000000000000000",
+                "0000000000000014",
+                "00000000000000ac",
+                "000000074d727567657368",
+                "00000001",
+                "000000074d727567657368",
+            )
+        );
+        assert_eq!(
+            hex::encode(hash_block(&block)),
+            "231404bf8352d092bc5e8bd379a253b371438b47eb3dee2598ffeec178d5536d"
+        );
+    }
+    #[test]
+    fn transaction_id_test_vector() {
+        let transfer = Transaction {
+            event: Events::Transfer("Tom".to_string(), 1),
+            address: "Camper".to_string(),
+        };
+        let unlocked = TransactionTimeLocks::default();
+        assert_eq!(
+            hex::encode(encode_transaction_id("ab", 2, &transfer, &unlocked).unwrap()),
+            concat!(
+                "01",
+                "000000026162",
+                "0000000643616d706572",
+                "0000000000000002",
+                "000000085472616e73666572",
+                "00000003546f6d",
+                "0000000000000001",
+            )
+        );
+        assert_eq!(
+            hex::encode(hash_transaction_id("ab", 2, &transfer, &unlocked).unwrap()),
+            "59e28b8d208f3f14da9db16ebd29b757dc1176756ec0c1ce4058129c6fc18e30"
+        );
+    }
+
+    #[test]
+    fn empty_body_does_not_change_block_hash() {
+        let mut full_block = FullBlock::from(_fixture_block());
+        assert!(encode_body(&full_block.body).unwrap().is_empty());
+        assert_eq!(
+            hash_full_block(&full_block).unwrap(),
+            hash_block(&full_block.block)
+        );
+
+        full_block.body.transactions = vec![Transaction {
+            event: Events::Stake,
+            address: "Camper".to_string(),
+        }];
+        assert_eq!(
+            hex::encode(encode_body(&full_block.body).unwrap()),
+            concat!(
+                "01",
+                "00000001",
+                "0000000643616d706572",
+                "000000055374616b65",
+            )
+        );
+        assert_ne!(
+            hash_full_block(&full_block).unwrap(),
+            hash_block(&full_block.block)
+        );
+    }
+
+    #[test]
+    fn encode_genesis_config_test_vector() {
+        // The versions, then every parameter of the default config: its tag, then its value
+        assert_eq!(
+            hex::encode(encode_genesis_config(&GenesisConfig::default()).unwrap()),
+            concat!(
+                "0104",
+                "010000000b50726f6f664f66576f726b",
+                "020000000130",
+                "03000000000000000a",
+                "04000000000000000a",
+                "05000000000000000a",
+                "06000000000000000a",
+                "07000000000000000b",
+                "08000000000000003c",
+                "090000000000000064",
+                "0a00000000",
+                "0b0000000000000014",
+                "0c000000000000000a",
+                "0d0000000643616d706572",
+                "0e0000000000000003",
+                "0f00000000",
+                "1000000000000186a0",
+                "1100000000000f4240",
+                "1200000000000003e8",
+                "130000000000000064",
+                "140000000000000021",
+                "150000000000000064",
+                "16000000000000000a",
+                "1700000000",
+                "1800000001000000010000000000000001",
+                "1901",
+            )
+        );
+    }
+    #[test]
+    fn hash_snapshot_test_vector() {
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let snapshot = StateSnapshot {
+            block_id: 2,
+            accounts: vec![tom],
+            sequences: [("Tom".to_string(), 2)].into(),
+            ..Default::default()
+        };
+        // The contracts hash of a state without contracts
+        assert_eq!(
+            snapshot.contracts.hash().to_hex(),
+            "0x957b88b12730e646e0f33d3618b77dfa579e8231e3c59c7104be7165611c8027"
+        );
+        assert_eq!(
+            hex::encode(hash_snapshot(&snapshot)),
+            "f07cfdc734993fea83125ae554ec31070c1aa61dbf00a0d20f950392373a14a7"
+        );
+    }
+    #[test]
+    fn time_locks_are_committed() {
+        let mut full_block = FullBlock::from(_fixture_block());
+        full_block.body.time_locks = vec![TransactionTimeLocks {
+            index: 0,
+            valid_after: Some(TimeLock::Block(5)),
+            valid_until: None,
+        }];
+        assert_eq!(
+            hex::encode(encode_body(&full_block.body).unwrap()),
+            concat!(
+                "09",
+                "00000001",
+                "0000000000000000",
+                "0100000005426c6f636b0000000000000005",
+                "00",
+            )
+        );
+    }
+
+    #[test]
+    fn decode_round_trips_encode() {
+        let block = _fixture_block();
+        let bytes = encode(&block).unwrap();
+        assert_eq!(decode::<Block>(&bytes).unwrap(), block);
+
+        let transactions = vec![
+            Transaction {
+                event: Events::Transfer("Tom".to_string(), 1),
+                address: "Camper".to_string(),
+            },
+            Transaction {
+                event: Events::Stake,
+                address: "Tom".to_string(),
+            },
+        ];
+        let value = (Some(7u32), None::<u64>, transactions);
+        let bytes = encode(&value).unwrap();
+        assert_eq!(
+            decode::<(Option<u32>, Option<u64>, Vec<Transaction>)>(&bytes).unwrap(),
+            value
+        );
+    }
+    #[test]
+    fn decode_rejects_malformed_input() {
+        let bytes = encode(&_fixture_block()).unwrap();
+        assert_eq!(
+            decode::<Block>(&bytes[..bytes.len() - 1]),
+            Err(CodecError("Unexpected end of input".to_string()))
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode::<Block>(&trailing),
+            Err(CodecError("1 trailing bytes".to_string()))
+        );
+        assert!(decode::<Option<u8>>(&[2, 0]).is_err());
+        assert!(decode::<Events>(&encode("Unknown").unwrap()).is_err());
+    }
+
+    fn _fixture_block() -> Block {
+        Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: "0110".to_string(),
+            timestamp: 1650301013,
+            data: vec![Account::new("Ahmad")],
+            nonce: 172,
+            next_miner: "Mrugesh".to_string(),
+            next_validators: vec!["Mrugesh".to_string()],
+        }
+    }
+}
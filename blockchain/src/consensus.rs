@@ -0,0 +1,544 @@
+//! # Consensus
+//!
+//! The consensus mode, chosen in the `GenesisConfig`, decides how blocks are produced:
+//! - `ProofOfWork`: any Account mines the block, by searching for a nonce meeting the difficulty.
+//! - `ProofOfStake`: the leader of a time slot produces the block, by signing it.
+//!   There is no nonce search, and at most one block is produced per slot. See the `slot` module for how leaders are elected.
+
+use crate::{
+    account::Account,
+    assembly::{assemble_block, AssembledBlock},
+    body::{headers, BlockSignature, FullBlock, FullChain},
+    difficulty::validate_governed_chain_difficulty,
+    genesis::GenesisConfig,
+    hash::{full_block_digest, verify_full_block_hash, HashFormat},
+    miner::next_block,
+    slot::{slot_leader, SlotClock},
+    state::ChainState,
+    timelock::ScheduledTransaction,
+    timestamp::{validate_block_timestamp, validate_chain_timestamps, Clock, SystemClock},
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// How blocks are produced.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsensusMode {
+    /// Blocks are mined, by searching for a nonce meeting the difficulty.
+    #[default]
+    ProofOfWork,
+    /// Blocks are signed by the leader of their time slot.
+    ProofOfStake,
+}
+
+/// Returns the slot the `timestamp` falls in, counting from the slot of the genesis block.
+pub fn slot_of(timestamp: u64, genesis_timestamp: u64, slot_duration: u64) -> u64 {
+    timestamp.saturating_sub(genesis_timestamp) / slot_duration.max(1)
+}
+
+/// Signs the `block` with the `signing_key`, setting its `hash` to the hex digest of the block and its body.
+///
+/// **Note:** The `nonce` is reset to `0`, as blocks produced by stake are not mined.
+///
+/// # Errors
+///
+/// Returns an error if the body has no canonical encoding.
+pub fn seal_block(
+    block: impl Into<FullBlock>,
+    signing_key: &SigningKey,
+) -> Result<FullBlock, String> {
+    let mut full_block = block.into();
+    full_block.block.nonce = 0;
+    let digest = full_block_digest(&full_block, HashFormat::Hex)?;
+    full_block.block.hash = digest.to_hex();
+    let signature = signing_key.sign(&digest.0);
+    full_block.body.signature = Some(BlockSignature {
+        public_key: hex::encode(signing_key.verifying_key().as_bytes()),
+        signature: hex::encode(signature.to_bytes()),
+    });
+    Ok(full_block)
+}
+
+/// Returns the registered public key of the Account with the given `address`.
+fn producer_key(address: &str, config: &GenesisConfig) -> Result<VerifyingKey, String> {
+    let key = config
+        .producer_keys
+        .get(address)
+        .ok_or_else(|| format!("'{}' has no producer key", address))?;
+    let key: [u8; 32] = hex::decode(key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| format!("'{}' has an invalid producer key", address))?;
+    VerifyingKey::from_bytes(&key).map_err(|_| format!("'{}' has an invalid producer key", address))
+}
+
+/// Returns the address of the Account which produced the `full_block`, given the block before it.
+///
+/// In `ProofOfWork` mode, the miner is the `next_miner` elected by the `previous` block. In `ProofOfStake` mode,
+/// the producer is the Account whose producer key signed the block.
+///
+/// **Note:** The genesis block, which has neither, is attributed to its own `next_miner`, the Account which initialised the chain.
+pub fn block_producer(
+    full_block: &FullBlock,
+    previous: Option<&FullBlock>,
+    config: &GenesisConfig,
+) -> String {
+    if config.consensus == ConsensusMode::ProofOfStake {
+        let signer = full_block.body.signature.as_ref().and_then(|signature| {
+            config
+                .producer_keys
+                .keys()
+                .find(|address| {
+                    producer_key(address, config)
+                        .map(|key| hex::encode(key.as_bytes()) == signature.public_key)
+                        .unwrap_or(false)
+                })
+                .cloned()
+        });
+        if let Some(signer) = signer {
+            return signer;
+        }
+    }
+    match previous {
+        Some(previous) => previous.block.next_miner.clone(),
+        None => full_block.block.next_miner.clone(),
+    }
+}
+
+/// Produces the next block of the `chain`, holding the given `data`, from the offered `transactions`, signed by the leader
+/// of the slot of the `timestamp`.
+///
+/// The block is built by `assembly::assemble_block`. It also records its slot, and the slots missed since the previous block.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The chain is not in `ProofOfStake` mode, or is empty
+/// - The slot of the `timestamp` already has a block
+/// - The `signing_key` is not the registered key of the leader of the slot
+/// - The block cannot be assembled
+pub fn produce_block(
+    chain: &FullChain,
+    data: Vec<Account>,
+    network: Vec<String>,
+    transactions: Vec<ScheduledTransaction>,
+    signing_key: &SigningKey,
+    timestamp: u64,
+    config: &GenesisConfig,
+) -> Result<AssembledBlock, String> {
+    if config.consensus != ConsensusMode::ProofOfStake {
+        return Err("Blocks must be mined in proof of work mode".to_string());
+    }
+    let clock = SlotClock::of_chain(chain, config)?;
+    let previous_block = chain.last().ok_or("Chain is too short")?;
+    let slot = clock.slot_of(timestamp);
+    let previous_slot = clock.slot_of(previous_block.block.timestamp);
+    if slot <= previous_slot {
+        return Err(format!("Slot {} already has a block", slot));
+    }
+    let leader = slot_leader(chain, slot, &clock, config)?;
+    if producer_key(&leader, config)? != signing_key.verifying_key() {
+        return Err(format!(
+            "Only '{}' can produce a block in slot {}",
+            leader, slot
+        ));
+    }
+
+    let mut block = next_block(&headers(chain), data, network);
+    block.timestamp = timestamp;
+    let assembled = assemble_block(chain, block, transactions, config)?;
+    let mut full_block = seal_block(assembled.full_block, signing_key)?;
+    full_block.body.slot = Some(slot);
+    full_block.body.missed_slots = clock.missed_slots(previous_slot, slot);
+    Ok(AssembledBlock {
+        full_block,
+        ..assembled
+    })
+}
+
+/// Validates a block produced by stake against its `ancestors`, the blocks of the chain before it.
+///
+/// # Errors
+///
+/// Returns an error describing the first check the block fails. The timestamp is checked against the `clock`.
+pub fn validate_produced_block(
+    block: &FullBlock,
+    ancestors: &[FullBlock],
+    clock: &dyn Clock,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let id = block.block.id;
+    let slot_clock = SlotClock::of_chain(ancestors, config)?;
+    let previous_block = ancestors.last().ok_or("Chain is too short")?;
+    if previous_block.block.id.checked_add(1) != Some(id)
+        || block.block.previous_hash != previous_block.block.hash
+    {
+        return Err(format!("Block {} does not follow the previous block", id));
+    }
+    if block.block.nonce != 0
+        || block.body.difficulty != 0
+        || HashFormat::of(&block.block.hash) != HashFormat::Hex
+    {
+        return Err(format!("Block {} was not produced by stake", id));
+    }
+    let digest = verify_full_block_hash(block)?;
+
+    validate_block_timestamp(&block.block, &headers(ancestors), clock, config)?;
+    let timestamp = block.block.timestamp;
+    let slot = slot_clock.slot_of(timestamp);
+    if block.body.slot != Some(slot) || !slot_clock.contains(slot, timestamp) {
+        return Err(format!("Block {} is not in its slot", id));
+    }
+    let previous_slot = slot_clock.slot_of(previous_block.block.timestamp);
+    if slot <= previous_slot {
+        return Err(format!("Block {} is not in a later slot", id));
+    }
+    if block.body.missed_slots != slot_clock.missed_slots(previous_slot, slot) {
+        return Err(format!("Block {} does not record its missed slots", id));
+    }
+
+    let leader = slot_leader(ancestors, slot, &slot_clock, config)?;
+    let signature = block
+        .body
+        .signature
+        .as_ref()
+        .ok_or_else(|| format!("Block {} is not signed", id))?;
+    let key = producer_key(&leader, config)?;
+    if hex::encode(key.as_bytes()) != signature.public_key {
+        return Err(format!("Block {} was not produced by '{}'", id, leader));
+    }
+    let signature: [u8; 64] = hex::decode(&signature.signature)
+        .ok()
+        .and_then(|signature| signature.try_into().ok())
+        .ok_or_else(|| format!("Block {} signature is invalid", id))?;
+    key.verify(&digest.0, &Signature::from_bytes(&signature))
+        .map_err(|_| format!("Block {} signature is invalid", id))?;
+    Ok(())
+}
+
+/// Validates the `chain` against the `config`, with the rules of its consensus mode.
+///
+/// # Errors
+///
+/// Returns an error if the config is invalid, the genesis block does not match the config, or a block is invalid.
+/// Block timestamps are checked against the `clock`. In a WASM build, a chain running a WASM contract returns a node
+/// capability error instead, see `wasm_contract::is_unsupported_error`.
+pub fn validate_full_chain(
+    chain: &FullChain,
+    clock: &dyn Clock,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    config.validate()?;
+    let chain_headers = headers(chain);
+    config.validate_genesis(&chain_headers)?;
+    let state = ChainState::from_chain(chain, config)?;
+    match config.consensus {
+        ConsensusMode::ProofOfWork => {
+            validate_governed_chain_difficulty(chain, &state.governance, config)?;
+            validate_chain_timestamps(&chain_headers, clock, config)
+        }
+        ConsensusMode::ProofOfStake => {
+            if chain.len() < 2 {
+                return Err("Chain is too short".to_string());
+            }
+            for (i, block) in chain.iter().enumerate().skip(1) {
+                validate_produced_block(block, &chain[..i], clock, config)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Validates the `chain` argument against the `config` argument, with the rules of its consensus mode.
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, or the chain is invalid, a `JsError` is thrown. If the chain runs a WASM
+/// contract, which this build cannot run, the `JsError` is a node capability error, see `is_unsupported_error`.
+#[wasm_bindgen]
+pub fn validate_chain_with_config(chain: JsValue, config: JsValue) -> Result<bool, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    validate_full_chain(&chain, &SystemClock, &config).map_err(|e| JsError::new(&e))?;
+    Ok(true)
+}
+
+/// Produces the next block of the `chain` argument, holding the `data` argument, from the offered `transactions` argument,
+/// signed with the hex encoded `secret_key` of the leader of the current slot.
+///
+/// # Examples
+///
+/// ```js
+/// const { full_block, pending, deferred } = produce_block_by_stake(chain, [], ["Tom"], transactions, secretKey, config);
+/// chain.push(full_block);
+/// nodeState.transactions = [...pending, ...deferred];
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the block cannot be produced, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn produce_block_by_stake(
+    chain: JsValue,
+    data: JsValue,
+    network: JsValue,
+    transactions: JsValue,
+    secret_key: String,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let data: Vec<Account> = data.into_serde()?;
+    let network: Vec<String> = network.into_serde()?;
+    let transactions: Vec<ScheduledTransaction> = transactions.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let secret_key: [u8; 32] = hex::decode(secret_key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| JsError::new("Invalid secret key"))?;
+    let signing_key = SigningKey::from_bytes(&secret_key);
+    let timestamp = SystemClock.now();
+    let assembled = produce_block(
+        &chain,
+        data,
+        network,
+        transactions,
+        &signing_key,
+        timestamp,
+        &config,
+    )
+    .map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&assembled)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{account::AccountTrait, timestamp::FixedClock, Events, Transaction};
+    #[test]
+    fn slot_of_counts_from_genesis() {
+        assert_eq!(slot_of(100, 100, 10), 0);
+        assert_eq!(slot_of(109, 100, 10), 0);
+        assert_eq!(slot_of(110, 100, 10), 1);
+        assert_eq!(slot_of(50, 100, 10), 0);
+    }
+    #[test]
+    fn produced_chain_is_valid() {
+        let (config, chain) = _fixture_chain();
+        assert_eq!(chain.len(), 3);
+        assert!(chain.iter().all(|b| b.block.nonce == 0));
+        assert_eq!(validate_full_chain(&chain, &NOW, &config), Ok(()));
+        // Each block pays the fee and the transfer of Tom
+        let tokens = |address: &str| {
+            let account = chain[2].block.data.iter().find(|a| a.address == address);
+            account.unwrap().tokens
+        };
+        assert_eq!((tokens("Tom"), tokens("Camper")), (16, 22));
+        assert_eq!(chain[2].body.receipts.len(), 1);
+    }
+    #[test]
+    fn missed_slots_are_recorded() {
+        let (config, chain) = _fixture_chain();
+        assert_eq!(chain[1].body.slot, Some(1));
+        assert_eq!(chain[1].body.missed_slots, 0);
+        assert_eq!(chain[2].body.slot, Some(3));
+        assert_eq!(chain[2].body.missed_slots, 1);
+
+        let mut tampered = chain.clone();
+        tampered[2].body.missed_slots = 0;
+        assert_eq!(
+            validate_full_chain(&tampered, &NOW, &config),
+            Err("Block 2 does not record its missed slots".to_string())
+        );
+    }
+    #[test]
+    fn only_slot_leader_can_produce() {
+        let (config, chain) = _fixture_chain();
+        let leader = _fixture_leader(&chain, 1045, &config);
+        let other = if leader == "Camper" { "Tom" } else { "Camper" };
+        let res = produce_block(
+            &chain,
+            vec![],
+            _fixture_network(),
+            vec![],
+            &_fixture_key(other),
+            1045,
+            &config,
+        );
+        assert_eq!(
+            res,
+            Err(format!("Only '{}' can produce a block in slot 4", leader))
+        );
+    }
+    #[test]
+    fn one_block_per_slot() {
+        let (config, chain) = _fixture_chain();
+        let timestamp = chain.last().unwrap().block.timestamp + 1;
+        let leader = _fixture_leader(&chain, timestamp, &config);
+        let res = produce_block(
+            &chain,
+            vec![],
+            _fixture_network(),
+            vec![],
+            &_fixture_key(&leader),
+            timestamp,
+            &config,
+        );
+        assert_eq!(res, Err("Slot 3 already has a block".to_string()));
+    }
+    #[test]
+    fn proof_of_work_chain_cannot_be_produced_by_stake() {
+        let (mut config, chain) = _fixture_chain();
+        config.consensus = ConsensusMode::ProofOfWork;
+        let leader = _fixture_leader(&chain, 5000, &config);
+        assert!(produce_block(
+            &chain,
+            vec![],
+            _fixture_network(),
+            vec![],
+            &_fixture_key(&leader),
+            5000,
+            &config
+        )
+        .is_err());
+    }
+    #[test]
+    fn future_block_is_invalid() {
+        let (config, chain) = _fixture_chain();
+        let clock = FixedClock(1034 - config.max_clock_drift);
+        assert_eq!(
+            validate_full_chain(&chain, &clock, &config),
+            Err("Block 2 is in the future".to_string())
+        );
+    }
+    #[test]
+    fn invalid_config_is_rejected() {
+        let (config, chain) = _fixture_chain();
+        let config = GenesisConfig {
+            validator_count: 0,
+            ..config
+        };
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err("Validator count must be at least 1".to_string())
+        );
+    }
+    #[test]
+    fn block_outside_its_slot_is_invalid() {
+        let (config, mut chain) = _fixture_chain();
+        chain[2].body.slot = Some(4);
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err("Block 2 is not in its slot".to_string())
+        );
+    }
+    #[test]
+    fn tampered_block_is_invalid() {
+        let (config, mut chain) = _fixture_chain();
+        chain[2].block.data[0].tokens += 1;
+        assert!(validate_full_chain(&chain, &NOW, &config).is_err());
+    }
+    #[test]
+    fn block_after_the_last_id_is_invalid() {
+        let (config, mut chain) = _fixture_chain();
+        chain[1].block.id = u64::MAX;
+        chain[2].block.id = 0;
+        assert_eq!(
+            validate_produced_block(&chain[2], &chain[..2], &NOW, &config),
+            Err("Block 0 does not follow the previous block".to_string())
+        );
+    }
+    #[test]
+    fn block_signed_by_wrong_key_is_invalid() {
+        let (config, mut chain) = _fixture_chain();
+        let mut block = chain.pop().unwrap();
+        block.body.signature = None;
+        let forged = seal_block(block, &_fixture_key("Mallory")).unwrap();
+        let leader = _fixture_leader(&chain, forged.block.timestamp, &config);
+        chain.push(forged);
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err(format!("Block 2 was not produced by '{}'", leader))
+        );
+    }
+    #[test]
+    fn unsigned_block_is_invalid() {
+        let (config, mut chain) = _fixture_chain();
+        chain[2].body.signature = None;
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err("Block 2 is not signed".to_string())
+        );
+    }
+    #[test]
+    fn mined_block_is_invalid_in_proof_of_stake_mode() {
+        let (config, mut chain) = _fixture_chain();
+        let produced = chain[2].clone();
+        chain[2].block.nonce = 1;
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err("Block 2 was not produced by stake".to_string())
+        );
+        chain[2] = produced;
+        chain[2].body.difficulty = 1;
+        assert_eq!(
+            validate_full_chain(&chain, &NOW, &config),
+            Err("Block 2 was not produced by stake".to_string())
+        );
+    }
+
+    /// A time after every block of the fixture chain.
+    const NOW: FixedClock = FixedClock(2000);
+
+    fn _fixture_key(address: &str) -> SigningKey {
+        let mut secret = [0u8; 32];
+        for (i, byte) in address.bytes().enumerate() {
+            secret[i % 32] ^= byte;
+        }
+        SigningKey::from_bytes(&secret)
+    }
+    fn _fixture_network() -> Vec<String> {
+        vec!["Camper".to_string(), "Tom".to_string()]
+    }
+    fn _fixture_leader(chain: &FullChain, timestamp: u64, config: &GenesisConfig) -> String {
+        let clock = SlotClock::of_chain(chain, config).unwrap();
+        slot_leader(chain, clock.slot_of(timestamp), &clock, config).unwrap()
+    }
+    /// A chain with blocks in slots `1` and `3`, missing slot `2`.
+    fn _fixture_chain() -> (GenesisConfig, FullChain) {
+        let mut config = GenesisConfig {
+            consensus: ConsensusMode::ProofOfStake,
+            ..Default::default()
+        };
+        for address in _fixture_network() {
+            let key = hex::encode(_fixture_key(&address).verifying_key().as_bytes());
+            config.producer_keys.insert(address.clone(), key);
+        }
+        let mut tom = Account::new("Tom");
+        tom.staked = 10;
+        let mut camper = Account::new("Camper");
+        camper.staked = 10;
+        config.allocations = vec![tom, camper];
+
+        let mut chain: FullChain = vec![FullBlock::from(config.mine_genesis("Camper", 1000))];
+        for timestamp in [1010, 1035] {
+            let leader = _fixture_leader(&chain, timestamp, &config);
+            let transfer = Transaction {
+                event: Events::Transfer("Camper".to_string(), 1),
+                address: "Tom".to_string(),
+            };
+            let assembled = produce_block(
+                &chain,
+                vec![],
+                _fixture_network(),
+                vec![transfer.into()],
+                &_fixture_key(&leader),
+                timestamp,
+                &config,
+            )
+            .unwrap();
+            chain.push(assembled.full_block);
+        }
+        (config, chain)
+    }
+}
@@ -0,0 +1,390 @@
+//! # Contract
+//!
+//! Contracts are `vm` bytecode, or WebAssembly modules run by `wasm_contract`, deployed on the chain:
+//! - A `DeployContract(code)` transaction deploys the hex encoded `code`, owned by the address of the transaction.
+//!   The contract address follows from the position of the transaction. See `contract_address`.
+//! - A `CallContract(address, args)` transaction runs the contract at `address` with the `args`. The contract can read
+//!   the `tokens` and `staked` tokens of the caller, as the transactions before the call in its block left them, and transfer
+//!   its spendable tokens. A call transferring more than its caller can spend, once its fee is paid, fails. See `ledger`.
+//!
+//! The `ContractState` holds every deployed contract, and its storage. Blocks with contract transactions commit to the hash
+//! of the state after them, as the `contracts_hash` of their `BlockBody`.
+//!
+//! **Note:** A failed deployment, or call, does not invalidate its block. The transaction is mined, but has no effect.
+
+use crate::{
+    account::Account,
+    address::Address,
+    body::FullBlock,
+    codec,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    protocol::{version_at, RuleSet},
+    state::ChainState,
+    vm::{self, Context, Execution, Storage},
+    wasm_contract, Events, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// A deployed contract.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Contract {
+    /// The address of the Account which deployed the contract.
+    pub owner: String,
+    /// The hex encoded bytecode of the contract.
+    pub code: String,
+    /// The storage of the contract.
+    pub storage: Storage,
+}
+
+/// The outcome of a transaction: the execution of a contract call, or `None` for other transactions.
+/// An error if a deployment is invalid, or the called contract does not exist.
+pub type Outcome = Result<Option<Execution>, String>;
+
+/// Returns the address of the contract deployed by transaction `index` of block `block_id`.
+pub fn contract_address(block_id: u64, index: usize) -> Address {
+    let encoded = codec::encode(&("contract", block_id, index as u64))
+        .expect("contract position to be encodable");
+    Address::from_hash(&Sha256::digest(encoded))
+}
+
+/// Returns `true` if the `transaction` deploys, or calls, a contract.
+pub fn is_contract_transaction(transaction: &Transaction) -> bool {
+    matches!(
+        transaction.event,
+        Events::DeployContract(_) | Events::CallContract(..)
+    )
+}
+
+/// Every deployed contract, by address.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ContractState {
+    contracts: BTreeMap<String, Contract>,
+}
+
+impl ContractState {
+    /// Creates a state without contracts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the contract at `address`, if any.
+    pub fn contract(&self, address: &str) -> Option<&Contract> {
+        self.contracts.get(address)
+    }
+
+    /// Returns the hash of the canonical encoding of every contract, ordered by address.
+    pub fn hash(&self) -> BlockHash {
+        let contracts: Vec<_> = self
+            .contracts
+            .iter()
+            .map(|(address, contract)| {
+                let storage: Vec<(u64, u64)> = contract.storage.clone().into_iter().collect();
+                (address, &contract.owner, &contract.code, storage)
+            })
+            .collect();
+        let mut output = vec![codec::FORMAT_VERSION];
+        output.extend(codec::encode(&contracts).expect("contracts to be encodable"));
+        BlockHash::from_slice(&Sha256::digest(output)).expect("Sha256 digest to be 32 bytes")
+    }
+
+    /// Returns the `contracts_hash` the `full_block` must commit to, once applied: the hash of the state,
+    /// if the block has contract transactions.
+    pub fn commitment(&self, full_block: &FullBlock) -> Option<BlockHash> {
+        let transactions = &full_block.body.transactions;
+        transactions
+            .iter()
+            .any(is_contract_transaction)
+            .then(|| self.hash())
+    }
+
+    /// Applies transaction `index` of block `block_id`, called by the `caller` Account, if it deploys or calls a contract.
+    ///
+    /// Returns the execution of a call. An execution which fails leaves the storage unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the code of a deployment is invalid, or a WASM module before the protocol version supports them,
+    /// or the called contract does not exist.
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &Transaction,
+        index: usize,
+        block_id: u64,
+        caller: Option<&Account>,
+        config: &GenesisConfig,
+    ) -> Outcome {
+        match &transaction.event {
+            Events::DeployContract(code) => {
+                let bytecode =
+                    hex::decode(code).map_err(|_| "Contract code is not hex".to_string())?;
+                if wasm_contract::is_wasm(&bytecode) {
+                    let version = version_at(block_id, config);
+                    if !RuleSet::of_version(version).is_some_and(|rules| rules.wasm_contracts) {
+                        return Err(format!(
+                            "Version {} does not support WASM contracts",
+                            version
+                        ));
+                    }
+                    wasm_contract::validate_module(&bytecode)?;
+                } else {
+                    vm::validate_code(&bytecode)?;
+                }
+                let contract = Contract {
+                    owner: transaction.address.clone(),
+                    code: code.to_lowercase(),
+                    storage: Storage::new(),
+                };
+                let address = contract_address(block_id, index);
+                self.contracts.insert(address.into(), contract);
+                Ok(None)
+            }
+            Events::CallContract(address, args) => {
+                let contract = self
+                    .contracts
+                    .get_mut(address)
+                    .ok_or_else(|| format!("'{}' is not a contract", address))?;
+                let context = Context {
+                    args: args.clone(),
+                    balance: caller.map_or(0, |account| account.tokens),
+                    staked: caller.map_or(0, |account| account.staked),
+                    locked: config.locked_tokens(&transaction.address, block_id),
+                    height: block_id,
+                    legacy_addresses: config.legacy_addresses,
+                };
+                let code = hex::decode(&contract.code).expect("deployed code to be hex");
+                let execute = if wasm_contract::is_wasm(&code) {
+                    wasm_contract::execute_module
+                } else {
+                    vm::execute
+                };
+                Ok(Some(execute(
+                    &code,
+                    &mut contract.storage,
+                    &context,
+                    config.contract_gas_limit,
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the `transaction` deploys, or calls, a WASM contract.
+    fn runs_wasm(&self, transaction: &Transaction) -> bool {
+        let code = match &transaction.event {
+            Events::DeployContract(code) => code,
+            Events::CallContract(address, _) => match self.contracts.get(address) {
+                Some(contract) => &contract.code,
+                None => return false,
+            },
+            _ => return false,
+        };
+        hex::decode(code).is_ok_and(|code| wasm_contract::is_wasm(&code))
+    }
+
+    /// Checks the `full_block` can be applied by this build: WASM builds cannot run WASM contracts.
+    ///
+    /// **Note:** Before the protocol version of the block supports WASM contracts, every build fails their deployment alike.
+    ///
+    /// # Errors
+    ///
+    /// Returns a node capability error, starting with `wasm_contract::UNSUPPORTED_ERROR`, if the block runs a WASM contract
+    /// in a WASM build. The block is not invalid: native builds validate it.
+    pub fn check_supported(&self, full_block: &FullBlock) -> Result<(), String> {
+        let transactions = &full_block.body.transactions;
+        let runs_wasm = RuleSet::of_version(full_block.body.version)
+            .is_some_and(|rules| rules.wasm_contracts)
+            && transactions.iter().any(|t| self.runs_wasm(t));
+        if !wasm_contract::IS_SUPPORTED && runs_wasm {
+            return Err(format!(
+                "{}: block {} runs a WASM contract",
+                wasm_contract::UNSUPPORTED_ERROR,
+                full_block.block.id
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the contract at the `address` argument, after the last block of the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const { owner, code, storage } = get_contract(chain, "fcc1...", config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, the chain is invalid, or there is no contract at the address, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_contract(chain: JsValue, address: String, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let state = ChainState::from_chain(&chain, &config).map_err(|e| JsError::new(&e))?;
+    let contract = state
+        .contracts
+        .contract(&address)
+        .ok_or_else(|| JsError::new(&format!("'{}' is not a contract", address)))?;
+    Ok(JsValue::from_serde(contract)?)
+}
+
+/// Assembles the `source` argument into hex encoded bytecode, for a `DeployContract` transaction.
+///
+/// # Examples
+///
+/// ```js
+/// const code = assemble_contract("PUSH 0 ARG PUSH 1 ARG ADD RETURN");
+/// const transaction = { event: { DeployContract: code }, address: "Tom" };
+/// ```
+///
+/// # Errors
+///
+/// If the source is not valid assembly, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn assemble_contract(source: String) -> Result<String, JsError> {
+    let code = vm::assemble(&source).map_err(|e| JsError::new(&e))?;
+    Ok(hex::encode(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{account::AccountTrait, assembly::assemble_block, block::Block};
+    #[test]
+    fn calls_update_committed_storage() {
+        let config = GenesisConfig::default();
+        let (chain, counter) = _fixture_chain(&config);
+        let state = ChainState::from_chain(&chain, &config).unwrap().contracts;
+        let contract = state.contract(&counter).unwrap();
+        assert_eq!(contract.owner, "Tom");
+        assert_eq!(contract.storage.get(&0), Some(&5));
+        assert_eq!(chain[2].body.contracts_hash, Some(state.hash()));
+        assert_eq!(chain[3].body.contracts_hash, None);
+    }
+    #[test]
+    fn tampered_storage_commitment_is_invalid() {
+        let config = GenesisConfig::default();
+        let (mut chain, _) = _fixture_chain(&config);
+        chain[2].body.contracts_hash = chain[1].body.contracts_hash;
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 2 does not commit to the contract state".to_string())
+        );
+    }
+    #[test]
+    fn failed_transactions_have_no_effect() {
+        let config = GenesisConfig {
+            contract_gas_limit: 10,
+            ..Default::default()
+        };
+        let mut state = ContractState::new();
+        let deploy = _fixture_transaction(Events::DeployContract("ff".to_string()));
+        assert!(state
+            .apply_transaction(&deploy, 0, 1, None, &config)
+            .is_err());
+        let call = _fixture_transaction(Events::CallContract("fcc1".to_string(), vec![]));
+        assert_eq!(
+            state.apply_transaction(&call, 0, 1, None, &config),
+            Err("'fcc1' is not a contract".to_string())
+        );
+
+        let code = vm::assemble("PUSH 1 PUSH 0 STORE PUSH 1 PUSH 1 STORE STOP").unwrap();
+        let deploy = _fixture_transaction(Events::DeployContract(hex::encode(code)));
+        state
+            .apply_transaction(&deploy, 0, 1, None, &config)
+            .unwrap();
+        let address = contract_address(1, 0).to_string();
+        let call = _fixture_transaction(Events::CallContract(address.clone(), vec![]));
+        let execution = state
+            .apply_transaction(&call, 0, 2, None, &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(execution.result, Err("Out of gas".to_string()));
+        assert!(state.contract(&address).unwrap().storage.is_empty());
+    }
+    #[test]
+    fn wasm_contracts_need_version_2() {
+        let config = GenesisConfig::default();
+        let mut state = ContractState::new();
+        let deploy = _fixture_transaction(Events::DeployContract(hex::encode(b"\0asm")));
+        assert_eq!(
+            state.apply_transaction(&deploy, 0, 1, None, &config),
+            Err("Version 1 does not support WASM contracts".to_string())
+        );
+        assert!(state
+            .contract(&contract_address(1, 0).to_string())
+            .is_none());
+    }
+    #[test]
+    fn calls_read_caller_balance() {
+        let config = GenesisConfig::default();
+        let mut state = ContractState::new();
+        let code = vm::assemble("BALANCE STAKED SUB RETURN").unwrap();
+        let deploy = _fixture_transaction(Events::DeployContract(hex::encode(code)));
+        state
+            .apply_transaction(&deploy, 0, 1, None, &config)
+            .unwrap();
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let call = _fixture_transaction(Events::CallContract(
+            contract_address(1, 0).to_string(),
+            vec![],
+        ));
+        let execution = state
+            .apply_transaction(&call, 0, 2, Some(&tom), &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(execution.result, Ok(Some(20)));
+    }
+
+    fn _fixture_transaction(event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: "Tom".to_string(),
+        }
+    }
+
+    /// Tom deploys a counter in block 1, and adds 2, then 3, to it in block 2. Block 3 has no contract transaction.
+    /// Tom pays the fee of every transaction.
+    fn _fixture_chain(config: &GenesisConfig) -> (Vec<FullBlock>, String) {
+        let code = vm::assemble("PUSH 0 LOAD PUSH 0 ARG ADD PUSH 0 STORE STOP").unwrap();
+        let counter = contract_address(1, 0).to_string();
+        let transactions = [
+            vec![],
+            vec![_fixture_transaction(Events::DeployContract(hex::encode(
+                code,
+            )))],
+            vec![
+                _fixture_transaction(Events::CallContract(counter.clone(), vec![2])),
+                _fixture_transaction(Events::CallContract(counter.clone(), vec![3])),
+            ],
+            vec![_fixture_transaction(Events::Unstake)],
+        ];
+        let mut chain = vec![];
+        for (id, transactions) in transactions.into_iter().enumerate() {
+            let block = Block {
+                id: id as u64,
+                hash: BlockHash::default().to_hex(),
+                previous_hash: String::new(),
+                timestamp: 1000,
+                data: match id {
+                    0 => vec![Account::new("Tom")],
+                    _ => vec![],
+                },
+                nonce: 0,
+                next_miner: "Tom".to_string(),
+                next_validators: vec![],
+            };
+            let transactions = transactions.into_iter().map(Into::into).collect();
+            let assembled = assemble_block(&chain, block, transactions, config).unwrap();
+            assert!(assembled.rejected.is_empty());
+            chain.push(assembled.full_block);
+        }
+        (chain, counter)
+    }
+}
@@ -0,0 +1,696 @@
+//! # Difficulty
+//!
+//! The difficulty is the number of leading zero bits the `Sha256` digest of a block must start with.
+//! It is retargeted every `retarget_interval` blocks, so that blocks are mined close to the `target_block_time`.
+//!
+//! From the protocol version with committed difficulties, the `difficulty` of a mined block is stored in its `BlockBody`,
+//! and committed to by its hash. It must equal the difficulty expected from the timestamps of its ancestors, so every
+//! Account derives the same value when validating. Blocks before it, without a body, only meet the expected difficulty.
+
+use crate::{
+    assembly::{assemble_block, AssembledBlock},
+    block::Block,
+    body::{headers, FullBlock, FullChain},
+    chain::Chain,
+    genesis::GenesisConfig,
+    governance::GovernanceState,
+    hash::{
+        block_digest, full_block_digest, verify_block_hash, verify_full_block_hash, BlockHash,
+        HashFormat,
+    },
+    protocol::RuleSet,
+    state::ChainState,
+    timelock::ScheduledTransaction,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// The highest difficulty: every bit of the `Sha256` digest is zero.
+pub const MAX_DIFFICULTY: u32 = 256;
+
+/// The parameters used to retarget the difficulty.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DifficultyParams {
+    /// The difficulty of the genesis block, and of every block until the first retarget.
+    pub initial_difficulty: u32,
+    /// The difficulty never drops below this value.
+    pub min_difficulty: u32,
+    /// The desired number of seconds between two blocks.
+    pub target_block_time: u64,
+    /// The number of blocks between two retargets.
+    pub retarget_interval: u64,
+}
+
+impl Default for DifficultyParams {
+    fn default() -> Self {
+        Self::from(&GenesisConfig::default())
+    }
+}
+
+impl From<&GenesisConfig> for DifficultyParams {
+    fn from(config: &GenesisConfig) -> Self {
+        let initial_difficulty = prefix_difficulty(&config.difficulty_prefix);
+        Self {
+            initial_difficulty,
+            min_difficulty: initial_difficulty.min(8),
+            target_block_time: config.target_block_time,
+            retarget_interval: config.retarget_interval,
+        }
+    }
+}
+
+/// Returns the difficulty equivalent to a legacy binary hash prefix, such as `DIFFICULTY_PREFIX`.
+///
+/// **Note:** `hash_to_binary` encodes a zero byte as a single `"0"`, and any other byte starting with `"1"`.
+/// So each leading `"0"` of a legacy hash is a whole zero byte of the digest.
+pub fn prefix_difficulty(prefix: &str) -> u32 {
+    prefix.chars().take_while(|c| *c == '0').count() as u32 * 8
+}
+
+/// Returns the difficulty expected of the block at `height`, given the preceding blocks of the chain.
+///
+/// Every `retarget_interval` blocks, the time taken to mine the previous interval is compared to the target:
+/// - If the blocks were mined in less than half the target time, the difficulty increases by 1
+/// - If the blocks took more than twice the target time, the difficulty decreases by 1
+///
+/// The difficulty stays between `min_difficulty` and `MAX_DIFFICULTY`.
+///
+/// **Note:** Only `chain[..height]` is read, so `chain` may contain blocks after `height`.
+pub fn difficulty_at(chain: &[Block], height: u64, params: &DifficultyParams) -> u32 {
+    retarget(chain, height, params, |_| params.target_block_time)
+}
+
+/// Returns the difficulty expected of the block at `height`, as `difficulty_at` does, except each retarget aims for the
+/// `target_block_time` active at its height, as changed by `governance`.
+pub fn governed_difficulty_at(
+    chain: &[Block],
+    height: u64,
+    governance: &GovernanceState,
+    config: &GenesisConfig,
+) -> u32 {
+    retarget(chain, height, &DifficultyParams::from(config), |retarget| {
+        governance.config_at(retarget, config).target_block_time
+    })
+}
+
+/// Retargets the difficulty up to `height`, aiming for the `target_block_time` returned for the height of each retarget.
+fn retarget(
+    chain: &[Block],
+    height: u64,
+    params: &DifficultyParams,
+    target_block_time: impl Fn(u64) -> u64,
+) -> u32 {
+    let mut difficulty = params.initial_difficulty.min(MAX_DIFFICULTY);
+    let interval = params.retarget_interval;
+    if interval < 2 {
+        return difficulty;
+    }
+    let mut retarget = interval;
+    while retarget <= height && retarget as usize <= chain.len() {
+        let blocks = &chain[(retarget - interval) as usize..retarget as usize];
+        difficulty = adjust(difficulty, blocks, target_block_time(retarget), params);
+        retarget += interval;
+    }
+    difficulty
+}
+
+/// Returns the `difficulty` retargeted after the `blocks` of an interval, from the time taken to mine them.
+fn adjust(
+    difficulty: u32,
+    blocks: &[Block],
+    target_block_time: u64,
+    params: &DifficultyParams,
+) -> u32 {
+    let (first, last) = match (blocks.first(), blocks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return difficulty,
+    };
+    let expected_span = target_block_time.saturating_mul(blocks.len() as u64 - 1);
+    let actual_span = last.timestamp.saturating_sub(first.timestamp);
+    if actual_span.saturating_mul(2) < expected_span {
+        (difficulty + 1).min(MAX_DIFFICULTY)
+    } else if actual_span > expected_span.saturating_mul(2) {
+        difficulty.saturating_sub(1).max(params.min_difficulty)
+    } else {
+        difficulty
+    }
+}
+
+/// The difficulty of each block of a chain read in order, retargeted once per interval, rather than from the genesis
+/// block for every block.
+struct Retargets<'a, F> {
+    params: &'a DifficultyParams,
+    target_block_time: F,
+    difficulty: u32,
+}
+
+impl<'a, F: Fn(u64) -> u64> Retargets<'a, F> {
+    fn new(params: &'a DifficultyParams, target_block_time: F) -> Self {
+        Self {
+            params,
+            target_block_time,
+            difficulty: params.initial_difficulty.min(MAX_DIFFICULTY),
+        }
+    }
+
+    /// Returns the difficulty expected of the block after `ancestors`, the previous call having been for the block before.
+    fn next(&mut self, ancestors: &[Block]) -> u32 {
+        let height = ancestors.len() as u64;
+        let interval = self.params.retarget_interval;
+        if interval >= 2 && height > 0 && height.checked_rem(interval) == Some(0) {
+            let blocks = &ancestors[(height - interval) as usize..];
+            let target_block_time = (self.target_block_time)(height);
+            self.difficulty = adjust(self.difficulty, blocks, target_block_time, self.params);
+        }
+        self.difficulty
+    }
+}
+
+/// Returns the difficulty expected of the next block mined onto `chain`.
+pub fn next_difficulty(chain: &[Block], params: &DifficultyParams) -> u32 {
+    difficulty_at(chain, chain.len() as u64, params)
+}
+
+/// Returns the difficulty expected of the next block mined onto the `chain` of `FullBlock`s, with the `target_block_time`
+/// governed by the chain, as `validate_governed_chain_difficulty` expects.
+///
+/// # Errors
+///
+/// Returns an error if the `chain` is invalid.
+pub fn next_governed_difficulty(
+    chain: &[FullBlock],
+    config: &GenesisConfig,
+) -> Result<u32, String> {
+    let state = ChainState::from_chain(chain, config)?;
+    Ok(governed_difficulty_at(
+        &headers(chain),
+        chain.len() as u64,
+        &state.governance,
+        config,
+    ))
+}
+
+/// Validates `block` against its `ancestors`: the blocks preceding it, with the genesis block first.
+///
+/// As with `Account::validate_block`, the block must follow the previous block, and its hash must be correct.
+/// Hashes in either `HashFormat` are accepted, and must meet the difficulty expected from the ancestors.
+pub fn validate_block_with_ancestors(
+    block: &Block,
+    ancestors: &[Block],
+    params: &DifficultyParams,
+) -> bool {
+    let difficulty = difficulty_at(ancestors, block.id, params);
+    follows_with_difficulty(block, ancestors, verify_block_hash(block), difficulty)
+}
+
+/// Checks the `block` follows its `ancestors`, and its verified `digest` meets the `difficulty`.
+fn follows_with_difficulty(
+    block: &Block,
+    ancestors: &[Block],
+    digest: Result<BlockHash, String>,
+    difficulty: u32,
+) -> bool {
+    let previous_block = match ancestors.last() {
+        Some(previous_block) => previous_block,
+        None => return false,
+    };
+    if block.id != ancestors.len() as u64
+        || previous_block.id.checked_add(1) != Some(block.id)
+        || block.previous_hash != previous_block.hash
+    {
+        return false;
+    }
+    match digest {
+        Ok(digest) => digest.meets_difficulty(difficulty),
+        Err(_) => false,
+    }
+}
+
+/// Validates every block of `chain` against the difficulty expected from its ancestors.
+///
+/// # Errors
+///
+/// Returns an error naming the first block which is invalid.
+pub fn validate_chain_difficulty(chain: &[Block], params: &DifficultyParams) -> Result<(), String> {
+    if chain.len() < 2 {
+        return Err("Chain is too short".to_string());
+    }
+    let mut retargets = Retargets::new(params, |_| params.target_block_time);
+    for height in 1..chain.len() {
+        let (block, ancestors) = (&chain[height], &chain[..height]);
+        let difficulty = retargets.next(ancestors);
+        if !follows_with_difficulty(block, ancestors, verify_block_hash(block), difficulty) {
+            return Err(format!("Block {} is invalid", height));
+        }
+    }
+    Ok(())
+}
+
+/// Validates every block of the `chain` of `FullBlock`s, whose hashes also commit to their bodies,
+/// against the difficulty expected from its ancestors.
+///
+/// # Errors
+///
+/// Returns an error naming the first block which is invalid, or does not commit to its expected difficulty.
+pub fn validate_full_chain_difficulty(
+    chain: &[FullBlock],
+    params: &DifficultyParams,
+) -> Result<(), String> {
+    validate_full_chain_with(chain, Retargets::new(params, |_| params.target_block_time))
+}
+
+/// Validates every block of the `chain` of `FullBlock`s against the difficulty expected from its ancestors, with the
+/// `target_block_time` passed by its `governance`, see `governed_difficulty_at`.
+///
+/// # Errors
+///
+/// Returns an error naming the first block which is invalid, or does not commit to its expected difficulty.
+pub fn validate_governed_chain_difficulty(
+    chain: &[FullBlock],
+    governance: &GovernanceState,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let params = DifficultyParams::from(config);
+    let retargets = Retargets::new(&params, |retarget| {
+        governance.config_at(retarget, config).target_block_time
+    });
+    validate_full_chain_with(chain, retargets)
+}
+
+/// Validates every block of the `chain` against the difficulty of its height, as retargeted by `retargets`.
+///
+/// Blocks following a protocol version with committed difficulties must commit to that difficulty.
+fn validate_full_chain_with(
+    chain: &[FullBlock],
+    mut retargets: Retargets<impl Fn(u64) -> u64>,
+) -> Result<(), String> {
+    if chain.len() < 2 {
+        return Err("Chain is too short".to_string());
+    }
+    let chain_headers = headers(chain);
+    for height in 1..chain.len() {
+        let full_block = &chain[height];
+        let digest = verify_full_block_hash(full_block);
+        let ancestors = &chain_headers[..height];
+        let difficulty = retargets.next(ancestors);
+        if !follows_with_difficulty(&chain_headers[height], ancestors, digest, difficulty) {
+            return Err(format!("Block {} is invalid", height));
+        }
+        let commits =
+            RuleSet::of_version(full_block.body.version).is_some_and(|rules| rules.difficulty);
+        if commits && full_block.body.difficulty != difficulty {
+            return Err(format!(
+                "Block {} does not commit to its difficulty {}",
+                height, difficulty
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Searches for a `nonce`, starting from the current one, for which the digest of the `block` meets the `difficulty`.
+/// The digest is then encoded into the `hash` field, in the given `format`.
+pub fn mine_hash(block: &mut Block, difficulty: u32, format: HashFormat) {
+    loop {
+        let digest = block_digest(block, format);
+        if digest.meets_difficulty(difficulty) {
+            block.hash = digest.encode(format);
+            return;
+        }
+        block.nonce += 1;
+    }
+}
+
+/// Searches for a `nonce`, starting from the current one, for which the hex digest of the `full_block`,
+/// including its body, meets the `difficulty`. The digest is then encoded into the `hash` field.
+///
+/// # Errors
+///
+/// Returns an error if the body has no canonical encoding.
+pub fn mine_full_block_hash(full_block: &mut FullBlock, difficulty: u32) -> Result<(), String> {
+    loop {
+        let digest = full_block_digest(full_block, HashFormat::Hex)?;
+        if digest.meets_difficulty(difficulty) {
+            full_block.block.hash = digest.to_hex();
+            return Ok(());
+        }
+        full_block.block.nonce += 1;
+    }
+}
+
+/// Assembles the `block` following the `chain` from the offered `transactions`, as `assembly::assemble_block` does, and
+/// mines it onto the chain, at the difficulty governed by the chain.
+///
+/// Returns the assembled block, once mined.
+///
+/// **Note:** The hash of the new block is hex encoded. Use `miner::mine_block_parallel` to mine across multiple threads.
+///
+/// # Errors
+///
+/// Returns an error if the chain is invalid, or the block cannot be assembled.
+pub fn mine_block_with_difficulty(
+    chain: &mut FullChain,
+    block: Block,
+    transactions: Vec<ScheduledTransaction>,
+    config: &GenesisConfig,
+) -> Result<AssembledBlock, String> {
+    let mut assembled = assemble_block(chain, block, transactions, config)?;
+    let difficulty = next_governed_difficulty(chain, config)?;
+    mine_full_block_hash(&mut assembled.full_block, difficulty)?;
+    chain.push(assembled.full_block.clone());
+    Ok(assembled)
+}
+
+/// Returns the difficulty the next block of the `chain` argument must meet, under the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const difficulty = next_block_difficulty(chain, config);
+/// const job = new MiningJob(full_block, difficulty, 0, 1);
+/// ```
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, or the chain is invalid, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn next_block_difficulty(chain: JsValue, config: JsValue) -> Result<u32, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    next_governed_difficulty(&chain, &config).map_err(|e| JsError::new(&e))
+}
+
+/// Validates every block of the `chain` argument against the difficulty retargeting of the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const isChainValid = validate_chain_with_difficulty(chain, { target_block_time: 10 });
+/// assert.equal(isChainValid, true);
+/// ```
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, or a block does not meet its expected difficulty, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn validate_chain_with_difficulty(chain: JsValue, config: JsValue) -> Result<bool, JsError> {
+    let chain: Chain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    validate_chain_difficulty(&chain, &DifficultyParams::from(&config))
+        .map_err(|e| JsError::new(&e))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::{Account, AccountTrait},
+        governance::{Parameter, ParameterChange},
+        miner::next_block,
+        Events, Transaction, DIFFICULTY_PREFIX,
+    };
+    #[test]
+    fn prefix_difficulty_counts_zero_bytes() {
+        assert_eq!(prefix_difficulty(""), 0);
+        assert_eq!(prefix_difficulty("0"), 8);
+        assert_eq!(prefix_difficulty("000"), 24);
+        assert_eq!(prefix_difficulty(DIFFICULTY_PREFIX), 8);
+    }
+    #[test]
+    fn default_params_match_genesis_config() {
+        let params = DifficultyParams::default();
+        assert_eq!(params.initial_difficulty, 8);
+        assert_eq!(params.min_difficulty, 8);
+        assert_eq!(params.target_block_time, 10);
+        assert_eq!(params.retarget_interval, 10);
+    }
+    #[test]
+    fn difficulty_is_initial_before_first_retarget() {
+        let params = _fixture_params();
+        let chain = _fixture_chain(&[0, 1, 2]);
+        assert_eq!(difficulty_at(&chain, 0, &params), 1);
+        assert_eq!(next_difficulty(&chain, &params), 1);
+    }
+    #[test]
+    fn fast_blocks_increase_difficulty() {
+        let params = _fixture_params();
+        // 4 blocks, 1 second apart, against a target of 10 seconds
+        let chain = _fixture_chain(&[0, 1, 2, 3]);
+        assert_eq!(next_difficulty(&chain, &params), 2);
+    }
+    #[test]
+    fn slow_blocks_decrease_difficulty() {
+        let params = DifficultyParams {
+            initial_difficulty: 3,
+            .._fixture_params()
+        };
+        let chain = _fixture_chain(&[0, 100, 200, 300]);
+        assert_eq!(next_difficulty(&chain, &params), 2);
+        let chain = _fixture_chain(&[0, 100, 200, 300, 400, 500, 600, 700]);
+        assert_eq!(next_difficulty(&chain, &params), 1);
+    }
+    #[test]
+    fn difficulty_does_not_drop_below_minimum() {
+        let params = _fixture_params();
+        let chain = _fixture_chain(&[0, 100, 200, 300, 400, 500, 600, 700]);
+        assert_eq!(next_difficulty(&chain, &params), 1);
+    }
+    #[test]
+    fn difficulty_does_not_rise_above_maximum() {
+        let params = DifficultyParams {
+            initial_difficulty: MAX_DIFFICULTY,
+            .._fixture_params()
+        };
+        let chain = _fixture_chain(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(next_difficulty(&chain, &params), MAX_DIFFICULTY);
+        // Spans too long to double do not overflow
+        let params = DifficultyParams {
+            target_block_time: u64::MAX,
+            .._fixture_params()
+        };
+        assert_eq!(next_difficulty(&chain, &params), 3);
+        let chain = _fixture_chain(&[0, 1, 2, u64::MAX]);
+        assert_eq!(next_difficulty(&chain, &_fixture_params()), 1);
+    }
+    #[test]
+    fn on_target_blocks_keep_difficulty() {
+        let params = _fixture_params();
+        let chain = _fixture_chain(&[0, 10, 20, 30]);
+        assert_eq!(next_difficulty(&chain, &params), 1);
+    }
+    #[test]
+    fn difficulty_at_ignores_later_blocks() {
+        let params = _fixture_params();
+        let chain = _fixture_chain(&[0, 1, 2, 3, 4]);
+        assert_eq!(difficulty_at(&chain, 3, &params), 1);
+        assert_eq!(difficulty_at(&chain, 4, &params), 2);
+    }
+    #[test]
+    fn mine_hash_meets_difficulty_in_format() {
+        let mut block = _fixture_chain(&[0]).remove(0);
+        mine_hash(&mut block, 6, HashFormat::Hex);
+        let digest = verify_block_hash(&block).unwrap();
+        assert!(digest.meets_difficulty(6));
+        assert_eq!(HashFormat::of(&block.hash), HashFormat::Hex);
+
+        mine_hash(&mut block, 8, HashFormat::Legacy);
+        assert!(block.hash.starts_with(DIFFICULTY_PREFIX));
+        assert_eq!(HashFormat::of(&block.hash), HashFormat::Legacy);
+    }
+    #[test]
+    fn mined_chain_is_valid() {
+        let config = GenesisConfig {
+            difficulty_prefix: String::new(),
+            retarget_interval: 2,
+            ..Default::default()
+        };
+        let chain = _fixture_mined_chain(&config);
+        assert_eq!(chain.len(), 6);
+        assert_eq!(chain[5].body.transactions.len(), 1);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert!(validate_governed_chain_difficulty(&chain, &state.governance, &config).is_ok());
+        // Blocks are mined a second apart, so the difficulty increases at each retarget
+        assert_eq!(next_governed_difficulty(&chain[..5], &config), Ok(2));
+        let digest = BlockHash::from_hex(&chain[5].block.hash).unwrap();
+        assert!(digest.meets_difficulty(2));
+    }
+    #[test]
+    fn mined_blocks_commit_to_their_difficulty() {
+        let config = GenesisConfig {
+            difficulty_prefix: String::new(),
+            retarget_interval: 2,
+            ..Default::default()
+        };
+        let chain = _fixture_mined_chain(&config);
+        assert_eq!(chain[1].body.difficulty, 0);
+        assert_eq!(chain[5].body.difficulty, 2);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        // A hash meeting a higher difficulty than expected does not make up for committing to it
+        let mut tampered = chain.clone();
+        tampered[5].body.difficulty = 3;
+        mine_full_block_hash(&mut tampered[5], 3).unwrap();
+        assert_eq!(
+            validate_governed_chain_difficulty(&tampered, &state.governance, &config),
+            Err("Block 5 does not commit to its difficulty 2".to_string())
+        );
+    }
+    #[test]
+    fn legacy_blocks_are_valid() {
+        let params = DifficultyParams::default();
+        let mut chain = _fixture_chain(&[1648987026, 1650301013]);
+        mine_hash(&mut chain[0], 8, HashFormat::Legacy);
+        chain[1].previous_hash = chain[0].hash.clone();
+        mine_hash(&mut chain[1], 8, HashFormat::Legacy);
+        assert!(Account::validate_block(&chain[1], &chain[0]));
+        assert!(validate_chain_difficulty(&chain, &params).is_ok());
+
+        // A hex block may follow a legacy block
+        let mut block = _fixture_chain(&[0, 1, 1650301020]).remove(2);
+        block.previous_hash = chain[1].hash.clone();
+        mine_hash(&mut block, 8, HashFormat::Hex);
+        chain.push(block);
+        assert!(validate_chain_difficulty(&chain, &params).is_ok());
+    }
+    #[test]
+    fn block_below_expected_difficulty_is_invalid() {
+        let params = DifficultyParams {
+            initial_difficulty: 8,
+            .._fixture_params()
+        };
+        let mut chain = _fixture_chain(&[0, 1]);
+        mine_hash(&mut chain[0], 8, HashFormat::Hex);
+        chain[1].previous_hash = chain[0].hash.clone();
+        loop {
+            mine_hash(&mut chain[1], 4, HashFormat::Hex);
+            if !verify_block_hash(&chain[1]).unwrap().meets_difficulty(8) {
+                break;
+            }
+            chain[1].nonce += 1;
+        }
+        let easier = DifficultyParams {
+            initial_difficulty: 4,
+            ..params.clone()
+        };
+        assert!(validate_block_with_ancestors(
+            &chain[1],
+            &chain[..1],
+            &easier
+        ));
+        assert!(!validate_block_with_ancestors(
+            &chain[1],
+            &chain[..1],
+            &params
+        ));
+    }
+    #[test]
+    fn block_after_the_last_id_is_invalid() {
+        let params = _fixture_params();
+        let mut chain = _fixture_chain(&[0, 10, 20]);
+        chain[1].id = u64::MAX;
+        mine_hash(&mut chain[2], 1, HashFormat::Hex);
+        assert!(!validate_block_with_ancestors(
+            &chain[2],
+            &chain[..2],
+            &params
+        ));
+    }
+    #[test]
+    fn block_with_wrong_hash_is_invalid() {
+        let params = _fixture_params();
+        let mut chain = _fixture_chain(&[0, 1]);
+        mine_hash(&mut chain[0], 1, HashFormat::Hex);
+        chain[1].previous_hash = chain[0].hash.clone();
+        mine_hash(&mut chain[1], 1, HashFormat::Hex);
+        assert!(validate_chain_difficulty(&chain, &params).is_ok());
+        chain[1].timestamp += 1;
+        assert!(validate_chain_difficulty(&chain, &params).is_err());
+    }
+
+    #[test]
+    fn governed_target_block_time_applies_from_its_retarget() {
+        let config = GenesisConfig {
+            retarget_interval: 4,
+            ..Default::default()
+        };
+        let chain = _fixture_chain(&[0, 10, 20, 30, 40, 50, 60, 70]);
+        let mut governance = GovernanceState::new();
+        assert_eq!(governed_difficulty_at(&chain, 8, &governance, &config), 8);
+        governance.changes.push(ParameterChange {
+            active_from: 5,
+            parameter: Parameter::TargetBlockTime,
+            value: 100,
+        });
+        // Only the retarget at height 8 aims for 100 seconds per block
+        assert_eq!(governed_difficulty_at(&chain, 7, &governance, &config), 8);
+        assert_eq!(governed_difficulty_at(&chain, 8, &governance, &config), 9);
+        assert_eq!(
+            difficulty_at(&chain, 8, &DifficultyParams::from(&config)),
+            8
+        );
+    }
+
+    /// Camper mines 5 blocks onto the genesis block, a second apart.
+    fn _fixture_mined_chain(config: &GenesisConfig) -> FullChain {
+        let mut chain = vec![FullBlock::from(config.mine_genesis("Camper", 0))];
+        for timestamp in 1..6 {
+            let mut block = next_block(&headers(&chain), vec![], vec!["Camper".to_string()]);
+            block.timestamp = timestamp;
+            let update = Transaction {
+                event: Events::UpdateChain,
+                address: "Camper".to_string(),
+            };
+            mine_block_with_difficulty(&mut chain, block, vec![update.into()], config).unwrap();
+        }
+        chain
+    }
+    fn _fixture_params() -> DifficultyParams {
+        DifficultyParams {
+            initial_difficulty: 1,
+            min_difficulty: 1,
+            target_block_time: 10,
+            retarget_interval: 4,
+        }
+    }
+    #[test]
+    fn full_chain_hashes_commit_to_transactions() {
+        let params = _fixture_params();
+        let mut chain: Vec<FullBlock> = _fixture_chain(&[0, 10])
+            .into_iter()
+            .map(FullBlock::from)
+            .collect();
+        chain[1].body.transactions = vec![Transaction {
+            event: Events::Stake,
+            address: "Camper".to_string(),
+        }];
+        mine_full_block_hash(&mut chain[0], 1).unwrap();
+        chain[1].block.previous_hash = chain[0].block.hash.clone();
+        mine_full_block_hash(&mut chain[1], 1).unwrap();
+        assert_eq!(validate_full_chain_difficulty(&chain, &params), Ok(()));
+        // The header alone does not hash to the committed digest
+        assert!(validate_chain_difficulty(&headers(&chain), &params).is_err());
+
+        chain[1].body.transactions[0].event = Events::Unstake;
+        assert_eq!(
+            validate_full_chain_difficulty(&chain, &params),
+            Err("Block 1 is invalid".to_string())
+        );
+    }
+
+    fn _fixture_chain(timestamps: &[u64]) -> Chain {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(id, timestamp)| Block {
+                id: id as u64,
+                hash: String::new(),
+                previous_hash: String::new(),
+                timestamp: *timestamp,
+                data: vec![],
+                nonce: 0,
+                next_miner: "Camper".to_string(),
+                next_validators: vec![],
+            })
+            .collect()
+    }
+}
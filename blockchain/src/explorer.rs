@@ -0,0 +1,520 @@
+//! # Explorer
+//!
+//! The explorer answers the queries of a block explorer UI from indexes, built once from a chain,
+//! then kept up to date by adding each block as it is mined or synced:
+//! - Blocks by hash, by id range, and by miner
+//! - Transactions by hash
+//! - Aggregate stats of the latest `Account`s
+//!
+//! A transaction is identified by its hash, the ID bound to its chain and the sequence of its sender. See `sequence::transaction_id`.
+//! Clients can hash a pending transaction with `get_transaction_hash`, and find it once mined with `get_transaction`.
+//!
+//! **Note:** The miner of a block depends on the consensus mode of the chain, see `consensus::block_producer`.
+
+use crate::{
+    account::Account,
+    body::{FullBlock, FullChain},
+    consensus::block_producer,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    sequence::{transaction_id, Sequences},
+    timelock::ScheduledTransaction,
+    Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use wasm_bindgen::prelude::*;
+
+/// Where a transaction was mined.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TransactionLocation {
+    /// The id of the block holding the transaction.
+    pub block_id: u64,
+    /// The hash of the block holding the transaction.
+    pub block_hash: String,
+    /// The position of the transaction in the block.
+    pub index: usize,
+    pub transaction: Transaction,
+}
+
+/// Aggregate stats of a chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChainStats {
+    /// The number of blocks, including the genesis block.
+    pub block_count: usize,
+    /// The number of distinct Accounts.
+    pub account_count: usize,
+    /// The sum of the tokens of every Account, staked or not. Summed as a `u128`, as the balances of a chain can exceed
+    /// a `u64` together.
+    pub total_supply: u128,
+    /// The sum of the staked tokens of every Account.
+    pub total_staked: u128,
+    /// The mean number of seconds between two blocks, or `None` if the chain has fewer than two blocks.
+    pub average_block_time: Option<f64>,
+}
+
+/// Indexes of a chain, answering explorer queries.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct Explorer {
+    config: GenesisConfig,
+    chain: FullChain,
+    by_hash: HashMap<String, usize>,
+    by_transaction: HashMap<BlockHash, (usize, usize)>,
+    by_miner: HashMap<String, Vec<usize>>,
+    accounts: BTreeMap<String, Account>,
+    sequences: Sequences,
+}
+
+impl Explorer {
+    /// Creates an explorer of an empty chain, configured by the `config`.
+    pub fn new(config: GenesisConfig) -> Self {
+        Self {
+            sequences: Sequences::new(&config),
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Creates an explorer of every block of the `chain`, configured by the `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a block does not follow the previous one.
+    pub fn from_chain(chain: &[FullBlock], config: GenesisConfig) -> Result<Self, String> {
+        let mut explorer = Self::new(config);
+        for full_block in chain {
+            explorer.add_block(full_block.clone())?;
+        }
+        Ok(explorer)
+    }
+
+    /// Returns the indexed chain.
+    pub fn chain(&self) -> &FullChain {
+        &self.chain
+    }
+
+    /// Adds the `full_block` to the end of the chain, and to every index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block does not follow the last block, or a transaction has no canonical encoding.
+    pub fn add_block(&mut self, full_block: FullBlock) -> Result<(), String> {
+        let block = &full_block.block;
+        let height = self.chain.len();
+        let follows = match self.chain.last() {
+            Some(previous) => block.previous_hash == previous.block.hash,
+            None => true,
+        };
+        if block.id != height as u64 || !follows {
+            return Err(format!("Block {} does not follow the last block", block.id));
+        }
+
+        let ids = self.sequences.apply_block(&full_block)?;
+        self.by_transaction.extend(
+            ids.into_iter()
+                .enumerate()
+                .map(|(index, id)| (id, (height, index))),
+        );
+        self.by_hash.insert(block.hash.clone(), height);
+        let miner = block_producer(&full_block, self.chain.last(), &self.config);
+        self.by_miner.entry(miner).or_default().push(height);
+        for account in &block.data {
+            self.accounts
+                .insert(account.address.clone(), account.clone());
+        }
+        self.chain.push(full_block);
+        Ok(())
+    }
+
+    /// Returns the block with the given `hash`, in either `HashFormat`.
+    pub fn block_by_hash(&self, hash: &str) -> Option<&FullBlock> {
+        self.by_hash.get(hash).map(|&height| &self.chain[height])
+    }
+
+    /// Returns the blocks with ids from `start`, up to but not including `end`.
+    pub fn blocks_in_range(&self, start: u64, end: u64) -> &[FullBlock] {
+        let len = self.chain.len();
+        let end = (end.min(len as u64)) as usize;
+        let start = (start as usize).min(end);
+        &self.chain[start..end]
+    }
+
+    /// Returns the location of the transaction with the given hex `hash`.
+    pub fn transaction_by_hash(&self, hash: &str) -> Option<TransactionLocation> {
+        let hash = BlockHash::from_hex(hash).ok()?;
+        let &(height, index) = self.by_transaction.get(&hash)?;
+        let full_block = &self.chain[height];
+        Some(TransactionLocation {
+            block_id: full_block.block.id,
+            block_hash: full_block.block.hash.clone(),
+            index,
+            transaction: full_block.body.transactions[index].clone(),
+        })
+    }
+
+    /// Returns the blocks mined by the Account with the given `address`, oldest first.
+    pub fn blocks_by_miner(&self, address: &str) -> Vec<&FullBlock> {
+        self.by_miner
+            .get(address)
+            .map(|heights| heights.iter().map(|&height| &self.chain[height]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the latest version of the Account with the given `address`.
+    pub fn account_by_address(&self, address: &str) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    /// Returns the aggregate stats of the chain.
+    pub fn stats(&self) -> ChainStats {
+        let average_block_time = match (self.chain.first(), self.chain.last()) {
+            (Some(first), Some(last)) if self.chain.len() > 1 => Some(
+                last.block.timestamp.saturating_sub(first.block.timestamp) as f64
+                    / (self.chain.len() - 1) as f64,
+            ),
+            _ => None,
+        };
+        ChainStats {
+            block_count: self.chain.len(),
+            account_count: self.accounts.len(),
+            total_supply: self.accounts.values().map(|a| a.tokens as u128).sum(),
+            total_staked: self.accounts.values().map(|a| a.staked as u128).sum(),
+            average_block_time,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Explorer {
+    /// Creates an explorer of every block of the `chain` argument, configured by the `config` argument.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const explorer = new Explorer(chain, config);
+    /// explorer.add_block(newBlock);
+    /// const block = explorer.get_block_by_hash(hash);
+    /// const { total_supply, average_block_time } = explorer.get_stats();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If either argument is not deserialisable, or the blocks of the chain are not in order, a `JsError` is thrown.
+    #[wasm_bindgen(constructor)]
+    pub fn from_js(chain: JsValue, config: JsValue) -> Result<Explorer, JsError> {
+        let chain: FullChain = chain.into_serde()?;
+        let config: GenesisConfig = config.into_serde()?;
+        Self::from_chain(&chain, config).map_err(|e| JsError::new(&e))
+    }
+
+    /// Adds the `block` argument to the end of the chain.
+    ///
+    /// # Errors
+    ///
+    /// If `block` argument is not deserialisable, or does not follow the last block, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = add_block)]
+    pub fn add_block_js(&mut self, block: JsValue) -> Result<(), JsError> {
+        let block: FullBlock = block.into_serde()?;
+        self.add_block(block).map_err(|e| JsError::new(&e))
+    }
+
+    /// Returns the block with the given `hash`, or `null`.
+    pub fn get_block_by_hash(&self, hash: String) -> Result<JsValue, JsError> {
+        Ok(JsValue::from_serde(&self.block_by_hash(&hash))?)
+    }
+
+    /// Returns the blocks with ids from `start`, up to but not including `end`.
+    pub fn get_blocks_in_range(&self, start: u32, end: u32) -> Result<JsValue, JsError> {
+        Ok(JsValue::from_serde(
+            &self.blocks_in_range(start as u64, end as u64),
+        )?)
+    }
+
+    /// Returns the location of the transaction with the given `hash`, or `null`.
+    pub fn get_transaction_by_hash(&self, hash: String) -> Result<JsValue, JsError> {
+        Ok(JsValue::from_serde(&self.transaction_by_hash(&hash))?)
+    }
+
+    /// Returns the blocks mined by the Account with the given `address`.
+    pub fn get_blocks_by_miner(&self, address: String) -> Result<JsValue, JsError> {
+        Ok(JsValue::from_serde(&self.blocks_by_miner(&address))?)
+    }
+
+    /// Returns the aggregate stats of the chain.
+    pub fn get_stats(&self) -> Result<JsValue, JsError> {
+        Ok(JsValue::from_serde(&self.stats())?)
+    }
+}
+
+/// Returns the hex hash of the `transaction` argument, with its time locks and the `sequence` argument of its sender,
+/// which identifies it once mined in a chain configured by the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const sequence = get_next_sequence(chain, "Tom", config);
+/// const hash = get_transaction_hash({ event: "Stake", address: "Tom" }, sequence, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the transaction has no canonical encoding, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_transaction_hash(
+    transaction: JsValue,
+    sequence: u64,
+    config: JsValue,
+) -> Result<String, JsError> {
+    let scheduled: ScheduledTransaction = transaction.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let hash = transaction_id(
+        &config.hash(),
+        sequence,
+        &scheduled.transaction,
+        &scheduled.time_locks(0),
+    )
+    .map_err(|e| JsError::new(&e))?;
+    Ok(hash.to_hex())
+}
+
+/// Returns the transaction with the hex `hash` argument, with the id and hash of its block, and its position in the block.
+///
+/// # Examples
+///
+/// ```js
+/// const { transaction, block_id, block_hash, index } = get_transaction(chain, hash, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, the blocks of the chain are not in order, or the transaction is not mined, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_transaction(chain: JsValue, hash: String, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let explorer = Explorer::from_chain(&chain, config).map_err(|e| JsError::new(&e))?;
+    let location = explorer
+        .transaction_by_hash(&hash)
+        .ok_or_else(|| JsError::new(&format!("Transaction {} is not mined", hash)))?;
+    Ok(JsValue::from_serde(&location)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait,
+        block::Block,
+        consensus::{seal_block, ConsensusMode},
+        timelock::TransactionTimeLocks,
+        Events,
+    };
+    use ed25519_dalek::SigningKey;
+    #[test]
+    fn blocks_are_found_by_hash_and_range() {
+        let explorer = Explorer::from_chain(&_fixture_chain(), GenesisConfig::default()).unwrap();
+        assert_eq!(explorer.block_by_hash("0x01").unwrap().block.id, 1);
+        assert!(explorer.block_by_hash("0x05").is_none());
+        let ids: Vec<u64> = explorer
+            .blocks_in_range(1, 10)
+            .iter()
+            .map(|b| b.block.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(explorer.blocks_in_range(5, 10).is_empty());
+        assert!(explorer.blocks_in_range(2, 1).is_empty());
+    }
+    #[test]
+    fn transactions_are_found_by_hash() {
+        let chain = _fixture_chain();
+        let explorer = Explorer::from_chain(&chain, GenesisConfig::default()).unwrap();
+        let transaction = chain[2].body.transactions[1].clone();
+        let hash = transaction_id(
+            &GenesisConfig::default().hash(),
+            1,
+            &transaction,
+            &TransactionTimeLocks::default(),
+        )
+        .unwrap()
+        .to_hex();
+        assert_eq!(
+            explorer.transaction_by_hash(&hash),
+            Some(TransactionLocation {
+                block_id: 2,
+                block_hash: "0x02".to_string(),
+                index: 1,
+                transaction
+            })
+        );
+        assert_eq!(explorer.transaction_by_hash(&"0x00".repeat(32)), None);
+        assert_eq!(explorer.transaction_by_hash("not a hash"), None);
+    }
+    #[test]
+    fn identical_transactions_are_found_apart() {
+        let config = GenesisConfig::default();
+        let mut chain = _fixture_chain();
+        let stake = chain[2].body.transactions[1].clone();
+        let mut block_3 = _fixture_block(3, 1040, "Tom", vec![]);
+        block_3.body.transactions = vec![stake.clone()];
+        chain.push(block_3);
+        let explorer = Explorer::from_chain(&chain, config.clone()).unwrap();
+        let hash = |sequence| {
+            transaction_id(
+                &config.hash(),
+                sequence,
+                &stake,
+                &TransactionTimeLocks::default(),
+            )
+            .unwrap()
+            .to_hex()
+        };
+        assert_ne!(hash(1), hash(2));
+        let location = |sequence| {
+            explorer
+                .transaction_by_hash(&hash(sequence))
+                .map(|location| (location.block_id, location.index))
+        };
+        assert_eq!(location(1), Some((2, 1)));
+        assert_eq!(location(2), Some((3, 0)));
+        assert_eq!(location(3), None);
+        // A transaction of another chain is not found
+        let other = GenesisConfig {
+            starting_tokens: 1,
+            ..Default::default()
+        };
+        let other_hash = transaction_id(&other.hash(), 1, &stake, &TransactionTimeLocks::default())
+            .unwrap()
+            .to_hex();
+        assert_eq!(explorer.transaction_by_hash(&other_hash), None);
+    }
+    #[test]
+    fn blocks_are_found_by_miner() {
+        let explorer = Explorer::from_chain(&_fixture_chain(), GenesisConfig::default()).unwrap();
+        let ids = |address| -> Vec<u64> {
+            explorer
+                .blocks_by_miner(address)
+                .iter()
+                .map(|b| b.block.id)
+                .collect()
+        };
+        assert_eq!(ids("Camper"), vec![0, 1]);
+        assert_eq!(ids("Tom"), vec![2]);
+        assert!(ids("Mallory").is_empty());
+    }
+    #[test]
+    fn staked_blocks_are_found_by_signer() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut config = GenesisConfig {
+            consensus: ConsensusMode::ProofOfStake,
+            ..Default::default()
+        };
+        config.producer_keys.insert(
+            "Tom".to_string(),
+            hex::encode(key.verifying_key().as_bytes()),
+        );
+        let mut chain = _fixture_chain();
+        chain.truncate(2);
+        // Block 1 elects Camper as next miner, but Tom signs block 2
+        chain[0].block.next_miner = "Shaun".to_string();
+        chain[1].block.next_miner = "Camper".to_string();
+        let mut block = _fixture_block(2, 1030, "Camper", vec![]);
+        block.block.previous_hash = chain[1].block.hash.clone();
+        let block = seal_block(block, &key).unwrap();
+        let mut explorer = Explorer::from_chain(&chain, config).unwrap();
+        explorer.add_block(block).unwrap();
+        let ids = |address| -> Vec<u64> {
+            explorer
+                .blocks_by_miner(address)
+                .iter()
+                .map(|b| b.block.id)
+                .collect()
+        };
+        assert_eq!(ids("Tom"), vec![2]);
+        assert!(ids("Camper").is_empty());
+        // Unsigned blocks fall back to the elected miner
+        assert_eq!(ids("Shaun"), vec![0, 1]);
+    }
+    #[test]
+    fn stats_use_latest_accounts() {
+        let explorer = Explorer::from_chain(&_fixture_chain(), GenesisConfig::default()).unwrap();
+        assert_eq!(explorer.account_by_address("Tom").unwrap().staked, 5);
+        assert_eq!(
+            explorer.stats(),
+            ChainStats {
+                block_count: 3,
+                account_count: 2,
+                total_supply: 40,
+                total_staked: 5,
+                average_block_time: Some(15.0),
+            }
+        );
+        assert_eq!(
+            Explorer::new(GenesisConfig::default())
+                .stats()
+                .average_block_time,
+            None
+        );
+    }
+    #[test]
+    fn stats_do_not_overflow() {
+        let mut explorer = Explorer::new(GenesisConfig::default());
+        for address in ["Tom", "Shaun"] {
+            let mut account = Account::new(address);
+            account.tokens = u64::MAX;
+            account.staked = u64::MAX;
+            explorer.accounts.insert(address.to_string(), account);
+        }
+        let stats = explorer.stats();
+        assert_eq!(stats.total_supply, 2 * u64::MAX as u128);
+        assert_eq!(stats.total_staked, 2 * u64::MAX as u128);
+    }
+    #[test]
+    fn blocks_must_follow_the_last_block() {
+        let chain = _fixture_chain();
+        let mut explorer = Explorer::from_chain(&chain[..1], GenesisConfig::default()).unwrap();
+        assert_eq!(
+            explorer.add_block(chain[2].clone()),
+            Err("Block 2 does not follow the last block".to_string())
+        );
+        let mut unlinked = chain[1].clone();
+        unlinked.block.previous_hash = "0x05".to_string();
+        assert!(explorer.add_block(unlinked).is_err());
+    }
+
+    fn _fixture_block(id: u64, timestamp: u64, next_miner: &str, data: Vec<Account>) -> FullBlock {
+        FullBlock::from(Block {
+            id,
+            hash: format!("0x{:02x}", id),
+            previous_hash: if id == 0 {
+                String::new()
+            } else {
+                format!("0x{:02x}", id - 1)
+            },
+            timestamp,
+            data,
+            nonce: 0,
+            next_miner: next_miner.to_string(),
+            next_validators: vec![],
+        })
+    }
+    /// Three blocks, 15 seconds apart on average. Tom stakes 5 tokens in the last block.
+    fn _fixture_chain() -> FullChain {
+        let genesis = _fixture_block(0, 1000, "Camper", vec![Account::new("Camper")]);
+        let block_1 = _fixture_block(1, 1010, "Tom", vec![Account::new("Tom")]);
+        let mut tom = Account::new("Tom");
+        tom.staked = 5;
+        let mut block_2 = _fixture_block(2, 1030, "Camper", vec![tom]);
+        block_2.body.transactions = vec![
+            Transaction {
+                event: Events::UpdateChain,
+                address: "Tom".to_string(),
+            },
+            Transaction {
+                event: Events::Stake,
+                address: "Tom".to_string(),
+            },
+        ];
+        vec![genesis, block_1, block_2]
+    }
+}
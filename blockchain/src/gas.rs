@@ -0,0 +1,413 @@
+//! # Gas
+//!
+//! Every transaction costs gas: `TRANSACTION_GAS`, plus `CODE_BYTE_GAS` per byte of deployed contract code,
+//! plus the gas used by a contract call. A block uses at most the `block_gas_limit` of the `GenesisConfig`,
+//! and records the gas its transactions use as the `gas_used` of its `BlockBody`.
+//!
+//! The sender of each transaction pays a fee of one token per `gas_per_token` gas, rounded up, from its spendable tokens.
+//! Fees are burned. See `ledger::spend_transaction`.
+//!
+//! The gas limit and fee rate are those active at each block, as changed by `governance`.
+//!
+//! When a block is assembled, see `assembly`, `select_transactions` picks the transactions of the next block: transactions whose sender cannot pay
+//! the fee, or spend the tokens of the event, are rejected, and transactions which do not fit in the block are deferred to a later block.
+
+use crate::{
+    account::Account,
+    body::FullBlock,
+    contract::Outcome,
+    genesis::GenesisConfig,
+    ledger::{apply_transfers, spend_transaction},
+    protocol::{version_at, RuleSet},
+    state::ChainState,
+    timelock::ScheduledTransaction,
+    wallet::check_signature,
+    Events, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// The gas every transaction costs.
+pub const TRANSACTION_GAS: u64 = 1_000;
+
+/// The gas each byte of deployed contract code costs.
+pub const CODE_BYTE_GAS: u64 = 10;
+
+/// Returns the gas the `transaction` costs, given its `outcome`.
+pub fn transaction_gas(transaction: &Transaction, outcome: &Outcome) -> u64 {
+    let event_gas = match &transaction.event {
+        Events::DeployContract(code) => (code.len() as u64 / 2).saturating_mul(CODE_BYTE_GAS),
+        _ => 0,
+    };
+    let execution_gas = match outcome {
+        Ok(Some(execution)) => execution.gas_used,
+        _ => 0,
+    };
+    TRANSACTION_GAS
+        .saturating_add(event_gas)
+        .saturating_add(execution_gas)
+}
+
+/// Returns the fee, in tokens, of a transaction costing `gas`.
+pub fn fee(gas: u64, config: &GenesisConfig) -> u64 {
+    match config.gas_per_token {
+        0 => 0,
+        gas_per_token => gas.div_ceil(gas_per_token),
+    }
+}
+
+/// Deducts the `fee` from the spendable tokens of the `account` at block `height`.
+///
+/// # Errors
+///
+/// Returns an error if the account has fewer spendable tokens than the fee.
+pub fn charge_fee(
+    account: &mut Account,
+    fee: u64,
+    height: u64,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    if config.spendable_tokens(account, height) < fee {
+        return Err(format!(
+            "'{}' cannot pay the fee of {} tokens",
+            account.address, fee
+        ));
+    }
+    account.tokens -= fee;
+    Ok(())
+}
+
+/// A transaction which cannot be mined.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Rejection {
+    /// The rejected transaction.
+    pub transaction: ScheduledTransaction,
+    /// Why the transaction was rejected.
+    pub error: String,
+}
+
+/// The transactions for the next block, as returned by `select_transactions`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Selection {
+    /// The transactions to mine, in order, with their time locks.
+    pub included: Vec<ScheduledTransaction>,
+    /// The gas the included transactions use, to record as the `gas_used` of the block.
+    pub gas_used: u64,
+    /// The total fee each sender pays, by address.
+    pub fees: BTreeMap<String, u64>,
+    /// The transactions which do not fit in the block, to keep for a later block.
+    pub deferred: Vec<ScheduledTransaction>,
+    /// The transactions whose sender cannot pay the fee, or spend the tokens of the event.
+    pub rejected: Vec<Rejection>,
+}
+
+/// Selects, in order, the `transactions` fitting in the next block of the `chain`, whose senders can pay their fees.
+///
+/// Senders must also be able to spend the tokens of their events, see `ledger::apply_spending`. Transactions of a feature the protocol version of the block does not have are rejected,
+/// as are transactions whose signature is not valid, see `wallet::check_signature`, and transactions of multisig
+/// accounts whose witness does not meet the threshold. Before gas is enabled,
+/// transactions cost no gas, and pay no fee.
+///
+/// **Note:** A new Account pays for its `AddAccount` transaction from its starting tokens.
+///
+/// # Errors
+///
+/// Returns an error if the `chain` is invalid, or the protocol version of the block is not supported.
+pub fn select_transactions(
+    transactions: Vec<ScheduledTransaction>,
+    chain: &[FullBlock],
+    config: &GenesisConfig,
+) -> Result<Selection, String> {
+    let block_id = chain.last().map_or(0, |full_block| full_block.block.id + 1);
+    let state = ChainState::from_chain(chain, config)?;
+    select_transactions_after(transactions, &state, block_id, config)
+}
+
+/// Selects the `transactions` for block `block_id`, as `select_transactions` does, following the chain whose replayed
+/// `state` is given.
+///
+/// # Errors
+///
+/// Returns an error if the protocol version of the block is not supported.
+pub fn select_transactions_after(
+    transactions: Vec<ScheduledTransaction>,
+    state: &ChainState,
+    block_id: u64,
+    config: &GenesisConfig,
+) -> Result<Selection, String> {
+    let mut accounts = state.accounts.clone();
+    let mut contracts = state.contracts.clone();
+    let mut multisig = state.multisig.clone();
+    let mut sequences = state.sequences.clone();
+    let version = version_at(block_id, config);
+    let rules = RuleSet::of_version(version)
+        .ok_or_else(|| format!("Protocol version {} is not supported", version))?;
+    let config = &state.governance.config_at(block_id, config);
+    let mut selection = Selection::default();
+    for scheduled in transactions {
+        let transaction = &scheduled.transaction;
+        let index = selection.included.len();
+        // Check the signature and witness on copies of the policies and sequences, kept only if the transaction is included
+        let mut next_multisig = multisig.clone();
+        let mut next_sequences = sequences.clone();
+        let time_locks = scheduled.time_locks(index);
+        let authorised = rules.check_event(&transaction.event).and_then(|_| {
+            if rules.signatures {
+                let transaction_id = next_sequences.next_id(transaction, &time_locks)?;
+                check_signature(transaction, scheduled.signature.as_ref(), &transaction_id)?;
+            }
+            next_multisig.apply_transaction(
+                block_id,
+                index,
+                transaction,
+                &time_locks,
+                scheduled.witness.as_ref(),
+                &mut next_sequences,
+            )
+        });
+        if let Err(error) = authorised {
+            selection.rejected.push(Rejection {
+                transaction: scheduled,
+                error,
+            });
+            continue;
+        }
+        // Run contract transactions on a copy, kept only if the transaction is included, and the call makes its transfers
+        let mut next_contracts = contracts.clone();
+        let caller = accounts.get(&transaction.address);
+        let outcome =
+            next_contracts.apply_transaction(transaction, index, block_id, caller, config);
+        let gas = match rules.gas {
+            true => transaction_gas(transaction, &outcome),
+            false => 0,
+        };
+        if selection.gas_used.saturating_add(gas) > config.block_gas_limit {
+            selection.deferred.push(scheduled);
+            continue;
+        }
+        let fee = fee(gas, config);
+        let mut next_accounts = accounts.clone();
+        let address = &transaction.address;
+        let adds_account = matches!(transaction.event, Events::AddAccount);
+        let spent = if !adds_account && !accounts.contains_key(address) {
+            Err(format!("'{}' has no Account", address))
+        } else if rules.spending {
+            spend_transaction(&mut next_accounts, transaction, fee, block_id, config)
+        } else {
+            if adds_account {
+                next_accounts
+                    .entry(address.clone())
+                    .or_insert_with(|| config.new_account(address));
+            }
+            Ok(())
+        };
+        if let Err(error) = spent {
+            selection.rejected.push(Rejection {
+                transaction: scheduled,
+                error,
+            });
+            continue;
+        }
+        // A call transferring more than its sender can spend is mined as failed, without changing the contracts
+        let transfers = match &outcome {
+            Ok(Some(execution)) => execution.transfers.as_slice(),
+            _ => &[],
+        };
+        let transferred = !rules.spending
+            || apply_transfers(&mut next_accounts, address, transfers, block_id, config).is_ok();
+        accounts = next_accounts;
+        multisig = next_multisig;
+        sequences = next_sequences;
+        if transferred {
+            contracts = next_contracts;
+        }
+        selection.gas_used += gas;
+        *selection
+            .fees
+            .entry(transaction.address.clone())
+            .or_default() += fee;
+        selection.included.push(scheduled);
+    }
+    Ok(selection)
+}
+
+/// Selects the transactions for the next block of the `chain` argument, from the `transactions` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const { included, gas_used, fees, deferred, rejected } = select_transactions_for(chain, transactions, config);
+/// nodeState.transactions = included;
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the chain is invalid, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn select_transactions_for(
+    chain: JsValue,
+    transactions: JsValue,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let transactions: Vec<ScheduledTransaction> = transactions.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let selection =
+        select_transactions(transactions, &chain, &config).map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&selection)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait, block::Block, hash::BlockHash, vesting::VestingSchedule, vm,
+    };
+    #[test]
+    fn transactions_beyond_block_limit_are_deferred() {
+        let config = GenesisConfig {
+            block_gas_limit: 2 * TRANSACTION_GAS,
+            ..Default::default()
+        };
+        let updates = vec![_fixture_transaction("Tom", Events::UpdateChain); 3];
+        let selection = select_transactions(updates, &_fixture_chain(&config), &config).unwrap();
+        assert_eq!(selection.included.len(), 2);
+        assert_eq!(selection.deferred.len(), 1);
+        assert_eq!(selection.gas_used, 2 * TRANSACTION_GAS);
+        assert_eq!(selection.fees.get("Tom"), Some(&2));
+    }
+    #[test]
+    fn senders_who_cannot_pay_are_rejected() {
+        let config = GenesisConfig {
+            gas_per_token: 100,
+            ..Default::default()
+        };
+        // Tom has 30 tokens, of which 10 are staked, so can pay for 2 transactions at 10 tokens each
+        let updates = vec![_fixture_transaction("Tom", Events::UpdateChain); 3];
+        let selection = select_transactions(updates, &_fixture_chain(&config), &config).unwrap();
+        assert_eq!(selection.included.len(), 2);
+        assert_eq!(
+            selection.rejected[0].error,
+            "'Tom' cannot pay the fee of 10 tokens"
+        );
+
+        let transactions = vec![
+            _fixture_transaction("Mallory", Events::Stake),
+            _fixture_transaction("Camper", Events::AddAccount),
+        ];
+        let selection =
+            select_transactions(transactions, &_fixture_chain(&config), &config).unwrap();
+        assert_eq!(selection.rejected[0].error, "'Mallory' has no Account");
+        assert_eq!(selection.fees.get("Camper"), Some(&10));
+    }
+    #[test]
+    fn locked_tokens_cannot_be_spent() {
+        let mut config = GenesisConfig::default();
+        config.vesting.insert(
+            "Tom".to_string(),
+            VestingSchedule {
+                amount: 19,
+                start: 0,
+                cliff: 10,
+                duration: 10,
+            },
+        );
+        // The fee of the first transfer takes the only spendable token of Tom
+        let transfers =
+            vec![_fixture_transaction("Tom", Events::Transfer("Camper".to_string(), 0)); 2];
+        let selection = select_transactions(transfers, &_fixture_chain(&config), &config).unwrap();
+        assert_eq!(selection.included.len(), 1);
+        assert_eq!(
+            selection.rejected[0].error,
+            "'Tom' cannot pay the fee of 1 tokens"
+        );
+        let rack = vec![_fixture_transaction("Tom", Events::BuyRack)];
+        let selection = select_transactions(rack, &_fixture_chain(&config), &config).unwrap();
+        assert_eq!(selection.rejected[0].error, "'Tom' cannot afford a rack");
+    }
+    #[test]
+    fn transactions_before_their_version_are_rejected() {
+        let config = GenesisConfig {
+            upgrades: vec![],
+            ..Default::default()
+        };
+        let transactions = vec![
+            _fixture_transaction("Tom", Events::Stake),
+            _fixture_transaction("Tom", Events::DeployContract("00".to_string())),
+        ];
+        let selection =
+            select_transactions(transactions, &_fixture_chain(&config), &config).unwrap();
+        // Version 0 has no gas, so the stake is free
+        assert_eq!(selection.included.len(), 1);
+        assert_eq!(
+            (selection.gas_used, selection.fees.get("Tom")),
+            (0, Some(&0))
+        );
+        assert_eq!(
+            selection.rejected[0].error,
+            "Version 0 does not support contract transactions"
+        );
+    }
+    #[test]
+    fn contract_calls_cost_their_execution() {
+        let code = vm::assemble("PUSH 1 PUSH 0 STORE STOP").unwrap();
+        let deploy = _fixture_transaction("Tom", Events::DeployContract(hex::encode(&code)));
+        let outcome = Ok(None);
+        assert_eq!(
+            transaction_gas(&deploy.transaction, &outcome),
+            TRANSACTION_GAS + code.len() as u64 * CODE_BYTE_GAS
+        );
+        let call = _fixture_transaction("Tom", Events::CallContract("fcc1".to_string(), vec![]));
+        let mut storage = vm::Storage::new();
+        let execution = vm::execute(&code, &mut storage, &Default::default(), 1000);
+        assert_eq!(
+            transaction_gas(&call.transaction, &Ok(Some(execution))),
+            TRANSACTION_GAS + 1 + 1 + 200 + 1
+        );
+    }
+    #[test]
+    fn fees_round_up() {
+        let config = GenesisConfig {
+            gas_per_token: 1000,
+            ..Default::default()
+        };
+        assert_eq!(fee(1000, &config), 1);
+        assert_eq!(fee(1001, &config), 2);
+        let free = GenesisConfig {
+            gas_per_token: 0,
+            ..config
+        };
+        assert_eq!(fee(1001, &free), 0);
+    }
+
+    fn _fixture_transaction(address: &str, event: Events) -> ScheduledTransaction {
+        ScheduledTransaction::from(Transaction {
+            event,
+            address: address.to_string(),
+        })
+    }
+
+    /// Tom holds 30 tokens, of which 10 are staked.
+    fn _fixture_chain(config: &GenesisConfig) -> Vec<FullBlock> {
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        (0..2u64)
+            .map(|id| {
+                let mut full_block = FullBlock::from(Block {
+                    id,
+                    hash: BlockHash::default().to_hex(),
+                    previous_hash: String::new(),
+                    timestamp: 1000,
+                    data: if id == 0 { vec![tom.clone()] } else { vec![] },
+                    nonce: 0,
+                    next_miner: "Tom".to_string(),
+                    next_validators: vec![],
+                });
+                full_block.body.version = version_at(id, config);
+                full_block
+            })
+            .collect()
+    }
+}
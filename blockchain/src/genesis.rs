@@ -0,0 +1,673 @@
+//! # Genesis
+//!
+//! The genesis configuration holds the parameters every Account must agree on before joining the network.
+//! Its hash is committed into the genesis block, so Accounts started with a different configuration refuse to sync.
+
+use crate::{
+    account::Account,
+    address::{validate_chain_address, Address},
+    block::Block,
+    chain::Chain,
+    codec,
+    consensus::ConsensusMode,
+    difficulty::{mine_hash, prefix_difficulty},
+    gas::TRANSACTION_GAS,
+    hash::{verify_block_hash, HashFormat},
+    protocol::{validate_upgrades, Upgrade},
+    vesting::VestingSchedule,
+};
+use chrono::Utc;
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// The parameters of a chain, consumed by `initialise_chain_with_config`.
+///
+/// Missing fields fall back to the values of `GenesisConfig::default()`, which match the course defaults.
+///
+/// # Examples
+///
+/// ```json
+/// {
+///   "difficulty_prefix": "00",
+///   "starting_tokens": 50,
+///   "allocations": [{ "address": "Tom", "staked": 0, "tokens": 100 }]
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct GenesisConfig {
+    /// How blocks are produced.
+    pub consensus: ConsensusMode,
+    /// The legacy binary prefix the hash of the genesis block must start with. Each `"0"` is 8 bits of initial difficulty.
+    pub difficulty_prefix: String,
+    /// The desired number of seconds between two blocks.
+    pub target_block_time: u64,
+    /// The number of blocks between two difficulty retargets.
+    pub retarget_interval: u64,
+    /// The number of seconds in a block production slot.
+    pub slot_duration: u64,
+    /// The number of slots in an epoch. The validator set only changes at epoch boundaries.
+    pub epoch_length: u64,
+    /// The number of previous blocks whose median timestamp a new block must be after.
+    pub median_time_span: usize,
+    /// The maximum number of seconds a block timestamp may be ahead of the local clock.
+    pub max_clock_drift: u64,
+    /// The number of blocks between two state snapshots. `0` disables snapshots.
+    pub snapshot_interval: u64,
+    /// The hex encoded Ed25519 public keys of the Accounts allowed to produce blocks by stake, by address.
+    pub producer_keys: BTreeMap<String, String>,
+    /// The number of tokens a new `Account` starts with.
+    pub starting_tokens: u64,
+    /// The number of unstaked tokens needed to buy a server rack.
+    pub rack_price: u64,
+    /// The miner returned when no `Account` has any weight.
+    pub default_miner: String,
+    /// The maximum number of validators chosen for each block.
+    pub validator_count: usize,
+    /// The `Account`s present in the genesis block.
+    pub allocations: Vec<Account>,
+    /// The maximum gas a contract call may use.
+    pub contract_gas_limit: u64,
+    /// The maximum gas the transactions of a block may use.
+    pub block_gas_limit: u64,
+    /// The gas a transaction fee of one token pays for. `0` makes transactions free.
+    pub gas_per_token: u64,
+    /// The number of blocks after a governance proposal during which votes are accepted.
+    pub voting_period: u64,
+    /// The percentage of the total stake which must vote on a governance proposal for it to pass.
+    pub quorum_percent: u64,
+    /// The number of blocks after the voting period of a governance proposal during which it can be executed.
+    pub execution_period: u64,
+    /// The maximum number of tokens a single `Reward` transaction may give.
+    pub max_reward: u64,
+    /// The block heights protocol versions activate at, in order, committed by the hash of the configuration. By default,
+    /// version `1` activates at block `1`.
+    pub upgrades: Vec<Upgrade>,
+    /// The vesting schedules of allocated tokens, by address.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub vesting: BTreeMap<String, VestingSchedule>,
+    /// Whether free-form names, such as `"Camper"`, are valid addresses on the chain. See `address`.
+    pub legacy_addresses: bool,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            consensus: ConsensusMode::ProofOfWork,
+            difficulty_prefix: "0".to_string(),
+            target_block_time: 10,
+            retarget_interval: 10,
+            slot_duration: 10,
+            epoch_length: 10,
+            median_time_span: 11,
+            max_clock_drift: 60,
+            snapshot_interval: 100,
+            producer_keys: BTreeMap::new(),
+            starting_tokens: 20,
+            rack_price: 10,
+            default_miner: "Camper".to_string(),
+            validator_count: 3,
+            allocations: vec![],
+            contract_gas_limit: 100_000,
+            block_gas_limit: 1_000_000,
+            gas_per_token: 1_000,
+            voting_period: 100,
+            quorum_percent: 33,
+            execution_period: 100,
+            max_reward: 10,
+            upgrades: vec![Upgrade {
+                version: 1,
+                height: 1,
+            }],
+            vesting: BTreeMap::new(),
+            legacy_addresses: true,
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// Parses a configuration from a JSON string.
+    pub fn from_json(config: &str) -> Result<Self, String> {
+        let config: Self =
+            serde_json::from_str(config).map_err(|e| format!("Invalid genesis config: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses a configuration from a TOML string.
+    pub fn from_toml(config: &str) -> Result<Self, String> {
+        let config: Self =
+            toml::from_str(config).map_err(|e| format!("Invalid genesis config: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the parameters are usable.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.difficulty_prefix.chars().any(|c| c != '0') {
+            return Err("Difficulty prefix must only contain zeros".to_string());
+        }
+        if self.validator_count == 0 {
+            return Err("Validator count must be at least 1".to_string());
+        }
+        if self.slot_duration == 0 {
+            return Err("Slot duration must be at least 1 second".to_string());
+        }
+        if self.median_time_span == 0 {
+            return Err("Median time span must be at least 1 block".to_string());
+        }
+        if self.epoch_length == 0 {
+            return Err("Epoch length must be at least 1 slot".to_string());
+        }
+        if self.block_gas_limit < TRANSACTION_GAS {
+            return Err(format!(
+                "Block gas limit must be at least {}",
+                TRANSACTION_GAS
+            ));
+        }
+        if self.quorum_percent > 100 {
+            return Err("Quorum must be at most 100 percent".to_string());
+        }
+        validate_upgrades(&self.upgrades)?;
+        validate_chain_address(&self.default_miner, self)?;
+        for (address, key) in &self.producer_keys {
+            let key = hex::decode(key)
+                .ok()
+                .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                .ok_or_else(|| format!("'{}' has an invalid producer key", address))?;
+            let address = Address::parse_with(address, self.legacy_addresses)?;
+            if !address.is_legacy()
+                && !matches!(VerifyingKey::from_bytes(&key), Ok(key) if address.is_owned_by(&key))
+            {
+                return Err(format!(
+                    "'{}' is not the address of its producer key",
+                    address
+                ));
+            }
+        }
+        for (i, account) in self.allocations.iter().enumerate() {
+            validate_chain_address(&account.address, self)?;
+            if account.staked > account.tokens {
+                return Err(format!(
+                    "'{}' cannot stake more than it owns",
+                    account.address
+                ));
+            }
+            if self.allocations[..i]
+                .iter()
+                .any(|a| a.address == account.address)
+            {
+                return Err(format!("'{}' is allocated twice", account.address));
+            }
+        }
+        for (address, schedule) in &self.vesting {
+            let account = self
+                .allocations
+                .iter()
+                .find(|account| account.address == *address)
+                .ok_or_else(|| format!("'{}' vests tokens it is not allocated", address))?;
+            if schedule.amount > account.tokens.saturating_sub(account.staked) {
+                return Err(format!(
+                    "'{}' vests more tokens than it has unstaked",
+                    address
+                ));
+            }
+            schedule.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the hex encoded `Sha256` hash of the canonical encoding of the configuration, see `codec::encode_genesis_config`.
+    ///
+    /// **Note:** The hash is used as the `previous_hash` of the genesis block. It is the hash of the genesis parameters,
+    /// not of the parameters later changed by `governance`.
+    pub fn hash(&self) -> String {
+        let config =
+            codec::encode_genesis_config(self).expect("genesis parameters to be encodable");
+        hex::encode(Sha256::digest(config))
+    }
+
+    /// Creates a new `Account` holding the configured starting tokens.
+    pub fn new_account(&self, address: &str) -> Account {
+        Account {
+            address: address.to_string(),
+            staked: 0,
+            tokens: self.starting_tokens,
+        }
+    }
+
+    /// Returns the number of tokens of the Account with the given `address` still locked by its vesting schedule at block `height`.
+    pub fn locked_tokens(&self, address: &str, height: u64) -> u64 {
+        self.vesting
+            .get(address)
+            .map_or(0, |schedule| schedule.locked(height))
+    }
+
+    /// Returns the number of tokens of the `account` which are neither staked, nor locked, at block `height`.
+    pub fn spendable_tokens(&self, account: &Account, height: u64) -> u64 {
+        account
+            .tokens
+            .saturating_sub(account.staked)
+            .saturating_sub(self.locked_tokens(&account.address, height))
+    }
+
+    /// Check if the `Account` can afford a server rack at the configured price, with its spendable tokens at block `height`.
+    pub fn can_buy_rack(&self, account: &Account, height: u64) -> bool {
+        self.spendable_tokens(account, height) >= self.rack_price
+    }
+
+    /// Check if the `Account` can stake, by checking if it has any spendable tokens at block `height`.
+    pub fn can_stake(&self, account: &Account, height: u64) -> bool {
+        self.spendable_tokens(account, height) > 0
+    }
+
+    /// Check if the `Account` can transfer the `amount`, by checking if it has enough spendable tokens at block `height`.
+    pub fn can_transfer(&self, account: &Account, amount: &u64, height: u64) -> bool {
+        self.spendable_tokens(account, height) >= *amount
+    }
+
+    /// Returns the `Account`s of the genesis block: the allocations, plus the initialising `address` if it is not allocated.
+    pub fn genesis_accounts(&self, address: &str) -> Vec<Account> {
+        let mut accounts = self.allocations.clone();
+        if !accounts.iter().any(|a| a.address == address) {
+            accounts.push(self.new_account(address));
+        }
+        accounts
+    }
+
+    /// Returns the difficulty the genesis block must meet.
+    ///
+    /// **Note:** Blocks produced by stake are not mined, so have no difficulty.
+    pub fn genesis_difficulty(&self) -> u32 {
+        match self.consensus {
+            ConsensusMode::ProofOfWork => prefix_difficulty(&self.difficulty_prefix),
+            ConsensusMode::ProofOfStake => 0,
+        }
+    }
+
+    /// Mines the genesis block for the Account with the given `address`.
+    pub fn mine_genesis(&self, address: &str, timestamp: u64) -> Block {
+        let id = 0;
+        let data = self.genesis_accounts(address);
+        let previous_hash = self.hash();
+        let next_miner = address.to_string();
+        let next_validators = vec![address.to_string()];
+
+        let mut genesis = Block {
+            id,
+            hash: String::new(),
+            previous_hash,
+            timestamp,
+            data,
+            nonce: 0,
+            next_miner,
+            next_validators,
+        };
+        mine_hash(&mut genesis, self.genesis_difficulty(), HashFormat::Hex);
+        genesis
+    }
+
+    /// Checks the genesis block of `chain` was created with this configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chain is empty, or if its genesis block commits to a different configuration, or does not hold
+    /// the `genesis_accounts` of its initialising Account, the `next_miner` of the block.
+    pub fn validate_genesis(&self, chain: &Chain) -> Result<(), String> {
+        let genesis = chain.first().ok_or("Chain is too short")?;
+        if genesis.id != 0 {
+            return Err("First block is not a genesis block".to_string());
+        }
+        if genesis.previous_hash != self.hash() {
+            return Err("Genesis config does not match".to_string());
+        }
+        if genesis.data != self.genesis_accounts(&genesis.next_miner) {
+            return Err("Genesis block does not hold the genesis accounts".to_string());
+        }
+        match verify_block_hash(genesis) {
+            Ok(digest) if digest.meets_difficulty(self.genesis_difficulty()) => Ok(()),
+            _ => Err("Genesis block hash is invalid".to_string()),
+        }
+    }
+}
+
+/// Initialise a new blockchain from a `GenesisConfig`, and returns the corresponding chain.
+/// This is only to be called by the first Account starting the network.
+///
+/// # Examples
+///
+/// ```js
+/// const config = { difficulty_prefix: "00", starting_tokens: 50 };
+/// const chain = initialise_chain_with_config("Camper", config);
+/// assert.equal(chain.length, 1);
+/// ```
+///
+/// # Errors
+///
+/// If `config` argument is not deserialisable into type `GenesisConfig`, or is invalid, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn initialise_chain_with_config(address: String, config: JsValue) -> Result<JsValue, JsError> {
+    let config: GenesisConfig = config.into_serde()?;
+    config.validate().map_err(|e| JsError::new(&e))?;
+    let genesis = config.mine_genesis(&address, Utc::now().timestamp() as u64);
+    let chain: Chain = vec![genesis];
+    Ok(JsValue::from_serde(&chain)?)
+}
+
+/// Checks whether the `chain` argument was initialised with the `config` argument, before syncing with it.
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, or the genesis block does not match the config, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn validate_genesis(chain: JsValue, config: JsValue) -> Result<bool, JsError> {
+    let chain: Chain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    config
+        .validate_genesis(&chain)
+        .map_err(|e| JsError::new(&e))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    #[test]
+    fn default_config_matches_course_values() {
+        let config = GenesisConfig::default();
+        assert_eq!(config.difficulty_prefix, "0");
+        assert_eq!(config.starting_tokens, 20);
+        assert_eq!(config.rack_price, 10);
+        assert_eq!(config.default_miner, "Camper");
+        assert!(config.allocations.is_empty());
+    }
+    #[test]
+    fn from_json_fills_missing_fields_with_defaults() {
+        let config = GenesisConfig::from_json(r#"{ "starting_tokens": 50 }"#).unwrap();
+        assert_eq!(config.starting_tokens, 50);
+        assert_eq!(config.rack_price, 10);
+        assert_eq!(config.new_account("Tom").tokens, 50);
+    }
+    #[test]
+    fn from_toml_reads_allocations() {
+        let config = GenesisConfig::from_toml(
+            r#"
+difficulty_prefix = "00"
+rack_price = 15
+
+[[allocations]]
+address = "Tom"
+staked = 10
+tokens = 100
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.difficulty_prefix, "00");
+        assert_eq!(config.rack_price, 15);
+        assert_eq!(config.allocations.len(), 1);
+        assert_eq!(config.allocations[0].tokens, 100);
+    }
+    #[test]
+    fn invalid_config_is_rejected() {
+        assert!(GenesisConfig::from_json(r#"{ "difficulty_prefix": "ab" }"#).is_err());
+        assert!(GenesisConfig::from_json(r#"{ "validator_count": 0 }"#).is_err());
+        assert!(GenesisConfig::from_json(r#"{ "epoch_length": 0 }"#).is_err());
+        assert!(GenesisConfig::from_json(r#"{ "quorum_percent": 101 }"#).is_err());
+        assert!(GenesisConfig::from_json(
+            r#"{ "allocations": [{ "address": "Tom", "staked": 0, "tokens": 1 },
+                                 { "address": "Tom", "staked": 0, "tokens": 2 }] }"#
+        )
+        .is_err());
+    }
+    #[test]
+    fn hash_changes_with_parameters() {
+        let config = GenesisConfig::default();
+        assert_eq!(config.hash(), GenesisConfig::default().hash());
+        assert_eq!(config.hash().len(), 64);
+        let other = GenesisConfig {
+            rack_price: 11,
+            ..Default::default()
+        };
+        assert_ne!(config.hash(), other.hash());
+        let other = GenesisConfig {
+            voting_period: 50,
+            ..Default::default()
+        };
+        assert_ne!(config.hash(), other.hash());
+        let other = GenesisConfig {
+            upgrades: vec![Upgrade {
+                version: 1,
+                height: 10,
+            }],
+            ..Default::default()
+        };
+        assert_ne!(config.hash(), other.hash());
+    }
+    #[test]
+    fn default_hash_is_fixed() {
+        // See the encoding in `codec::tests::encode_genesis_config_test_vector`
+        assert_eq!(
+            GenesisConfig::default().hash(),
+            "4aeb1f6330b0814c7531d8c314d0e101c728d71703e29f65f2e20beffb5d2e52"
+        );
+    }
+    #[test]
+    fn can_buy_rack_uses_rack_price() {
+        let config = GenesisConfig {
+            rack_price: 15,
+            ..Default::default()
+        };
+        let mut account = config.new_account("Camper");
+        account.staked = 5;
+        assert!(config.can_buy_rack(&account, 0));
+        account.staked = 6;
+        assert!(!config.can_buy_rack(&account, 0));
+    }
+    #[test]
+    fn locked_tokens_cannot_be_spent() {
+        let mut config = GenesisConfig {
+            allocations: vec![Account {
+                address: "Tom".to_string(),
+                staked: 0,
+                tokens: 100,
+            }],
+            ..Default::default()
+        };
+        config.vesting.insert(
+            "Tom".to_string(),
+            VestingSchedule {
+                amount: 95,
+                start: 0,
+                cliff: 10,
+                duration: 10,
+            },
+        );
+        assert!(config.validate().is_ok());
+        let tom = &config.allocations[0];
+        assert_eq!(config.spendable_tokens(tom, 0), 5);
+        assert!(config.can_stake(tom, 0));
+        assert!(!config.can_buy_rack(tom, 0));
+        assert!(!config.can_transfer(tom, &6, 9));
+        assert!(config.can_transfer(tom, &100, 10));
+
+        config.vesting.insert(
+            "Camper".to_string(),
+            VestingSchedule {
+                amount: 1,
+                start: 0,
+                cliff: 0,
+                duration: 0,
+            },
+        );
+        assert_eq!(
+            config.validate(),
+            Err("'Camper' vests tokens it is not allocated".to_string())
+        );
+    }
+    #[test]
+    fn genesis_accounts_include_initialising_address_once() {
+        let config = GenesisConfig {
+            allocations: vec![Account {
+                address: "Camper".to_string(),
+                staked: 0,
+                tokens: 100,
+            }],
+            ..Default::default()
+        };
+        let accounts = config.genesis_accounts("Camper");
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].tokens, 100);
+        assert_eq!(config.genesis_accounts("Tom").len(), 2);
+    }
+    #[test]
+    fn genesis_block_commits_to_config() {
+        let config = GenesisConfig::default();
+        let genesis = config.mine_genesis("Camper", 1648987026);
+        assert_eq!(genesis.previous_hash, config.hash());
+        assert!(genesis.hash.starts_with("0x00"));
+        let chain: Chain = vec![genesis];
+        assert!(config.validate_genesis(&chain).is_ok());
+    }
+    #[test]
+    fn proof_of_stake_genesis_is_not_mined() {
+        let config = GenesisConfig {
+            consensus: ConsensusMode::ProofOfStake,
+            ..Default::default()
+        };
+        let genesis = config.mine_genesis("Camper", 1648987026);
+        assert_eq!(genesis.nonce, 0);
+        assert!(config.validate_genesis(&vec![genesis]).is_ok());
+    }
+    #[test]
+    fn producer_keys_must_be_ed25519_public_keys() {
+        let mut config = GenesisConfig::default();
+        config
+            .producer_keys
+            .insert("Camper".to_string(), "00".repeat(32));
+        assert!(config.validate().is_ok());
+        config
+            .producer_keys
+            .insert("Tom".to_string(), "00".repeat(31));
+        assert_eq!(
+            config.validate(),
+            Err("'Tom' has an invalid producer key".to_string())
+        );
+    }
+    #[test]
+    fn producer_addresses_must_match_their_keys() {
+        let key = SigningKey::from_bytes(&[3; 32]).verifying_key();
+        let address = String::from(Address::from_public_key(&key));
+        let mut config = GenesisConfig::default();
+        config
+            .producer_keys
+            .insert(address.clone(), hex::encode(key.as_bytes()));
+        assert!(config.validate().is_ok());
+
+        let other = SigningKey::from_bytes(&[4; 32]).verifying_key();
+        config
+            .producer_keys
+            .insert(address.clone(), hex::encode(other.as_bytes()));
+        assert_eq!(
+            config.validate(),
+            Err(format!(
+                "'{}' is not the address of its producer key",
+                address
+            ))
+        );
+        config.producer_keys.clear();
+        config.default_miner = "Tom Mondays".to_string();
+        assert_eq!(
+            config.validate(),
+            Err("'Tom Mondays' is not a valid address".to_string())
+        );
+    }
+    #[test]
+    fn legacy_addresses_are_committed() {
+        let config = GenesisConfig {
+            legacy_addresses: false,
+            ..Default::default()
+        };
+        assert_ne!(config.hash(), GenesisConfig::default().hash());
+        assert_eq!(
+            config.validate(),
+            Err("'Camper' is not a valid address".to_string())
+        );
+        let address = Address::from_public_key(&SigningKey::from_bytes(&[3; 32]).verifying_key());
+        let config = GenesisConfig {
+            default_miner: address.into(),
+            ..config
+        };
+        assert!(config.validate().is_ok());
+    }
+    #[test]
+    fn upgrade_schedule_is_committed() {
+        let config = GenesisConfig {
+            upgrades: vec![],
+            ..Default::default()
+        };
+        let chain: Chain = vec![config.mine_genesis("Camper", 1648987026)];
+        // A node scheduling another activation height refuses the chain, rather than forking at the height
+        let upgraded = GenesisConfig {
+            upgrades: vec![Upgrade {
+                version: 1,
+                height: 10,
+            }],
+            ..config
+        };
+        assert!(upgraded.validate().is_ok());
+        assert_eq!(
+            upgraded.validate_genesis(&chain),
+            Err("Genesis config does not match".to_string())
+        );
+    }
+    #[test]
+    fn mismatching_config_refuses_chain() {
+        let config = GenesisConfig::default();
+        let chain: Chain = vec![config.mine_genesis("Camper", 1648987026)];
+        let other = GenesisConfig {
+            starting_tokens: 21,
+            ..Default::default()
+        };
+        assert_eq!(
+            other.validate_genesis(&chain),
+            Err("Genesis config does not match".to_string())
+        );
+        assert!(config.validate_genesis(&vec![]).is_err());
+    }
+    #[test]
+    fn genesis_block_must_hold_genesis_accounts() {
+        let config = GenesisConfig {
+            allocations: vec![Account {
+                address: "Tom".to_string(),
+                staked: 0,
+                tokens: 100,
+            }],
+            ..Default::default()
+        };
+        let genesis = config.mine_genesis("Camper", 1648987026);
+        assert!(config.validate_genesis(&vec![genesis.clone()]).is_ok());
+        for data in [
+            vec![],
+            vec![genesis.data[0].clone()],
+            vec![genesis.data[1].clone(), genesis.data[0].clone()],
+        ] {
+            let mut tampered = Block {
+                data,
+                ..genesis.clone()
+            };
+            mine_hash(&mut tampered, config.genesis_difficulty(), HashFormat::Hex);
+            assert_eq!(
+                config.validate_genesis(&vec![tampered]),
+                Err("Genesis block does not hold the genesis accounts".to_string())
+            );
+        }
+        let mut minted = genesis;
+        minted.data[0].tokens = 1_000_000;
+        mine_hash(&mut minted, config.genesis_difficulty(), HashFormat::Hex);
+        assert!(config.validate_genesis(&vec![minted]).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+//! # Governance
+//!
+//! Staked Accounts change whitelisted chain parameters by vote, without a code change:
+//! - A `Propose(parameter, value)` transaction proposes a new value for one of the `Parameter`s. Proposals are numbered
+//!   from `0`, in the order they are mined.
+//! - A `Vote(proposal, in_favour)` transaction votes on a proposal, until `voting_period` blocks after the block it was proposed in.
+//!   A later vote of the same Account replaces its earlier one.
+//! - An `Execute(proposal)` transaction tallies the votes, within `execution_period` blocks after the voting period.
+//!   Each vote weighs the `weight_as_validator` of its Account, before the block of the `Execute`. The votes must weigh at
+//!   least `quorum_percent` of the weight of every Account. If the votes in favour then outweigh the votes against,
+//!   the new value is active from the next block on.
+//!
+//! The parameters active at any block height are returned by `GovernanceState::config_at`.
+//!
+//! **Note:** A governance transaction which fails, such as a vote after the voting period, does not invalidate its block.
+//! The transaction is mined, but has no effect.
+
+use crate::{
+    account::{Account, AccountTrait},
+    body::FullBlock,
+    genesis::GenesisConfig,
+    Events, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use wasm_bindgen::prelude::*;
+
+/// The chain parameters which can be changed by governance.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Parameter {
+    /// The `rack_price` of the `GenesisConfig`.
+    RackPrice,
+    /// The `target_block_time` of the `GenesisConfig`, which the difficulty is retargeted towards.
+    TargetBlockTime,
+    /// The `max_reward` of the `GenesisConfig`.
+    MaxReward,
+    /// The `starting_tokens` of the `GenesisConfig`.
+    StartingTokens,
+    /// The `validator_count` of the `GenesisConfig`.
+    ValidatorCount,
+    /// The `block_gas_limit` of the `GenesisConfig`.
+    BlockGasLimit,
+    /// The `gas_per_token` of the `GenesisConfig`.
+    GasPerToken,
+}
+
+impl Parameter {
+    const ALL: [Parameter; 7] = [
+        Parameter::RackPrice,
+        Parameter::TargetBlockTime,
+        Parameter::MaxReward,
+        Parameter::StartingTokens,
+        Parameter::ValidatorCount,
+        Parameter::BlockGasLimit,
+        Parameter::GasPerToken,
+    ];
+
+    /// Returns the name of the parameter, as used by `Propose` transactions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::RackPrice => "rack_price",
+            Parameter::TargetBlockTime => "target_block_time",
+            Parameter::MaxReward => "max_reward",
+            Parameter::StartingTokens => "starting_tokens",
+            Parameter::ValidatorCount => "validator_count",
+            Parameter::BlockGasLimit => "block_gas_limit",
+            Parameter::GasPerToken => "gas_per_token",
+        }
+    }
+
+    /// Sets the parameter of the `config` to `value`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the resulting config is not valid.
+    pub fn set(&self, config: &mut GenesisConfig, value: u64) -> Result<(), String> {
+        match self {
+            Parameter::RackPrice => config.rack_price = value,
+            Parameter::TargetBlockTime => config.target_block_time = value,
+            Parameter::MaxReward => config.max_reward = value,
+            Parameter::StartingTokens => config.starting_tokens = value,
+            Parameter::ValidatorCount => {
+                config.validator_count =
+                    usize::try_from(value).map_err(|_| "Validator count is too large")?
+            }
+            Parameter::BlockGasLimit => config.block_gas_limit = value,
+            Parameter::GasPerToken => config.gas_per_token = value,
+        }
+        config.validate()
+    }
+}
+
+impl FromStr for Parameter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|parameter| parameter.name() == name)
+            .ok_or_else(|| format!("'{}' is not a governed parameter", name))
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A proposed parameter change, and its votes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Proposal {
+    /// The number of the proposal.
+    pub id: u64,
+    /// The address of the proposing Account.
+    pub proposer: String,
+    /// The parameter to change.
+    pub parameter: Parameter,
+    /// The proposed value of the parameter.
+    pub value: u64,
+    /// The id of the block the proposal was mined in.
+    pub proposed_at: u64,
+    /// Whether each voting Account is in favour, by address.
+    pub votes: BTreeMap<String, bool>,
+    /// Whether the proposal was executed.
+    pub executed: bool,
+}
+
+impl Proposal {
+    /// Returns the last block id votes are accepted in.
+    pub fn voting_ends(&self, config: &GenesisConfig) -> u64 {
+        self.proposed_at.saturating_add(config.voting_period)
+    }
+
+    /// Returns the last block id the proposal can be executed in.
+    pub fn execution_ends(&self, config: &GenesisConfig) -> u64 {
+        self.voting_ends(config)
+            .saturating_add(config.execution_period)
+    }
+
+    /// Returns the weight of the votes in favour, and against, with the weights of the `accounts`.
+    pub fn tally(&self, accounts: &BTreeMap<String, Account>) -> (u64, u64) {
+        let (mut in_favour, mut against) = (0u64, 0u64);
+        for (address, vote) in &self.votes {
+            let weight = accounts
+                .get(address)
+                .map_or(0, |account| account.weight_as_validator());
+            if *vote {
+                in_favour = in_favour.saturating_add(weight);
+            } else {
+                against = against.saturating_add(weight);
+            }
+        }
+        (in_favour, against)
+    }
+}
+
+/// Returns the weight the votes on a proposal must reach: the `quorum_percent` of the weight of all the `accounts`,
+/// rounded up.
+pub fn quorum(accounts: &BTreeMap<String, Account>, config: &GenesisConfig) -> u64 {
+    let total = accounts
+        .values()
+        .map(|account| u128::from(account.weight_as_validator()))
+        .sum::<u128>();
+    let quorum = (total * u128::from(config.quorum_percent)).div_ceil(100);
+    u64::try_from(quorum).unwrap_or(u64::MAX)
+}
+
+/// A parameter change passed by governance.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ParameterChange {
+    /// The first block id the change is active at.
+    pub active_from: u64,
+    /// The changed parameter.
+    pub parameter: Parameter,
+    /// The new value of the parameter.
+    pub value: u64,
+}
+
+/// Every proposal, and every passed parameter change, in order.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct GovernanceState {
+    /// Every proposal, by id.
+    pub proposals: Vec<Proposal>,
+    /// Every passed parameter change, in the order they were executed.
+    pub changes: Vec<ParameterChange>,
+}
+
+impl GovernanceState {
+    /// Creates a state without proposals.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replays the governance transactions of the `chain`.
+    pub fn from_chain(chain: &[FullBlock], config: &GenesisConfig) -> Self {
+        let mut state = Self::new();
+        let mut accounts = BTreeMap::new();
+        for full_block in chain {
+            state.apply_block(full_block, &accounts, config);
+            for account in &full_block.block.data {
+                accounts.insert(account.address.clone(), account.clone());
+            }
+        }
+        state
+    }
+
+    /// Returns the proposal with the given `id`, if any.
+    pub fn proposal(&self, id: u64) -> Option<&Proposal> {
+        self.proposals.get(usize::try_from(id).ok()?)
+    }
+
+    /// Returns the `config`, with the parameter changes active at block `height`.
+    pub fn config_at(&self, height: u64, config: &GenesisConfig) -> GenesisConfig {
+        let mut active = config.clone();
+        for change in self.changes.iter().filter(|c| c.active_from <= height) {
+            change
+                .parameter
+                .set(&mut active, change.value)
+                .expect("passed changes to be valid");
+        }
+        active
+    }
+
+    /// Applies the `transaction` of block `block_id`, if it is a governance transaction.
+    /// Weights are read from the `accounts` before the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction cannot be applied. The state is then unchanged.
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &Transaction,
+        block_id: u64,
+        accounts: &BTreeMap<String, Account>,
+        config: &GenesisConfig,
+    ) -> Result<(), String> {
+        let address = &transaction.address;
+        let is_staked = accounts
+            .get(address)
+            .is_some_and(|account| account.weight_as_validator() > 0);
+        match &transaction.event {
+            Events::Propose(name, value) => {
+                let parameter: Parameter = name.parse()?;
+                if !is_staked {
+                    return Err(format!("'{}' has no stake to propose with", address));
+                }
+                parameter.set(&mut self.config_at(block_id, config), *value)?;
+                self.proposals.push(Proposal {
+                    id: self.proposals.len() as u64,
+                    proposer: address.clone(),
+                    parameter,
+                    value: *value,
+                    proposed_at: block_id,
+                    votes: BTreeMap::new(),
+                    executed: false,
+                });
+            }
+            Events::Vote(id, in_favour) => {
+                if !is_staked {
+                    return Err(format!("'{}' has no stake to vote with", address));
+                }
+                let proposal = self.proposal_mut(*id)?;
+                if block_id > proposal.voting_ends(config) {
+                    return Err(format!("Voting on proposal {} has ended", id));
+                }
+                proposal.votes.insert(address.clone(), *in_favour);
+            }
+            Events::Execute(id) => {
+                let proposal = self.proposal_mut(*id)?;
+                if proposal.executed {
+                    return Err(format!("Proposal {} is already executed", id));
+                }
+                if block_id <= proposal.voting_ends(config) {
+                    return Err(format!("Voting on proposal {} has not ended", id));
+                }
+                if block_id > proposal.execution_ends(config) {
+                    return Err(format!("Execution of proposal {} has expired", id));
+                }
+                let (in_favour, against) = proposal.tally(accounts);
+                let quorum = quorum(accounts, config);
+                if in_favour.saturating_add(against) < quorum {
+                    return Err(format!(
+                        "Proposal {} did not reach the quorum of {}",
+                        id, quorum
+                    ));
+                }
+                if in_favour <= against {
+                    return Err(format!("Proposal {} did not pass", id));
+                }
+                proposal.executed = true;
+                let change = ParameterChange {
+                    active_from: block_id + 1,
+                    parameter: proposal.parameter,
+                    value: proposal.value,
+                };
+                self.changes.push(change);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn proposal_mut(&mut self, id: u64) -> Result<&mut Proposal, String> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|i| self.proposals.get_mut(i))
+            .ok_or_else(|| format!("Proposal {} does not exist", id))
+    }
+
+    /// Applies the governance transactions of the `full_block`, with weights read from the `accounts` before the block.
+    pub fn apply_block(
+        &mut self,
+        full_block: &FullBlock,
+        accounts: &BTreeMap<String, Account>,
+        config: &GenesisConfig,
+    ) {
+        for transaction in &full_block.body.transactions {
+            // Failed transactions are mined without effect
+            let _ = self.apply_transaction(transaction, full_block.block.id, accounts, config);
+        }
+    }
+}
+
+/// Returns the parameters of the `chain` active at block `height`.
+pub fn active_config(chain: &[FullBlock], height: u64, config: &GenesisConfig) -> GenesisConfig {
+    GovernanceState::from_chain(chain, config).config_at(height, config)
+}
+
+/// Returns the config of the `chain` argument, with the parameters active at block `height`.
+///
+/// # Examples
+///
+/// ```js
+/// const { rack_price, block_gas_limit } = get_parameters(chain, 100, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_parameters(chain: JsValue, height: u64, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    Ok(JsValue::from_serde(&active_config(
+        &chain, height, &config,
+    ))?)
+}
+
+/// Returns the proposal with the `id` argument, with its votes, after the last block of the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const { parameter, value, votes, executed } = get_proposal(chain, 0, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the proposal does not exist, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_proposal(chain: JsValue, id: u64, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let state = GovernanceState::from_chain(&chain, &config);
+    let proposal = state
+        .proposal(id)
+        .ok_or_else(|| JsError::new(&format!("Proposal {} does not exist", id)))?;
+    Ok(JsValue::from_serde(proposal)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    #[test]
+    fn passed_proposal_changes_parameter_after_execution() {
+        let config = _fixture_config();
+        let chain = _fixture_chain(vec![
+            vec![_fixture_transaction(
+                "Tom",
+                Events::Propose("rack_price".to_string(), 20),
+            )],
+            vec![
+                _fixture_transaction("Tom", Events::Vote(0, true)),
+                _fixture_transaction("Camper", Events::Vote(0, false)),
+            ],
+            vec![],
+            vec![_fixture_transaction("Camper", Events::Execute(0))],
+        ]);
+        let state = GovernanceState::from_chain(&chain, &config);
+        assert!(state.proposal(0).unwrap().executed);
+        assert_eq!(state.config_at(4, &config).rack_price, 10);
+        assert_eq!(state.config_at(5, &config).rack_price, 20);
+        assert_eq!(active_config(&chain[..4], 5, &config).rack_price, 10);
+    }
+    #[test]
+    fn votes_are_weighted_by_stake() {
+        let config = _fixture_config();
+        let mut state = GovernanceState::new();
+        let accounts = _fixture_accounts();
+        let propose =
+            _fixture_transaction("Camper", Events::Propose("gas_per_token".to_string(), 0));
+        state
+            .apply_transaction(&propose, 1, &accounts, &config)
+            .unwrap();
+        for (address, vote) in [("Camper", true), ("Tom", false), ("Camper", true)] {
+            let vote = _fixture_transaction(address, Events::Vote(0, vote));
+            state
+                .apply_transaction(&vote, 2, &accounts, &config)
+                .unwrap();
+        }
+        assert_eq!(state.proposal(0).unwrap().tally(&accounts), (10, 30));
+        let execute = _fixture_transaction("Camper", Events::Execute(0));
+        assert_eq!(
+            state.apply_transaction(&execute, 4, &accounts, &config),
+            Err("Proposal 0 did not pass".to_string())
+        );
+        assert!(state.changes.is_empty());
+    }
+    #[test]
+    fn invalid_governance_transactions_are_rejected() {
+        let config = _fixture_config();
+        let mut state = GovernanceState::new();
+        let accounts = _fixture_accounts();
+        let apply = |state: &mut GovernanceState, address: &str, event: Events, block_id: u64| {
+            state.apply_transaction(
+                &_fixture_transaction(address, event),
+                block_id,
+                &accounts,
+                &config,
+            )
+        };
+        assert_eq!(
+            apply(
+                &mut state,
+                "Tom",
+                Events::Propose("difficulty_prefix".to_string(), 1),
+                1
+            ),
+            Err("'difficulty_prefix' is not a governed parameter".to_string())
+        );
+        assert!(apply(
+            &mut state,
+            "Tom",
+            Events::Propose("block_gas_limit".to_string(), 1),
+            1
+        )
+        .is_err());
+        assert_eq!(
+            apply(
+                &mut state,
+                "Mallory",
+                Events::Propose("rack_price".to_string(), 1),
+                1
+            ),
+            Err("'Mallory' has no stake to propose with".to_string())
+        );
+        apply(
+            &mut state,
+            "Tom",
+            Events::Propose("rack_price".to_string(), 1),
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            apply(&mut state, "Tom", Events::Execute(0), 3),
+            Err("Voting on proposal 0 has not ended".to_string())
+        );
+        assert_eq!(
+            apply(&mut state, "Tom", Events::Vote(0, true), 4),
+            Err("Voting on proposal 0 has ended".to_string())
+        );
+        assert_eq!(
+            apply(&mut state, "Tom", Events::Vote(1, true), 2),
+            Err("Proposal 1 does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn votes_must_reach_quorum() {
+        let config = _fixture_config();
+        let mut state = GovernanceState::new();
+        let accounts = _fixture_accounts();
+        assert_eq!(quorum(&accounts, &config), 14);
+        for (transaction, block_id) in [
+            (Events::Propose("max_reward".to_string(), 20), 1),
+            (Events::Vote(0, true), 2),
+        ] {
+            state
+                .apply_transaction(
+                    &_fixture_transaction("Camper", transaction),
+                    block_id,
+                    &accounts,
+                    &config,
+                )
+                .unwrap();
+        }
+        let execute = _fixture_transaction("Camper", Events::Execute(0));
+        assert_eq!(
+            state.apply_transaction(&execute, 4, &accounts, &config),
+            Err("Proposal 0 did not reach the quorum of 14".to_string())
+        );
+        let config = GenesisConfig {
+            quorum_percent: 25,
+            ..config
+        };
+        assert_eq!(
+            state.apply_transaction(&execute, 4, &accounts, &config),
+            Ok(())
+        );
+        assert_eq!(state.config_at(5, &config).max_reward, 20);
+    }
+    #[test]
+    fn proposals_expire_after_execution_period() {
+        let config = GenesisConfig {
+            execution_period: 2,
+            .._fixture_config()
+        };
+        let mut state = GovernanceState::new();
+        let accounts = _fixture_accounts();
+        for (transaction, block_id) in [
+            (Events::Propose("target_block_time".to_string(), 20), 1),
+            (Events::Vote(0, true), 2),
+        ] {
+            state
+                .apply_transaction(
+                    &_fixture_transaction("Tom", transaction),
+                    block_id,
+                    &accounts,
+                    &config,
+                )
+                .unwrap();
+        }
+        assert_eq!(state.proposal(0).unwrap().execution_ends(&config), 5);
+        let execute = _fixture_transaction("Tom", Events::Execute(0));
+        assert_eq!(
+            state.apply_transaction(&execute, 6, &accounts, &config),
+            Err("Execution of proposal 0 has expired".to_string())
+        );
+        assert_eq!(
+            state.apply_transaction(&execute, 5, &accounts, &config),
+            Ok(())
+        );
+        assert_eq!(state.config_at(6, &config).target_block_time, 20);
+    }
+
+    fn _fixture_transaction(address: &str, event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: address.to_string(),
+        }
+    }
+
+    /// Votes last 2 blocks.
+    fn _fixture_config() -> GenesisConfig {
+        GenesisConfig {
+            voting_period: 2,
+            ..Default::default()
+        }
+    }
+
+    /// Tom has 30 staked tokens, and Camper 10.
+    fn _fixture_accounts() -> BTreeMap<String, Account> {
+        [("Tom", 30), ("Camper", 10)]
+            .into_iter()
+            .map(|(address, staked)| {
+                let mut account = Account::new(address);
+                account.tokens = 50;
+                account.staked = staked;
+                (address.to_string(), account)
+            })
+            .collect()
+    }
+
+    /// A genesis block holding the accounts, followed by a block for each list of `transactions`.
+    fn _fixture_chain(transactions: Vec<Vec<Transaction>>) -> Vec<FullBlock> {
+        let genesis = vec![vec![]];
+        genesis
+            .into_iter()
+            .chain(transactions)
+            .enumerate()
+            .map(|(id, transactions)| {
+                let mut full_block = FullBlock::from(Block {
+                    id: id as u64,
+                    hash: String::new(),
+                    previous_hash: String::new(),
+                    timestamp: 1000,
+                    data: if id == 0 {
+                        _fixture_accounts().into_values().collect()
+                    } else {
+                        vec![]
+                    },
+                    nonce: 0,
+                    next_miner: "Tom".to_string(),
+                    next_validators: vec![],
+                });
+                full_block.body.transactions = transactions;
+                full_block
+            })
+            .collect()
+    }
+}
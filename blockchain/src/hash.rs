@@ -0,0 +1,319 @@
+//! # Hash
+//!
+//! A block hash is a 32 byte `Sha256` digest.
+//!
+//! The `hash` and `previous_hash` fields of a `Block` encode the digest in one of two formats:
+//! - Hex: `"0x"` followed by 64 hex characters. This is the format of newly mined blocks,
+//!   and the digest is of the canonical encoding returned by `codec::encode_block`.
+//! - Legacy: the variable length binary string returned by `hash_to_binary`. Existing chains use this format, and still validate.
+//!   The digest is the one returned by `calculate_hash`.
+//!
+//! The hex digest of a `FullBlock` also commits to its body, see `codec::encode_body`. Legacy digests cannot, so a legacy
+//! block must have an empty body.
+//!
+//! **Note:** The `hash` fields stay `String`s rather than `BlockHash`es. `hash_to_binary` drops the leading zeros of each
+//! byte, so a legacy hash cannot be decoded back into the 32 bytes of its digest, and existing chains would no longer
+//! deserialise. `HashFormat::of` tells the formats apart, and `verify_block_hash` returns the digest as a `BlockHash`.
+
+use crate::{block::Block, body::FullBlock, calculate_hash, codec, hash_to_binary};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// The prefix of a hex encoded hash.
+pub const HEX_PREFIX: &str = "0x";
+
+/// A fixed size `Sha256` digest, serialised as a `"0x"` prefixed hex string.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    /// Creates a `BlockHash` from a digest slice, such as the one returned by `calculate_hash`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the slice is not 32 bytes long.
+    pub fn from_slice(digest: &[u8]) -> Result<Self, String> {
+        let digest: [u8; 32] = digest
+            .try_into()
+            .map_err(|_| format!("Hash must be 32 bytes, not {}", digest.len()))?;
+        Ok(Self(digest))
+    }
+
+    /// Parses a hex string, with or without the `"0x"` prefix.
+    pub fn from_hex(hash: &str) -> Result<Self, String> {
+        let hash = hash.strip_prefix(HEX_PREFIX).unwrap_or(hash);
+        let digest = hex::decode(hash).map_err(|e| format!("Invalid hex hash: {}", e))?;
+        Self::from_slice(&digest)
+    }
+
+    /// Returns the `"0x"` prefixed hex encoding of the digest.
+    pub fn to_hex(&self) -> String {
+        format!("{}{}", HEX_PREFIX, hex::encode(self.0))
+    }
+
+    /// Returns the legacy binary string encoding of the digest.
+    pub fn to_binary(&self) -> String {
+        hash_to_binary(&self.0)
+    }
+
+    /// Encodes the digest in the given `format`.
+    pub fn encode(&self, format: HashFormat) -> String {
+        match format {
+            HashFormat::Hex => self.to_hex(),
+            HashFormat::Legacy => self.to_binary(),
+        }
+    }
+
+    /// Returns the number of leading zero bits of the digest.
+    pub fn leading_zero_bits(&self) -> u32 {
+        let mut bits = 0;
+        for byte in self.0 {
+            bits += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        bits
+    }
+
+    /// Check if the digest has at least `difficulty` leading zero bits.
+    pub fn meets_difficulty(&self, difficulty: u32) -> bool {
+        self.leading_zero_bits() >= difficulty
+    }
+
+    /// Check if the encoded `hash` is an encoding of this digest, in either format.
+    pub fn matches(&self, hash: &str) -> bool {
+        self.encode(HashFormat::of(hash)) == hash
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl FromStr for BlockHash {
+    type Err = String;
+
+    fn from_str(hash: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(hash)
+    }
+}
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hash = String::deserialize(deserializer)?;
+        Self::from_hex(&hash).map_err(de::Error::custom)
+    }
+}
+
+/// The format a hash is encoded in, within a `Block`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum HashFormat {
+    /// The binary string returned by `hash_to_binary`.
+    Legacy,
+    /// A `"0x"` prefixed hex string.
+    Hex,
+}
+
+impl HashFormat {
+    /// Detects the format of an encoded `hash`.
+    ///
+    /// **Note:** Legacy hashes only contain `0` and `1`, so can never start with `"0x"`.
+    pub fn of(hash: &str) -> Self {
+        if hash.starts_with(HEX_PREFIX) {
+            HashFormat::Hex
+        } else {
+            HashFormat::Legacy
+        }
+    }
+}
+
+/// Calculates the digest of the `block` from its fields, as hashed for the given `format`.
+pub fn block_digest(block: &Block, format: HashFormat) -> BlockHash {
+    let digest = match format {
+        HashFormat::Hex => codec::hash_block(block),
+        HashFormat::Legacy => calculate_hash(
+            &block.data,
+            block.id,
+            &block.next_miner,
+            &block.next_validators,
+            block.nonce,
+            &block.previous_hash,
+            block.timestamp,
+        ),
+    };
+    BlockHash::from_slice(&digest).expect("Sha256 digest to be 32 bytes")
+}
+
+/// Recalculates the digest of the `block`, and checks its `hash` field encodes it.
+///
+/// # Errors
+///
+/// Returns an error if the `hash` field does not match the fields of the `block`.
+pub fn verify_block_hash(block: &Block) -> Result<BlockHash, String> {
+    let format = HashFormat::of(&block.hash);
+    let digest = block_digest(block, format);
+    if digest.encode(format) == block.hash {
+        Ok(digest)
+    } else {
+        Err(format!("Block {} hash is not correct", block.id))
+    }
+}
+
+/// Calculates the digest of the `full_block` from its fields, and the committed fields of its body.
+///
+/// # Errors
+///
+/// Returns an error if the body has no canonical encoding.
+pub fn full_block_digest(full_block: &FullBlock, format: HashFormat) -> Result<BlockHash, String> {
+    let digest = match format {
+        HashFormat::Hex => codec::hash_full_block(full_block).map_err(|e| e.to_string())?,
+        HashFormat::Legacy => return Ok(block_digest(&full_block.block, format)),
+    };
+    BlockHash::from_slice(&digest)
+}
+
+/// Recalculates the digest of the `full_block`, and checks its `hash` field encodes it.
+///
+/// # Errors
+///
+/// Returns an error if the `hash` field does not match the fields and body of the block,
+/// or a legacy block has a body its hash cannot commit to.
+pub fn verify_full_block_hash(full_block: &FullBlock) -> Result<BlockHash, String> {
+    let block = &full_block.block;
+    let format = HashFormat::of(&block.hash);
+    let committed_body = codec::encode_body(&full_block.body).map_err(|e| e.to_string())?;
+    if format == HashFormat::Legacy && !committed_body.is_empty() {
+        return Err(format!(
+            "Block {} hash does not commit to its body",
+            block.id
+        ));
+    }
+    let digest = full_block_digest(full_block, format)?;
+    if digest.encode(format) == block.hash {
+        Ok(digest)
+    } else {
+        Err(format!("Block {} hash is not correct", block.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::{Account, AccountTrait},
+        Events, Transaction,
+    };
+    #[test]
+    fn hex_round_trip() {
+        let hash = BlockHash([7; 32]);
+        let hex = hash.to_hex();
+        assert_eq!(hex.len(), 66);
+        assert!(hex.starts_with("0x0707"));
+        assert_eq!(BlockHash::from_hex(&hex), Ok(hash));
+        assert_eq!(BlockHash::from_hex(&hex[2..]), Ok(hash));
+        assert_eq!(hex.parse::<BlockHash>(), Ok(hash));
+    }
+    #[test]
+    fn invalid_hex_is_rejected() {
+        assert!(BlockHash::from_hex("0x1234").is_err());
+        assert!(BlockHash::from_hex(&"zz".repeat(32)).is_err());
+        assert!(BlockHash::from_slice(&[0; 31]).is_err());
+    }
+    #[test]
+    fn serialises_as_hex_string() {
+        let hash = BlockHash([255; 32]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"0x{}\"", "ff".repeat(32)));
+        let parsed: BlockHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, hash);
+    }
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        let mut digest = [255; 32];
+        assert_eq!(BlockHash(digest).leading_zero_bits(), 0);
+        digest[0] = 0;
+        digest[1] = 0b0001_0000;
+        assert_eq!(BlockHash(digest).leading_zero_bits(), 11);
+        assert!(BlockHash(digest).meets_difficulty(11));
+        assert!(!BlockHash(digest).meets_difficulty(12));
+        assert_eq!(BlockHash([0; 32]).leading_zero_bits(), 256);
+    }
+    #[test]
+    fn format_is_detected() {
+        assert_eq!(HashFormat::of("0x00ff"), HashFormat::Hex);
+        assert_eq!(HashFormat::of("0110"), HashFormat::Legacy);
+        assert_eq!(HashFormat::of(""), HashFormat::Legacy);
+    }
+    #[test]
+    fn legacy_encoding_matches_hash_to_binary() {
+        let digest = calculate_hash(&vec![Account::new("Shaun")], 1, "test", &vec![], 1, "", 1);
+        let hash = BlockHash::from_slice(&digest).unwrap();
+        assert_eq!(hash.to_binary(), hash_to_binary(&digest));
+        assert!(hash.matches(&hash_to_binary(&digest)));
+        assert!(hash.matches(&hash.to_hex()));
+        assert!(!hash.matches("0x00"));
+    }
+    #[test]
+    fn verify_block_hash_accepts_both_formats() {
+        let mut block = _fixture_block();
+
+        let digest = block_digest(&block, HashFormat::Legacy);
+        block.hash = digest.to_binary();
+        assert_eq!(verify_block_hash(&block), Ok(digest));
+
+        let digest = block_digest(&block, HashFormat::Hex);
+        block.hash = digest.to_hex();
+        assert_eq!(verify_block_hash(&block), Ok(digest));
+
+        // The digest of one format is not accepted in the other
+        block.hash = block_digest(&block, HashFormat::Legacy).to_hex();
+        assert!(verify_block_hash(&block).is_err());
+
+        block.nonce += 1;
+        assert!(verify_block_hash(&block).is_err());
+    }
+
+    #[test]
+    fn full_block_hash_commits_to_transactions() {
+        let mut full_block = FullBlock::from(_fixture_block());
+        full_block.block.hash = block_digest(&full_block.block, HashFormat::Hex).to_hex();
+        assert!(verify_full_block_hash(&full_block).is_ok());
+
+        full_block.body.transactions = vec![Transaction {
+            event: Events::Stake,
+            address: "Ahmad".to_string(),
+        }];
+        assert!(verify_full_block_hash(&full_block).is_err());
+        let digest = full_block_digest(&full_block, HashFormat::Hex).unwrap();
+        full_block.block.hash = digest.to_hex();
+        assert_eq!(verify_full_block_hash(&full_block), Ok(digest));
+
+        full_block.block.hash = block_digest(&full_block.block, HashFormat::Legacy).to_binary();
+        assert_eq!(
+            verify_full_block_hash(&full_block),
+            Err("Block 1 hash does not commit to its body".to_string())
+        );
+    }
+
+    fn _fixture_block() -> Block {
+        Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: "0110".to_string(),
+            timestamp: 1650301013,
+            data: vec![Account::new("Ahmad")],
+            nonce: 172,
+            next_miner: "Mrugesh".to_string(),
+            next_validators: vec!["Mrugesh".to_string()],
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! # History
+//!
+//! The account history index maps every address to the blocks which changed its `Account`, in chain order.
+//! It is built once from a chain, then kept up to date by indexing each block as it is mined or synced,
+//! so the activity of an Account is found without walking the chain.
+//!
+//! A node bootstrapped from a state snapshot starts its index at the height of the snapshot, see `AccountIndex::from_pruned`.
+
+use crate::{
+    account::Account,
+    body::{FullBlock, FullChain},
+    snapshot::{PrunedChain, StateSnapshot},
+    Events, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// A change to an `Account`, made by a block.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// The id of the block holding the change.
+    pub block_id: u64,
+    /// The transaction involving the Account, or `None` if the block holds the Account without a transaction involving it,
+    /// such as in the genesis block.
+    pub transaction: Option<Transaction>,
+    /// The Account, with its balances after the block. `None` if the block does not hold the Account.
+    ///
+    /// **Note:** If several transactions of a block involve the Account, each entry holds the balances after the whole block.
+    pub account: Option<Account>,
+}
+
+/// A page of the history of an Account, returned by `get_account_history`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryPage {
+    /// The entries of the page, oldest first.
+    pub entries: Vec<HistoryEntry>,
+    /// The total number of entries of the Account.
+    pub total: usize,
+    /// The `from` argument of the next page, or `None` if this is the last page.
+    pub next: Option<usize>,
+}
+
+/// Returns the addresses a `transaction` changes the Account of: its sender, and the other Account of the event, if any.
+pub fn transaction_addresses(transaction: &Transaction) -> Vec<&str> {
+    let mut addresses = vec![transaction.address.as_str()];
+    match &transaction.event {
+        Events::Transfer(address, _) | Events::Punish(address, _) | Events::Reward(address, _)
+            if *address != transaction.address =>
+        {
+            addresses.push(address)
+        }
+        _ => {}
+    }
+    addresses
+}
+
+/// An index from address to the `HistoryEntry`s of the Account.
+#[wasm_bindgen]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct AccountIndex {
+    entries: HashMap<String, Vec<HistoryEntry>>,
+    /// The id of the last indexed block.
+    last_block_id: Option<u64>,
+}
+
+impl AccountIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an index of every block of the `chain`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blocks are not in order.
+    pub fn from_chain(chain: &[FullBlock]) -> Result<Self, String> {
+        let mut index = Self::new();
+        for full_block in chain {
+            index.index_block(full_block)?;
+        }
+        Ok(index)
+    }
+
+    /// Creates an index starting at the `snapshot`, with an entry for each of its Accounts at the height of the snapshot.
+    ///
+    /// **Note:** The history before the snapshot is not known, so the next indexed block must be the one after it.
+    pub fn from_snapshot(snapshot: &StateSnapshot) -> Self {
+        let mut index = Self::new();
+        for account in &snapshot.accounts {
+            index
+                .entries
+                .entry(account.address.clone())
+                .or_default()
+                .push(HistoryEntry {
+                    block_id: snapshot.block_id,
+                    transaction: None,
+                    account: Some(account.clone()),
+                });
+        }
+        index.last_block_id = Some(snapshot.block_id);
+        index
+    }
+
+    /// Creates an index of the `pruned` chain: its snapshot, then every block after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blocks after the snapshot are not in order.
+    pub fn from_pruned(pruned: &PrunedChain) -> Result<Self, String> {
+        let mut index = Self::from_snapshot(&pruned.snapshot);
+        for full_block in pruned.blocks.iter().skip(1) {
+            index.index_block(full_block)?;
+        }
+        Ok(index)
+    }
+
+    /// Returns the id of the last indexed block, if any.
+    pub fn last_block_id(&self) -> Option<u64> {
+        self.last_block_id
+    }
+
+    /// Adds the changes made by the `full_block` to the index.
+    ///
+    /// Every transaction of the block adds an entry for each of its `transaction_addresses`.
+    /// Every Account of the block not involved in a transaction adds an entry without a transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block does not follow the last indexed block, or the index is empty and the block is not
+    /// the genesis block.
+    pub fn index_block(&mut self, full_block: &FullBlock) -> Result<(), String> {
+        let block = &full_block.block;
+        let expected_id = self.last_block_id.map_or(0, |id| id + 1);
+        if block.id != expected_id {
+            return Err(format!(
+                "Block {} does not follow the indexed blocks",
+                block.id
+            ));
+        }
+        let account_of = |address: &str| {
+            block
+                .data
+                .iter()
+                .rev()
+                .find(|account| account.address == address)
+                .cloned()
+        };
+
+        let mut involved: Vec<&str> = vec![];
+        for transaction in &full_block.body.transactions {
+            for address in transaction_addresses(transaction) {
+                self.entries
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(HistoryEntry {
+                        block_id: block.id,
+                        transaction: Some(transaction.clone()),
+                        account: account_of(address),
+                    });
+                involved.push(address);
+            }
+        }
+        for account in &block.data {
+            if involved.contains(&account.address.as_str()) {
+                continue;
+            }
+            involved.push(&account.address);
+            self.entries
+                .entry(account.address.clone())
+                .or_default()
+                .push(HistoryEntry {
+                    block_id: block.id,
+                    transaction: None,
+                    account: account_of(&account.address),
+                });
+        }
+        self.last_block_id = Some(block.id);
+        Ok(())
+    }
+
+    /// Returns up to `limit` entries of the history of the Account with the given `address`, starting at entry `from`.
+    ///
+    /// **Note:** An unknown address has an empty history.
+    pub fn get_account_history(&self, address: &str, from: usize, limit: usize) -> HistoryPage {
+        let entries = self
+            .entries
+            .get(address)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let total = entries.len();
+        let from = from.min(total);
+        let to = from.saturating_add(limit).min(total);
+        HistoryPage {
+            entries: entries[from..to].to_vec(),
+            total,
+            next: if to < total { Some(to) } else { None },
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl AccountIndex {
+    /// Creates an index of every block of the `chain` argument.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const index = new AccountIndex(chain);
+    /// index.index_block(newBlock);
+    /// const page = index.get_account_history("Tom", 0, 20);
+    /// const nextPage = index.get_account_history("Tom", page.next, 20);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If `chain` argument is not deserialisable into type `FullChain`, or its blocks are not in order, a `JsError` is thrown.
+    #[wasm_bindgen(constructor)]
+    pub fn from_js(chain: JsValue) -> Result<AccountIndex, JsError> {
+        let chain: FullChain = chain.into_serde()?;
+        Self::from_chain(&chain).map_err(|e| JsError::new(&e))
+    }
+
+    /// Creates an index of the `pruned` argument, a chain pruned to a state snapshot, such as the one returned by `prune_chain`.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const index = AccountIndex.from_pruned(bootstrap_from_snapshot(snapshot, trustedHash, headers, blocks, config));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If `pruned` argument is not deserialisable into type `PrunedChain`, or its blocks are not in order, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = from_pruned)]
+    pub fn from_pruned_js(pruned: JsValue) -> Result<AccountIndex, JsError> {
+        let pruned: PrunedChain = pruned.into_serde()?;
+        Self::from_pruned(&pruned).map_err(|e| JsError::new(&e))
+    }
+
+    /// Adds the changes made by the `block` argument to the index.
+    ///
+    /// # Errors
+    ///
+    /// If `block` argument is not deserialisable, or does not follow the last indexed block, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = index_block)]
+    pub fn index_block_js(&mut self, block: JsValue) -> Result<(), JsError> {
+        let block: FullBlock = block.into_serde()?;
+        self.index_block(&block).map_err(|e| JsError::new(&e))
+    }
+
+    /// Returns up to `limit` entries of the history of the Account with the given `address`, starting at entry `from`.
+    #[wasm_bindgen(js_name = get_account_history)]
+    pub fn get_account_history_js(
+        &self,
+        address: String,
+        from: u32,
+        limit: u32,
+    ) -> Result<JsValue, JsError> {
+        let page = self.get_account_history(&address, from as usize, limit as usize);
+        Ok(JsValue::from_serde(&page)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{account::AccountTrait, block::Block, body::headers, snapshot::latest_accounts};
+    #[test]
+    fn index_records_transactions_and_balances() {
+        let index = AccountIndex::from_chain(&_fixture_chain()).unwrap();
+        assert_eq!(index.last_block_id(), Some(2));
+
+        let page = index.get_account_history("Tom", 0, 10);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.next, None);
+        let block_ids: Vec<u64> = page.entries.iter().map(|e| e.block_id).collect();
+        assert_eq!(block_ids, vec![0, 1, 2]);
+        // Genesis holds Tom without a transaction
+        assert_eq!(page.entries[0].transaction, None);
+        // Tom received a transfer from Camper
+        assert_eq!(
+            page.entries[1].transaction.as_ref().unwrap().address,
+            "Camper"
+        );
+        assert_eq!(page.entries[1].account.as_ref().unwrap().tokens, 25);
+        assert_eq!(page.entries[2].account.as_ref().unwrap().staked, 5);
+
+        let camper = index.get_account_history("Camper", 0, 10);
+        assert_eq!(camper.total, 2);
+        assert_eq!(camper.entries[1].account.as_ref().unwrap().tokens, 15);
+    }
+    #[test]
+    fn history_is_paginated() {
+        let index = AccountIndex::from_chain(&_fixture_chain()).unwrap();
+        let first = index.get_account_history("Tom", 0, 2);
+        assert_eq!(first.entries.len(), 2);
+        assert_eq!(first.next, Some(2));
+        let second = index.get_account_history("Tom", first.next.unwrap(), 2);
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].block_id, 2);
+        assert_eq!(second.next, None);
+
+        assert!(index.get_account_history("Tom", 10, 2).entries.is_empty());
+        assert_eq!(index.get_account_history("Mallory", 0, 2).total, 0);
+    }
+    #[test]
+    fn blocks_are_indexed_in_order() {
+        let chain = _fixture_chain();
+        let mut index = AccountIndex::from_chain(&chain[..1]).unwrap();
+        assert_eq!(
+            index.index_block(&chain[2]),
+            Err("Block 2 does not follow the indexed blocks".to_string())
+        );
+        index.index_block(&chain[1]).unwrap();
+        index.index_block(&chain[2]).unwrap();
+        assert_eq!(index, AccountIndex::from_chain(&chain).unwrap());
+    }
+    #[test]
+    fn index_starts_at_snapshot_height() {
+        let chain = _fixture_chain();
+        let snapshot = StateSnapshot {
+            block_id: 1,
+            accounts: latest_accounts(&chain[..2]),
+            ..Default::default()
+        };
+        let pruned = PrunedChain {
+            headers: headers(&chain[..1]),
+            snapshot,
+            blocks: chain[1..].to_vec(),
+        };
+        let index = AccountIndex::from_pruned(&pruned).unwrap();
+        assert_eq!(index.last_block_id(), Some(2));
+
+        let page = index.get_account_history("Tom", 0, 10);
+        let block_ids: Vec<u64> = page.entries.iter().map(|e| e.block_id).collect();
+        assert_eq!(block_ids, vec![1, 2]);
+        assert_eq!(page.entries[0].transaction, None);
+        assert_eq!(page.entries[0].account.as_ref().unwrap().tokens, 25);
+        assert_eq!(page.entries[1].account.as_ref().unwrap().staked, 5);
+
+        let mut index = AccountIndex::from_snapshot(&pruned.snapshot);
+        assert_eq!(
+            index.index_block(&chain[1]),
+            Err("Block 1 does not follow the indexed blocks".to_string())
+        );
+        assert_eq!(index.index_block(&chain[2]), Ok(()));
+    }
+    #[test]
+    fn transaction_addresses_include_counterparty() {
+        let transfer = _fixture_transaction("Camper", Events::Transfer("Tom".to_string(), 5));
+        assert_eq!(transaction_addresses(&transfer), vec!["Camper", "Tom"]);
+        let stake = _fixture_transaction("Tom", Events::Stake);
+        assert_eq!(transaction_addresses(&stake), vec!["Tom"]);
+    }
+
+    fn _fixture_transaction(address: &str, event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: address.to_string(),
+        }
+    }
+    fn _fixture_block(id: u64, data: Vec<Account>, transactions: Vec<Transaction>) -> FullBlock {
+        let mut full_block = FullBlock::from(Block {
+            id,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 1000 + id * 10,
+            data,
+            nonce: 0,
+            next_miner: "Camper".to_string(),
+            next_validators: vec![],
+        });
+        full_block.body.transactions = transactions;
+        full_block
+    }
+    /// Camper transfers 5 tokens to Tom, then Tom stakes 5 tokens.
+    fn _fixture_chain() -> FullChain {
+        let genesis = _fixture_block(0, vec![Account::new("Camper"), Account::new("Tom")], vec![]);
+
+        let mut camper = Account::new("Camper");
+        camper.tokens = 15;
+        let mut tom = Account::new("Tom");
+        tom.tokens = 25;
+        let transfer = _fixture_transaction("Camper", Events::Transfer("Tom".to_string(), 5));
+        let block_1 = _fixture_block(1, vec![camper, tom.clone()], vec![transfer]);
+
+        tom.staked = 5;
+        let stake = _fixture_transaction("Tom", Events::Stake);
+        let block_2 = _fixture_block(2, vec![tom], vec![stake]);
+        vec![genesis, block_1, block_2]
+    }
+}
@@ -0,0 +1,422 @@
+//! # Keychain
+//!
+//! A keychain derives any number of `Wallet`s from a single BIP-39 mnemonic, so a fleet of Accounts is backed up,
+//! and regenerated, with one seed phrase.
+//!
+//! The seed of the mnemonic is the root of a SLIP-10 tree of Ed25519 keys. The wallet of Account `i` is at the path
+//! `m/44'/1'/i'/0'/0'`, where `1` is the SLIP-44 coin type of test networks.
+//!
+//! **Note:** Ed25519 keys only have hardened children, so every index of a `DerivationPath` is hardened.
+
+use crate::{address::Address, wallet::Wallet};
+use bip39::Mnemonic;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha512;
+use std::{fmt, str::FromStr};
+use wasm_bindgen::prelude::*;
+
+/// The SLIP-44 coin type of the wallets of a keychain.
+pub const COIN_TYPE: u32 = 1;
+
+/// The offset of hardened indices.
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// The HMAC key deriving the master key from a seed, as defined by SLIP-10 for Ed25519.
+const MASTER_KEY: &[u8] = b"ed25519 seed";
+
+/// A path of hardened indices from the master key to a child key, such as `m/44'/1'/0'/0'/0'`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// Returns the path of the wallet of Account `index`: `m/44'/1'/index'/0'/0'`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `index` is not below `HARDENED`, as it is hardened by the path.
+    pub fn account(index: u32) -> Result<Self, String> {
+        if index >= HARDENED {
+            return Err(format!("Account index '{}' is not valid", index));
+        }
+        Ok(Self(
+            [44, COIN_TYPE, index, 0, 0]
+                .into_iter()
+                .map(|i| i | HARDENED)
+                .collect(),
+        ))
+    }
+
+    /// Returns the indices of the path, with their hardened offset.
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = String;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut segments = path.split('/');
+        if segments.next() != Some("m") {
+            return Err(format!(
+                "Derivation path '{}' does not start with 'm'",
+                path
+            ));
+        }
+        segments
+            .map(|segment| {
+                let index = segment
+                    .strip_suffix('\'')
+                    .or_else(|| segment.strip_suffix('H'))
+                    .ok_or_else(|| format!("Index '{}' is not hardened", segment))?;
+                match index.parse::<u32>() {
+                    Ok(index) if index < HARDENED => Ok(index + HARDENED),
+                    _ => Err(format!("Index '{}' is not valid", segment)),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("m")?;
+        for index in &self.0 {
+            write!(f, "/{}'", index - HARDENED)?;
+        }
+        Ok(())
+    }
+}
+
+/// A SLIP-10 Ed25519 key, with the chain code its children are derived with.
+#[derive(Clone)]
+pub struct ExtendedKey {
+    /// The Ed25519 secret key.
+    pub secret_key: [u8; 32],
+    /// The chain code.
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// Returns the master key of the `seed`.
+    pub fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(MASTER_KEY, &[seed])
+    }
+
+    /// Returns the hardened child at `index`, which includes the `HARDENED` offset.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index is not hardened.
+    pub fn child(&self, index: u32) -> Result<Self, String> {
+        if index < HARDENED {
+            return Err(format!("Index {} is not hardened", index));
+        }
+        Ok(Self::from_hmac(
+            &self.chain_code,
+            &[&[0], &self.secret_key, &index.to_be_bytes()],
+        ))
+    }
+
+    /// Returns the key at the `path` from the master key of the `seed`.
+    pub fn derive(seed: &[u8], path: &DerivationPath) -> Self {
+        path.indices()
+            .iter()
+            .fold(Self::master(seed), |key, index| {
+                key.child(*index).expect("derivation paths to be hardened")
+            })
+    }
+
+    /// Returns the wallet of the key.
+    pub fn wallet(&self) -> Wallet {
+        Wallet::from_secret_key(&self.secret_key)
+    }
+
+    /// Splits the HMAC-SHA512 of the `data` into a secret key, and a chain code.
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC to take keys of any size");
+        for data in data {
+            mac.update(data);
+        }
+        let output = mac.finalize().into_bytes();
+        let (secret_key, chain_code) = output.split_at(32);
+        Self {
+            secret_key: secret_key.try_into().expect("HMAC-SHA512 to be 64 bytes"),
+            chain_code: chain_code.try_into().expect("HMAC-SHA512 to be 64 bytes"),
+        }
+    }
+}
+
+/// The wallets derived from a mnemonic.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct Keychain {
+    mnemonic: Mnemonic,
+    seed: [u8; 64],
+}
+
+impl Keychain {
+    /// Generates a keychain from a random mnemonic of `word_count` words.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the word count is not 12, 15, 18, 21, or 24.
+    pub fn generate(word_count: usize) -> Result<Self, String> {
+        if !(12..=24).contains(&word_count) || word_count.checked_rem(3) != Some(0) {
+            return Err("Mnemonic must have 12, 15, 18, 21, or 24 words".to_string());
+        }
+        let mut entropy = vec![0u8; word_count * 4 / 3];
+        OsRng.fill_bytes(&mut entropy);
+        let mnemonic = Mnemonic::from_entropy(&entropy).map_err(|e| e.to_string())?;
+        Ok(Self::from_mnemonic(mnemonic, ""))
+    }
+
+    /// Restores the keychain of the mnemonic `phrase`, protected by the optional `passphrase`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the phrase is not a BIP-39 English mnemonic, or its checksum is not correct.
+    pub fn from_phrase(phrase: &str, passphrase: &str) -> Result<Self, String> {
+        let mnemonic = Mnemonic::parse(phrase).map_err(|e| format!("Invalid mnemonic: {}", e))?;
+        Ok(Self::from_mnemonic(mnemonic, passphrase))
+    }
+
+    fn from_mnemonic(mnemonic: Mnemonic, passphrase: &str) -> Self {
+        let seed = mnemonic.to_seed(passphrase);
+        Self { mnemonic, seed }
+    }
+
+    /// Returns the mnemonic phrase of the keychain.
+    ///
+    /// **Note:** The phrase, and the passphrase, regenerate every wallet of the keychain.
+    pub fn phrase(&self) -> String {
+        self.mnemonic.to_string()
+    }
+
+    /// Returns the seed of the keychain.
+    pub fn seed(&self) -> &[u8; 64] {
+        &self.seed
+    }
+
+    /// Returns the wallet at the `path`.
+    pub fn derive(&self, path: &DerivationPath) -> Wallet {
+        ExtendedKey::derive(&self.seed, path).wallet()
+    }
+
+    /// Returns the wallet of Account `index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `index` is not below `HARDENED`.
+    pub fn wallet(&self, index: u32) -> Result<Wallet, String> {
+        Ok(self.derive(&DerivationPath::account(index)?))
+    }
+
+    /// Returns the addresses of the first `count` Accounts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `count` is above `HARDENED`.
+    pub fn addresses(&self, count: u32) -> Result<Vec<Address>, String> {
+        (0..count)
+            .map(|index| self.wallet(index).map(|wallet| wallet.address()))
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl Keychain {
+    /// Restores the keychain of the mnemonic `phrase` argument, protected by the `passphrase` argument.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const keychain = new Keychain(phrase, "");
+    /// const wallets = [0, 1, 2].map((i) => keychain.wallet(i));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the phrase is not a valid mnemonic, a `JsError` is thrown.
+    #[wasm_bindgen(constructor)]
+    pub fn from_js(phrase: String, passphrase: String) -> Result<Keychain, JsError> {
+        Self::from_phrase(&phrase, &passphrase).map_err(|e| JsError::new(&e))
+    }
+
+    /// Generates a keychain from a random mnemonic of `word_count` words.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const keychain = Keychain.generate(24);
+    /// console.log(keychain.phrase());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the word count is not 12, 15, 18, 21, or 24, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = generate)]
+    pub fn generate_js(word_count: u32) -> Result<Keychain, JsError> {
+        Self::generate(word_count as usize).map_err(|e| JsError::new(&e))
+    }
+
+    /// Returns the mnemonic phrase of the keychain.
+    #[wasm_bindgen(js_name = phrase)]
+    pub fn phrase_js(&self) -> String {
+        self.phrase()
+    }
+
+    /// Returns the wallet at the `path` argument, such as `"m/44'/1'/0'/0'/0'"`.
+    ///
+    /// # Errors
+    ///
+    /// If the path is not a path of hardened indices, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = derive)]
+    pub fn derive_js(&self, path: String) -> Result<Wallet, JsError> {
+        let path = DerivationPath::from_str(&path).map_err(|e| JsError::new(&e))?;
+        Ok(self.derive(&path))
+    }
+
+    /// Returns the wallet of Account `index`.
+    ///
+    /// # Errors
+    ///
+    /// If the index is not below `2 ** 31`, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = wallet)]
+    pub fn wallet_js(&self, index: u32) -> Result<Wallet, JsError> {
+        self.wallet(index).map_err(|e| JsError::new(&e))
+    }
+
+    /// Returns the addresses of the first `count` Accounts.
+    ///
+    /// # Errors
+    ///
+    /// If the count is above `2 ** 31`, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = addresses)]
+    pub fn addresses_js(&self, count: u32) -> Result<JsValue, JsError> {
+        let addresses = self.addresses(count).map_err(|e| JsError::new(&e))?;
+        Ok(JsValue::from_serde(&addresses)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn bip39_test_vectors() {
+        // Vectors of the BIP-39 reference implementation, with the passphrase "TREZOR"
+        let keychain = Keychain::from_phrase(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(keychain.seed()),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        let keychain = Keychain::from_phrase(
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(keychain.seed()),
+            "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607"
+        );
+        // The last word holds the checksum
+        assert!(Keychain::from_phrase(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon",
+            "",
+        )
+        .is_err());
+    }
+    #[test]
+    fn slip10_test_vector() {
+        // Test vector 1 for Ed25519 of SLIP-10: (path, chain code, secret key, public key)
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        for (path, chain_code, secret_key, public_key) in [
+            (
+                "m",
+                "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+                "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+                "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+            ),
+            (
+                "m/0H",
+                "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+                "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+                "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+            ),
+            (
+                "m/0H/1H",
+                "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+                "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+                "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+            ),
+            (
+                "m/0H/1H/2H",
+                "2e69929e00b5ab250f49c3fb1c12f252de4fed2c1db88387094a0f8c4c9ccd6c",
+                "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9",
+                "ae98736566d30ed0e9d2f4486a64bc95740d89c7db33f52121f8ea8f76ff0fc1",
+            ),
+            (
+                "m/0H/1H/2H/2H",
+                "8f6d87f93d750e0efccda017d662a1b31a266e4a6f5993b15f5c1f07f74dd5cc",
+                "30d1dc7e5fc04c31219ab25a27ae00b50f6fd66622f6e9c913253d6511d1e662",
+                "8abae2d66361c879b900d204ad2cc4984fa2aa344dd7ddc46007329ac76c429c",
+            ),
+            (
+                "m/0H/1H/2H/2H/1000000000H",
+                "68789923a0cac2cd5a29172a475fe9e0fb14cd6adb5ad98a3fa70333e7afa230",
+                "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+                "3c24da049451555d51a7014a37337aa4e12d41e485abccfa46b47dfb2af54b7a",
+            ),
+        ] {
+            let key = ExtendedKey::derive(&seed, &path.parse().unwrap());
+            assert_eq!(hex::encode(key.chain_code), chain_code, "{}", path);
+            assert_eq!(hex::encode(key.secret_key), secret_key, "{}", path);
+            assert_eq!(
+                hex::encode(key.wallet().public_key().as_bytes()),
+                public_key,
+                "{}",
+                path
+            );
+        }
+    }
+    #[test]
+    fn keychain_regenerates_wallets() {
+        let keychain = Keychain::generate(24).unwrap();
+        assert_eq!(keychain.phrase().split(' ').count(), 24);
+        let restored = Keychain::from_phrase(&keychain.phrase(), "").unwrap();
+        assert_eq!(restored.addresses(3), keychain.addresses(3));
+
+        let addresses = keychain.addresses(3).unwrap();
+        assert_ne!(addresses[0], addresses[1]);
+        assert_eq!(keychain.wallet(1).unwrap().address(), addresses[1]);
+        // A passphrase derives other wallets
+        let protected = Keychain::from_phrase(&keychain.phrase(), "secret").unwrap();
+        assert_ne!(protected.addresses(1), keychain.addresses(1));
+        assert!(Keychain::generate(13).is_err());
+    }
+    #[test]
+    fn derivation_paths_are_hardened() {
+        let path = DerivationPath::account(7).unwrap();
+        assert_eq!(path.to_string(), "m/44'/1'/7'/0'/0'");
+        assert_eq!(path.to_string().parse(), Ok(path));
+        // An index at or above the hardened offset would alias another Account
+        assert_eq!(
+            DerivationPath::account(HARDENED),
+            Err("Account index '2147483648' is not valid".to_string())
+        );
+        assert!(DerivationPath::account(HARDENED - 1).is_ok());
+        assert_eq!(
+            "m/44'/1".parse::<DerivationPath>(),
+            Err("Index '1' is not hardened".to_string())
+        );
+        assert!("44'/1'".parse::<DerivationPath>().is_err());
+        assert!("m/2147483648'".parse::<DerivationPath>().is_err());
+        assert!(ExtendedKey::master(&[0; 16]).child(1).is_err());
+    }
+}
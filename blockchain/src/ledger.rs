@@ -0,0 +1,542 @@
+//! # Ledger
+//!
+//! The `data` of a block holds the `Account`s its transactions change. Besides the events of the transactions, tokens move
+//! by the fee of each transaction, see `gas`, and by the transfers of contract calls, see `contract`.
+//!
+//! Senders may only spend the tokens which are neither staked, nor locked by a vesting schedule. When a block is replayed,
+//! see `receipt::apply_block`, each transaction spends from the Accounts as the transactions before it left them:
+//! `spend_transaction` charges its fee and the tokens of its event, then `apply_transfers` makes the transfers of its contract call.
+//!
+//! From the protocol version with spending checks, the `data` of a block must be exactly the Accounts its transactions change,
+//! as the replay leaves them, in the order they are first changed. See `changed_accounts` and `validate_data`.
+
+use crate::{
+    account::Account,
+    body::FullBlock,
+    gas::charge_fee,
+    genesis::GenesisConfig,
+    receipt::{Receipt, StateChange},
+    vm::TokenTransfer,
+    Events, Transaction,
+};
+use std::collections::BTreeMap;
+
+/// Applies the tokens moved by the event of the `transaction` to the `accounts`, mined in block `height`.
+///
+/// Transfers, stakes, and rack purchases are checked with `GenesisConfig::can_transfer`, `can_stake`, and `can_buy_rack`.
+/// A `Reward` may give at most `max_reward` tokens, and a `Punish` takes at most the tokens of the punished Account.
+/// Rewards and transfers to an address without an Account give no tokens.
+///
+/// **Note:** The number of tokens a `Stake` stakes is not part of the event, so the staked tokens are left unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the sender cannot spend the tokens of the event. The `accounts` are then unchanged.
+pub fn apply_spending(
+    accounts: &mut BTreeMap<String, Account>,
+    transaction: &Transaction,
+    height: u64,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let address = &transaction.address;
+    let sender = accounts.get(address);
+    match &transaction.event {
+        Events::Transfer(to, amount) => {
+            if !sender.is_some_and(|account| config.can_transfer(account, amount, height)) {
+                return Err(format!("'{}' cannot transfer {} tokens", address, amount));
+            }
+            move_tokens(accounts, Some(address), Some(to), *amount)
+        }
+        Events::Stake if !sender.is_some_and(|account| config.can_stake(account, height)) => {
+            Err(format!("'{}' has no spendable tokens to stake", address))
+        }
+        Events::BuyRack => {
+            if !sender.is_some_and(|account| config.can_buy_rack(account, height)) {
+                return Err(format!("'{}' cannot afford a rack", address));
+            }
+            move_tokens(accounts, Some(address), None, config.rack_price)
+        }
+        Events::Reward(to, amount) => {
+            if *amount > config.max_reward {
+                return Err(format!(
+                    "Reward of {} tokens is above the limit of {}",
+                    amount, config.max_reward
+                ));
+            }
+            move_tokens(accounts, None, Some(to), *amount)
+        }
+        Events::Punish(to, amount) => {
+            let held = accounts.get(to).map_or(0, |account| account.tokens);
+            move_tokens(accounts, Some(to), None, held.min(*amount))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Takes `amount` tokens from the Account at `from`, and gives them to the Account at `to`, if they exist.
+///
+/// # Errors
+///
+/// Returns an error if the Account at `from` holds fewer than `amount` tokens. The `accounts` are then unchanged.
+fn move_tokens(
+    accounts: &mut BTreeMap<String, Account>,
+    from: Option<&String>,
+    to: Option<&String>,
+    amount: u64,
+) -> Result<(), String> {
+    if let Some(account) = from.and_then(|from| accounts.get_mut(from)) {
+        account.tokens = account
+            .tokens
+            .checked_sub(amount)
+            .ok_or_else(|| format!("'{}' holds fewer than {} tokens", account.address, amount))?;
+    }
+    if let Some(account) = to.and_then(|to| accounts.get_mut(to)) {
+        account.tokens = account.tokens.saturating_add(amount);
+    }
+    Ok(())
+}
+
+/// Charges the `fee` of the `transaction` to its sender, then spends the tokens of its event with `apply_spending`, from
+/// the `accounts` as the transactions before it in block `height` left them.
+///
+/// An `AddAccount` transaction creates the Account of its sender first, holding the `starting_tokens`.
+///
+/// # Errors
+///
+/// Returns an error if the sender cannot pay the fee, or spend the tokens of the event. The `accounts` may then be partly changed.
+pub fn spend_transaction(
+    accounts: &mut BTreeMap<String, Account>,
+    transaction: &Transaction,
+    fee: u64,
+    height: u64,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let address = &transaction.address;
+    if matches!(transaction.event, Events::AddAccount) && !accounts.contains_key(address) {
+        accounts.insert(address.clone(), config.new_account(address));
+    }
+    match accounts.get_mut(address) {
+        Some(account) => charge_fee(account, fee, height, config)?,
+        None if fee > 0 => return Err(format!("'{}' has no Account", address)),
+        None => {}
+    }
+    apply_spending(accounts, transaction, height, config)
+}
+
+/// Moves the tokens of the contract `transfers` from the spendable tokens of the `sender` at block `height`, as the
+/// transactions before them, and the fee of their call, left them. An address without an Account is credited from none.
+///
+/// # Errors
+///
+/// Returns an error if the transfers are above the spendable tokens of the sender. The `accounts` are then unchanged.
+pub fn apply_transfers(
+    accounts: &mut BTreeMap<String, Account>,
+    sender: &String,
+    transfers: &[TokenTransfer],
+    height: u64,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    let total = transfers.iter().fold(0u64, |total, transfer| {
+        total.saturating_add(transfer.amount)
+    });
+    let spendable = accounts
+        .get(sender)
+        .map_or(0, |account| config.spendable_tokens(account, height));
+    if total > spendable {
+        return Err(format!(
+            "Transfers of {} tokens are above the spendable tokens of '{}'",
+            total, sender
+        ));
+    }
+    for transfer in transfers {
+        accounts
+            .entry(transfer.to.clone())
+            .or_insert_with(|| Account {
+                address: transfer.to.clone(),
+                staked: 0,
+                tokens: 0,
+            });
+        move_tokens(accounts, Some(sender), Some(&transfer.to), transfer.amount)?;
+    }
+    Ok(())
+}
+
+/// Returns the Accounts the transactions of the `full_block` change, as held in the `accounts` after it, in the order
+/// they are first changed: the sender of each transaction, the Account named by its event, then the recipients of its
+/// contract transfers.
+///
+/// **Note:** An address without an Account after the block, such as the recipient of a `Transfer` to no Account, is left out.
+pub fn changed_accounts(
+    full_block: &FullBlock,
+    receipts: &[Receipt],
+    accounts: &BTreeMap<String, Account>,
+) -> Vec<Account> {
+    let mut changed: Vec<&str> = vec![];
+    for (transaction, receipt) in full_block.body.transactions.iter().zip(receipts) {
+        changed.push(&transaction.address);
+        if let Events::Transfer(to, _) | Events::Reward(to, _) | Events::Punish(to, _) =
+            &transaction.event
+        {
+            changed.push(to);
+        }
+        for change in &receipt.state_changes {
+            if let StateChange::TokensTransferred { to, .. } = change {
+                changed.push(to);
+            }
+        }
+    }
+    let mut data: Vec<Account> = vec![];
+    for address in changed {
+        if data.iter().all(|account| account.address != address) {
+            data.extend(accounts.get(address).cloned());
+        }
+    }
+    data
+}
+
+/// Checks the `data` of the `full_block` is exactly the Accounts its transactions change, see `changed_accounts`, given
+/// the `receipts` of its transactions, and the `accounts` after it.
+///
+/// # Errors
+///
+/// Returns an error naming the block, and the first Account which is missing, extra, or holds other tokens.
+pub fn validate_data(
+    full_block: &FullBlock,
+    receipts: &[Receipt],
+    accounts: &BTreeMap<String, Account>,
+) -> Result<(), String> {
+    let id = full_block.block.id;
+    let data = &full_block.block.data;
+    let expected = changed_accounts(full_block, receipts, accounts);
+    let Some(position) = (0..data.len().max(expected.len()))
+        .find(|&position| data.get(position) != expected.get(position))
+    else {
+        return Ok(());
+    };
+    let Some(expected) = expected.get(position) else {
+        return Err(format!(
+            "Block {} holds the Account of '{}', which its transactions do not change",
+            id, data[position].address
+        ));
+    };
+    match data.get(position) {
+        Some(held) if held.address == expected.address && held.tokens != expected.tokens => {
+            Err(format!(
+                "Block {} holds {} tokens for '{}', but its transactions leave {}",
+                id, held.tokens, expected.address, expected.tokens
+            ))
+        }
+        Some(held) if held.address == expected.address => Err(format!(
+            "Block {} holds {} staked tokens for '{}', but its transactions leave {}",
+            id, held.staked, expected.address, expected.staked
+        )),
+        _ => Err(format!(
+            "Block {} does not hold the Account of '{}' where its transactions change it",
+            id, expected.address
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait, block::Block, hash::BlockHash, receipt::ReceiptStatus,
+        vesting::VestingSchedule,
+    };
+    #[test]
+    fn data_must_be_the_replayed_accounts() {
+        let config = GenesisConfig::default();
+        let transfer = _fixture_transaction(Events::Transfer("Camper".to_string(), 5));
+        let receipts = [_fixture_receipt(1)];
+        let check = |tokens| {
+            let full_block = _fixture_block(vec![transfer.clone()], tokens);
+            let spent = _fixture_spend(&full_block, &receipts, &_fixture_accounts(), &config)?;
+            validate_data(&full_block, &receipts, &spent)
+        };
+        assert_eq!(check(vec![("Tom", 14), ("Camper", 25)]), Ok(()));
+        assert_eq!(
+            check(vec![("Tom", 14), ("Camper", 1_000_000)]),
+            Err(
+                "Block 1 holds 1000000 tokens for 'Camper', but its transactions leave 25"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(vec![("Tom", 15), ("Camper", 25)]),
+            Err("Block 1 holds 15 tokens for 'Tom', but its transactions leave 14".to_string())
+        );
+        assert_eq!(
+            check(vec![("Tom", 14)]),
+            Err(
+                "Block 1 does not hold the Account of 'Camper' where its transactions change it"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(vec![("Camper", 25), ("Tom", 14)]),
+            Err(
+                "Block 1 does not hold the Account of 'Tom' where its transactions change it"
+                    .to_string()
+            )
+        );
+    }
+    #[test]
+    fn untouched_accounts_cannot_be_rewritten() {
+        let config = GenesisConfig::default();
+        let mut accounts = _fixture_accounts();
+        accounts.insert("Shaun".to_string(), Account::new("Shaun"));
+        let update = _fixture_transaction(Events::UpdateChain);
+        let receipts = [_fixture_receipt(1)];
+        let full_block = _fixture_block(vec![update.clone()], vec![("Tom", 19), ("Shaun", 20)]);
+        let spent = _fixture_spend(&full_block, &receipts, &accounts, &config).unwrap();
+        assert_eq!(
+            validate_data(&full_block, &receipts, &spent),
+            Err(
+                "Block 1 holds the Account of 'Shaun', which its transactions do not change"
+                    .to_string()
+            )
+        );
+        // Listing an Account twice is no different
+        let full_block = _fixture_block(vec![update], vec![("Tom", 19), ("Tom", 19)]);
+        assert!(validate_data(&full_block, &receipts, &spent).is_err());
+    }
+    #[test]
+    fn contract_transfers_credit_new_addresses() {
+        let config = GenesisConfig::default();
+        let call = _fixture_transaction(Events::CallContract("fcc1".to_string(), vec![]));
+        let mut receipt = _fixture_receipt(1);
+        receipt.state_changes = vec![StateChange::TokensTransferred {
+            from: "Tom".to_string(),
+            to: "Shaun".to_string(),
+            amount: 3,
+        }];
+        let receipts = [receipt];
+        let full_block = _fixture_block(vec![call], vec![("Tom", 16), ("Shaun", 3)]);
+        let spent = _fixture_spend(&full_block, &receipts, &_fixture_accounts(), &config).unwrap();
+        assert_eq!(spent["Shaun"].tokens, 3);
+        assert_eq!(validate_data(&full_block, &receipts, &spent), Ok(()));
+    }
+    #[test]
+    fn transfers_cannot_overdraw() {
+        let config = GenesisConfig::default();
+        let mut accounts = _fixture_accounts();
+        accounts.get_mut("Tom").unwrap().staked = 5;
+        let transfer = |amount| TokenTransfer {
+            to: "Camper".to_string(),
+            amount,
+        };
+        let tom = "Tom".to_string();
+        assert_eq!(
+            apply_transfers(
+                &mut accounts,
+                &tom,
+                &[transfer(10), transfer(6)],
+                1,
+                &config
+            ),
+            Err("Transfers of 16 tokens are above the spendable tokens of 'Tom'".to_string())
+        );
+        assert_eq!(accounts, _fixture_accounts_staking(5));
+        assert_eq!(
+            apply_transfers(
+                &mut accounts,
+                &tom,
+                &[transfer(10), transfer(5)],
+                1,
+                &config
+            ),
+            Ok(())
+        );
+        assert_eq!((accounts["Tom"].tokens, accounts["Camper"].tokens), (5, 35));
+        // Amounts which overflow are above any balance
+        assert!(apply_transfers(
+            &mut accounts,
+            &tom,
+            &[transfer(u64::MAX), transfer(1)],
+            1,
+            &config
+        )
+        .is_err());
+    }
+    #[test]
+    fn punishments_take_at_most_the_held_tokens() {
+        let config = GenesisConfig::default();
+        let mut accounts = _fixture_accounts();
+        let punish = _fixture_transaction(Events::Punish("Camper".to_string(), 50));
+        assert_eq!(apply_spending(&mut accounts, &punish, 1, &config), Ok(()));
+        assert_eq!(accounts["Camper"].tokens, 0);
+    }
+    #[test]
+    fn locked_tokens_cannot_be_spent() {
+        let config = _fixture_vesting_config();
+        let mut accounts = _fixture_accounts();
+        accounts.get_mut("Tom").unwrap().tokens = 30;
+        let transfer =
+            |amount| _fixture_transaction(Events::Transfer("Camper".to_string(), amount));
+        assert_eq!(
+            apply_spending(&mut accounts, &transfer(6), 5, &config),
+            Err("'Tom' cannot transfer 6 tokens".to_string())
+        );
+        assert_eq!(
+            apply_spending(&mut accounts, &transfer(5), 5, &config),
+            Ok(())
+        );
+        assert_eq!(
+            (accounts["Tom"].tokens, accounts["Camper"].tokens),
+            (25, 25)
+        );
+        let stake = _fixture_transaction(Events::Stake);
+        assert_eq!(
+            apply_spending(&mut accounts, &stake, 5, &config),
+            Err("'Tom' has no spendable tokens to stake".to_string())
+        );
+        let rack = _fixture_transaction(Events::BuyRack);
+        assert_eq!(
+            apply_spending(&mut accounts, &rack, 5, &config),
+            Err("'Tom' cannot afford a rack".to_string())
+        );
+        // Every token unlocks at block 10
+        assert_eq!(apply_spending(&mut accounts, &rack, 10, &config), Ok(()));
+        assert_eq!(accounts["Tom"].tokens, 15);
+    }
+    #[test]
+    fn rewards_are_limited() {
+        let config = GenesisConfig::default();
+        let mut accounts = _fixture_accounts();
+        let reward = |amount| _fixture_transaction(Events::Reward("Camper".to_string(), amount));
+        assert_eq!(
+            apply_spending(&mut accounts, &reward(11), 1, &config),
+            Err("Reward of 11 tokens is above the limit of 10".to_string())
+        );
+        assert_eq!(
+            apply_spending(&mut accounts, &reward(10), 1, &config),
+            Ok(())
+        );
+        assert_eq!(accounts["Camper"].tokens, 30);
+    }
+    #[test]
+    fn spending_is_checked_after_fees_in_order() {
+        let config = _fixture_vesting_config();
+        let mut accounts = _fixture_accounts();
+        accounts.get_mut("Tom").unwrap().tokens = 30;
+        let transfer = _fixture_transaction(Events::Transfer("Camper".to_string(), 4));
+        let mut full_block = _fixture_block(vec![transfer.clone()], vec![]);
+        full_block.block.id = 5;
+        assert_eq!(
+            _fixture_spend(&full_block, &[_fixture_receipt(1)], &accounts, &config).map(|_| ()),
+            Ok(())
+        );
+        // The fee of the first transfer leaves 0 spendable tokens for the second
+        full_block.body.transactions = vec![transfer.clone(), transfer];
+        let receipts = [_fixture_receipt(1), _fixture_receipt(0)];
+        assert_eq!(
+            _fixture_spend(&full_block, &receipts, &accounts, &config).map(|_| ()),
+            Err("Block 5 transaction 1: 'Tom' cannot transfer 4 tokens".to_string())
+        );
+        assert_eq!(
+            _fixture_spend(&full_block, &[_fixture_receipt(6)], &accounts, &config).map(|_| ()),
+            Err("Block 5 transaction 0: 'Tom' cannot pay the fee of 6 tokens".to_string())
+        );
+    }
+
+    /// Replays the `full_block` as `receipt::apply_block` does, with the fees and transfers of the `receipts`.
+    fn _fixture_spend(
+        full_block: &FullBlock,
+        receipts: &[Receipt],
+        accounts: &BTreeMap<String, Account>,
+        config: &GenesisConfig,
+    ) -> Result<BTreeMap<String, Account>, String> {
+        let id = full_block.block.id;
+        let mut accounts = accounts.clone();
+        let transactions = full_block.body.transactions.iter().zip(receipts);
+        for (index, (transaction, receipt)) in transactions.enumerate() {
+            let transfers: Vec<_> = receipt
+                .state_changes
+                .iter()
+                .filter_map(|change| match change {
+                    StateChange::TokensTransferred { to, amount, .. } => Some(TokenTransfer {
+                        to: to.clone(),
+                        amount: *amount,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            spend_transaction(&mut accounts, transaction, receipt.fee, id, config)
+                .and_then(|_| {
+                    apply_transfers(&mut accounts, &transaction.address, &transfers, id, config)
+                })
+                .map_err(|e| format!("Block {} transaction {}: {}", id, index, e))?;
+        }
+        Ok(accounts)
+    }
+    /// Tom, with `staked` of his tokens staked, and Camper, each holding 20 tokens, before the block.
+    fn _fixture_accounts_staking(staked: u64) -> BTreeMap<String, Account> {
+        let mut accounts = _fixture_accounts();
+        accounts.get_mut("Tom").unwrap().staked = staked;
+        accounts
+    }
+    /// Tom and Camper, each holding 20 tokens, before the block.
+    fn _fixture_accounts() -> BTreeMap<String, Account> {
+        ["Tom", "Camper"]
+            .into_iter()
+            .map(|address| (address.to_string(), Account::new(address)))
+            .collect()
+    }
+    /// Block 1, with the `transactions`, and the Accounts holding the `tokens` as its data.
+    fn _fixture_block(transactions: Vec<Transaction>, tokens: Vec<(&str, u64)>) -> FullBlock {
+        let mut full_block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: tokens
+                .into_iter()
+                .map(|(address, tokens)| {
+                    let mut account = Account::new(address);
+                    account.tokens = tokens;
+                    account
+                })
+                .collect(),
+            nonce: 0,
+            next_miner: "Tom".to_string(),
+            next_validators: vec![],
+        });
+        full_block.body.transactions = transactions;
+        full_block
+    }
+    /// 25 of the tokens of Tom are locked until block 10.
+    fn _fixture_vesting_config() -> GenesisConfig {
+        let mut config = GenesisConfig::default();
+        config.vesting.insert(
+            "Tom".to_string(),
+            VestingSchedule {
+                amount: 25,
+                start: 0,
+                cliff: 10,
+                duration: 10,
+            },
+        );
+        config
+    }
+    fn _fixture_transaction(event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: "Tom".to_string(),
+        }
+    }
+    /// The receipt of a successful transaction, paying the `fee`.
+    fn _fixture_receipt(fee: u64) -> Receipt {
+        Receipt {
+            tx_hash: BlockHash::default(),
+            block_id: 5,
+            index: 0,
+            status: ReceiptStatus::Success,
+            error_code: None,
+            output: None,
+            state_changes: vec![],
+            gas_used: 0,
+            fee,
+        }
+    }
+}
@@ -3,8 +3,39 @@
 //! `blockchain` is a WASM module for handling a Proof of Stake blockchain.
 
 pub mod account;
+pub mod address;
+pub mod archive;
+pub mod assembly;
 pub mod block;
+pub mod body;
 pub mod chain;
+pub mod codec;
+pub mod consensus;
+pub mod contract;
+pub mod difficulty;
+pub mod explorer;
+pub mod gas;
+pub mod genesis;
+pub mod governance;
+pub mod hash;
+pub mod history;
+pub mod keychain;
+pub mod ledger;
+pub mod merkle;
+pub mod miner;
+pub mod multisig;
+pub mod protocol;
+pub mod receipt;
+pub mod sequence;
+pub mod slot;
+pub mod snapshot;
+pub mod state;
+pub mod timelock;
+pub mod timestamp;
+pub mod vesting;
+pub mod vm;
+pub mod wallet;
+pub mod wasm_contract;
 
 // TODO: Import necessary modules
 
@@ -0,0 +1,268 @@
+//! # Merkle
+//!
+//! Blocks following a protocol version with transaction roots commit to the Merkle root of the hashes of their transactions,
+//! as the `transactions_root` of their `BlockBody`. A light client holding only the block can check a transaction is in it
+//! from a `merkle_proof`, without the other transactions.
+//!
+//! The leaves of the tree are the hashes of `LEAF_PREFIX`, followed by each transaction ID, in block order. See
+//! `sequence::transaction_id`. Each parent is the hash of `NODE_PREFIX`, followed by its two children. A node without a
+//! sibling is moved up a level unchanged. As leaves and parents are hashed with distinct prefixes, a parent cannot be proven
+//! as a transaction, and `verify_proof` checks the proof has exactly the steps of the path of its leaf.
+
+use crate::{
+    body::FullBlock,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    sequence::block_transaction_ids,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The byte every leaf hash starts with, so a leaf cannot be mistaken for a parent.
+pub const LEAF_PREFIX: u8 = 0x00;
+
+/// The byte every parent node hash starts with, so a parent cannot be mistaken for a leaf.
+pub const NODE_PREFIX: u8 = 0x80;
+
+/// The sibling of a node on the path from a leaf to the root.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ProofStep {
+    pub sibling: BlockHash,
+    /// Whether the sibling is the left child of their parent.
+    pub is_left: bool,
+}
+
+fn leaf(transaction_id: &BlockHash) -> BlockHash {
+    let mut input = vec![LEAF_PREFIX];
+    input.extend(transaction_id.0);
+    BlockHash::from_slice(&Sha256::digest(input)).expect("Sha256 digest to be 32 bytes")
+}
+
+fn parent(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut input = vec![NODE_PREFIX];
+    input.extend(left.0);
+    input.extend(right.0);
+    BlockHash::from_slice(&Sha256::digest(input)).expect("Sha256 digest to be 32 bytes")
+}
+
+fn next_level(level: &[BlockHash]) -> Vec<BlockHash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => parent(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks to hold one or two nodes"),
+        })
+        .collect()
+}
+
+/// Returns the Merkle root of the `leaves`, or `None` if there are none.
+pub fn merkle_root(leaves: &[BlockHash]) -> Option<BlockHash> {
+    let mut level: Vec<_> = leaves.iter().map(leaf).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.first().copied()
+}
+
+/// Returns the siblings on the path from leaf `index` to the root, or `None` if there is no such leaf.
+pub fn merkle_proof(leaves: &[BlockHash], index: usize) -> Option<Vec<ProofStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut level: Vec<_> = leaves.iter().map(leaf).collect();
+    let mut index = index;
+    let mut proof = vec![];
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if let Some(&hash) = level.get(sibling) {
+            proof.push(ProofStep {
+                sibling: hash,
+                is_left: sibling < index,
+            });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(proof)
+}
+
+/// Returns `true` if the `proof` leads from the `leaf` at `index`, among `leaf_count` leaves, to the `root`.
+///
+/// The proof must have a step for every level at which the leaf has a sibling, on the side of the sibling, and no more.
+pub fn verify_proof(
+    leaf_hash: &BlockHash,
+    index: usize,
+    leaf_count: usize,
+    proof: &[ProofStep],
+    root: &BlockHash,
+) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let mut steps = proof.iter();
+    let mut node = leaf(leaf_hash);
+    let (mut index, mut len) = (index, leaf_count);
+    while len > 1 {
+        let sibling = index ^ 1;
+        if sibling < len {
+            match steps.next() {
+                Some(step) if step.is_left == (sibling < index) => {
+                    node = match step.is_left {
+                        true => parent(&step.sibling, &node),
+                        false => parent(&node, &step.sibling),
+                    };
+                }
+                _ => return false,
+            }
+        }
+        index /= 2;
+        len = len.div_ceil(2);
+    }
+    steps.next().is_none() && node == *root
+}
+
+/// Returns the Merkle root of the IDs of the transactions of the `full_block` following the `ancestors`,
+/// or `None` if it has none.
+///
+/// # Errors
+///
+/// Returns an error if a transaction has no canonical encoding.
+pub fn transactions_root(
+    full_block: &FullBlock,
+    ancestors: &[FullBlock],
+    config: &GenesisConfig,
+) -> Result<Option<BlockHash>, String> {
+    let ids = block_transaction_ids(full_block, ancestors, config)?;
+    Ok(merkle_root(&ids))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::{Account, AccountTrait},
+        assembly::assemble_block,
+        block::Block,
+        protocol::RuleSet,
+        sequence::block_transaction_ids,
+        Events, Transaction,
+    };
+    #[test]
+    fn every_leaf_has_a_proof_to_the_root() {
+        let leaves: Vec<BlockHash> = (0..5u8)
+            .map(|i| BlockHash::from_slice(&[i; 32]).unwrap())
+            .collect();
+        assert_eq!(merkle_root(&[]), None);
+        for len in 1..=leaves.len() {
+            let root = merkle_root(&leaves[..len]).unwrap();
+            for index in 0..len {
+                let proof = merkle_proof(&leaves[..len], index).unwrap();
+                assert!(verify_proof(&leaves[index], index, len, &proof, &root));
+                assert!(!verify_proof(
+                    &leaves[(index + 1) % 5],
+                    index,
+                    len,
+                    &proof,
+                    &root
+                ));
+            }
+            assert_eq!(merkle_proof(&leaves[..len], len), None);
+        }
+        // Moving a leaf changes the root
+        let swapped = [leaves[1], leaves[0]];
+        assert_ne!(merkle_root(&swapped), merkle_root(&leaves[..2]));
+        // A single leaf is still hashed
+        assert_ne!(merkle_root(&leaves[..1]), Some(leaves[0]));
+    }
+    #[test]
+    fn proofs_are_bound_to_their_leaf_position() {
+        let leaves: Vec<BlockHash> = (0..4u8)
+            .map(|i| BlockHash::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let root = merkle_root(&leaves).unwrap();
+        let proof = merkle_proof(&leaves, 2).unwrap();
+        assert!(verify_proof(&leaves[2], 2, 4, &proof, &root));
+        // Nor at another index, nor in a tree of another size
+        assert!(!verify_proof(&leaves[2], 3, 4, &proof, &root));
+        assert!(!verify_proof(&leaves[2], 2, 3, &proof, &root));
+        assert!(!verify_proof(&leaves[2], 4, 4, &proof, &root));
+        // Nor with a step dropped, added, or flipped
+        assert!(!verify_proof(&leaves[2], 2, 4, &proof[..1], &root));
+        let mut longer = proof.clone();
+        longer.push(proof[0]);
+        assert!(!verify_proof(&leaves[2], 2, 4, &longer, &root));
+        let mut flipped = proof.clone();
+        flipped[1].is_left = !flipped[1].is_left;
+        assert!(!verify_proof(&leaves[2], 2, 4, &flipped, &root));
+        // A parent is not a leaf: the parent of leaves 0 and 1 cannot be proven as the first of two leaves
+        let left_parent = proof[1].sibling;
+        let right_parent = merkle_proof(&leaves, 0).unwrap()[1];
+        assert!(!verify_proof(&left_parent, 0, 2, &[right_parent], &root));
+    }
+    #[test]
+    fn transactions_root_is_required_from_version_1() {
+        let config = GenesisConfig::default();
+        let validate = |chain: &[FullBlock], config: &GenesisConfig| {
+            let ids = block_transaction_ids(&chain[1], &chain[..1], config)?;
+            RuleSet::of_version(chain[1].body.version)
+                .unwrap()
+                .validate_block(&chain[1], &ids)
+        };
+        let mut chain: Vec<FullBlock> = vec![];
+        for full_block in _fixture_chain() {
+            let transactions = full_block.body.transactions.into_iter().map(Into::into);
+            let assembled =
+                assemble_block(&chain, full_block.block, transactions.collect(), &config).unwrap();
+            chain.push(assembled.full_block);
+        }
+        assert_eq!(chain[0].body.transactions_root, None);
+        assert!(chain[1].body.transactions_root.is_some());
+        assert_eq!(validate(&chain, &config), Ok(()));
+        chain[1].body.transactions.swap(0, 1);
+        assert_eq!(
+            validate(&chain, &config),
+            Err("Block 1 does not commit to the root of its transactions".to_string())
+        );
+
+        let config = GenesisConfig {
+            upgrades: vec![],
+            ..Default::default()
+        };
+        let mut chain = _fixture_chain();
+        chain[1].body.transactions_root =
+            transactions_root(&chain[1], &chain[..1], &config).unwrap();
+        assert_eq!(
+            validate(&chain, &config),
+            Err("Block 1 has a transactions root, which version 0 does not support".to_string())
+        );
+    }
+
+    /// Tom stakes, then unstakes, in block 1.
+    fn _fixture_chain() -> Vec<FullBlock> {
+        (0..2)
+            .map(|id| {
+                let mut full_block = FullBlock::from(Block {
+                    id,
+                    hash: BlockHash::default().to_hex(),
+                    previous_hash: String::new(),
+                    timestamp: 1000,
+                    data: vec![Account::new("Tom")],
+                    nonce: 0,
+                    next_miner: "Tom".to_string(),
+                    next_validators: vec![],
+                });
+                if id == 1 {
+                    full_block.body.transactions = [Events::Stake, Events::Unstake]
+                        .into_iter()
+                        .map(|event| Transaction {
+                            event,
+                            address: "Tom".to_string(),
+                        })
+                        .collect();
+                }
+                full_block
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,549 @@
+//! # Miner
+//!
+//! The mining engine searches the nonce space for a block hash meeting the difficulty.
+//!
+//! Natively, the `Miner` splits the nonce space across threads. In WASM, where threads are unavailable,
+//! each Web Worker drives its own `MiningJob` in batches, yielding to the event loop between batches.
+//! Either way, mining can be cancelled when a competing block arrives.
+//!
+//! The hash of a block commits to its body, so the miner searches over a `FullBlock`, as built by `assembly::assemble_block`.
+
+use crate::{
+    account::Account,
+    assembly::{assemble_block, AssembledBlock},
+    block::Block,
+    body::{FullBlock, FullChain},
+    chain::{Chain, ChainTrait},
+    difficulty::next_governed_difficulty,
+    genesis::GenesisConfig,
+    hash::{full_block_digest, HashFormat},
+    timelock::ScheduledTransaction,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+use wasm_bindgen::prelude::*;
+
+/// A token shared between the miner and the caller, used to stop mining.
+///
+/// # Examples
+///
+/// ```
+/// # use blockchain::miner::CancelToken;
+/// let cancel = CancelToken::new();
+/// let handle = cancel.clone();
+/// // When a competing block arrives:
+/// handle.cancel();
+/// assert!(cancel.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every miner holding a clone of this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The progress of a mining search.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MiningProgress {
+    /// The number of nonces tried so far.
+    pub hashes_tried: u64,
+    /// The number of milliseconds since mining started.
+    pub elapsed_ms: u64,
+}
+
+impl MiningProgress {
+    /// Returns the number of hashes tried per second.
+    pub fn hashrate(&self) -> f64 {
+        if self.elapsed_ms == 0 {
+            return 0.0;
+        }
+        self.hashes_tried as f64 * 1000.0 / self.elapsed_ms as f64
+    }
+}
+
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// A resumable search over the nonces `start`, `start + stride`, `start + 2 * stride`, ...
+///
+/// Giving each of `n` workers a distinct `start` in `0..n`, and a `stride` of `n`, splits the nonce space between them.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct MiningJob {
+    full_block: FullBlock,
+    difficulty: u32,
+    stride: u64,
+    hashes_tried: u64,
+    started_at: i64,
+    exhausted: bool,
+}
+
+impl MiningJob {
+    /// Creates a job mining the `template` block, starting at nonce `start`. Its hex hash commits to the body.
+    ///
+    /// **Note:** A `stride` of `0` is treated as `1`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body of the `template` has no canonical encoding.
+    pub fn new(
+        template: FullBlock,
+        difficulty: u32,
+        start: u64,
+        stride: u64,
+    ) -> Result<Self, String> {
+        let mut full_block = template;
+        full_block.block.nonce = start;
+        full_block_digest(&full_block, HashFormat::Hex)?;
+        Ok(Self {
+            full_block,
+            difficulty,
+            stride: stride.max(1),
+            hashes_tried: 0,
+            started_at: now_ms(),
+            exhausted: false,
+        })
+    }
+
+    /// Tries up to `batch` nonces. Returns the mined block, if one of them meets the difficulty.
+    pub fn step(&mut self, batch: u64) -> Option<FullBlock> {
+        for _ in 0..batch {
+            if self.exhausted {
+                return None;
+            }
+            // The body was encoded by `new`, and only the nonce changes
+            let digest = match full_block_digest(&self.full_block, HashFormat::Hex) {
+                Ok(digest) => digest,
+                Err(_) => {
+                    self.exhausted = true;
+                    return None;
+                }
+            };
+            self.hashes_tried += 1;
+            if digest.meets_difficulty(self.difficulty) {
+                let mut full_block = self.full_block.clone();
+                full_block.block.hash = digest.to_hex();
+                self.advance();
+                return Some(full_block);
+            }
+            self.advance();
+        }
+        None
+    }
+
+    fn advance(&mut self) {
+        let block = &mut self.full_block.block;
+        match block.nonce.checked_add(self.stride) {
+            Some(nonce) => block.nonce = nonce,
+            None => self.exhausted = true,
+        }
+    }
+
+    /// Check if every nonce of the job has been tried.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Returns the progress of the job.
+    pub fn progress(&self) -> MiningProgress {
+        MiningProgress {
+            hashes_tried: self.hashes_tried,
+            elapsed_ms: (now_ms() - self.started_at).max(0) as u64,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl MiningJob {
+    /// Creates a job mining the `template` argument, a block assembled with its body, for use in a Web Worker.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// // In worker `i` of `n`:
+    /// const { full_block } = assemble_next_block(chain, [], network, transactions, config);
+    /// const job = new MiningJob(full_block, next_block_difficulty(chain, config), i, n);
+    /// let block;
+    /// while (!cancelled && !(block = job.step(10000))) {
+    ///   postMessage({ hashrate: job.hashrate });
+    ///   await new Promise((resolve) => setTimeout(resolve));
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If `template` argument is not deserialisable into type `FullBlock`, or its body has no canonical encoding,
+    /// a `JsError` is thrown.
+    #[wasm_bindgen(constructor)]
+    pub fn from_js(
+        template: JsValue,
+        difficulty: u32,
+        start: u32,
+        stride: u32,
+    ) -> Result<MiningJob, JsError> {
+        let template: FullBlock = template.into_serde()?;
+        Self::new(template, difficulty, start as u64, stride as u64).map_err(|e| JsError::new(&e))
+    }
+
+    /// Tries up to `batch` nonces, and returns the mined block, or `null` if none met the difficulty.
+    #[wasm_bindgen(js_name = step)]
+    pub fn step_js(&mut self, batch: u32) -> Result<JsValue, JsError> {
+        match self.step(batch as u64) {
+            Some(block) => Ok(JsValue::from_serde(&block)?),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    /// The number of nonces tried so far.
+    #[wasm_bindgen(getter = hashesTried)]
+    pub fn hashes_tried(&self) -> f64 {
+        self.hashes_tried as f64
+    }
+
+    /// The number of hashes tried per second.
+    #[wasm_bindgen(getter)]
+    pub fn hashrate(&self) -> f64 {
+        self.progress().hashrate()
+    }
+}
+
+/// Mines blocks across multiple threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Miner {
+    /// The number of threads searching the nonce space.
+    pub threads: usize,
+    /// The number of nonces each thread tries between checking for cancellation, and reporting progress.
+    pub batch_size: u64,
+}
+
+impl Default for Miner {
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        #[cfg(target_arch = "wasm32")]
+        let threads = 1;
+        Self {
+            threads,
+            batch_size: 10_000,
+        }
+    }
+}
+
+impl Miner {
+    /// Searches for a nonce for which the digest of the `template`, including its body, meets the `difficulty`.
+    ///
+    /// `on_progress` is called with the combined progress of all threads after each batch.
+    /// Returns `None` if `cancel` is cancelled before a block is found.
+    ///
+    /// **Note:** In WASM, the search runs on the calling thread. Use a `MiningJob` per Web Worker to avoid blocking.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body of the `template` has no canonical encoding.
+    pub fn mine<F>(
+        &self,
+        template: &FullBlock,
+        difficulty: u32,
+        cancel: &CancelToken,
+        on_progress: F,
+    ) -> Result<Option<FullBlock>, String>
+    where
+        F: Fn(MiningProgress) + Sync,
+    {
+        let threads = if cfg!(target_arch = "wasm32") {
+            1
+        } else {
+            self.threads.max(1) as u64
+        };
+        let batch_size = self.batch_size.max(1);
+        let started_at = now_ms();
+        let hashes_tried = AtomicU64::new(0);
+        let found = AtomicBool::new(false);
+        let jobs = (0..threads)
+            .map(|start| MiningJob::new(template.clone(), difficulty, start, threads))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let search = |mut job: MiningJob| loop {
+            if cancel.is_cancelled() || found.load(Ordering::Relaxed) || job.is_exhausted() {
+                return None;
+            }
+            let before = job.hashes_tried;
+            let block = job.step(batch_size);
+            let batch = job.hashes_tried - before;
+            let tried = hashes_tried.fetch_add(batch, Ordering::Relaxed) + batch;
+            on_progress(MiningProgress {
+                hashes_tried: tried,
+                elapsed_ms: (now_ms() - started_at).max(0) as u64,
+            });
+            if block.is_some() {
+                found.store(true, Ordering::Relaxed);
+                return block;
+            }
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            Ok(jobs.into_iter().next().and_then(search))
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let search = &search;
+            Ok(std::thread::scope(|scope| {
+                let handles: Vec<_> = jobs
+                    .into_iter()
+                    .map(|job| scope.spawn(move || search(job)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .filter_map(|handle| handle.join().expect("mining thread not to panic"))
+                    .min_by_key(|full_block| full_block.block.nonce)
+            }))
+        }
+    }
+}
+
+/// Returns the next block of the `chain`, holding the given `data`, with a `nonce` of `0` and an empty `hash`.
+pub fn next_block(chain: &Chain, data: Vec<Account>, network: Vec<String>) -> Block {
+    let (id, previous_hash) = match chain.get_last_block() {
+        Some(block) => (block.id + 1, block.hash),
+        None => (0, String::new()),
+    };
+    let next_miner = chain.get_next_miner();
+    let next_validators = chain.get_next_validators(&next_miner, network);
+    Block {
+        id,
+        hash: String::new(),
+        previous_hash,
+        timestamp: Utc::now().timestamp() as u64,
+        data,
+        nonce: 0,
+        next_miner,
+        next_validators,
+    }
+}
+
+/// Assembles the `block` following the `chain` from the offered `transactions`, as `assembly::assemble_block` does, and
+/// mines it with the `miner`, at the difficulty governed by the chain.
+///
+/// Returns the assembled block, once mined and pushed onto the chain, or `None`, leaving the chain unchanged, if mining
+/// is cancelled.
+///
+/// # Errors
+///
+/// Returns an error if the chain is invalid, or the block cannot be assembled.
+pub fn mine_block_parallel<F>(
+    chain: &mut FullChain,
+    block: Block,
+    transactions: Vec<ScheduledTransaction>,
+    config: &GenesisConfig,
+    miner: &Miner,
+    cancel: &CancelToken,
+    on_progress: F,
+) -> Result<Option<AssembledBlock>, String>
+where
+    F: Fn(MiningProgress) + Sync,
+{
+    let mut assembled = assemble_block(chain, block, transactions, config)?;
+    let difficulty = next_governed_difficulty(chain, config)?;
+    match miner.mine(&assembled.full_block, difficulty, cancel, on_progress)? {
+        Some(full_block) => {
+            chain.push(full_block.clone());
+            assembled.full_block = full_block;
+            Ok(Some(assembled))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait,
+        body::headers,
+        consensus::validate_full_chain,
+        difficulty::mine_full_block_hash,
+        hash::verify_full_block_hash,
+        timestamp::{Clock, SystemClock},
+        Events, Transaction,
+    };
+    use std::sync::Mutex;
+    #[test]
+    fn cancel_token_is_shared_between_clones() {
+        let cancel = CancelToken::new();
+        let handle = cancel.clone();
+        assert!(!cancel.is_cancelled());
+        handle.cancel();
+        assert!(cancel.is_cancelled());
+    }
+    #[test]
+    fn hashrate_is_hashes_per_second() {
+        let progress = MiningProgress {
+            hashes_tried: 500,
+            elapsed_ms: 250,
+        };
+        assert_eq!(progress.hashrate(), 2000.0);
+        let progress = MiningProgress {
+            hashes_tried: 500,
+            elapsed_ms: 0,
+        };
+        assert_eq!(progress.hashrate(), 0.0);
+    }
+    #[test]
+    fn job_with_stride_one_finds_same_nonce_as_mine_full_block_hash() {
+        let template = _fixture_template();
+        let mut expected = template.clone();
+        mine_full_block_hash(&mut expected, 8).unwrap();
+
+        let mut job = MiningJob::new(template, 8, 0, 1).unwrap();
+        let full_block = loop {
+            if let Some(full_block) = job.step(100) {
+                break full_block;
+            }
+        };
+        assert_eq!(full_block, expected);
+        assert_eq!(job.progress().hashes_tried, expected.block.nonce + 1);
+    }
+    #[test]
+    fn job_only_tries_its_own_nonces() {
+        let mut job = MiningJob::new(_fixture_template(), 256, 3, 4).unwrap();
+        assert!(job.step(10).is_none());
+        assert_eq!(job.full_block.block.nonce, 3 + 4 * 10);
+        assert_eq!(job.progress().hashes_tried, 10);
+    }
+    #[test]
+    fn job_stops_at_end_of_nonce_space() {
+        let mut job = MiningJob::new(_fixture_template(), 256, u64::MAX - 1, 1).unwrap();
+        assert!(job.step(10).is_none());
+        assert!(job.is_exhausted());
+        assert_eq!(job.progress().hashes_tried, 2);
+    }
+    #[test]
+    fn parallel_miner_finds_valid_block() {
+        let miner = Miner {
+            threads: 4,
+            batch_size: 64,
+        };
+        let reports = Mutex::new(0);
+        let full_block = miner
+            .mine(&_fixture_template(), 10, &CancelToken::new(), |_| {
+                *reports.lock().unwrap() += 1
+            })
+            .unwrap()
+            .expect("block to be mined");
+        assert!(verify_full_block_hash(&full_block)
+            .unwrap()
+            .meets_difficulty(10));
+        assert!(*reports.lock().unwrap() > 0);
+    }
+    #[test]
+    fn cancelled_miner_returns_none() {
+        let miner = Miner {
+            threads: 2,
+            batch_size: 16,
+        };
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(
+            miner.mine(&_fixture_template(), 256, &cancel, |_| {}),
+            Ok(None)
+        );
+    }
+    #[test]
+    fn miner_can_be_cancelled_from_progress() {
+        let miner = Miner {
+            threads: 2,
+            batch_size: 16,
+        };
+        let cancel = CancelToken::new();
+        let full_block = miner.mine(&_fixture_template(), 256, &cancel, |progress| {
+            if progress.hashes_tried >= 64 {
+                cancel.cancel();
+            }
+        });
+        assert_eq!(full_block, Ok(None));
+    }
+    #[test]
+    fn mined_blocks_are_valid() {
+        let config = GenesisConfig {
+            allocations: vec![Account::new("Tom")],
+            ..Default::default()
+        };
+        let now = SystemClock.now();
+        let mut chain = vec![FullBlock::from(config.mine_genesis("Camper", now - 10))];
+        let miner = Miner::default();
+        for timestamp in now - 2..now {
+            let mut block = next_block(&headers(&chain), vec![], vec!["Camper".to_string()]);
+            block.timestamp = timestamp;
+            let transfer = Transaction {
+                event: Events::Transfer("Tom".to_string(), 2),
+                address: "Camper".to_string(),
+            };
+            let assembled = mine_block_parallel(
+                &mut chain,
+                block,
+                vec![transfer.into()],
+                &config,
+                &miner,
+                &CancelToken::new(),
+                |_| {},
+            )
+            .unwrap()
+            .expect("block to be mined");
+            assert_eq!(assembled.full_block, *chain.last().unwrap());
+        }
+        assert_eq!(chain.len(), 3);
+        // The blocks are of the version with spending checks, and hold the Accounts their transfers change
+        assert_eq!(chain[2].body.version, 1);
+        assert_eq!(chain[2].body.transactions.len(), 1);
+        assert_eq!(chain[2].block.data.len(), 2);
+        assert_eq!(validate_full_chain(&chain, &SystemClock, &config), Ok(()));
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let block = next_block(&headers(&chain), vec![], vec![]);
+        assert_eq!(
+            mine_block_parallel(&mut chain, block, vec![], &config, &miner, &cancel, |_| {}),
+            Ok(None)
+        );
+        assert_eq!(chain.len(), 3);
+    }
+
+    /// A block assembled with a transfer in its body, before it is mined.
+    fn _fixture_template() -> FullBlock {
+        let mut full_block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: "0x00".to_string(),
+            timestamp: 1650301013,
+            data: vec![Account::new("Ahmad")],
+            nonce: 0,
+            next_miner: "Mrugesh".to_string(),
+            next_validators: vec!["Mrugesh".to_string()],
+        });
+        full_block.body.version = 1;
+        full_block.body.transactions = vec![Transaction {
+            event: Events::Transfer("Mrugesh".to_string(), 1),
+            address: "Ahmad".to_string(),
+        }];
+        full_block
+    }
+}
@@ -0,0 +1,696 @@
+//! # Multisig
+//!
+//! A multisig account is governed by an M-of-N `MultisigPolicy`: a set of Ed25519 public keys, and the number of them
+//! which must sign each transaction of the account. Its address is derived from the policy it was created with, and encoded
+//! after the `MULTISIG_PREFIX`, so it is told apart from the address of a key, even before the account is registered.
+//!
+//! Each transaction from a multisig account is mined with a `MultisigWitness` in the `BlockBody`, holding the current policy,
+//! the sequence of the transaction, and the signatures of the transaction ID. See `sequence::transaction_id`. So, signatures
+//! are only valid on one chain, for the next transaction of the account. The first witness of an address registers its policy,
+//! and no transaction of a multisig address is mined without a witness, so tokens sent to it are only spent by its signers.
+//! Afterwards, signers are only changed by the `AddSigner` and `RemoveSigner` events, which themselves need the threshold.
+
+use crate::{
+    address::{Address, MULTISIG_PREFIX},
+    body::{BlockSignature, FullBlock},
+    codec,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    sequence::{check_sequence, transaction_id, Sequences},
+    timelock::{block_time_locks, ScheduledTransaction, TransactionTimeLocks},
+    Events, Transaction,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// The maximum number of signers of a multisig account.
+pub const MAX_SIGNERS: usize = 16;
+
+/// The signers of a multisig account, and how many of them must sign.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    /// The number of signatures each transaction needs.
+    pub threshold: u8,
+    /// The hex encoded Ed25519 public keys of the signers, in ascending order.
+    pub signers: Vec<String>,
+}
+
+/// The signatures of a transaction from a multisig account.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MultisigWitness {
+    /// The index of the transaction in the `transactions` of the block.
+    pub index: usize,
+    /// The sequence of the transaction among the transactions of the account.
+    pub sequence: u64,
+    /// The current policy of the account.
+    pub policy: MultisigPolicy,
+    /// The signatures of the transaction ID, by signers of the policy.
+    pub signatures: Vec<BlockSignature>,
+}
+
+impl MultisigPolicy {
+    /// Creates a policy of `threshold` of the `signers`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `validate`.
+    pub fn new(threshold: u8, mut signers: Vec<String>) -> Result<Self, String> {
+        signers.sort();
+        let policy = Self { threshold, signers };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Checks the signers are distinct, sorted, Ed25519 public keys, and the threshold is reachable.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.threshold == 0 {
+            return Err("Threshold must be at least 1".to_string());
+        }
+        if self.signers.len() > MAX_SIGNERS {
+            return Err(format!(
+                "Multisig accounts have at most {} signers",
+                MAX_SIGNERS
+            ));
+        }
+        if self.threshold as usize > self.signers.len() {
+            return Err(format!(
+                "Threshold {} is above the {} signers",
+                self.threshold,
+                self.signers.len()
+            ));
+        }
+        for (i, signer) in self.signers.iter().enumerate() {
+            verifying_key(signer)?;
+            if i > 0 && self.signers[i - 1] >= *signer {
+                return Err("Signers must be distinct, and in ascending order".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the address of the multisig account created with the policy.
+    pub fn address(&self) -> Address {
+        let encoded = codec::encode(&("multisig", self.threshold, &self.signers))
+            .expect("policy to be encodable");
+        Address::from_policy_hash(&Sha256::digest(encoded))
+    }
+
+    /// Returns the policy after the `event`, if it changes the signers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a signer is added twice, an unknown signer is removed, or the new policy is not valid.
+    pub fn apply(&self, event: &Events) -> Result<Option<Self>, String> {
+        let (signers, threshold) = match event {
+            Events::AddSigner(signer, threshold) => {
+                if self.signers.contains(signer) {
+                    return Err(format!("'{}' is already a signer", signer));
+                }
+                let mut signers = self.signers.clone();
+                signers.push(signer.clone());
+                (signers, *threshold)
+            }
+            Events::RemoveSigner(signer, threshold) => {
+                if !self.signers.contains(signer) {
+                    return Err(format!("'{}' is not a signer", signer));
+                }
+                let signers = self
+                    .signers
+                    .iter()
+                    .filter(|s| *s != signer)
+                    .cloned()
+                    .collect();
+                (signers, *threshold)
+            }
+            _ => return Ok(None),
+        };
+        Self::new(threshold, signers).map(Some)
+    }
+
+    /// Checks at least `threshold` distinct signers signed the transaction with the ID `transaction_id`.
+    ///
+    /// **Note:** Signatures by keys which are not signers, or which are not valid, are not counted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the number of valid signatures, if it is below the threshold.
+    pub fn verify(
+        &self,
+        transaction_id: &BlockHash,
+        signatures: &[BlockSignature],
+    ) -> Result<(), String> {
+        let mut signed: Vec<&str> = vec![];
+        for signature in signatures {
+            if !self.signers.contains(&signature.public_key)
+                || signed.contains(&signature.public_key.as_str())
+            {
+                continue;
+            }
+            let valid = verifying_key(&signature.public_key)
+                .ok()
+                .zip(
+                    hex::decode(&signature.signature)
+                        .ok()
+                        .and_then(|s| <[u8; 64]>::try_from(s).ok()),
+                )
+                .is_some_and(|(key, s)| {
+                    key.verify(&transaction_id.0, &Signature::from_bytes(&s))
+                        .is_ok()
+                });
+            if valid {
+                signed.push(&signature.public_key);
+            }
+        }
+        if signed.len() < self.threshold as usize {
+            return Err(format!(
+                "Transaction has {} of the {} signatures required",
+                signed.len(),
+                self.threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl MultisigWitness {
+    /// Creates a witness of the transaction at `index`, with the given `sequence`, without signatures.
+    pub fn new(index: usize, sequence: u64, policy: MultisigPolicy) -> Self {
+        Self {
+            index,
+            sequence,
+            policy,
+            signatures: vec![],
+        }
+    }
+
+    /// Adds the signature of the `transaction`, with its time locks, in the chain configured by the `config`, by the
+    /// `signing_key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is not a signer of the policy, or the transaction has no canonical encoding.
+    pub fn sign(
+        &mut self,
+        transaction: &ScheduledTransaction,
+        config: &GenesisConfig,
+        signing_key: &SigningKey,
+    ) -> Result<(), String> {
+        let public_key = hex::encode(signing_key.verifying_key().as_bytes());
+        if !self.policy.signers.contains(&public_key) {
+            return Err(format!("'{}' is not a signer", public_key));
+        }
+        let id = transaction_id(
+            &config.hash(),
+            self.sequence,
+            &transaction.transaction,
+            &transaction.time_locks(0),
+        )?;
+        self.signatures.push(BlockSignature {
+            public_key,
+            signature: hex::encode(signing_key.sign(&id.0).to_bytes()),
+        });
+        Ok(())
+    }
+}
+
+/// The current policies of the multisig accounts of a chain, by address.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MultisigRegistry {
+    policies: BTreeMap<String, MultisigPolicy>,
+}
+
+impl MultisigRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the registry of the `chain` configured by the `config`, checking the witnesses of every block.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `apply_block` for the first block which fails.
+    pub fn from_chain(chain: &[FullBlock], config: &GenesisConfig) -> Result<Self, String> {
+        let mut registry = Self::new();
+        let mut sequences = Sequences::new(config);
+        for full_block in chain {
+            registry.apply_block(full_block, &sequences)?;
+            sequences.apply_block(full_block)?;
+        }
+        Ok(registry)
+    }
+
+    /// Returns the current policy of the multisig account at `address`, if any.
+    pub fn policy(&self, address: &str) -> Option<&MultisigPolicy> {
+        self.policies.get(address)
+    }
+
+    /// Returns the current policy of every multisig account, by address.
+    pub fn policies(&self) -> &BTreeMap<String, MultisigPolicy> {
+        &self.policies
+    }
+
+    /// Checks the witnesses of the `full_block`, without changing the registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `apply_block`.
+    pub fn verify_block(
+        &self,
+        full_block: &FullBlock,
+        sequences: &Sequences,
+    ) -> Result<(), String> {
+        self.clone().apply_block(full_block, sequences)
+    }
+
+    /// Checks the witnesses of the `full_block`, then registers new multisig accounts, and applies changes of signers.
+    /// The `sequences` are those of the chain before the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `apply_transaction` for the first transaction which fails, or an error if a witness names
+    /// no transaction, or a transaction twice.
+    pub fn apply_block(
+        &mut self,
+        full_block: &FullBlock,
+        sequences: &Sequences,
+    ) -> Result<(), String> {
+        let mut sequences = sequences.clone();
+        let id = full_block.block.id;
+        let transactions = &full_block.body.transactions;
+        let mut witnesses: Vec<Option<&MultisigWitness>> = vec![None; transactions.len()];
+        for witness in &full_block.body.multisig {
+            match witnesses.get_mut(witness.index) {
+                Some(slot @ None) => *slot = Some(witness),
+                _ => {
+                    return Err(format!(
+                        "Block {} has an invalid witness for transaction {}",
+                        id, witness.index
+                    ))
+                }
+            }
+        }
+        let time_locks = block_time_locks(full_block);
+        for (i, ((transaction, time_locks), witness)) in transactions
+            .iter()
+            .zip(&time_locks)
+            .zip(witnesses)
+            .enumerate()
+        {
+            self.apply_transaction(id, i, transaction, time_locks, witness, &mut sequences)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the `witness` of the `transaction` at `index` in block `id`, with the given `time_locks`, then registers its
+    /// multisig account, or applies its change of signers. The transaction is counted in the `sequences`.
+    ///
+    /// Every transaction from a multisig address must have a witness of the next sequence of the account, meeting the
+    /// threshold. Once the account is registered, the witness must hold its current policy. Before, it must hold the policy
+    /// the address was created with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the block, and the transaction, at fault. The registry may then be partly changed.
+    pub fn apply_transaction(
+        &mut self,
+        id: u64,
+        index: usize,
+        transaction: &Transaction,
+        time_locks: &TransactionTimeLocks,
+        witness: Option<&MultisigWitness>,
+        sequences: &mut Sequences,
+    ) -> Result<(), String> {
+        let address = &transaction.address;
+        let sequence = sequences.next(address);
+        let transaction_id = sequences.apply_transaction(transaction, time_locks)?;
+        let witness = match (self.policies.get(address), witness) {
+            (None, None) if is_multisig_address(address) => {
+                return Err(format!(
+                    "Transaction {} of block {} is not signed by the signers of '{}'",
+                    index, id, address
+                ))
+            }
+            (None, None) => {
+                if matches!(
+                    transaction.event,
+                    Events::AddSigner(..) | Events::RemoveSigner(..)
+                ) {
+                    return Err(format!("'{}' is not a multisig account", address));
+                }
+                return Ok(());
+            }
+            (Some(_), None) => {
+                return Err(format!(
+                    "Transaction {} of block {} is not signed by the signers of '{}'",
+                    index, id, address
+                ))
+            }
+            (Some(policy), Some(witness)) if *policy != witness.policy => {
+                return Err(format!(
+                    "Transaction {} of block {} is not witnessed with the current signers of '{}'",
+                    index, id, address
+                ))
+            }
+            (None, Some(witness)) if witness.policy.address().as_str() != address => {
+                return Err(format!(
+                    "Transaction {} of block {} is not witnessed with the signers of '{}'",
+                    index, id, address
+                ))
+            }
+            (_, Some(witness)) => witness,
+        };
+        let policy = &witness.policy;
+        policy.validate()?;
+        check_sequence(address, witness.sequence, sequence)
+            .and_then(|_| policy.verify(&transaction_id, &witness.signatures))
+            .map_err(|e| format!("Block {}: {}", id, e))?;
+        let policy = policy
+            .apply(&transaction.event)?
+            .unwrap_or_else(|| policy.clone());
+        self.policies.insert(address.clone(), policy);
+        Ok(())
+    }
+}
+
+/// Returns `true` if the `address` is the address of a multisig account, registered or not.
+fn is_multisig_address(address: &str) -> bool {
+    address.starts_with(&format!("{}1", MULTISIG_PREFIX))
+}
+
+/// Returns the Ed25519 public key of the hex encoded `signer`.
+fn verifying_key(signer: &str) -> Result<VerifyingKey, String> {
+    hex::decode(signer)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or_else(|| format!("'{}' is not a signer key", signer))
+}
+
+/// Returns the address of the multisig account created with the `policy` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const treasury = get_multisig_address({ threshold: 2, signers: [aliceKey, bobKey, carolKey] });
+/// ```
+///
+/// # Errors
+///
+/// If `policy` argument is not deserialisable into type `MultisigPolicy`, or is not valid, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_multisig_address(policy: JsValue) -> Result<String, JsError> {
+    let policy: MultisigPolicy = policy.into_serde()?;
+    let policy =
+        MultisigPolicy::new(policy.threshold, policy.signers).map_err(|e| JsError::new(&e))?;
+    Ok(policy.address().into())
+}
+
+/// Adds the signature of the `transaction` argument, with its time locks, in the chain configured by the `config` argument,
+/// by the hex encoded `secret_key`, to the `witness` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const sequence = get_next_sequence(chain, treasury, config);
+/// let witness = { index: 0, sequence, policy, signatures: [] };
+/// witness = sign_multisig_transaction(witness, transaction, aliceSecretKey, config);
+/// witness = sign_multisig_transaction(witness, transaction, bobSecretKey, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the key is not a signer, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn sign_multisig_transaction(
+    witness: JsValue,
+    transaction: JsValue,
+    secret_key: String,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let mut witness: MultisigWitness = witness.into_serde()?;
+    let transaction: ScheduledTransaction = transaction.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let secret_key: [u8; 32] = hex::decode(secret_key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| JsError::new("Invalid secret key"))?;
+    witness
+        .sign(&transaction, &config, &SigningKey::from_bytes(&secret_key))
+        .map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&witness)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    #[test]
+    fn threshold_of_signatures_is_required() {
+        let config = GenesisConfig::default();
+        let sequences = Sequences::new(&config);
+        let (keys, policy) = _fixture_policy();
+        let transfer = _fixture_transaction(&policy, Events::Transfer("Tom".to_string(), 5));
+
+        let scheduled = ScheduledTransaction::from(transfer.clone());
+        let mut witness = MultisigWitness::new(0, 0, policy.clone());
+        witness.sign(&scheduled, &config, &keys[0]).unwrap();
+        // The same signer twice only counts once
+        witness.sign(&scheduled, &config, &keys[0]).unwrap();
+        let block = _fixture_block(vec![transfer.clone()], vec![witness.clone()]);
+        assert_eq!(
+            MultisigRegistry::new().verify_block(&block, &sequences),
+            Err("Block 1: Transaction has 1 of the 2 signatures required".to_string())
+        );
+
+        witness.sign(&scheduled, &config, &keys[2]).unwrap();
+        let block = _fixture_block(vec![transfer], vec![witness]);
+        let registry = MultisigRegistry::from_chain(&[block], &config).unwrap();
+        assert_eq!(registry.policy(policy.address().as_str()), Some(&policy));
+    }
+    #[test]
+    fn registered_accounts_need_witnesses() {
+        let config = GenesisConfig::default();
+        let (keys, policy) = _fixture_policy();
+        let stake = _fixture_transaction(&policy, Events::Stake);
+        let witness = _fixture_witness(0, 0, &stake, &policy, &keys[..2]);
+        let chain = [_fixture_block(vec![stake.clone()], vec![witness])];
+        let mut registry = MultisigRegistry::from_chain(&chain, &config).unwrap();
+        let sequences = Sequences::from_chain(&chain, &config);
+
+        let unsigned = _fixture_block(vec![stake.clone()], vec![]);
+        assert_eq!(
+            registry.apply_block(&unsigned, &sequences),
+            Err(format!(
+                "Transaction 0 of block 1 is not signed by the signers of '{}'",
+                policy.address()
+            ))
+        );
+        // Nor before the account is registered, as the address commits to a policy
+        assert_eq!(
+            MultisigRegistry::new().verify_block(&unsigned, &Sequences::new(&config)),
+            Err(format!(
+                "Transaction 0 of block 1 is not signed by the signers of '{}'",
+                policy.address()
+            ))
+        );
+        // A witness of another policy does not create the account
+        let other =
+            MultisigPolicy::new(1, vec![hex::encode(keys[0].verifying_key().as_bytes())]).unwrap();
+        let forged = _fixture_witness(0, 0, &stake, &other, &keys[..1]);
+        assert!(MultisigRegistry::new()
+            .verify_block(
+                &_fixture_block(vec![stake], vec![forged]),
+                &Sequences::new(&config)
+            )
+            .is_err());
+    }
+    #[test]
+    fn witnesses_cannot_be_replayed() {
+        let config = GenesisConfig::default();
+        let (keys, policy) = _fixture_policy();
+        let address = policy.address();
+        let transfer = _fixture_transaction(&policy, Events::Transfer("Tom".to_string(), 5));
+        let witness = _fixture_witness(0, 0, &transfer, &policy, &keys[..2]);
+        let chain = [_fixture_block(
+            vec![transfer.clone()],
+            vec![witness.clone()],
+        )];
+        let registry = MultisigRegistry::from_chain(&chain, &config).unwrap();
+        let sequences = Sequences::from_chain(&chain, &config);
+
+        // The mined witness cannot be used again
+        let replayed = _fixture_block(vec![transfer.clone()], vec![witness.clone()]);
+        assert_eq!(
+            registry.verify_block(&replayed, &sequences),
+            Err(format!(
+                "Block 1: Sequence 0 of '{}' is already used",
+                address
+            ))
+        );
+        // Nor relabelled with the next sequence
+        let relabelled = MultisigWitness {
+            sequence: 1,
+            ..witness
+        };
+        assert_eq!(
+            registry.verify_block(
+                &_fixture_block(vec![transfer.clone()], vec![relabelled]),
+                &sequences
+            ),
+            Err("Block 1: Transaction has 0 of the 2 signatures required".to_string())
+        );
+        let skipping = _fixture_witness(0, 2, &transfer, &policy, &keys[..2]);
+        assert_eq!(
+            registry.verify_block(
+                &_fixture_block(vec![transfer.clone()], vec![skipping]),
+                &sequences
+            ),
+            Err(format!(
+                "Block 1: Sequence 2 of '{}' skips sequence 1",
+                address
+            ))
+        );
+        let next = _fixture_witness(0, 1, &transfer, &policy, &keys[1..]);
+        assert_eq!(
+            registry.verify_block(
+                &_fixture_block(vec![transfer.clone()], vec![next]),
+                &sequences
+            ),
+            Ok(())
+        );
+        // Signatures of another chain are not valid
+        let other = GenesisConfig {
+            starting_tokens: 1,
+            ..Default::default()
+        };
+        let mut foreign = MultisigWitness::new(0, 0, policy.clone());
+        for key in &keys[..2] {
+            foreign.sign(&transfer.clone().into(), &other, key).unwrap();
+        }
+        assert!(MultisigRegistry::new()
+            .verify_block(
+                &_fixture_block(vec![transfer], vec![foreign]),
+                &Sequences::new(&config)
+            )
+            .is_err());
+    }
+    #[test]
+    fn signer_changes_need_the_threshold() {
+        let config = GenesisConfig::default();
+        let (keys, policy) = _fixture_policy();
+        let new_signer = _fixture_key(9);
+        let new_key = hex::encode(new_signer.verifying_key().as_bytes());
+        let add = _fixture_transaction(&policy, Events::AddSigner(new_key.clone(), 3));
+        let chain = [_fixture_block(
+            vec![add.clone()],
+            vec![_fixture_witness(0, 0, &add, &policy, &keys[..2])],
+        )];
+        let registry = MultisigRegistry::from_chain(&chain, &config).unwrap();
+        let sequences = Sequences::from_chain(&chain, &config);
+        let updated = registry.policy(policy.address().as_str()).unwrap();
+        assert_eq!(updated.threshold, 3);
+        assert!(updated.signers.contains(&new_key));
+
+        // The old policy no longer witnesses the account
+        let stake = _fixture_transaction(&policy, Events::Stake);
+        let stale = _fixture_block(
+            vec![stake.clone()],
+            vec![_fixture_witness(0, 1, &stake, &policy, &keys)],
+        );
+        assert!(registry.verify_block(&stale, &sequences).is_err());
+        let current = _fixture_block(
+            vec![stake.clone()],
+            vec![_fixture_witness(
+                0,
+                1,
+                &stake,
+                updated,
+                &[keys[0].clone(), keys[1].clone(), new_signer],
+            )],
+        );
+        assert_eq!(registry.verify_block(&current, &sequences), Ok(()));
+
+        let remove = Transaction {
+            event: Events::RemoveSigner(new_key, 1),
+            address: "Tom".to_string(),
+        };
+        assert_eq!(
+            registry.verify_block(&_fixture_block(vec![remove], vec![]), &sequences),
+            Err("'Tom' is not a multisig account".to_string())
+        );
+    }
+    #[test]
+    fn policies_are_validated() {
+        let (_, policy) = _fixture_policy();
+        assert!(MultisigPolicy::new(4, policy.signers.clone()).is_err());
+        assert!(MultisigPolicy::new(0, policy.signers.clone()).is_err());
+        let mut twice = policy.signers.clone();
+        twice.push(twice[0].clone());
+        assert!(MultisigPolicy::new(1, twice).is_err());
+        assert!(MultisigPolicy::new(1, vec!["00".to_string()]).is_err());
+        assert_eq!(
+            policy.apply(&Events::RemoveSigner(policy.signers[0].clone(), 3)),
+            Err("Threshold 3 is above the 2 signers".to_string())
+        );
+        assert_ne!(
+            policy.address(),
+            MultisigPolicy::new(1, policy.signers.clone())
+                .unwrap()
+                .address()
+        );
+    }
+
+    fn _fixture_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+    /// A 2-of-3 policy, with the keys of its signers.
+    fn _fixture_policy() -> (Vec<SigningKey>, MultisigPolicy) {
+        let keys: Vec<SigningKey> = (1..=3).map(_fixture_key).collect();
+        let signers = keys
+            .iter()
+            .map(|key| hex::encode(key.verifying_key().as_bytes()))
+            .collect();
+        (keys, MultisigPolicy::new(2, signers).unwrap())
+    }
+    fn _fixture_transaction(policy: &MultisigPolicy, event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: policy.address().into(),
+        }
+    }
+    /// A witness of the `transaction` with the given `sequence`, in a chain of the default config, signed by the `keys`.
+    fn _fixture_witness(
+        index: usize,
+        sequence: u64,
+        transaction: &Transaction,
+        policy: &MultisigPolicy,
+        keys: &[SigningKey],
+    ) -> MultisigWitness {
+        let config = GenesisConfig::default();
+        let mut witness = MultisigWitness::new(index, sequence, policy.clone());
+        let scheduled = ScheduledTransaction::from(transaction.clone());
+        for key in keys {
+            witness.sign(&scheduled, &config, key).unwrap();
+        }
+        witness
+    }
+    fn _fixture_block(transactions: Vec<Transaction>, multisig: Vec<MultisigWitness>) -> FullBlock {
+        let mut full_block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![],
+            nonce: 0,
+            next_miner: "Camper".to_string(),
+            next_validators: vec![],
+        });
+        full_block.body.transactions = transactions;
+        full_block.body.multisig = multisig;
+        full_block
+    }
+}
@@ -0,0 +1,420 @@
+//! # Protocol
+//!
+//! The rules blocks are validated with are versioned. Each block records the protocol version it follows,
+//! as the `version` of its `BlockBody`, and is validated with the `RuleSet` of that version.
+//!
+//! The `upgrades` of the `GenesisConfig` schedule the block height each version activates at. Blocks before the first
+//! upgrade are version `0`. So, a new rule only applies from its activation height, and existing blocks keep validating
+//! with the rules they were produced under.
+//!
+//! Version `0` follows the rules of the original chain, without a `BlockBody`. Version `1` enables every feature of the body:
+//! snapshots, checked addresses, multisig accounts, contracts, gas, governance, receipts, transaction roots, time locks,
+//! spending checks, transaction signatures, and committed difficulties.
+//! Version `2` also runs WASM contracts. As WASM builds cannot run them, see `wasm_contract`, only native builds can validate
+//! a chain once it runs one: WASM builds then return a node capability error, rather than finding the chain invalid.
+//! The default `upgrades` activate version `1` from block `1`, after the genesis block.
+//!
+//! The `upgrades` are part of the hash of the `GenesisConfig`, so every node of a chain follows the same schedule: a node
+//! scheduling different activation heights refuses the chain at its genesis block, instead of forking at the first height
+//! the schedules disagree on.
+//!
+//! **Note:** To change a rule, add a `RuleSet` with the next version to `RULE_SETS`, then schedule its activation.
+
+use crate::{
+    body::FullBlock,
+    genesis::GenesisConfig,
+    hash::{BlockHash, HashFormat},
+    merkle::merkle_root,
+    timelock::validate_time_locks,
+    wallet::validate_signatures,
+    Events,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// The rules a block of a protocol version must follow.
+///
+/// Each flag enables a feature. A block following rules without a feature cannot use it: its body has no field of
+/// the feature, and it has no transaction of the feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    /// The protocol version of the rules.
+    pub version: u32,
+    /// Whether block hashes may be in the `HashFormat::Legacy` format.
+    pub legacy_hashes: bool,
+    /// Whether blocks at a snapshot interval commit to a `StateSnapshot`.
+    pub snapshots: bool,
+    /// Whether the addresses of a block must be valid `Address`es.
+    pub addresses: bool,
+    /// Whether Accounts may be multisig accounts, whose transactions are witnessed.
+    pub multisig: bool,
+    /// Whether transactions may deploy and call contracts.
+    pub contracts: bool,
+    /// Whether contracts may be WebAssembly modules, which only native builds run.
+    pub wasm_contracts: bool,
+    /// Whether blocks record the gas of their transactions, and charge their fees.
+    pub gas: bool,
+    /// Whether transactions may propose, vote on, and execute parameter changes.
+    pub governance: bool,
+    /// Whether blocks store the `Receipt`s of their transactions.
+    pub receipts: bool,
+    /// Whether blocks with transactions commit to the Merkle root of their IDs.
+    pub transactions_root: bool,
+    /// Whether transactions may be mined with time locks, checked against the block.
+    pub time_locks: bool,
+    /// Whether senders may only spend their tokens which are neither staked, nor locked by vesting.
+    pub spending: bool,
+    /// Whether transactions of addresses derived from a key are mined with the signature of the key.
+    pub signatures: bool,
+    /// Whether blocks mined by proof of work commit to the difficulty their hash meets.
+    pub difficulty: bool,
+}
+
+/// The rule set of every protocol version, by version.
+pub const RULE_SETS: [RuleSet; 3] = [
+    RuleSet {
+        version: 0,
+        legacy_hashes: true,
+        snapshots: false,
+        addresses: false,
+        multisig: false,
+        contracts: false,
+        wasm_contracts: false,
+        gas: false,
+        governance: false,
+        receipts: false,
+        transactions_root: false,
+        time_locks: false,
+        spending: false,
+        signatures: false,
+        difficulty: false,
+    },
+    // Legacy hashes cannot commit to a body
+    RuleSet {
+        version: 1,
+        legacy_hashes: false,
+        snapshots: true,
+        addresses: true,
+        multisig: true,
+        contracts: true,
+        wasm_contracts: false,
+        gas: true,
+        governance: true,
+        receipts: true,
+        transactions_root: true,
+        time_locks: true,
+        spending: true,
+        signatures: true,
+        difficulty: true,
+    },
+    // Only native builds can validate chains running WASM contracts
+    RuleSet {
+        version: 2,
+        legacy_hashes: false,
+        snapshots: true,
+        addresses: true,
+        multisig: true,
+        contracts: true,
+        wasm_contracts: true,
+        gas: true,
+        governance: true,
+        receipts: true,
+        transactions_root: true,
+        time_locks: true,
+        spending: true,
+        signatures: true,
+        difficulty: true,
+    },
+];
+
+/// The latest protocol version.
+pub const LATEST_VERSION: u32 = RULE_SETS[RULE_SETS.len() - 1].version;
+
+impl RuleSet {
+    /// Returns the rule set of the protocol `version`, if it is known.
+    pub fn of_version(version: u32) -> Option<&'static Self> {
+        RULE_SETS.iter().find(|rules| rules.version == version)
+    }
+
+    /// Checks the `event` belongs to a feature of the rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the feature, if the rules do not have it.
+    pub fn check_event(&self, event: &Events) -> Result<(), String> {
+        let (enabled, feature) = match event {
+            Events::AddSigner(..) | Events::RemoveSigner(..) => (self.multisig, "multisig"),
+            Events::DeployContract(_) | Events::CallContract(..) => (self.contracts, "contract"),
+            Events::Propose(..) | Events::Vote(..) | Events::Execute(_) => {
+                (self.governance, "governance")
+            }
+            _ => (true, ""),
+        };
+        if !enabled {
+            return Err(format!(
+                "Version {} does not support {} transactions",
+                self.version, feature
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the `full_block`, whose transactions have the IDs `transaction_ids`, follows the rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the block, and the rule it breaks.
+    pub fn validate_block(
+        &self,
+        full_block: &FullBlock,
+        transaction_ids: &[BlockHash],
+    ) -> Result<(), String> {
+        let block = &full_block.block;
+        let body = &full_block.body;
+        if !self.legacy_hashes && HashFormat::of(&block.hash) == HashFormat::Legacy {
+            return Err(format!(
+                "Block {} has a legacy hash, which version {} does not accept",
+                block.id, self.version
+            ));
+        }
+        // Stored receipts are checked against the replayed chain by `state::ChainState::apply_block`
+        let fields = [
+            (
+                self.snapshots,
+                body.snapshot_hash.is_some(),
+                "a snapshot hash",
+            ),
+            (
+                self.multisig,
+                !body.multisig.is_empty(),
+                "multisig witnesses",
+            ),
+            (
+                self.contracts,
+                body.contracts_hash.is_some(),
+                "a contracts hash",
+            ),
+            (self.gas, body.gas_used > 0, "gas"),
+            (self.receipts, !body.receipts.is_empty(), "receipts"),
+            (self.time_locks, !body.time_locks.is_empty(), "time locks"),
+            (
+                self.signatures,
+                !body.transaction_signatures.is_empty(),
+                "transaction signatures",
+            ),
+            (self.difficulty, body.difficulty > 0, "a difficulty"),
+        ];
+        if let Some((_, _, field)) = fields
+            .iter()
+            .find(|(enabled, present, _)| !enabled && *present)
+        {
+            return Err(format!(
+                "Block {} has {}, which version {} does not support",
+                block.id, field, self.version
+            ));
+        }
+        for transaction in &body.transactions {
+            self.check_event(&transaction.event)
+                .map_err(|e| format!("Block {}: {}", block.id, e))?;
+        }
+        validate_time_locks(full_block)?;
+        if self.signatures {
+            validate_signatures(full_block, transaction_ids)?;
+        }
+        let transactions_root = &full_block.body.transactions_root;
+        if !self.transactions_root && transactions_root.is_some() {
+            return Err(format!(
+                "Block {} has a transactions root, which version {} does not support",
+                block.id, self.version
+            ));
+        }
+        if self.transactions_root && *transactions_root != merkle_root(transaction_ids) {
+            return Err(format!(
+                "Block {} does not commit to the root of its transactions",
+                block.id
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The activation of a protocol version.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Upgrade {
+    /// The protocol version activated.
+    pub version: u32,
+    /// The id of the first block following the version.
+    pub height: u64,
+}
+
+/// Checks every upgrade activates a known version, later than the previous upgrade, in both version and height.
+pub fn validate_upgrades(upgrades: &[Upgrade]) -> Result<(), String> {
+    let mut previous = Upgrade {
+        version: 0,
+        height: 0,
+    };
+    for upgrade in upgrades {
+        if RuleSet::of_version(upgrade.version).is_none() {
+            return Err(format!(
+                "Protocol version {} is not supported",
+                upgrade.version
+            ));
+        }
+        if upgrade.version <= previous.version || upgrade.height <= previous.height {
+            return Err(format!(
+                "Protocol version {} must activate after version {}",
+                upgrade.version, previous.version
+            ));
+        }
+        previous = *upgrade;
+    }
+    Ok(())
+}
+
+/// Returns the protocol version active at block `height`.
+pub fn version_at(height: u64, config: &GenesisConfig) -> u32 {
+    config
+        .upgrades
+        .iter()
+        .filter(|upgrade| upgrade.height <= height)
+        .map(|upgrade| upgrade.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the protocol version active at block `height`, with the upgrades of the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const version = get_protocol_version(chain.length, config);
+/// ```
+///
+/// # Errors
+///
+/// If the config is not deserialisable, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_protocol_version(height: u64, config: JsValue) -> Result<u32, JsError> {
+    let config: GenesisConfig = config.into_serde()?;
+    Ok(version_at(height, &config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::{Account, AccountTrait},
+        block::Block,
+        hash::BlockHash,
+        state::ChainState,
+        timelock::{TimeLock, TransactionTimeLocks},
+        Transaction,
+    };
+    #[test]
+    fn versions_activate_at_their_height() {
+        let config = _fixture_config();
+        assert_eq!(version_at(0, &config), 0);
+        assert_eq!(version_at(4, &config), 0);
+        assert_eq!(version_at(5, &config), 1);
+        // By default, every block after the genesis block follows version 1
+        let config = GenesisConfig::default();
+        assert_eq!((version_at(0, &config), version_at(1, &config)), (0, 1));
+        assert_eq!(version_at(u64::MAX, &config), 1);
+    }
+    #[test]
+    fn blocks_are_validated_with_their_version() {
+        let config = _fixture_config();
+        let mut chain: Vec<FullBlock> = (0..7).map(_fixture_block).collect();
+        for full_block in &mut chain {
+            full_block.body.version = version_at(full_block.block.id, &config);
+            // From version 1, the data of a block is the Accounts its transactions change
+            if full_block.body.version > 0 {
+                full_block.block.data.clear();
+            }
+        }
+        // Legacy hashes are accepted before version 1 only
+        chain[4].block.hash = "0101".to_string();
+        assert_eq!(ChainState::from_chain(&chain, &config).map(|_| ()), Ok(()));
+        chain[6].block.hash = "0101".to_string();
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|_| ()),
+            Err("Block 6 has a legacy hash, which version 1 does not accept".to_string())
+        );
+        chain[6] = _fixture_block(6);
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|_| ()),
+            Err("Block 6 is version 0, but version 1 is active".to_string())
+        );
+    }
+    #[test]
+    fn features_are_gated_by_version() {
+        let config = _fixture_config();
+        let mut chain: Vec<FullBlock> = (0..2).map(_fixture_block).collect();
+        chain[1].body.transactions = vec![Transaction {
+            event: Events::Vote(0, true),
+            address: "Tom".to_string(),
+        }];
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|_| ()),
+            Err("Block 1: Version 0 does not support governance transactions".to_string())
+        );
+        chain[1].body.transactions = vec![];
+        chain[1].body.gas_used = 1000;
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|_| ()),
+            Err("Block 1 has gas, which version 0 does not support".to_string())
+        );
+        chain[1].body.gas_used = 0;
+        chain[1].body.time_locks = vec![TransactionTimeLocks {
+            index: 0,
+            valid_after: Some(TimeLock::Block(0)),
+            valid_until: None,
+        }];
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|_| ()),
+            Err("Block 1 has time locks, which version 0 does not support".to_string())
+        );
+        let rules = RuleSet::of_version(1).unwrap();
+        assert!(rules
+            .check_event(&Events::DeployContract("00".to_string()))
+            .is_ok());
+    }
+    #[test]
+    fn upgrades_must_be_ordered_and_known() {
+        let upgrade = |version, height| Upgrade { version, height };
+        assert_eq!(validate_upgrades(&[upgrade(1, 5)]), Ok(()));
+        assert_eq!(
+            validate_upgrades(&[upgrade(LATEST_VERSION + 1, 5)]),
+            Err(format!(
+                "Protocol version {} is not supported",
+                LATEST_VERSION + 1
+            ))
+        );
+        assert!(validate_upgrades(&[upgrade(1, 0)]).is_err());
+        assert!(validate_upgrades(&[upgrade(1, 5), upgrade(1, 6)]).is_err());
+    }
+
+    /// Version 1 activates at block 5.
+    fn _fixture_config() -> GenesisConfig {
+        GenesisConfig {
+            upgrades: vec![Upgrade {
+                version: 1,
+                height: 5,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn _fixture_block(id: u64) -> FullBlock {
+        FullBlock::from(Block {
+            id,
+            hash: BlockHash::default().to_hex(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![Account::new("Tom")],
+            nonce: 0,
+            next_miner: "Tom".to_string(),
+            next_validators: vec![],
+        })
+    }
+}
@@ -0,0 +1,566 @@
+//! # Receipt
+//!
+//! Every mined transaction has a `Receipt`: whether it succeeded, why it failed, the state it changed, and the gas and
+//! fee it cost, at its position in its block. Clients confirm their transactions by the `tx_hash` of the receipt.
+//!
+//! Receipts follow from replaying the chain. Blocks following a protocol version with receipts also store them, as the
+//! `receipts` of their `BlockBody`, and validation checks they match the replayed ones.
+//!
+//! **Note:** A failed transaction is still mined, without effect, so it has a receipt, and pays its fee. Transactions rejected
+//! by `gas::select_transactions` are not mined, and have none. The changes to `Account`s are the `data` of the block,
+//! so the state changes of a receipt are those of contracts and governance.
+
+use crate::{
+    account::Account,
+    body::FullBlock,
+    contract::{contract_address, ContractState, Outcome},
+    explorer::Explorer,
+    gas::{fee, transaction_gas},
+    genesis::GenesisConfig,
+    governance::{GovernanceState, Parameter},
+    hash::BlockHash,
+    ledger::{apply_transfers, spend_transaction},
+    protocol::{version_at, RuleSet},
+    sequence::Sequences,
+    state::ChainState,
+    vm::{Storage, OUT_OF_GAS},
+    Events, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// Whether a transaction had an effect.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    Success,
+    /// The transaction was mined without effect.
+    Failed,
+}
+
+/// Why a transaction failed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The code of a deployment is not valid bytecode, or a valid module.
+    InvalidContract,
+    /// The called address is not a contract.
+    UnknownContract,
+    /// The contract call ran out of gas.
+    OutOfGas,
+    /// The contract call failed, or reverted.
+    ExecutionFailed,
+    /// The proposal, vote, or execution cannot be applied.
+    GovernanceRejected,
+}
+
+/// A change of the contract or governance state, made by a transaction.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum StateChange {
+    ContractDeployed {
+        address: String,
+    },
+    /// A storage key of a contract was set. A removed key reads as `0`.
+    StorageWritten {
+        contract: String,
+        key: u64,
+        value: u64,
+    },
+    TokensTransferred {
+        from: String,
+        to: String,
+        amount: u64,
+    },
+    EventEmitted {
+        contract: String,
+        event: String,
+    },
+    ProposalCreated {
+        id: u64,
+    },
+    VoteCast {
+        proposal: u64,
+        in_favour: bool,
+    },
+    ParameterChanged {
+        proposal: u64,
+        parameter: Parameter,
+        value: u64,
+        active_from: u64,
+    },
+}
+
+/// The result of a mined transaction.
+///
+/// **Note:** No field is skipped when serialising, as receipts are committed to by the canonical encoding of the block.
+/// So a failure is only recorded by its `error_code`: error messages, such as the traps of WASM contracts, may differ between
+/// builds. The message is returned by the `DetailedReceipt`s of `block_receipts`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// The ID of the transaction. See `sequence::transaction_id`.
+    pub tx_hash: BlockHash,
+    /// The id of the block holding the transaction.
+    pub block_id: u64,
+    /// The position of the transaction in the block.
+    pub index: usize,
+    pub status: ReceiptStatus,
+    /// Why the transaction failed, if it did.
+    pub error_code: Option<ErrorCode>,
+    /// The value returned by a successful contract call.
+    pub output: Option<u64>,
+    /// The changes made, in order. Empty for a failed transaction.
+    pub state_changes: Vec<StateChange>,
+    /// The gas the transaction cost. See `gas::transaction_gas`.
+    pub gas_used: u64,
+    /// The fee, in tokens, paid by the sender.
+    pub fee: u64,
+}
+
+/// A `Receipt`, with the error message of a failed transaction, as replayed by this build.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DetailedReceipt {
+    #[serde(flatten)]
+    pub receipt: Receipt,
+    /// The error message of a failed transaction.
+    pub error: Option<String>,
+}
+
+/// Returns why a transaction with the `outcome` failed, given the result of applying it to the governance state.
+fn failure(
+    transaction: &Transaction,
+    outcome: &Outcome,
+    governance: Result<(), String>,
+) -> Option<(ErrorCode, String)> {
+    match (&transaction.event, outcome) {
+        (Events::DeployContract(_), Err(error)) => {
+            Some((ErrorCode::InvalidContract, error.clone()))
+        }
+        (_, Err(error)) => Some((ErrorCode::UnknownContract, error.clone())),
+        (_, Ok(Some(execution))) => match &execution.result {
+            Err(error) if error == OUT_OF_GAS => Some((ErrorCode::OutOfGas, error.clone())),
+            Err(error) => Some((ErrorCode::ExecutionFailed, error.clone())),
+            Ok(_) => None,
+        },
+        (_, Ok(None)) => governance
+            .err()
+            .map(|error| (ErrorCode::GovernanceRejected, error)),
+    }
+}
+
+/// Returns the keys of `after` whose value differs from `before`, with their new value.
+fn storage_changes(contract: &str, before: &Storage, after: &Storage) -> Vec<StateChange> {
+    let removed = before
+        .keys()
+        .filter(|key| !after.contains_key(key))
+        .map(|&key| (key, 0));
+    let written = after
+        .iter()
+        .filter(|(key, value)| before.get(key) != Some(value))
+        .map(|(&key, &value)| (key, value));
+    let mut changes: Vec<_> = removed.chain(written).collect();
+    changes.sort_unstable();
+    changes
+        .into_iter()
+        .map(|(key, value)| StateChange::StorageWritten {
+            contract: contract.to_string(),
+            key,
+            value,
+        })
+        .collect()
+}
+
+/// Applies the transactions of the `full_block` to the `contracts`, `governance`, and `sequences` states before it, and
+/// returns the receipt of every transaction, in order, with its error message.
+///
+/// From the protocol version with spending checks, each transaction also spends from the `accounts`, which hold the
+/// Accounts before the block: see `ledger::spend_transaction`. So, a contract call reads the balance of its caller as the
+/// transactions before it left it. A call transferring more tokens than its caller can spend, once its fee is paid, fails.
+///
+/// # Errors
+///
+/// Returns an error if a transaction has no canonical encoding, or a sender cannot spend the tokens of its transaction.
+/// The states may then be partly applied. In a WASM build, a block running a WASM contract returns a node capability
+/// error, see `ContractState::check_supported`.
+pub fn apply_block(
+    full_block: &FullBlock,
+    contracts: &mut ContractState,
+    governance: &mut GovernanceState,
+    sequences: &mut Sequences,
+    accounts: &mut BTreeMap<String, Account>,
+    config: &GenesisConfig,
+) -> Result<Vec<DetailedReceipt>, String> {
+    contracts.check_supported(full_block)?;
+    let block_id = full_block.block.id;
+    let transactions = &full_block.body.transactions;
+    let hashes = sequences.apply_block(full_block)?;
+    let active = governance.config_at(block_id, config);
+    let version = version_at(block_id, config);
+    let rules = RuleSet::of_version(version)
+        .ok_or_else(|| format!("Protocol version {} is not supported", version))?;
+    // Governance weights are read from the Accounts before the block
+    let before = accounts.clone();
+    let mut receipts = vec![];
+    for (index, (transaction, tx_hash)) in transactions.iter().zip(hashes).enumerate() {
+        let caller = accounts.get(&transaction.address);
+        let storage_before = match &transaction.event {
+            Events::CallContract(address, _) => contracts
+                .contract(address)
+                .map(|contract| contract.storage.clone()),
+            _ => None,
+        };
+        // Run contract transactions on a copy, kept only if the call can make its transfers
+        let mut next_contracts = contracts.clone();
+        let mut outcome =
+            next_contracts.apply_transaction(transaction, index, block_id, caller, &active);
+        let applied = governance.apply_transaction(transaction, block_id, &before, config);
+        // Transactions cost no gas before the protocol version charges it
+        let gas_used = match rules.gas {
+            true => transaction_gas(transaction, &outcome),
+            false => 0,
+        };
+        let fee = fee(gas_used, &active);
+        let mut transferred = Ok(());
+        if rules.spending {
+            spend_transaction(accounts, transaction, fee, block_id, &active)
+                .map_err(|e| format!("Block {} transaction {}: {}", block_id, index, e))?;
+            if let Ok(Some(execution)) = &mut outcome {
+                transferred = apply_transfers(
+                    accounts,
+                    &transaction.address,
+                    &execution.transfers,
+                    block_id,
+                    &active,
+                );
+                if let Err(error) = &transferred {
+                    execution.fail(error.clone());
+                }
+            }
+        }
+        if transferred.is_ok() {
+            *contracts = next_contracts;
+        }
+        let mut receipt = Receipt {
+            tx_hash,
+            block_id,
+            index,
+            status: ReceiptStatus::Success,
+            error_code: None,
+            output: None,
+            state_changes: vec![],
+            gas_used,
+            fee,
+        };
+        if let Some((error_code, error)) = failure(transaction, &outcome, applied) {
+            receipt.status = ReceiptStatus::Failed;
+            receipt.error_code = Some(error_code);
+            receipts.push(DetailedReceipt {
+                receipt,
+                error: Some(error),
+            });
+            continue;
+        }
+        let changes = &mut receipt.state_changes;
+        match &transaction.event {
+            Events::DeployContract(_) => changes.push(StateChange::ContractDeployed {
+                address: contract_address(block_id, index).into(),
+            }),
+            Events::CallContract(address, _) => {
+                let execution = outcome
+                    .expect("successful call to have an outcome")
+                    .expect("successful call to have an execution");
+                let storage_after = &contracts
+                    .contract(address)
+                    .expect("contract to exist")
+                    .storage;
+                let before = storage_before.unwrap_or_default();
+                changes.extend(storage_changes(address, &before, storage_after));
+                changes.extend(execution.transfers.into_iter().map(|transfer| {
+                    StateChange::TokensTransferred {
+                        from: transaction.address.clone(),
+                        to: transfer.to,
+                        amount: transfer.amount,
+                    }
+                }));
+                changes.extend(execution.events.into_iter().map(|event| {
+                    StateChange::EventEmitted {
+                        contract: address.clone(),
+                        event,
+                    }
+                }));
+                receipt.output = execution.result.expect("successful call to have a result");
+            }
+            Events::Propose(..) => changes.push(StateChange::ProposalCreated {
+                id: governance.proposals.len() as u64 - 1,
+            }),
+            Events::Vote(id, in_favour) => changes.push(StateChange::VoteCast {
+                proposal: *id,
+                in_favour: *in_favour,
+            }),
+            Events::Execute(id) => {
+                let change = governance
+                    .changes
+                    .last()
+                    .expect("execution to add a change");
+                changes.push(StateChange::ParameterChanged {
+                    proposal: *id,
+                    parameter: change.parameter,
+                    value: change.value,
+                    active_from: change.active_from,
+                });
+            }
+            _ => {}
+        }
+        receipts.push(DetailedReceipt {
+            receipt,
+            error: None,
+        });
+    }
+    Ok(receipts)
+}
+
+/// Returns the receipts of the transactions of the `full_block` following the `ancestors`, with their error messages.
+///
+/// # Errors
+///
+/// Returns an error if the `ancestors` are invalid, or the block cannot be applied.
+pub fn block_receipts(
+    full_block: &FullBlock,
+    ancestors: &[FullBlock],
+    config: &GenesisConfig,
+) -> Result<Vec<DetailedReceipt>, String> {
+    let ChainState {
+        mut accounts,
+        mut sequences,
+        mut contracts,
+        mut governance,
+        ..
+    } = ChainState::from_chain(ancestors, config)?;
+    apply_block(
+        full_block,
+        &mut contracts,
+        &mut governance,
+        &mut sequences,
+        &mut accounts,
+        config,
+    )
+}
+
+/// Returns the receipts of the transactions of the block with the `block_id` argument, in the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const receipts = get_receipts(chain, 12, config);
+/// const failed = receipts.filter(({ status }) => status === "Failed");
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, the block does not exist, or the chain cannot be replayed, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_receipts(chain: JsValue, block_id: u64, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let height = usize::try_from(block_id)
+        .ok()
+        .filter(|&height| height < chain.len())
+        .ok_or_else(|| JsError::new(&format!("Block {} does not exist", block_id)))?;
+    let receipts =
+        block_receipts(&chain[height], &chain[..height], &config).map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&receipts)?)
+}
+
+/// Returns the receipt of the transaction with the hex `tx_hash` argument, in the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const { status, error_code, error, fee } = get_receipt(chain, txHash, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, the transaction is not mined, or the chain cannot be replayed, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_receipt(chain: JsValue, tx_hash: String, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let explorer = Explorer::from_chain(&chain, config.clone()).map_err(|e| JsError::new(&e))?;
+    let location = explorer
+        .transaction_by_hash(&tx_hash)
+        .ok_or_else(|| JsError::new(&format!("Transaction {} is not mined", tx_hash)))?;
+    let height = location.block_id as usize;
+    let receipts =
+        block_receipts(&chain[height], &chain[..height], &config).map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&receipts[location.index])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait, assembly::assemble_block, block::Block, gas::TRANSACTION_GAS,
+        sequence::transaction_id, timelock::TransactionTimeLocks, vm,
+    };
+    #[test]
+    fn receipts_record_changes_and_failures() {
+        let config = GenesisConfig::default();
+        let (chain, receipts) = _fixture_chain(&config);
+        let counter = contract_address(1, 0).to_string();
+        let deploy = &receipts[1][0];
+        assert_eq!(deploy.status, ReceiptStatus::Success);
+        assert_eq!(
+            deploy.state_changes,
+            vec![StateChange::ContractDeployed {
+                address: counter.clone()
+            }]
+        );
+        assert_eq!(
+            deploy.tx_hash,
+            transaction_id(
+                &config.hash(),
+                0,
+                &chain[1].body.transactions[0],
+                &TransactionTimeLocks::default()
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            receipts[1][1].state_changes,
+            vec![StateChange::ProposalCreated { id: 0 }]
+        );
+
+        let call = &receipts[2][0];
+        assert_eq!((call.block_id, call.index), (2, 0));
+        assert_eq!(
+            call.state_changes,
+            vec![StateChange::StorageWritten {
+                contract: counter,
+                key: 0,
+                value: 7
+            }]
+        );
+        assert!(call.gas_used > TRANSACTION_GAS);
+        assert_eq!(call.fee, fee(call.gas_used, &config));
+
+        let unknown = &receipts[2][1];
+        assert_eq!(unknown.status, ReceiptStatus::Failed);
+        assert_eq!(unknown.error_code, Some(ErrorCode::UnknownContract));
+
+        assert!(unknown.state_changes.is_empty());
+        assert_eq!(
+            receipts[2][2].state_changes,
+            vec![StateChange::VoteCast {
+                proposal: 0,
+                in_favour: true
+            }]
+        );
+        assert_eq!(
+            receipts[2][3].error_code,
+            Some(ErrorCode::GovernanceRejected)
+        );
+
+        // Error messages are returned alongside the receipts, but not committed to
+        let detailed = block_receipts(&chain[2], &chain[..2], &config).unwrap();
+        assert_eq!(detailed[1].receipt, *unknown);
+        assert_eq!(
+            detailed[1].error,
+            Some("'fcc1' is not a contract".to_string())
+        );
+        assert_eq!(detailed[2].error, None);
+        assert_eq!(
+            detailed[3].error,
+            Some("Proposal 5 does not exist".to_string())
+        );
+        assert_eq!(chain[2].body.receipts, receipts[2]);
+    }
+    #[test]
+    fn calls_out_of_gas_have_error_code() {
+        let config = GenesisConfig {
+            contract_gas_limit: 2,
+            ..Default::default()
+        };
+        let (chain, receipts) = _fixture_chain(&config);
+        assert_eq!(receipts[2][0].error_code, Some(ErrorCode::OutOfGas));
+        assert_eq!(receipts[2][0].gas_used, TRANSACTION_GAS + 2);
+        assert_eq!(chain[2].body.receipts, receipts[2]);
+    }
+    #[test]
+    fn stored_receipts_are_validated() {
+        let config = GenesisConfig::default();
+        let (mut chain, receipts) = _fixture_chain(&config);
+        assert_eq!(chain[2].body.receipts, receipts[2]);
+        assert!(ChainState::from_chain(&chain, &config).is_ok());
+        chain[2].body.receipts[1].status = ReceiptStatus::Success;
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 2 does not store the receipts of its transactions".to_string())
+        );
+
+        // The genesis block is version 0, which does not store receipts
+        chain[0].body.receipts = receipts[1].clone();
+        assert_eq!(
+            ChainState::from_chain(&chain[..1], &config),
+            Err("Block 0 has receipts, which version 0 does not support".to_string())
+        );
+    }
+
+    fn _fixture_transaction(event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: "Tom".to_string(),
+        }
+    }
+
+    /// Tom, who has staked tokens, deploys a contract storing its argument, and proposes a new rack price, in block 1.
+    /// In block 2, Tom calls the contract with `7`, calls an address which is not a contract, votes for his proposal,
+    /// and votes for a proposal which does not exist.
+    fn _fixture_chain(config: &GenesisConfig) -> (Vec<FullBlock>, Vec<Vec<Receipt>>) {
+        let code = vm::assemble("PUSH 0 ARG PUSH 0 STORE STOP").unwrap();
+        let counter = contract_address(1, 0).to_string();
+        let transactions = [
+            vec![],
+            vec![
+                _fixture_transaction(Events::DeployContract(hex::encode(code))),
+                _fixture_transaction(Events::Propose("rack_price".to_string(), 20)),
+            ],
+            vec![
+                _fixture_transaction(Events::CallContract(counter, vec![7])),
+                _fixture_transaction(Events::CallContract("fcc1".to_string(), vec![])),
+                _fixture_transaction(Events::Vote(0, true)),
+                _fixture_transaction(Events::Vote(5, true)),
+            ],
+        ];
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let mut chain = vec![];
+        let mut receipts = vec![];
+        for (id, transactions) in transactions.into_iter().enumerate() {
+            let block = Block {
+                id: id as u64,
+                hash: BlockHash::default().to_hex(),
+                previous_hash: String::new(),
+                timestamp: 1000,
+                data: match id {
+                    0 => vec![tom.clone()],
+                    _ => vec![],
+                },
+                nonce: 0,
+                next_miner: "Tom".to_string(),
+                next_validators: vec![],
+            };
+            let transactions = transactions.into_iter().map(Into::into).collect();
+            let assembled = assemble_block(&chain, block, transactions, config).unwrap();
+            assert!(assembled.rejected.is_empty());
+            receipts.push(
+                assembled
+                    .receipts
+                    .into_iter()
+                    .map(|detailed| detailed.receipt)
+                    .collect(),
+            );
+            chain.push(assembled.full_block);
+        }
+        (chain, receipts)
+    }
+}
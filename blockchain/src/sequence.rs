@@ -0,0 +1,339 @@
+//! # Sequence
+//!
+//! Every transaction of an Account has a sequence number: the number of transactions of its address mined before it,
+//! counting from `0`. The sequence is not stored in the `Transaction`, but follows from its position in the chain.
+//!
+//! A transaction is identified by its ID, the digest of the hash of the `GenesisConfig` of its chain, its address,
+//! its sequence, its event, and its time locks, if any. See `transaction_id`. So, identical transactions of the same Account have different IDs,
+//! and a signature over an ID is only valid for one chain, and one position in the history of the Account.
+//!
+//! Clients find the sequence of a new transaction with `get_next_sequence`.
+
+use crate::{
+    body::FullBlock,
+    codec,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    timelock::{block_time_locks, TransactionTimeLocks},
+    Transaction,
+};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// Returns the ID of the `transaction` with the given `sequence` and `time_locks`, in the chain whose `GenesisConfig` has
+/// the `genesis_hash`.
+///
+/// # Errors
+///
+/// Returns an error if the transaction has no canonical encoding.
+pub fn transaction_id(
+    genesis_hash: &str,
+    sequence: u64,
+    transaction: &Transaction,
+    time_locks: &TransactionTimeLocks,
+) -> Result<BlockHash, String> {
+    let digest = codec::hash_transaction_id(genesis_hash, sequence, transaction, time_locks)
+        .map_err(|e| e.to_string())?;
+    BlockHash::from_slice(&digest)
+}
+
+/// Checks the `sequence` signed for a transaction of the `address` is the `expected` next sequence of the address.
+///
+/// # Errors
+///
+/// Returns an error if the sequence is already used, or skips a sequence.
+pub fn check_sequence(address: &str, sequence: u64, expected: u64) -> Result<(), String> {
+    if sequence < expected {
+        return Err(format!(
+            "Sequence {} of '{}' is already used",
+            sequence, address
+        ));
+    }
+    if sequence > expected {
+        return Err(format!(
+            "Sequence {} of '{}' skips sequence {}",
+            sequence, address, expected
+        ));
+    }
+    Ok(())
+}
+
+/// The next sequence of every Account of a chain, by address.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Sequences {
+    genesis_hash: String,
+    next: BTreeMap<String, u64>,
+}
+
+impl Sequences {
+    /// Creates the sequences of a chain without transactions, configured by the `config`.
+    pub fn new(config: &GenesisConfig) -> Self {
+        Self {
+            genesis_hash: config.hash(),
+            next: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the sequences of a chain configured by the `config`, from the `next` sequence of every address.
+    pub fn from_next(config: &GenesisConfig, next: BTreeMap<String, u64>) -> Self {
+        Self {
+            genesis_hash: config.hash(),
+            next,
+        }
+    }
+
+    /// Returns the next sequence of every address with transactions.
+    pub fn all_next(&self) -> &BTreeMap<String, u64> {
+        &self.next
+    }
+
+    /// Counts the transactions of every Account of the `chain`.
+    pub fn from_chain(chain: &[FullBlock], config: &GenesisConfig) -> Self {
+        let mut sequences = Self::new(config);
+        for full_block in chain {
+            for transaction in &full_block.body.transactions {
+                sequences.advance(&transaction.address);
+            }
+        }
+        sequences
+    }
+
+    /// Returns the hash of the `GenesisConfig` transaction IDs are bound to.
+    pub fn genesis_hash(&self) -> &str {
+        &self.genesis_hash
+    }
+
+    /// Returns the sequence of the next transaction of the `address`.
+    pub fn next(&self, address: &str) -> u64 {
+        self.next.get(address).copied().unwrap_or(0)
+    }
+
+    fn advance(&mut self, address: &str) {
+        *self.next.entry(address.to_string()).or_default() += 1;
+    }
+
+    /// Returns the ID the `transaction`, with the given `time_locks`, would have, if it were the next transaction of its address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction has no canonical encoding.
+    pub fn next_id(
+        &self,
+        transaction: &Transaction,
+        time_locks: &TransactionTimeLocks,
+    ) -> Result<BlockHash, String> {
+        transaction_id(
+            &self.genesis_hash,
+            self.next(&transaction.address),
+            transaction,
+            time_locks,
+        )
+    }
+
+    /// Counts the `transaction`, with the given `time_locks`, as the next transaction of its address, and returns its ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction has no canonical encoding. The sequences are then unchanged.
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &Transaction,
+        time_locks: &TransactionTimeLocks,
+    ) -> Result<BlockHash, String> {
+        let id = self.next_id(transaction, time_locks)?;
+        self.advance(&transaction.address);
+        Ok(id)
+    }
+
+    /// Counts the transactions of the `full_block`, and returns their IDs, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a transaction has no canonical encoding. The sequences are then unchanged.
+    pub fn apply_block(&mut self, full_block: &FullBlock) -> Result<Vec<BlockHash>, String> {
+        let mut next = self.clone();
+        let ids = full_block
+            .body
+            .transactions
+            .iter()
+            .zip(block_time_locks(full_block))
+            .map(|(transaction, time_locks)| next.apply_transaction(transaction, &time_locks))
+            .collect::<Result<Vec<_>, _>>()?;
+        *self = next;
+        Ok(ids)
+    }
+}
+
+/// Returns the IDs of the transactions of the `full_block` following the `ancestors`, in order.
+///
+/// # Errors
+///
+/// Returns an error if a transaction has no canonical encoding.
+pub fn block_transaction_ids(
+    full_block: &FullBlock,
+    ancestors: &[FullBlock],
+    config: &GenesisConfig,
+) -> Result<Vec<BlockHash>, String> {
+    Sequences::from_chain(ancestors, config).apply_block(full_block)
+}
+
+/// Returns the sequence of the next transaction of the `address` argument, after the last block of the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const sequence = get_next_sequence(chain, wallet.address(), config);
+/// const signed = wallet.sign_transaction({ event: "Stake", address: wallet.address() }, sequence, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_next_sequence(chain: JsValue, address: String, config: JsValue) -> Result<u64, JsError> {
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    Ok(Sequences::from_chain(&chain, &config).next(&address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::Block, timelock::TimeLock, Events};
+    #[test]
+    fn identical_transactions_have_different_ids() {
+        let config = GenesisConfig::default();
+        let mut sequences = Sequences::new(&config);
+        let ids = sequences.apply_block(&_fixture_block()).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(
+            ids[0],
+            transaction_id(
+                &config.hash(),
+                0,
+                &_fixture_stake("Tom"),
+                &TransactionTimeLocks::default()
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            ids[1],
+            transaction_id(
+                &config.hash(),
+                1,
+                &_fixture_stake("Tom"),
+                &TransactionTimeLocks::default()
+            )
+            .unwrap()
+        );
+        // Sequences are counted per address
+        assert_eq!(
+            ids[2],
+            transaction_id(
+                &config.hash(),
+                0,
+                &_fixture_stake("Shaun"),
+                &TransactionTimeLocks::default()
+            )
+            .unwrap()
+        );
+        assert_eq!((sequences.next("Tom"), sequences.next("Shaun")), (2, 1));
+        assert_eq!(
+            sequences,
+            Sequences::from_chain(&[_fixture_block()], &config)
+        );
+        assert_eq!(
+            sequences.next_id(&_fixture_stake("Tom"), &TransactionTimeLocks::default()),
+            transaction_id(
+                &config.hash(),
+                2,
+                &_fixture_stake("Tom"),
+                &TransactionTimeLocks::default()
+            )
+        );
+    }
+    #[test]
+    fn ids_are_bound_to_their_chain() {
+        let other = GenesisConfig {
+            starting_tokens: 1,
+            ..Default::default()
+        };
+        let stake = _fixture_stake("Tom");
+        let unlocked = TransactionTimeLocks::default();
+        assert_ne!(
+            transaction_id(&GenesisConfig::default().hash(), 0, &stake, &unlocked),
+            transaction_id(&other.hash(), 0, &stake, &unlocked)
+        );
+    }
+    #[test]
+    fn ids_are_bound_to_their_time_locks() {
+        let config = GenesisConfig::default();
+        let stake = _fixture_stake("Tom");
+        let unlocked = TransactionTimeLocks::default();
+        let locked = TransactionTimeLocks {
+            index: 0,
+            valid_after: Some(TimeLock::Block(10)),
+            valid_until: None,
+        };
+        let id = transaction_id(&config.hash(), 0, &stake, &locked);
+        assert_ne!(id, transaction_id(&config.hash(), 0, &stake, &unlocked));
+        // The index of the transaction in its block is not part of the id
+        let moved = TransactionTimeLocks { index: 4, ..locked };
+        assert_eq!(id, transaction_id(&config.hash(), 0, &stake, &moved));
+        // A lock until a block is not a lock after it
+        let until = TransactionTimeLocks {
+            index: 0,
+            valid_after: None,
+            valid_until: Some(TimeLock::Block(10)),
+        };
+        assert_ne!(id, transaction_id(&config.hash(), 0, &stake, &until));
+
+        let mut full_block = _fixture_block();
+        full_block.body.time_locks = vec![locked];
+        let ids = Sequences::new(&config).apply_block(&full_block).unwrap();
+        assert_eq!(ids[0], id.unwrap());
+        assert_eq!(
+            ids[1],
+            transaction_id(&config.hash(), 1, &stake, &unlocked).unwrap()
+        );
+    }
+    #[test]
+    fn sequences_cannot_be_reused_or_skipped() {
+        assert_eq!(check_sequence("Tom", 1, 1), Ok(()));
+        assert_eq!(
+            check_sequence("Tom", 0, 1),
+            Err("Sequence 0 of 'Tom' is already used".to_string())
+        );
+        assert_eq!(
+            check_sequence("Tom", 2, 1),
+            Err("Sequence 2 of 'Tom' skips sequence 1".to_string())
+        );
+    }
+
+    fn _fixture_stake(address: &str) -> Transaction {
+        Transaction {
+            event: Events::Stake,
+            address: address.to_string(),
+        }
+    }
+    /// Tom stakes twice, then Shaun stakes.
+    fn _fixture_block() -> FullBlock {
+        let mut full_block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![],
+            nonce: 0,
+            next_miner: "Tom".to_string(),
+            next_validators: vec![],
+        });
+        full_block.body.transactions = vec![
+            _fixture_stake("Tom"),
+            _fixture_stake("Tom"),
+            _fixture_stake("Shaun"),
+        ];
+        full_block
+    }
+}
@@ -0,0 +1,369 @@
+//! # Slot
+//!
+//! Time is divided into slots of `slot_duration` seconds, counted from the timestamp of the genesis block, and slots are
+//! grouped into epochs of `epoch_length` slots. The genesis block occupies slot `0`.
+//!
+//! In proof of stake mode, every slot has one leader, elected from the validator set of its epoch. The leader may produce
+//! at most one block in its slot. Slots in which no block is produced are missed, and counted by the next block: they are
+//! the slots between the slot of the previous block and its own.
+//!
+//! The validator set of an epoch is taken from a snapshot of the stake at the start of the epoch. So, staking and unstaking
+//! only change the validators, and the leaders they are elected from, at the next epoch boundary.
+
+use crate::{
+    account::Account,
+    body::{FullBlock, FullChain},
+    consensus::slot_of,
+    genesis::GenesisConfig,
+    snapshot::latest_accounts,
+};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+
+/// Converts between timestamps, slots, and epochs, for a chain with the given genesis timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotClock {
+    /// The timestamp of the genesis block, at the start of slot `0`.
+    pub genesis_timestamp: u64,
+    /// The number of seconds in a slot.
+    pub slot_duration: u64,
+    /// The number of slots in an epoch.
+    pub epoch_length: u64,
+}
+
+impl SlotClock {
+    /// Creates the clock of a chain whose genesis block has the given `genesis_timestamp`.
+    pub fn new(genesis_timestamp: u64, config: &GenesisConfig) -> Self {
+        Self {
+            genesis_timestamp,
+            slot_duration: config.slot_duration.max(1),
+            epoch_length: config.epoch_length.max(1),
+        }
+    }
+
+    /// Creates the clock of the `chain`, from the timestamp of its genesis block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chain is empty.
+    pub fn of_chain(chain: &[FullBlock], config: &GenesisConfig) -> Result<Self, String> {
+        let genesis = chain.first().ok_or("Chain is too short")?;
+        Ok(Self::new(genesis.block.timestamp, config))
+    }
+
+    /// Returns the slot the `timestamp` falls in.
+    ///
+    /// **Note:** Timestamps before the genesis block fall in slot `0`. Use `contains` to check a timestamp is within its slot.
+    pub fn slot_of(&self, timestamp: u64) -> u64 {
+        slot_of(timestamp, self.genesis_timestamp, self.slot_duration)
+    }
+
+    /// Returns the timestamp the `slot` starts at, or `None` if it is past the last timestamp.
+    pub fn slot_start(&self, slot: u64) -> Option<u64> {
+        slot.checked_mul(self.slot_duration)?
+            .checked_add(self.genesis_timestamp)
+    }
+
+    /// Check if the `timestamp` is within the `slot`.
+    pub fn contains(&self, slot: u64, timestamp: u64) -> bool {
+        self.slot_start(slot).is_some_and(|start| {
+            timestamp
+                .checked_sub(start)
+                .is_some_and(|offset| offset < self.slot_duration)
+        })
+    }
+
+    /// Returns the epoch the `slot` belongs to.
+    pub fn epoch_of(&self, slot: u64) -> u64 {
+        slot / self.epoch_length
+    }
+
+    /// Returns the first slot of the `epoch`.
+    pub fn epoch_start(&self, epoch: u64) -> u64 {
+        epoch * self.epoch_length
+    }
+
+    /// Returns the number of slots after the `previous_slot` and before the `slot`, in which no block was produced.
+    pub fn missed_slots(&self, previous_slot: u64, slot: u64) -> u64 {
+        slot.saturating_sub(previous_slot).saturating_sub(1)
+    }
+}
+
+/// Returns the blocks of the `chain` produced before the `epoch` starts. The genesis block is always included.
+fn blocks_before_epoch(
+    chain: &[FullBlock],
+    epoch: u64,
+    clock: SlotClock,
+) -> impl Iterator<Item = &FullBlock> {
+    let start = clock.epoch_start(epoch);
+    chain
+        .iter()
+        .enumerate()
+        .take_while(move |(i, full_block)| {
+            *i == 0 || clock.slot_of(full_block.block.timestamp) < start
+        })
+        .map(|(_, full_block)| full_block)
+}
+
+/// Returns the latest version of every `Account`, as of the start of the `epoch`.
+pub fn stake_snapshot(chain: &[FullBlock], epoch: u64, clock: &SlotClock) -> Vec<Account> {
+    latest_accounts(blocks_before_epoch(chain, epoch, *clock))
+}
+
+/// Returns the validator set of the `epoch`: the `validator_count` Accounts with the most stake at the start of the epoch.
+///
+/// Accounts with equal stake are ordered by address.
+///
+/// **Note:** If no Account has any stake, the `default_miner` of the config is the only validator.
+pub fn epoch_validators(
+    chain: &[FullBlock],
+    epoch: u64,
+    clock: &SlotClock,
+    config: &GenesisConfig,
+) -> Vec<Account> {
+    let mut validators: Vec<Account> = stake_snapshot(chain, epoch, clock)
+        .into_iter()
+        .filter(|account| account.staked > 0)
+        .collect();
+    validators.sort_by(|a, b| b.staked.cmp(&a.staked).then(a.address.cmp(&b.address)));
+    validators.truncate(config.validator_count);
+    if validators.is_empty() {
+        validators.push(config.new_account(&config.default_miner));
+    }
+    validators
+}
+
+/// The number of blocks before an epoch whose hashes are mixed into its seed.
+pub const SEED_BLOCKS: usize = 8;
+
+/// Returns the randomness leaders of the `epoch` are elected with: the digest of the hashes of the last `SEED_BLOCKS`
+/// blocks before the epoch.
+///
+/// **Note:** The producer of the last block before the epoch only controls one of the mixed hashes. The others are fixed
+/// by earlier producers, so grinding a single block hash cannot pick the leaders of the epoch.
+pub fn epoch_seed(chain: &[FullBlock], epoch: u64, clock: &SlotClock) -> [u8; 32] {
+    let blocks: Vec<&FullBlock> = blocks_before_epoch(chain, epoch, *clock).collect();
+    let mut hasher = Sha256::new();
+    for full_block in &blocks[blocks.len().saturating_sub(SEED_BLOCKS)..] {
+        let hash = Sha256::digest(full_block.block.hash.as_bytes());
+        hasher.update(hash);
+    }
+    hasher.update(epoch.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Elects the leader of the `slot`, by weighted selection on the stake of the validators of its epoch.
+///
+/// **Note:** The election only depends on blocks before the epoch of the slot, so every leader of an epoch is known at its start.
+///
+/// # Errors
+///
+/// Returns an error if the validators of the epoch have no weight to elect from.
+pub fn slot_leader(
+    chain: &[FullBlock],
+    slot: u64,
+    clock: &SlotClock,
+    config: &GenesisConfig,
+) -> Result<String, String> {
+    let epoch = clock.epoch_of(slot);
+    let validators = epoch_validators(chain, epoch, clock, config);
+    let mut hasher = Sha256::new();
+    hasher.update(epoch_seed(chain, epoch, clock));
+    hasher.update(slot.to_be_bytes());
+    let digest = hasher.finalize();
+    let random = u128::from_be_bytes(digest[..16].try_into().expect("digest to be 32 bytes"));
+
+    let total_weight = validators
+        .iter()
+        .try_fold(0u128, |total, v| {
+            total.checked_add(u128::from(v.staked.max(1)))
+        })
+        .filter(|total| *total > 0)
+        .ok_or_else(|| format!("Epoch {} has no validator weight", epoch))?;
+    let mut target = random % total_weight;
+    for validator in &validators {
+        let weight = u128::from(validator.staked.max(1));
+        if target < weight {
+            return Ok(validator.address.clone());
+        }
+        target -= weight;
+    }
+    Err(format!("Slot {} has no leader", slot))
+}
+
+/// Returns the address of the leader of the `slot` of the `chain` argument, with the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const leader = get_slot_leader(chain, 12, config);
+/// ```
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, the chain is empty, or the slot has no leader, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_slot_leader(chain: JsValue, slot: u64, config: JsValue) -> Result<String, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let clock = SlotClock::of_chain(&chain, &config).map_err(|e| JsError::new(&e))?;
+    slot_leader(&chain, slot, &clock, &config).map_err(|e| JsError::new(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{account::AccountTrait, block::Block};
+    #[test]
+    fn clock_converts_timestamps_to_slots_and_epochs() {
+        let clock = _fixture_clock();
+        assert_eq!(clock.slot_of(1000), 0);
+        assert_eq!(clock.slot_of(1035), 3);
+        assert_eq!(clock.slot_start(3), Some(1030));
+        assert!(clock.contains(3, 1039));
+        assert!(!clock.contains(3, 1040));
+        assert!(!clock.contains(0, 999));
+        // Slots past the last timestamp contain none
+        assert_eq!(clock.slot_start(u64::MAX / 5), None);
+        assert!(!clock.contains(u64::MAX / 5, u64::MAX));
+        let last = clock.slot_of(u64::MAX);
+        assert!(clock.contains(last, u64::MAX));
+        assert_eq!(clock.epoch_of(3), 0);
+        assert_eq!(clock.epoch_of(4), 1);
+        assert_eq!(clock.epoch_start(2), 8);
+        assert_eq!(clock.missed_slots(1, 4), 2);
+        assert_eq!(clock.missed_slots(1, 2), 0);
+        // An idle chain records a count, whatever its length
+        assert_eq!(clock.missed_slots(0, u64::MAX), u64::MAX - 1);
+    }
+    #[test]
+    fn validators_rotate_at_epoch_boundaries() {
+        let clock = _fixture_clock();
+        let config = _fixture_config();
+        let mut chain = _fixture_chain();
+        // Ahmad stakes in slot 2, during epoch 0
+        let mut ahmad = Account::new("Ahmad");
+        ahmad.staked = 50;
+        chain.push(_fixture_block(1020, vec![ahmad]));
+
+        let addresses = |epoch| -> Vec<String> {
+            epoch_validators(&chain, epoch, &clock, &config)
+                .into_iter()
+                .map(|v| v.address)
+                .collect()
+        };
+        assert_eq!(addresses(0), vec!["Tom"]);
+        assert_eq!(addresses(1), vec!["Ahmad", "Tom"]);
+    }
+    #[test]
+    fn validator_count_limits_validator_set() {
+        let clock = _fixture_clock();
+        let config = GenesisConfig {
+            validator_count: 1,
+            ..Default::default()
+        };
+        let mut ahmad = Account::new("Ahmad");
+        ahmad.staked = 50;
+        let mut chain = _fixture_chain();
+        chain.push(_fixture_block(1020, vec![ahmad]));
+        let validators = epoch_validators(&chain, 1, &clock, &config);
+        assert_eq!(validators.len(), 1);
+        assert_eq!(validators[0].address, "Ahmad");
+    }
+    #[test]
+    fn default_miner_validates_without_stake() {
+        let clock = _fixture_clock();
+        let config = _fixture_config();
+        let chain = vec![_fixture_block(1000, vec![Account::new("Tom")])];
+        let validators = epoch_validators(&chain, 0, &clock, &config);
+        assert_eq!(validators.len(), 1);
+        assert_eq!(validators[0].address, config.default_miner);
+        assert_eq!(
+            slot_leader(&chain, 3, &clock, &config),
+            Ok(config.default_miner.clone())
+        );
+    }
+    #[test]
+    fn leaders_are_deterministic_and_weighted() {
+        let clock = _fixture_clock();
+        let config = _fixture_config();
+        let mut chain = _fixture_chain();
+        let mut ahmad = Account::new("Ahmad");
+        ahmad.staked = 10;
+        chain.push(_fixture_block(1020, vec![ahmad]));
+
+        let leaders: Vec<String> = (4..404)
+            .map(|slot| slot_leader(&chain, slot, &clock, &config).unwrap())
+            .collect();
+        let again: Vec<String> = (4..404)
+            .map(|slot| slot_leader(&chain, slot, &clock, &config).unwrap())
+            .collect();
+        assert_eq!(leaders, again);
+        // Both validators have equal stake, so both lead some slots
+        let tom = leaders.iter().filter(|leader| *leader == "Tom").count();
+        assert!(tom > 100 && tom < 300);
+        // Blocks after the start of an epoch do not change its leaders
+        chain.push(_fixture_block(1045, vec![Account::new("Shaun")]));
+        assert_eq!(
+            slot_leader(&chain, 5, &clock, &config),
+            Ok(leaders[1].clone())
+        );
+    }
+    #[test]
+    fn epoch_seed_mixes_several_blocks() {
+        let clock = _fixture_clock();
+        let mut chain = _fixture_chain();
+        for timestamp in [1010, 1020, 1030] {
+            chain.push(_fixture_block(timestamp, vec![]));
+        }
+        let seed = epoch_seed(&chain, 1, &clock);
+        // Changing an earlier block changes the seed, not only the last block before the epoch
+        chain[1].block.hash = format!("0x{:064x}", 1);
+        assert_ne!(epoch_seed(&chain, 1, &clock), seed);
+        // Blocks after the start of the epoch are not mixed
+        let seed = epoch_seed(&chain, 1, &clock);
+        chain.push(_fixture_block(1045, vec![]));
+        assert_eq!(epoch_seed(&chain, 1, &clock), seed);
+    }
+    #[test]
+    fn huge_stakes_do_not_overflow_election() {
+        let clock = _fixture_clock();
+        let config = _fixture_config();
+        let mut tom = Account::new("Tom");
+        tom.staked = u64::MAX;
+        let mut ahmad = Account::new("Ahmad");
+        ahmad.staked = u64::MAX;
+        let chain = vec![_fixture_block(1000, vec![tom, ahmad])];
+        for slot in 0..8 {
+            assert!(slot_leader(&chain, slot, &clock, &config).is_ok());
+        }
+    }
+
+    fn _fixture_config() -> GenesisConfig {
+        GenesisConfig {
+            epoch_length: 4,
+            ..Default::default()
+        }
+    }
+    fn _fixture_clock() -> SlotClock {
+        SlotClock::new(1000, &_fixture_config())
+    }
+    fn _fixture_block(timestamp: u64, data: Vec<Account>) -> FullBlock {
+        FullBlock::from(Block {
+            id: 0,
+            hash: format!("0x{:064x}", timestamp),
+            previous_hash: String::new(),
+            timestamp,
+            data,
+            nonce: 0,
+            next_miner: String::new(),
+            next_validators: vec![],
+        })
+    }
+    /// A genesis block allocating stake to Tom.
+    fn _fixture_chain() -> FullChain {
+        let mut tom = Account::new("Tom");
+        tom.staked = 10;
+        vec![_fixture_block(1000, vec![tom])]
+    }
+}
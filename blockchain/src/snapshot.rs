@@ -0,0 +1,484 @@
+//! # Snapshot
+//!
+//! A state snapshot holds the `ChainState` after a block: the latest version of every `Account`, the sequence of every address,
+//! the multisig policies, the contracts, and the governance proposals. Every `snapshot_interval` blocks, the hash of the snapshot
+//! is committed in the body of the block, and checked when the block is applied, see `ChainState::apply_block`.
+//!
+//! A node trusting the hash of a snapshot can bootstrap from it, without replaying the blocks before it. It keeps the headers
+//! of those blocks, back to the genesis block, but not their bodies. See `PrunedChain`.
+
+use crate::{
+    account::Account,
+    block::Block,
+    body::{headers, FullBlock, FullChain},
+    chain::Chain,
+    codec,
+    contract::ContractState,
+    genesis::GenesisConfig,
+    governance::GovernanceState,
+    hash::{verify_block_hash, verify_full_block_hash, BlockHash, HashFormat},
+    multisig::MultisigRegistry,
+    protocol::{version_at, RuleSet},
+    sequence::Sequences,
+    state::ChainState,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// The latest version of every `Account`, by address.
+type AccountMap = BTreeMap<String, Account>;
+
+fn apply_block(accounts: &mut AccountMap, full_block: &FullBlock) {
+    for account in &full_block.block.data {
+        accounts.insert(account.address.clone(), account.clone());
+    }
+}
+
+/// Returns the latest version of every `Account` held by the `blocks`, ordered by address.
+pub fn latest_accounts<'a>(blocks: impl IntoIterator<Item = &'a FullBlock>) -> Vec<Account> {
+    let mut accounts = AccountMap::new();
+    for full_block in blocks {
+        apply_block(&mut accounts, full_block);
+    }
+    accounts.into_values().collect()
+}
+
+/// Check if the block with the given `id` commits to a state snapshot: it is at a snapshot interval, and follows
+/// a protocol version with snapshots.
+pub fn is_snapshot_block(id: u64, config: &GenesisConfig) -> bool {
+    id > 0
+        && id.checked_rem(config.snapshot_interval) == Some(0)
+        && RuleSet::of_version(version_at(id, config)).is_some_and(|rules| rules.snapshots)
+}
+
+/// The state of the chain after a block.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StateSnapshot {
+    /// The id of the block the snapshot was taken after.
+    pub block_id: u64,
+    /// The latest version of every `Account`, ordered by address.
+    pub accounts: Vec<Account>,
+    /// The next sequence of every address with transactions.
+    pub sequences: BTreeMap<String, u64>,
+    /// The policies of the multisig accounts.
+    pub multisig: MultisigRegistry,
+    /// The deployed contracts, with their storage.
+    pub contracts: ContractState,
+    /// The governance proposals, and passed parameter changes.
+    pub governance: GovernanceState,
+}
+
+impl StateSnapshot {
+    /// Takes a snapshot of the `state`, after the block with the given `block_id`.
+    pub fn of_state(block_id: u64, state: &ChainState) -> Self {
+        Self {
+            block_id,
+            accounts: state.accounts.values().cloned().collect(),
+            sequences: state.sequences.all_next().clone(),
+            multisig: state.multisig.clone(),
+            contracts: state.contracts.clone(),
+            governance: state.governance.clone(),
+        }
+    }
+
+    /// Takes a snapshot of the `chain`, after its last block, by replaying it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the chain is empty, or invalid.
+    pub fn of_chain(chain: &[FullBlock], config: &GenesisConfig) -> Result<Self, String> {
+        let last_block = chain.last().ok_or("Chain is too short")?;
+        let state = ChainState::from_chain(chain, config)?;
+        Ok(Self::of_state(last_block.block.id, &state))
+    }
+
+    /// Returns the `ChainState` the snapshot was taken of, in the chain configured by the `config`.
+    pub fn to_state(&self, config: &GenesisConfig) -> ChainState {
+        ChainState {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| (account.address.clone(), account.clone()))
+                .collect(),
+            sequences: Sequences::from_next(config, self.sequences.clone()),
+            multisig: self.multisig.clone(),
+            contracts: self.contracts.clone(),
+            governance: self.governance.clone(),
+        }
+    }
+
+    /// Returns the hash of the canonical encoding of the snapshot.
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::from_slice(&codec::hash_snapshot(self)).expect("Sha256 digest to be 32 bytes")
+    }
+}
+
+/// Checks the `headers` run from the genesis block, each following the one before it, up to the `next` block.
+///
+/// **Note:** A hex hash also commits to the body of its block, so only legacy hashes are recalculated from the header.
+/// The hex hashes of the headers are trusted through the `previous_hash` of the blocks after them.
+fn validate_headers(headers: &[Block], next: &Block) -> Result<(), String> {
+    let mut previous: Option<&Block> = None;
+    for block in headers.iter().chain([next]) {
+        let follows = match previous {
+            Some(previous) => {
+                previous.id.checked_add(1) == Some(block.id) && block.previous_hash == previous.hash
+            }
+            None => block.id == 0,
+        };
+        if !follows {
+            return Err(format!(
+                "Block {} does not follow the previous block",
+                block.id
+            ));
+        }
+        if HashFormat::of(&block.hash) == HashFormat::Legacy {
+            verify_block_hash(block)?;
+        }
+        previous = Some(block);
+    }
+    Ok(())
+}
+
+/// A chain whose blocks before a state snapshot have no bodies.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrunedChain {
+    /// The headers of the blocks before the snapshot, from the genesis block.
+    pub headers: Chain,
+    /// The snapshot the chain starts from.
+    pub snapshot: StateSnapshot,
+    /// The block the snapshot was taken after, followed by every later block.
+    pub blocks: FullChain,
+}
+
+impl PrunedChain {
+    /// Returns the latest version of every `Account`: the snapshot, updated by the blocks after it.
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: AccountMap = self
+            .snapshot
+            .accounts
+            .iter()
+            .map(|account| (account.address.clone(), account.clone()))
+            .collect();
+        for full_block in self.blocks.iter().skip(1) {
+            apply_block(&mut accounts, full_block);
+        }
+        accounts.into_values().collect()
+    }
+
+    /// Validates the headers, the snapshot against the block committing to it, and every later block against the state
+    /// of the snapshot. Returns the state after the last block.
+    ///
+    /// **Note:** The proof of work, or stake, of the blocks is checked by `consensus`, from the full chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first check which fails.
+    pub fn validate(&self, config: &GenesisConfig) -> Result<ChainState, String> {
+        let snapshot_block = self.blocks.first().ok_or("Chain is too short")?;
+        validate_headers(&self.headers, &snapshot_block.block)?;
+        verify_full_block_hash(snapshot_block)?;
+        if snapshot_block.block.id != self.snapshot.block_id
+            || snapshot_block.body.snapshot_hash != Some(self.snapshot.hash())
+        {
+            return Err(format!(
+                "Snapshot does not match block {}",
+                snapshot_block.block.id
+            ));
+        }
+        let mut state = self.snapshot.to_state(config);
+        for pair in self.blocks.windows(2) {
+            let (previous_block, full_block) = (&pair[0].block, &pair[1]);
+            let block = &full_block.block;
+            if previous_block.id.checked_add(1) != Some(block.id)
+                || block.previous_hash != previous_block.hash
+            {
+                return Err(format!(
+                    "Block {} does not follow the previous block",
+                    block.id
+                ));
+            }
+            verify_full_block_hash(full_block)?;
+            state.apply_block(full_block, config)?;
+        }
+        Ok(state)
+    }
+}
+
+/// Prunes the bodies of the blocks of the `chain` before its latest state snapshot, keeping their headers.
+///
+/// # Errors
+///
+/// Returns an error if the chain has no snapshot, or is invalid.
+pub fn prune(chain: &[FullBlock], config: &GenesisConfig) -> Result<PrunedChain, String> {
+    let height = chain
+        .iter()
+        .rposition(|full_block| full_block.body.snapshot_hash.is_some())
+        .ok_or("Chain has no snapshot")?;
+    let pruned = PrunedChain {
+        headers: headers(&chain[..height]),
+        snapshot: StateSnapshot::of_chain(&chain[..=height], config)?,
+        blocks: chain[height..].to_vec(),
+    };
+    pruned.validate(config)?;
+    Ok(pruned)
+}
+
+/// Bootstraps a node from a `snapshot`, the `headers` of the blocks before it, and the `blocks` from the one committing
+/// to it onwards.
+///
+/// # Errors
+///
+/// Returns an error if the hash of the snapshot is not the `trusted_hash`, or the headers or blocks are invalid.
+pub fn bootstrap(
+    snapshot: StateSnapshot,
+    trusted_hash: &BlockHash,
+    headers: Chain,
+    blocks: FullChain,
+    config: &GenesisConfig,
+) -> Result<PrunedChain, String> {
+    if snapshot.hash() != *trusted_hash {
+        return Err("Snapshot is not trusted".to_string());
+    }
+    let pruned = PrunedChain {
+        headers,
+        snapshot,
+        blocks,
+    };
+    pruned.validate(config)?;
+    Ok(pruned)
+}
+
+/// Prunes the bodies of the blocks of the `chain` argument before its latest state snapshot, with the `config` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const { headers, snapshot, blocks } = prune_chain(chain, config);
+/// ```
+///
+/// # Errors
+///
+/// If either argument is not deserialisable, or the chain cannot be pruned, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn prune_chain(chain: JsValue, config: JsValue) -> Result<JsValue, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let pruned = prune(&chain, &config).map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&pruned)?)
+}
+
+/// Bootstraps a node from the `snapshot` argument, trusted by its hex encoded `trusted_hash`, the `headers` argument,
+/// and the `blocks` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const pruned = bootstrap_from_snapshot(snapshot, trustedHash, headers, blocks, config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the snapshot or blocks are invalid, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn bootstrap_from_snapshot(
+    snapshot: JsValue,
+    trusted_hash: String,
+    headers: JsValue,
+    blocks: JsValue,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let snapshot: StateSnapshot = snapshot.into_serde()?;
+    let trusted_hash = BlockHash::from_hex(&trusted_hash).map_err(|e| JsError::new(&e))?;
+    let headers: Chain = headers.into_serde()?;
+    let blocks: FullChain = blocks.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let pruned = bootstrap(snapshot, &trusted_hash, headers, blocks, &config)
+        .map_err(|e| JsError::new(&e))?;
+    Ok(JsValue::from_serde(&pruned)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait, assembly::assemble_block, contract::contract_address,
+        difficulty::mine_full_block_hash, vm, Events, Transaction,
+    };
+    #[test]
+    fn snapshots_are_committed_at_intervals() {
+        let config = _fixture_config();
+        let chain = _fixture_chain(&config);
+        assert!(chain[1].body.snapshot_hash.is_none());
+        let snapshot = StateSnapshot::of_chain(&chain[..3], &config).unwrap();
+        assert_eq!(chain[2].body.snapshot_hash, Some(snapshot.hash()));
+        assert_eq!(snapshot.sequences.get("Tom"), Some(&2));
+        let counter = contract_address(1, 0).to_string();
+        assert_eq!(
+            snapshot
+                .contracts
+                .contract(&counter)
+                .unwrap()
+                .storage
+                .get(&0),
+            Some(&2)
+        );
+        assert!(chain[4].body.snapshot_hash.is_some());
+        assert!(ChainState::from_chain(&chain, &config).is_ok());
+
+        let mut tampered = chain.clone();
+        tampered[3].body.snapshot_hash = chain[2].body.snapshot_hash;
+        assert_eq!(
+            ChainState::from_chain(&tampered, &config),
+            Err("Block 3 does not commit to the state snapshot".to_string())
+        );
+    }
+    #[test]
+    fn pruned_chain_keeps_headers_and_latest_state() {
+        let config = _fixture_config();
+        let chain = _fixture_chain(&config);
+        let pruned = prune(&chain[..4], &config).unwrap();
+        assert_eq!(pruned.snapshot.block_id, 2);
+        assert_eq!(pruned.headers, headers(&chain[..2]));
+        assert_eq!(pruned.blocks.len(), 2);
+        assert_eq!(pruned.accounts(), latest_accounts(&chain[..4]));
+        assert_eq!(
+            pruned.validate(&config),
+            ChainState::from_chain(&chain[..4], &config)
+        );
+
+        let pruned = prune(&chain, &config).unwrap();
+        assert_eq!(pruned.snapshot.block_id, 4);
+        assert_eq!(pruned.headers.len(), 4);
+        assert_eq!(pruned.blocks.len(), 1);
+        assert_eq!(pruned.accounts(), latest_accounts(&chain));
+
+        assert_eq!(
+            prune(&chain[..2], &config),
+            Err("Chain has no snapshot".to_string())
+        );
+    }
+    #[test]
+    fn bootstrap_requires_trusted_snapshot() {
+        let config = _fixture_config();
+        let chain = _fixture_chain(&config);
+        let snapshot = StateSnapshot::of_chain(&chain[..3], &config).unwrap();
+        let trusted_hash = chain[2].body.snapshot_hash.unwrap();
+        let bootstrap_from = |snapshot: &StateSnapshot, trusted_hash, headers: Chain, blocks| {
+            bootstrap(snapshot.clone(), trusted_hash, headers, blocks, &config)
+        };
+        let pruned = bootstrap_from(
+            &snapshot,
+            &trusted_hash,
+            headers(&chain[..2]),
+            chain[2..].to_vec(),
+        )
+        .unwrap();
+        assert_eq!(pruned.accounts(), latest_accounts(&chain));
+        // The contract deployed before the snapshot is called after it
+        assert_eq!(
+            pruned.validate(&config),
+            ChainState::from_chain(&chain, &config)
+        );
+
+        let mut forged = snapshot.clone();
+        forged.accounts[0].tokens += 100;
+        assert_eq!(
+            bootstrap_from(
+                &forged,
+                &trusted_hash,
+                headers(&chain[..2]),
+                chain[2..].to_vec()
+            ),
+            Err("Snapshot is not trusted".to_string())
+        );
+        // A trusted, but forged, snapshot does not match the chain
+        let mut forged = snapshot.clone();
+        forged.contracts = ContractState::new();
+        let forged_hash = forged.hash();
+        assert_eq!(
+            bootstrap_from(
+                &forged,
+                &forged_hash,
+                headers(&chain[..2]),
+                chain[2..].to_vec()
+            ),
+            Err("Snapshot does not match block 2".to_string())
+        );
+
+        let mut tampered = headers(&chain[..2]);
+        tampered[1].previous_hash = chain[1].block.hash.clone();
+        assert_eq!(
+            bootstrap_from(&snapshot, &trusted_hash, tampered, chain[2..].to_vec()),
+            Err("Block 1 does not follow the previous block".to_string())
+        );
+        assert!(bootstrap_from(
+            &snapshot,
+            &trusted_hash,
+            headers(&chain[..1]),
+            chain[2..].to_vec()
+        )
+        .is_err());
+        let mut tampered = chain[2..].to_vec();
+        tampered[1].block.data[0].tokens += 1;
+        assert!(bootstrap_from(&snapshot, &trusted_hash, headers(&chain[..2]), tampered).is_err());
+    }
+
+    fn _fixture_config() -> GenesisConfig {
+        GenesisConfig {
+            snapshot_interval: 2,
+            ..Default::default()
+        }
+    }
+    /// Tom, holding 30 tokens, of which 10 are staked, deploys a counter in block 1, then adds 2, and 3, to it in blocks
+    /// 2 and 3. Snapshots are taken after blocks `2` and `4`.
+    fn _fixture_chain(config: &GenesisConfig) -> FullChain {
+        let code = vm::assemble("PUSH 0 LOAD PUSH 0 ARG ADD PUSH 0 STORE STOP").unwrap();
+        let counter = contract_address(1, 0).to_string();
+        let events = [
+            None,
+            Some(Events::DeployContract(hex::encode(code))),
+            Some(Events::CallContract(counter.clone(), vec![2])),
+            Some(Events::CallContract(counter, vec![3])),
+            None,
+        ];
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let mut chain: FullChain = vec![];
+        for (id, event) in events.into_iter().enumerate() {
+            let block = Block {
+                id: id as u64,
+                hash: String::new(),
+                previous_hash: chain
+                    .last()
+                    .map(|b: &FullBlock| b.block.hash.clone())
+                    .unwrap_or_default(),
+                timestamp: 1000 + id as u64 * 10,
+                data: match id {
+                    0 => vec![tom.clone()],
+                    _ => vec![],
+                },
+                nonce: 0,
+                next_miner: "Tom".to_string(),
+                next_validators: vec![],
+            };
+            let transactions = event
+                .map(|event| {
+                    Transaction {
+                        event,
+                        address: "Tom".to_string(),
+                    }
+                    .into()
+                })
+                .into_iter()
+                .collect();
+            let assembled = assemble_block(&chain, block, transactions, config).unwrap();
+            let mut full_block = assembled.full_block;
+            mine_full_block_hash(&mut full_block, 0).unwrap();
+            chain.push(full_block);
+        }
+        chain
+    }
+}
@@ -0,0 +1,439 @@
+//! # State
+//!
+//! The `ChainState` is the state of a chain after a block: the latest version of every `Account`, the sequence of every address,
+//! the multisig policies, the contracts, and the governance proposals.
+//!
+//! It is built by replaying the chain once, with `ChainState::apply_block`. Each block is checked against the state before it,
+//! with the `RuleSet` of its protocol version: its addresses, its multisig witnesses, its contract state commitment, its gas,
+//! its receipts, the tokens its transactions spend, its `data`, which must be the Accounts they change, and its snapshot.
+
+use crate::{
+    account::Account,
+    address::validate_block_addresses,
+    body::FullBlock,
+    contract::ContractState,
+    genesis::GenesisConfig,
+    governance::GovernanceState,
+    ledger::validate_data,
+    multisig::MultisigRegistry,
+    protocol::{version_at, RuleSet},
+    receipt::{self, DetailedReceipt, Receipt},
+    sequence::Sequences,
+    snapshot::{is_snapshot_block, StateSnapshot},
+};
+use std::collections::BTreeMap;
+
+/// The state of a chain after a block.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChainState {
+    /// The latest version of every `Account`, by address.
+    pub accounts: BTreeMap<String, Account>,
+    /// The next sequence of every address.
+    pub sequences: Sequences,
+    /// The policies of the multisig accounts.
+    pub multisig: MultisigRegistry,
+    /// The deployed contracts.
+    pub contracts: ContractState,
+    /// The governance proposals, and passed parameter changes.
+    pub governance: GovernanceState,
+}
+
+impl ChainState {
+    /// Creates the state of a chain configured by the `config`, before its genesis block.
+    pub fn new(config: &GenesisConfig) -> Self {
+        Self {
+            sequences: Sequences::new(config),
+            ..Self::default()
+        }
+    }
+
+    /// Replays the `chain`, checking every block.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `apply_block` for the first block which fails.
+    pub fn from_chain(chain: &[FullBlock], config: &GenesisConfig) -> Result<Self, String> {
+        let mut state = Self::new(config);
+        for full_block in chain {
+            state.apply_block(full_block, config)?;
+        }
+        Ok(state)
+    }
+
+    /// Checks the `full_block` against the state before it, then applies it. Returns the receipt of every transaction, in order.
+    ///
+    /// A block at a snapshot interval must commit to the `StateSnapshot` of the state after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the block, and the rule it breaks. The state is then unchanged.
+    pub fn apply_block(
+        &mut self,
+        full_block: &FullBlock,
+        config: &GenesisConfig,
+    ) -> Result<Vec<Receipt>, String> {
+        let (next, receipts) = self.next_state(full_block, config)?;
+        let id = full_block.block.id;
+        let snapshot_hash =
+            is_snapshot_block(id, config).then(|| StateSnapshot::of_state(id, &next).hash());
+        if full_block.body.snapshot_hash != snapshot_hash {
+            return Err(format!(
+                "Block {} does not commit to the state snapshot",
+                id
+            ));
+        }
+        *self = next;
+        Ok(receipts)
+    }
+
+    /// Checks the `full_block` against the state before it, except for its `snapshot_hash`, and returns the state after it,
+    /// with the receipt of every transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the block, and the rule it breaks.
+    pub fn next_state(
+        &self,
+        full_block: &FullBlock,
+        config: &GenesisConfig,
+    ) -> Result<(Self, Vec<Receipt>), String> {
+        let id = full_block.block.id;
+        let body = &full_block.body;
+        let version = version_at(id, config);
+        if body.version != version {
+            return Err(format!(
+                "Block {} is version {}, but version {} is active",
+                id, body.version, version
+            ));
+        }
+        let rules = RuleSet::of_version(version)
+            .ok_or_else(|| format!("Protocol version {} is not supported", version))?;
+        let active = self.governance.config_at(id, config);
+        if rules.addresses {
+            validate_block_addresses(full_block, config)?;
+        }
+
+        let (mut next, receipts) = self.replay_transactions(full_block, config)?;
+        let receipts: Vec<Receipt> = receipts
+            .into_iter()
+            .map(|detailed| detailed.receipt)
+            .collect();
+        if body.contracts_hash != next.contracts.commitment(full_block) {
+            return Err(format!(
+                "Block {} does not commit to the contract state",
+                id
+            ));
+        }
+
+        let transaction_ids: Vec<_> = receipts.iter().map(|receipt| receipt.tx_hash).collect();
+        rules.validate_block(full_block, &transaction_ids)?;
+
+        let gas_used = receipts
+            .iter()
+            .fold(0u64, |gas, receipt| gas.saturating_add(receipt.gas_used));
+        if body.gas_used != gas_used {
+            return Err(format!(
+                "Block {} records {} gas, but uses {}",
+                id, body.gas_used, gas_used
+            ));
+        }
+        if gas_used > active.block_gas_limit {
+            return Err(format!(
+                "Block {} uses {} gas, above the limit of {}",
+                id, gas_used, active.block_gas_limit
+            ));
+        }
+        if rules.receipts && body.receipts != receipts {
+            return Err(format!(
+                "Block {} does not store the receipts of its transactions",
+                id
+            ));
+        }
+
+        // Before spending is checked, the data of a block is taken as given
+        if rules.spending {
+            validate_data(full_block, &receipts, &next.accounts)?;
+        } else {
+            for account in &full_block.block.data {
+                next.accounts
+                    .insert(account.address.clone(), account.clone());
+            }
+        }
+        Ok((next, receipts))
+    }
+
+    /// Applies the multisig witnesses and the transactions of the `full_block` to a copy of the state, and returns it with
+    /// the receipt of every transaction. From the protocol version with spending checks, its Accounts are those the
+    /// transactions leave. Unlike `next_state`, the fields of the body are not checked, and the `data` of the block is not applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a witness is invalid, or a transaction cannot be applied.
+    pub fn replay_transactions(
+        &self,
+        full_block: &FullBlock,
+        config: &GenesisConfig,
+    ) -> Result<(Self, Vec<DetailedReceipt>), String> {
+        let mut next = self.clone();
+        next.multisig.apply_block(full_block, &self.sequences)?;
+        let receipts = receipt::apply_block(
+            full_block,
+            &mut next.contracts,
+            &mut next.governance,
+            &mut next.sequences,
+            &mut next.accounts,
+            config,
+        )?;
+        Ok((next, receipts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::AccountTrait,
+        assembly::assemble_block,
+        block::Block,
+        contract::contract_address,
+        hash::BlockHash,
+        merkle::transactions_root,
+        protocol::Upgrade,
+        receipt::{ErrorCode, ReceiptStatus},
+        vesting::VestingSchedule,
+        Events, Transaction,
+    };
+    #[test]
+    fn recorded_gas_must_match() {
+        let config = GenesisConfig::default();
+        let mut chain = _fixture_chain(vec![Events::UpdateChain], &config);
+        chain[1].body.gas_used = 0;
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 1 records 0 gas, but uses 1000".to_string())
+        );
+        // Assembled blocks keep to the limit, so the block is assembled with a higher one
+        let mut chain = _fixture_chain(vec![Events::UpdateChain], &config);
+        let config = GenesisConfig {
+            block_gas_limit: 999,
+            ..config
+        };
+        _fixture_recommit(&mut chain, &config);
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 1 uses 1000 gas, above the limit of 999".to_string())
+        );
+    }
+    #[test]
+    fn senders_must_be_charged_their_fees() {
+        let config = GenesisConfig::default();
+        let chain = _fixture_chain(vec![Events::UpdateChain], &config);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert_eq!(state.accounts["Tom"].tokens, 29);
+        assert_eq!(state.sequences.next("Tom"), 1);
+
+        let mut chain = _fixture_chain(vec![Events::UpdateChain], &config);
+        _fixture_tokens(&mut chain[1], "Tom", 30);
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 1 holds 30 tokens for 'Tom', but its transactions leave 29".to_string())
+        );
+        // An Account left out of the data of the block is not charged either
+        chain[1].block.data.clear();
+        assert!(ChainState::from_chain(&chain, &config).is_err());
+        // Tokens received in the block may pay the fee
+        let mut chain = _fixture_chain(vec![Events::Reward("Tom".to_string(), 1)], &config);
+        _fixture_tokens(&mut chain[1], "Tom", 30);
+        assert_eq!(
+            ChainState::from_chain(&chain, &config).map(|state| state.accounts["Tom"].tokens),
+            Ok(30)
+        );
+    }
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn contract_transfers_must_move_tokens() {
+        let config = GenesisConfig {
+            upgrades: vec![Upgrade {
+                version: 2,
+                height: 1,
+            }],
+            ..Default::default()
+        };
+        let code = wat::parse_str(
+            r#"(module
+                (import "env" "transfer" (func $transfer (param i32 i32 i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "Camper")
+                (func (export "call") (result i64)
+                 (call $transfer (i32.const 0) (i32.const 6) (i64.const 5))
+                 (i64.const 0)))"#,
+        )
+        .unwrap();
+        let events = vec![
+            Events::DeployContract(hex::encode(code)),
+            Events::CallContract(contract_address(1, 0).to_string(), vec![]),
+        ];
+        let chain = _fixture_chain(events, &config);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert_eq!(state.accounts["Camper"].tokens, 25);
+
+        let tom = state.accounts["Tom"].tokens;
+        let mut kept = chain.clone();
+        _fixture_tokens(&mut kept[2], "Tom", tom + 5);
+        assert_eq!(
+            ChainState::from_chain(&kept, &config),
+            Err(format!(
+                "Block 2 holds {} tokens for 'Tom', but its transactions leave {}",
+                tom + 5,
+                tom
+            ))
+        );
+        let mut lost = chain;
+        _fixture_tokens(&mut lost[2], "Camper", 20);
+        assert_eq!(
+            ChainState::from_chain(&lost, &config),
+            Err("Block 2 holds 20 tokens for 'Camper', but its transactions leave 25".to_string())
+        );
+    }
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn contract_calls_spend_the_running_balance() {
+        let config = GenesisConfig {
+            upgrades: vec![Upgrade {
+                version: 2,
+                height: 1,
+            }],
+            ..Default::default()
+        };
+        // Transfers the argument to Camper
+        let code = wat::parse_str(
+            r#"(module
+                (import "env" "arg" (func $arg (param i32) (result i64)))
+                (import "env" "transfer" (func $transfer (param i32 i32 i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "Camper")
+                (func (export "call") (result i64)
+                 (call $transfer (i32.const 0) (i32.const 6) (call $arg (i32.const 0)))
+                 (i64.const 0)))"#,
+        )
+        .unwrap();
+        let mut chain = _fixture_chain(vec![Events::DeployContract(hex::encode(code))], &config);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        let spendable = config.spendable_tokens(&state.accounts["Tom"], 2);
+        // Either call alone can pay its fee and transfer, but not both
+        let call = Transaction {
+            event: Events::CallContract(contract_address(1, 0).to_string(), vec![spendable - 6]),
+            address: "Tom".to_string(),
+        };
+        let block = Block {
+            id: 2,
+            ..chain[1].block.clone()
+        };
+        let assembled = assemble_block(
+            &chain,
+            block,
+            vec![call.clone().into(), call.into()],
+            &config,
+        )
+        .unwrap();
+        let receipts = &assembled.full_block.body.receipts;
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].status, ReceiptStatus::Success);
+        assert_eq!(receipts[1].error_code, Some(ErrorCode::ExecutionFailed));
+        assert!(receipts[1].state_changes.is_empty());
+        chain.push(assembled.full_block);
+        let state = ChainState::from_chain(&chain, &config).unwrap();
+        assert_eq!(state.accounts["Camper"].tokens, 20 + spendable - 6);
+    }
+    #[test]
+    fn locked_tokens_cannot_be_spent() {
+        let mut config = GenesisConfig::default();
+        config.vesting.insert(
+            "Tom".to_string(),
+            VestingSchedule {
+                amount: 15,
+                start: 0,
+                cliff: 10,
+                duration: 10,
+            },
+        );
+        // Of the 20 unstaked tokens of Tom, 15 are locked, and 1 pays the fee
+        let transfer = |amount| Events::Transfer("Camper".to_string(), amount);
+        let chain = _fixture_chain(vec![transfer(4)], &config);
+        assert_eq!(ChainState::from_chain(&chain, &config).map(|_| ()), Ok(()));
+        // Assembled blocks leave out transactions which cannot be mined
+        let mut chain = _fixture_chain(vec![transfer(4)], &config);
+        chain[1].body.transactions[0].event = transfer(5);
+        _fixture_recommit(&mut chain, &config);
+        assert_eq!(
+            ChainState::from_chain(&chain, &config),
+            Err("Block 1 transaction 0: 'Tom' cannot transfer 5 tokens".to_string())
+        );
+    }
+    #[test]
+    fn failed_blocks_leave_the_state_unchanged() {
+        let config = GenesisConfig::default();
+        let chain = _fixture_chain(vec![Events::UpdateChain], &config);
+        let mut state = ChainState::from_chain(&chain[..1], &config).unwrap();
+        let before = state.clone();
+        // The block is replayed before its gas is checked
+        let mut block = chain[1].clone();
+        block.body.gas_used = 0;
+        assert!(state.apply_block(&block, &config).is_err());
+        assert_eq!(state, before);
+    }
+
+    /// Commits the last block of the `chain` to the IDs of its transactions, as bound to the `config`.
+    fn _fixture_recommit(chain: &mut [FullBlock], config: &GenesisConfig) {
+        let (last, ancestors) = chain.split_last_mut().unwrap();
+        last.body.transactions_root = transactions_root(last, ancestors, config).unwrap();
+    }
+    /// Sets the `tokens` of the Account at `address` in the data of the `full_block`.
+    fn _fixture_tokens(full_block: &mut FullBlock, address: &str, tokens: u64) {
+        let account = full_block
+            .block
+            .data
+            .iter_mut()
+            .find(|account| account.address == address)
+            .unwrap();
+        account.tokens = tokens;
+    }
+    /// Tom holds 30 tokens, of which 10 are staked, and Camper 20. Then Tom sends a transaction with each of the `events`,
+    /// one per block, assembled with `assemble_block`.
+    fn _fixture_chain(events: Vec<Events>, config: &GenesisConfig) -> Vec<FullBlock> {
+        let mut tom = Account::new("Tom");
+        tom.tokens = 30;
+        tom.staked = 10;
+        let mut chain: Vec<FullBlock> = vec![];
+        let events = std::iter::once(None).chain(events.into_iter().map(Some));
+        for (id, event) in events.enumerate() {
+            let block = Block {
+                id: id as u64,
+                hash: BlockHash::default().to_hex(),
+                previous_hash: String::new(),
+                timestamp: 1000,
+                data: match id {
+                    0 => vec![tom.clone(), Account::new("Camper")],
+                    _ => vec![],
+                },
+                nonce: 0,
+                next_miner: "Tom".to_string(),
+                next_validators: vec![],
+            };
+            let transactions = event
+                .map(|event| {
+                    Transaction {
+                        event,
+                        address: "Tom".to_string(),
+                    }
+                    .into()
+                })
+                .into_iter()
+                .collect();
+            let assembled = assemble_block(&chain, block, transactions, config).unwrap();
+            assert!(assembled.rejected.is_empty() && assembled.deferred.is_empty());
+            chain.push(assembled.full_block);
+        }
+        chain
+    }
+}
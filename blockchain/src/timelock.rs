@@ -0,0 +1,418 @@
+//! # Time Lock
+//!
+//! A `ScheduledTransaction` is a `Transaction` with optional `valid_after` and `valid_until` time locks, each a block id or a timestamp.
+//! It serialises as a `Transaction`, with the time locks alongside, so a transaction without time locks reads as a scheduled transaction.
+//! A transaction also carries its `TransactionSignature`, or, for a multisig account, its `MultisigWitness`. Both are moved
+//! to the `BlockBody` when it is mined.
+//!
+//! When a block is assembled, see `assembly`, `schedule_transactions` splits the transactions into those valid in the next block, those which stay pending,
+//! and those past their deadline, which are expired.
+//!
+//! A mined `Transaction` does not hold its time locks. They are kept as the `time_locks` of the `BlockBody`, by the index of
+//! the transaction, and `validate_time_locks` checks every mined transaction was valid in its block.
+
+use crate::{
+    body::FullBlock,
+    chain::Chain,
+    multisig::MultisigWitness,
+    timestamp::{Clock, SystemClock},
+    wallet::TransactionSignature,
+    Transaction,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// A point in the chain: a block id, or a timestamp.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimeLock {
+    /// The id of a block.
+    Block(u64),
+    /// A timestamp, in seconds.
+    Timestamp(u64),
+}
+
+impl TimeLock {
+    /// Returns `true` if a block with the given `block_id` and `timestamp` is after the time lock.
+    pub fn is_passed(&self, block_id: u64, timestamp: u64) -> bool {
+        match self {
+            TimeLock::Block(id) => block_id > *id,
+            TimeLock::Timestamp(time) => timestamp > *time,
+        }
+    }
+}
+
+impl fmt::Display for TimeLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeLock::Block(id) => write!(f, "block {}", id),
+            TimeLock::Timestamp(time) => write!(f, "timestamp {}", time),
+        }
+    }
+}
+
+/// Whether a `ScheduledTransaction` can be mined into a block.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Validity {
+    /// The transaction can be mined.
+    Valid,
+    /// The transaction is not valid yet, and stays pending.
+    Pending,
+    /// The transaction is past its deadline, and is dropped.
+    Expired,
+}
+
+/// A `Transaction` which is only valid after, or until, a block id or timestamp.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduledTransaction {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    /// The transaction is only mined in blocks after this time lock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_after: Option<TimeLock>,
+    /// The transaction is only mined in blocks until this time lock, and expires after it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<TimeLock>,
+    /// The signatures of the transaction, if it is from a multisig account. The `index` is set when the block is assembled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness: Option<MultisigWitness>,
+    /// The signature of the transaction, by the key of its address. The `index` is set when the block is assembled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<TransactionSignature>,
+}
+
+/// The time locks of a mined transaction, as kept in the `BlockBody`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TransactionTimeLocks {
+    /// The index of the transaction in the `transactions` of the block.
+    pub index: usize,
+    /// The `valid_after` time lock of the transaction.
+    pub valid_after: Option<TimeLock>,
+    /// The `valid_until` time lock of the transaction.
+    pub valid_until: Option<TimeLock>,
+}
+
+impl From<Transaction> for ScheduledTransaction {
+    fn from(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            valid_after: None,
+            valid_until: None,
+            witness: None,
+            signature: None,
+        }
+    }
+}
+
+impl ScheduledTransaction {
+    /// Returns whether the transaction can be mined into a block with the given `block_id` and `timestamp`.
+    ///
+    /// **Note:** A transaction past its `valid_until` is expired, even if it has not reached its `valid_after`.
+    pub fn validity(&self, block_id: u64, timestamp: u64) -> Validity {
+        self.time_locks(0).validity(block_id, timestamp)
+    }
+
+    /// Checks the transaction can be mined into a block with the given `block_id` and `timestamp`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a distinct error for pending, and for expired, transactions.
+    pub fn check(&self, block_id: u64, timestamp: u64) -> Result<(), String> {
+        self.time_locks(0).check(block_id, timestamp)
+    }
+
+    /// Returns the time locks of the transaction, mined at `index` in the `transactions` of a block.
+    pub fn time_locks(&self, index: usize) -> TransactionTimeLocks {
+        TransactionTimeLocks {
+            index,
+            valid_after: self.valid_after,
+            valid_until: self.valid_until,
+        }
+    }
+}
+
+impl TransactionTimeLocks {
+    /// Returns `true` if neither time lock is set.
+    pub fn is_empty(&self) -> bool {
+        self.valid_after.is_none() && self.valid_until.is_none()
+    }
+
+    /// Returns whether the transaction can be mined into a block with the given `block_id` and `timestamp`.
+    pub fn validity(&self, block_id: u64, timestamp: u64) -> Validity {
+        if matches!(self.valid_until, Some(lock) if lock.is_passed(block_id, timestamp)) {
+            Validity::Expired
+        } else if matches!(self.valid_after, Some(lock) if !lock.is_passed(block_id, timestamp)) {
+            Validity::Pending
+        } else {
+            Validity::Valid
+        }
+    }
+
+    /// Checks the transaction can be mined into a block with the given `block_id` and `timestamp`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a distinct error for pending, and for expired, transactions.
+    pub fn check(&self, block_id: u64, timestamp: u64) -> Result<(), String> {
+        match (
+            self.validity(block_id, timestamp),
+            self.valid_after,
+            self.valid_until,
+        ) {
+            (Validity::Pending, Some(lock), _) => {
+                Err(format!("Transaction is not valid until after {}", lock))
+            }
+            (Validity::Expired, _, Some(lock)) => {
+                Err(format!("Transaction expired after {}", lock))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Splits the `transactions` to mine into the `transactions` and `time_locks` of a `BlockBody`.
+///
+/// Only transactions with a time lock have an entry in the `time_locks`.
+pub fn split_time_locks(
+    transactions: Vec<ScheduledTransaction>,
+) -> (Vec<Transaction>, Vec<TransactionTimeLocks>) {
+    let time_locks = transactions
+        .iter()
+        .enumerate()
+        .map(|(index, transaction)| transaction.time_locks(index))
+        .filter(|time_locks| !time_locks.is_empty())
+        .collect();
+    let transactions = transactions
+        .into_iter()
+        .map(|transaction| transaction.transaction)
+        .collect();
+    (transactions, time_locks)
+}
+
+/// Returns the time locks of every transaction of the `full_block`, in order. Transactions without time locks have empty ones.
+///
+/// **Note:** Time locks naming no transaction are left out. `validate_time_locks` rejects them.
+pub fn block_time_locks(full_block: &FullBlock) -> Vec<TransactionTimeLocks> {
+    let mut time_locks: Vec<_> = (0..full_block.body.transactions.len())
+        .map(|index| TransactionTimeLocks {
+            index,
+            ..Default::default()
+        })
+        .collect();
+    for locks in &full_block.body.time_locks {
+        if let Some(slot) = time_locks.get_mut(locks.index) {
+            *slot = *locks;
+        }
+    }
+    time_locks
+}
+
+/// Checks every time locked transaction of the `full_block` can be mined into it, at its id and timestamp.
+///
+/// # Errors
+///
+/// Returns an error if the time locks are not in ascending order of transaction, name no transaction, or set no time lock,
+/// or if a transaction is pending or expired in the block.
+pub fn validate_time_locks(full_block: &FullBlock) -> Result<(), String> {
+    let block = &full_block.block;
+    let mut previous: Option<usize> = None;
+    for time_locks in &full_block.body.time_locks {
+        let index = time_locks.index;
+        if previous.is_some_and(|previous| index <= previous)
+            || index >= full_block.body.transactions.len()
+            || time_locks.is_empty()
+        {
+            return Err(format!(
+                "Block {} has invalid time locks for transaction {}",
+                block.id, index
+            ));
+        }
+        time_locks
+            .check(block.id, block.timestamp)
+            .map_err(|e| format!("Block {} transaction {}: {}", block.id, index, e))?;
+        previous = Some(index);
+    }
+    Ok(())
+}
+
+/// The transactions for the next block, split by their `Validity`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Schedule {
+    /// The transactions to mine, with their time locks.
+    pub ready: Vec<ScheduledTransaction>,
+    /// The transactions to keep for a later block.
+    pub pending: Vec<ScheduledTransaction>,
+    /// The transactions to drop.
+    pub expired: Vec<ScheduledTransaction>,
+}
+
+/// Splits the `transactions` by their validity in a block with the given `block_id` and `timestamp`, keeping their order.
+pub fn schedule_transactions(
+    transactions: Vec<ScheduledTransaction>,
+    block_id: u64,
+    timestamp: u64,
+) -> Schedule {
+    let mut schedule = Schedule::default();
+    for transaction in transactions {
+        match transaction.validity(block_id, timestamp) {
+            Validity::Valid => schedule.ready.push(transaction),
+            Validity::Pending => schedule.pending.push(transaction),
+            Validity::Expired => schedule.expired.push(transaction),
+        }
+    }
+    schedule
+}
+
+/// Splits the `transactions` argument by their validity in the next block of the `chain` argument, mined now.
+///
+/// # Examples
+///
+/// ```js
+/// const { ready, pending, expired } = schedule_transactions_for(chain, [
+///   { event: "Unstake", address: "Tom", valid_after: { Block: 100 } },
+///   { event: { Transfer: ["Camper", 5] }, address: "Tom", valid_until: { Timestamp: 1650000000 } },
+/// ]);
+/// nodeState.transactions = ready;
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn schedule_transactions_for(
+    chain: JsValue,
+    transactions: JsValue,
+) -> Result<JsValue, JsError> {
+    let chain: Chain = chain.into_serde()?;
+    let transactions: Vec<ScheduledTransaction> = transactions.into_serde()?;
+    let block_id = chain.last().map_or(0, |block| block.id + 1);
+    let schedule = schedule_transactions(transactions, block_id, SystemClock.now());
+    Ok(JsValue::from_serde(&schedule)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::Block, Events};
+    #[test]
+    fn time_locks_decide_validity() {
+        let unstake = _fixture_scheduled(Some(TimeLock::Block(10)), Some(TimeLock::Block(20)));
+        assert_eq!(unstake.validity(10, 0), Validity::Pending);
+        assert_eq!(unstake.validity(11, 0), Validity::Valid);
+        assert_eq!(unstake.validity(20, 0), Validity::Valid);
+        assert_eq!(unstake.validity(21, 0), Validity::Expired);
+
+        let transfer = _fixture_scheduled(None, Some(TimeLock::Timestamp(1000)));
+        assert_eq!(transfer.validity(50, 1000), Validity::Valid);
+        assert_eq!(transfer.validity(50, 1001), Validity::Expired);
+    }
+    #[test]
+    fn pending_and_expired_have_distinct_errors() {
+        let unstake =
+            _fixture_scheduled(Some(TimeLock::Block(10)), Some(TimeLock::Timestamp(1000)));
+        assert_eq!(
+            unstake.check(5, 0),
+            Err("Transaction is not valid until after block 10".to_string())
+        );
+        assert_eq!(
+            unstake.check(15, 2000),
+            Err("Transaction expired after timestamp 1000".to_string())
+        );
+        assert_eq!(unstake.check(15, 500), Ok(()));
+    }
+    #[test]
+    fn schedule_splits_transactions() {
+        let pending = _fixture_scheduled(Some(TimeLock::Block(10)), None);
+        let expired = _fixture_scheduled(None, Some(TimeLock::Block(2)));
+        let ready = _fixture_scheduled(None, None);
+        let schedule =
+            schedule_transactions(vec![pending.clone(), expired.clone(), ready.clone()], 5, 0);
+        assert_eq!(schedule.ready, vec![ready]);
+        assert_eq!(schedule.pending, vec![pending]);
+        assert_eq!(schedule.expired, vec![expired]);
+    }
+    #[test]
+    fn mined_time_locks_are_kept_by_index() {
+        let locked = _fixture_scheduled(Some(TimeLock::Block(10)), None);
+        let (transactions, time_locks) =
+            split_time_locks(vec![_fixture_scheduled(None, None), locked.clone()]);
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(time_locks, vec![locked.time_locks(1)]);
+    }
+    #[test]
+    fn mined_time_locks_are_checked_against_the_block() {
+        let unstake =
+            _fixture_scheduled(Some(TimeLock::Block(10)), Some(TimeLock::Timestamp(1000)));
+        let full_block = |id, timestamp, time_locks| {
+            let mut full_block = FullBlock::from(Block {
+                id,
+                hash: String::new(),
+                previous_hash: String::new(),
+                timestamp,
+                data: vec![],
+                nonce: 0,
+                next_miner: "Tom".to_string(),
+                next_validators: vec![],
+            });
+            full_block.body.transactions = vec![unstake.transaction.clone(); 2];
+            full_block.body.time_locks = time_locks;
+            full_block
+        };
+        let time_locks = vec![unstake.time_locks(1)];
+        assert_eq!(
+            validate_time_locks(&full_block(11, 1000, time_locks.clone())),
+            Ok(())
+        );
+        assert_eq!(
+            validate_time_locks(&full_block(10, 1000, time_locks.clone())),
+            Err(
+                "Block 10 transaction 1: Transaction is not valid until after block 10".to_string()
+            )
+        );
+        assert_eq!(
+            validate_time_locks(&full_block(11, 1001, time_locks)),
+            Err("Block 11 transaction 1: Transaction expired after timestamp 1000".to_string())
+        );
+
+        let invalid = [
+            vec![unstake.time_locks(2)],
+            vec![unstake.time_locks(1), unstake.time_locks(1)],
+            vec![_fixture_scheduled(None, None).time_locks(0)],
+        ];
+        for time_locks in invalid {
+            assert!(validate_time_locks(&full_block(11, 1000, time_locks))
+                .unwrap_err()
+                .starts_with("Block 11 has invalid time locks"));
+        }
+    }
+    #[test]
+    fn transaction_json_reads_as_scheduled() {
+        let json = r#"{ "event": "Unstake", "address": "Tom" }"#;
+        let scheduled: ScheduledTransaction = serde_json::from_str(json).unwrap();
+        assert_eq!(scheduled, _fixture_scheduled(None, None));
+
+        let scheduled = _fixture_scheduled(Some(TimeLock::Block(10)), None);
+        let json = serde_json::to_value(&scheduled).unwrap();
+        assert_eq!(json["address"], "Tom");
+        assert_eq!(json["valid_after"]["Block"], 10);
+        assert!(json.get("valid_until").is_none());
+        let transaction: Transaction = serde_json::from_value(json).unwrap();
+        assert_eq!(transaction, scheduled.transaction);
+    }
+
+    fn _fixture_scheduled(
+        valid_after: Option<TimeLock>,
+        valid_until: Option<TimeLock>,
+    ) -> ScheduledTransaction {
+        ScheduledTransaction {
+            transaction: Transaction {
+                event: Events::Unstake,
+                address: "Tom".to_string(),
+            },
+            valid_after,
+            valid_until,
+            witness: None,
+            signature: None,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! # Timestamp
+//!
+//! A block timestamp is only accepted if it is:
+//! - After the median timestamp of the last `median_time_span` blocks. Using the median, rather than the previous block,
+//!   allows clocks to disagree by a few seconds, while stopping a miner from moving the chain's time backwards.
+//! - No more than `max_clock_drift` seconds ahead of the local `Clock`.
+//!
+//! The clock is passed to validation, so tests can use a `FixedClock` instead of the `SystemClock`.
+
+use crate::{block::Block, genesis::GenesisConfig};
+use chrono::Utc;
+
+/// A source of the current time, in seconds since the Unix epoch.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> u64;
+}
+
+/// The local system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        Utc::now().timestamp() as u64
+    }
+}
+
+/// A clock which always returns the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Returns the median timestamp of the last `span` blocks, or `None` if there are no blocks.
+///
+/// **Note:** For an even number of blocks, the later of the two middle timestamps is used.
+pub fn median_time_past(blocks: &[Block], span: usize) -> Option<u64> {
+    let mut timestamps: Vec<u64> = blocks
+        .iter()
+        .rev()
+        .take(span)
+        .map(|block| block.timestamp)
+        .collect();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied()
+}
+
+/// Validates the `timestamp` of the `block`, against its `ancestors`, the blocks of the chain before it, and the `clock`.
+///
+/// # Errors
+///
+/// Returns an error if the timestamp is not after the median time past of the ancestors,
+/// or is more than `max_clock_drift` seconds ahead of the clock.
+pub fn validate_block_timestamp(
+    block: &Block,
+    ancestors: &[Block],
+    clock: &dyn Clock,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    if let Some(median) = median_time_past(ancestors, config.median_time_span) {
+        if block.timestamp <= median {
+            return Err(format!(
+                "Block {} is not after the median time of the last {} blocks",
+                block.id, config.median_time_span
+            ));
+        }
+    }
+    if block.timestamp > clock.now().saturating_add(config.max_clock_drift) {
+        return Err(format!("Block {} is in the future", block.id));
+    }
+    Ok(())
+}
+
+/// Validates the timestamp of every block of the `chain`.
+///
+/// # Errors
+///
+/// Returns the error of the first block with an invalid timestamp.
+pub fn validate_chain_timestamps(
+    chain: &[Block],
+    clock: &dyn Clock,
+    config: &GenesisConfig,
+) -> Result<(), String> {
+    for (i, block) in chain.iter().enumerate() {
+        validate_block_timestamp(block, &chain[..i], clock, config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn median_time_past_uses_last_span_blocks() {
+        let chain = _fixture_chain(&[100, 200, 150, 300, 250]);
+        assert_eq!(median_time_past(&chain, 3), Some(250));
+        assert_eq!(median_time_past(&chain, 11), Some(200));
+        assert_eq!(median_time_past(&chain, 2), Some(300));
+        assert_eq!(median_time_past(&[], 11), None);
+    }
+    #[test]
+    fn timestamp_must_be_after_median() {
+        let config = _fixture_config();
+        let clock = FixedClock(1000);
+        let chain = _fixture_chain(&[100, 200, 300]);
+        // Earlier than the previous block, but after the median
+        let block = _fixture_chain(&[100, 200, 300, 250]).pop().unwrap();
+        assert_eq!(
+            validate_block_timestamp(&block, &chain, &clock, &config),
+            Ok(())
+        );
+
+        let block = _fixture_chain(&[100, 200, 300, 200]).pop().unwrap();
+        assert_eq!(
+            validate_block_timestamp(&block, &chain, &clock, &config),
+            Err("Block 3 is not after the median time of the last 3 blocks".to_string())
+        );
+    }
+    #[test]
+    fn timestamp_must_be_within_drift_of_clock() {
+        let config = _fixture_config();
+        let chain = _fixture_chain(&[100, 200, 300]);
+        assert_eq!(
+            validate_chain_timestamps(&chain, &FixedClock(240), &config),
+            Ok(())
+        );
+        assert_eq!(
+            validate_chain_timestamps(&chain, &FixedClock(239), &config),
+            Err("Block 2 is in the future".to_string())
+        );
+    }
+    #[test]
+    fn system_clock_is_after_fixture_times() {
+        assert!(SystemClock.now() > 1650301013);
+    }
+
+    fn _fixture_config() -> GenesisConfig {
+        GenesisConfig {
+            median_time_span: 3,
+            max_clock_drift: 60,
+            ..Default::default()
+        }
+    }
+    fn _fixture_chain(timestamps: &[u64]) -> Vec<Block> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(id, &timestamp)| Block {
+                id: id as u64,
+                hash: String::new(),
+                previous_hash: String::new(),
+                timestamp,
+                data: vec![],
+                nonce: 0,
+                next_miner: String::new(),
+                next_validators: vec![],
+            })
+            .collect()
+    }
+}
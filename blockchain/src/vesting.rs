@@ -0,0 +1,217 @@
+//! # Vesting
+//!
+//! A vesting schedule locks part of the genesis allocation of an `Account`, and unlocks it by block height:
+//! nothing unlocks before the cliff, then tokens unlock linearly until the end of the schedule.
+//! A schedule whose `cliff` equals its `duration` unlocks everything at once, and one without a `cliff` is purely linear.
+//!
+//! Locked tokens cannot be staked, transferred, or spent on racks. See `GenesisConfig::spendable_tokens`.
+
+use crate::{account::Account, body::FullChain, genesis::GenesisConfig, snapshot::latest_accounts};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// How the tokens of a vesting Account unlock.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct VestingSchedule {
+    /// The number of tokens of the allocation which vest.
+    pub amount: u64,
+    /// The block id the schedule starts at.
+    pub start: u64,
+    /// The number of blocks after the `start` before any token unlocks.
+    pub cliff: u64,
+    /// The number of blocks after the `start` until every token is unlocked.
+    pub duration: u64,
+}
+
+impl VestingSchedule {
+    /// Checks the cliff is not after the end of the schedule.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cliff > self.duration {
+            return Err("Vesting cliff is after the end of the schedule".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the number of tokens unlocked at block `height`.
+    pub fn vested(&self, height: u64) -> u64 {
+        let elapsed = height.saturating_sub(self.start);
+        if height < self.start || elapsed < self.cliff {
+            0
+        } else if elapsed >= self.duration {
+            self.amount
+        } else {
+            (self.amount as u128 * elapsed as u128 / self.duration as u128) as u64
+        }
+    }
+
+    /// Returns the number of tokens still locked at block `height`.
+    pub fn locked(&self, height: u64) -> u64 {
+        self.amount - self.vested(height)
+    }
+}
+
+/// The balances of an `Account` at a block height, as returned by `get_vesting_balance`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VestingBalance {
+    /// The address of the Account.
+    pub address: String,
+    /// The block height of the balances.
+    pub height: u64,
+    /// All tokens of the Account, including staked and locked tokens.
+    pub tokens: u64,
+    /// The staked tokens of the Account.
+    pub staked: u64,
+    /// The tokens still locked by the vesting schedule.
+    pub locked: u64,
+    /// The tokens of the vesting schedule already unlocked.
+    pub vested: u64,
+    /// The tokens which can be transferred, staked, or spent.
+    pub spendable: u64,
+}
+
+impl VestingBalance {
+    /// Returns the balances of the `account` at block `height`.
+    pub fn of_account(account: &Account, height: u64, config: &GenesisConfig) -> Self {
+        let schedule = config.vesting.get(&account.address);
+        Self {
+            address: account.address.clone(),
+            height,
+            tokens: account.tokens,
+            staked: account.staked,
+            locked: config.locked_tokens(&account.address, height),
+            vested: schedule.map_or(0, |schedule| schedule.vested(height)),
+            spendable: config.spendable_tokens(account, height),
+        }
+    }
+}
+
+/// Returns the balances of the Account with the given `address`, at the last block of the `chain`.
+pub fn vesting_balance(
+    chain: &FullChain,
+    address: &str,
+    config: &GenesisConfig,
+) -> Option<VestingBalance> {
+    let height = chain.last()?.block.id;
+    latest_accounts(chain)
+        .iter()
+        .find(|account| account.address == address)
+        .map(|account| VestingBalance::of_account(account, height, config))
+}
+
+/// Returns the locked, vested, and spendable balances of the Account with the given `address`, at the last block of the `chain`.
+///
+/// # Examples
+///
+/// ```js
+/// const { locked, vested, spendable } = get_vesting_balance(chain, "Tom", config);
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, or the chain does not hold the Account, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn get_vesting_balance(
+    chain: JsValue,
+    address: String,
+    config: JsValue,
+) -> Result<JsValue, JsError> {
+    let chain: FullChain = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let balance = vesting_balance(&chain, &address, &config)
+        .ok_or_else(|| JsError::new(&format!("'{}' has no Account", address)))?;
+    Ok(JsValue::from_serde(&balance)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{account::AccountTrait, block::Block, body::FullBlock};
+    #[test]
+    fn linear_schedule_unlocks_after_cliff() {
+        let schedule = VestingSchedule {
+            amount: 100,
+            start: 10,
+            cliff: 5,
+            duration: 20,
+        };
+        assert_eq!(schedule.vested(0), 0);
+        assert_eq!(schedule.vested(14), 0);
+        assert_eq!(schedule.vested(15), 25);
+        assert_eq!(schedule.vested(20), 50);
+        assert_eq!(schedule.locked(20), 50);
+        assert_eq!(schedule.vested(30), 100);
+        assert_eq!(schedule.vested(u64::MAX), 100);
+    }
+    #[test]
+    fn cliff_schedule_unlocks_at_once() {
+        let schedule = VestingSchedule {
+            amount: 100,
+            start: 0,
+            cliff: 10,
+            duration: 10,
+        };
+        assert_eq!(schedule.vested(9), 0);
+        assert_eq!(schedule.vested(10), 100);
+        assert!(VestingSchedule {
+            cliff: 11,
+            ..schedule
+        }
+        .validate()
+        .is_err());
+    }
+    #[test]
+    fn balance_reports_locked_and_vested_tokens() {
+        let config = _fixture_config();
+        let mut tom = Account::new("Tom");
+        tom.tokens = 100;
+        tom.staked = 10;
+        let chain: FullChain = (0..=5u64)
+            .map(|id| {
+                FullBlock::from(Block {
+                    id,
+                    hash: String::new(),
+                    previous_hash: String::new(),
+                    timestamp: 1000,
+                    data: if id == 0 { vec![tom.clone()] } else { vec![] },
+                    nonce: 0,
+                    next_miner: "Tom".to_string(),
+                    next_validators: vec![],
+                })
+            })
+            .collect();
+        let balance = vesting_balance(&chain, "Tom", &config).unwrap();
+        assert_eq!(
+            balance,
+            VestingBalance {
+                address: "Tom".to_string(),
+                height: 5,
+                tokens: 100,
+                staked: 10,
+                locked: 40,
+                vested: 40,
+                spendable: 50,
+            }
+        );
+        assert_eq!(vesting_balance(&chain, "Mallory", &config), None);
+    }
+
+    /// Tom is allocated 100 tokens, of which 80 vest linearly over 10 blocks.
+    fn _fixture_config() -> GenesisConfig {
+        let mut tom = Account::new("Tom");
+        tom.tokens = 100;
+        let mut config = GenesisConfig {
+            allocations: vec![tom],
+            ..Default::default()
+        };
+        config.vesting.insert(
+            "Tom".to_string(),
+            VestingSchedule {
+                amount: 80,
+                start: 0,
+                cliff: 0,
+                duration: 10,
+            },
+        );
+        config
+    }
+}
@@ -0,0 +1,536 @@
+//! # VM
+//!
+//! A minimal, deterministic, stack-based virtual machine for contracts.
+//!
+//! Bytecode is a sequence of one byte `Opcode`s. `PUSH` is followed by its 8 byte, big-endian, operand.
+//! Every value is a `u64`. Arithmetic is checked, so an overflow, or a division by zero, stops the execution with an error.
+//!
+//! Every opcode costs gas. An execution which runs out of gas, or fails, leaves the contract storage unchanged.
+//!
+//! **Note:** Contracts are written with `assemble`, which turns one opcode per word, and `label:` jump targets, into bytecode.
+//!
+//! # Examples
+//!
+//! ```
+//! # use blockchain::vm::{assemble, execute, Context, Storage};
+//! // Returns the sum of the two arguments
+//! let code = assemble("PUSH 0 ARG PUSH 1 ARG ADD RETURN").unwrap();
+//! let context = Context { args: vec![2, 3], ..Default::default() };
+//! let execution = execute(&code, &mut Storage::new(), &context, 1000);
+//! assert_eq!(execution.result, Ok(Some(5)));
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The storage of a contract: a map from key to value.
+pub type Storage = BTreeMap<u64, u64>;
+
+/// The maximum number of bytes of contract bytecode.
+pub const MAX_CODE_SIZE: usize = 24_576;
+
+/// The maximum number of values on the stack.
+pub const MAX_STACK_SIZE: usize = 1024;
+
+/// The error of an execution which runs out of gas.
+pub const OUT_OF_GAS: &str = "Out of gas";
+
+/// The instructions of the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    /// Stops the execution, without a result.
+    Stop = 0x00,
+    /// Pushes the 8 byte operand following the opcode.
+    Push = 0x01,
+    /// Removes the top value.
+    Pop = 0x02,
+    /// Duplicates the top value.
+    Dup = 0x03,
+    /// Swaps the two top values.
+    Swap = 0x04,
+    /// Pops `b`, then `a`, and pushes `a + b`.
+    Add = 0x10,
+    /// Pops `b`, then `a`, and pushes `a - b`.
+    Sub = 0x11,
+    /// Pops `b`, then `a`, and pushes `a * b`.
+    Mul = 0x12,
+    /// Pops `b`, then `a`, and pushes `a / b`.
+    Div = 0x13,
+    /// Pops `b`, then `a`, and pushes `a % b`.
+    Mod = 0x14,
+    /// Pops `b`, then `a`, and pushes `1` if `a == b`, else `0`.
+    Eq = 0x20,
+    /// Pops `b`, then `a`, and pushes `1` if `a < b`, else `0`.
+    Lt = 0x21,
+    /// Pops `b`, then `a`, and pushes `1` if `a > b`, else `0`.
+    Gt = 0x22,
+    /// Pops `a`, and pushes `1` if `a == 0`, else `0`.
+    Not = 0x23,
+    /// Pops a position, and continues the execution there.
+    Jump = 0x30,
+    /// Pops a position, then a condition, and continues the execution there if the condition is not `0`.
+    JumpIf = 0x31,
+    /// Pops a key, and pushes its value in storage, or `0`.
+    Load = 0x40,
+    /// Pops a key, then a value, and stores the value at the key.
+    Store = 0x41,
+    /// Pops `i`, and pushes argument `i` of the call.
+    Arg = 0x50,
+    /// Pushes the `tokens` of the caller.
+    Balance = 0x51,
+    /// Pushes the `staked` tokens of the caller.
+    Staked = 0x52,
+    /// Pushes the id of the block the call is mined in.
+    Height = 0x53,
+    /// Pops the result, and stops the execution.
+    Return = 0x60,
+    /// Stops the execution with an error, discarding changes to storage.
+    Revert = 0x61,
+}
+
+impl Opcode {
+    const ALL: [Opcode; 24] = [
+        Opcode::Stop,
+        Opcode::Push,
+        Opcode::Pop,
+        Opcode::Dup,
+        Opcode::Swap,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Mod,
+        Opcode::Eq,
+        Opcode::Lt,
+        Opcode::Gt,
+        Opcode::Not,
+        Opcode::Jump,
+        Opcode::JumpIf,
+        Opcode::Load,
+        Opcode::Store,
+        Opcode::Arg,
+        Opcode::Balance,
+        Opcode::Staked,
+        Opcode::Height,
+        Opcode::Return,
+        Opcode::Revert,
+    ];
+
+    /// Returns the opcode of the `byte`, if any.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| *op as u8 == byte)
+    }
+
+    /// Returns the opcode with the assembly `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|op| format!("{:?}", op).eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the gas the opcode costs.
+    pub fn gas(&self) -> u64 {
+        match self {
+            Opcode::Mul | Opcode::Div | Opcode::Mod => 3,
+            Opcode::Jump | Opcode::JumpIf => 2,
+            Opcode::Balance | Opcode::Staked => 20,
+            Opcode::Load => 50,
+            Opcode::Store => 200,
+            _ => 1,
+        }
+    }
+}
+
+/// What a contract can read about its call.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Context {
+    /// The arguments of the call.
+    pub args: Vec<u64>,
+    /// The `tokens` of the caller, as the transactions before the call in its block left them.
+    pub balance: u64,
+    /// The `staked` tokens of the caller.
+    pub staked: u64,
+    /// The tokens of the caller still locked by its vesting schedule.
+    pub locked: u64,
+    /// The id of the block the call is mined in.
+    pub height: u64,
+    /// Whether the chain accepts free-form names as addresses, so as the recipients of transfers.
+    #[serde(default)]
+    pub legacy_addresses: bool,
+}
+
+/// Tokens a contract call moves from the caller to another Account.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TokenTransfer {
+    /// The address of the receiving Account.
+    pub to: String,
+    /// The number of tokens moved.
+    pub amount: u64,
+}
+
+/// The result of an execution.
+///
+/// **Note:** Only `wasm_contract`s transfer tokens, and emit events. A failed execution has neither.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Execution {
+    /// The gas used, including by a failed execution.
+    pub gas_used: u64,
+    /// The value returned by `RETURN`, or `None` after `STOP`. An error if the execution failed.
+    pub result: Result<Option<u64>, String>,
+    /// The tokens moved from the caller.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transfers: Vec<TokenTransfer>,
+    /// The events emitted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+}
+
+impl Execution {
+    /// Fails the execution with the `error`, dropping its transfers and events. The gas it used is still paid for.
+    pub fn fail(&mut self, error: String) {
+        self.result = Err(error);
+        self.transfers.clear();
+        self.events.clear();
+    }
+}
+
+/// Checks the `code` is not too large, and every opcode is known, with its operand.
+///
+/// # Errors
+///
+/// Returns an error naming the position at fault.
+pub fn validate_code(code: &[u8]) -> Result<(), String> {
+    jump_targets(code).map(|_| ())
+}
+
+/// Validates the `code`, and returns whether each position is the start of an opcode, and so a valid jump target.
+fn jump_targets(code: &[u8]) -> Result<Vec<bool>, String> {
+    if code.is_empty() {
+        return Err("Contract code is empty".to_string());
+    }
+    if code.len() > MAX_CODE_SIZE {
+        return Err(format!(
+            "Contract code is larger than {} bytes",
+            MAX_CODE_SIZE
+        ));
+    }
+    let mut targets = vec![false; code.len()];
+    let mut pc = 0;
+    while pc < code.len() {
+        targets[pc] = true;
+        match Opcode::from_byte(code[pc]) {
+            Some(Opcode::Push) if pc + 9 > code.len() => {
+                return Err(format!("PUSH at {} has no operand", pc))
+            }
+            Some(Opcode::Push) => pc += 9,
+            Some(_) => pc += 1,
+            None => return Err(format!("Unknown opcode 0x{:02x} at {}", code[pc], pc)),
+        }
+    }
+    Ok(targets)
+}
+
+/// Runs the `code` with the `context`, until it stops, fails, or uses the `gas_limit`.
+///
+/// **Note:** The `storage` is only changed if the execution succeeds.
+pub fn execute(code: &[u8], storage: &mut Storage, context: &Context, gas_limit: u64) -> Execution {
+    let mut machine = Machine {
+        code,
+        targets: vec![],
+        storage: storage.clone(),
+        stack: vec![],
+        gas_used: 0,
+        gas_limit,
+    };
+    let result = jump_targets(code).and_then(|targets| {
+        machine.targets = targets;
+        machine.run(context)
+    });
+    if result.is_ok() {
+        *storage = machine.storage;
+    }
+    Execution {
+        gas_used: machine.gas_used,
+        result,
+        transfers: vec![],
+        events: vec![],
+    }
+}
+
+/// The state of a running execution.
+struct Machine<'a> {
+    code: &'a [u8],
+    /// Whether each position of the `code` starts an opcode.
+    targets: Vec<bool>,
+    storage: Storage,
+    stack: Vec<u64>,
+    gas_used: u64,
+    gas_limit: u64,
+}
+
+impl Machine<'_> {
+    fn pop(&mut self) -> Result<u64, String> {
+        self.stack
+            .pop()
+            .ok_or_else(|| "Stack underflow".to_string())
+    }
+
+    fn push(&mut self, value: u64) -> Result<(), String> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err("Stack overflow".to_string());
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Pops `b`, then `a`, and pushes `f(a, b)`, failing with `error` if it is `None`.
+    fn binary(&mut self, f: impl Fn(u64, u64) -> Option<u64>, error: &str) -> Result<(), String> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let value = f(a, b).ok_or_else(|| error.to_string())?;
+        self.push(value)
+    }
+
+    /// Returns the position of the next opcode after a jump to `target`.
+    fn jump(&self, target: u64) -> Result<usize, String> {
+        // Targets must be opcodes, not the operand of a `PUSH`, nor past the end of the code
+        usize::try_from(target)
+            .ok()
+            .filter(|&target| self.targets.get(target) == Some(&true))
+            .ok_or_else(|| format!("Invalid jump to {}", target))
+    }
+
+    fn run(&mut self, context: &Context) -> Result<Option<u64>, String> {
+        let mut pc = 0;
+        while pc < self.code.len() {
+            let op = Opcode::from_byte(self.code[pc]).expect("code to be validated");
+            self.gas_used = self.gas_used.saturating_add(op.gas());
+            if self.gas_used > self.gas_limit {
+                self.gas_used = self.gas_limit;
+                return Err(OUT_OF_GAS.to_string());
+            }
+            pc += 1;
+            match op {
+                Opcode::Stop => return Ok(None),
+                Opcode::Push => {
+                    let operand = self.code[pc..pc + 8]
+                        .try_into()
+                        .expect("operand to be 8 bytes");
+                    self.push(u64::from_be_bytes(operand))?;
+                    pc += 8;
+                }
+                Opcode::Pop => {
+                    self.pop()?;
+                }
+                Opcode::Dup => {
+                    let value = self.pop()?;
+                    self.push(value)?;
+                    self.push(value)?;
+                }
+                Opcode::Swap => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(a)?;
+                }
+                Opcode::Add => self.binary(u64::checked_add, "Arithmetic overflow")?,
+                Opcode::Sub => self.binary(u64::checked_sub, "Arithmetic overflow")?,
+                Opcode::Mul => self.binary(u64::checked_mul, "Arithmetic overflow")?,
+                Opcode::Div => self.binary(u64::checked_div, "Division by zero")?,
+                Opcode::Mod => self.binary(u64::checked_rem, "Division by zero")?,
+                Opcode::Eq => self.binary(|a, b| Some((a == b) as u64), "")?,
+                Opcode::Lt => self.binary(|a, b| Some((a < b) as u64), "")?,
+                Opcode::Gt => self.binary(|a, b| Some((a > b) as u64), "")?,
+                Opcode::Not => {
+                    let a = self.pop()?;
+                    self.push((a == 0) as u64)?;
+                }
+                Opcode::Jump => {
+                    let target = self.pop()?;
+                    pc = self.jump(target)?;
+                }
+                Opcode::JumpIf => {
+                    let target = self.pop()?;
+                    if self.pop()? != 0 {
+                        pc = self.jump(target)?;
+                    }
+                }
+                Opcode::Load => {
+                    let key = self.pop()?;
+                    let value = self.storage.get(&key).copied().unwrap_or_default();
+                    self.push(value)?;
+                }
+                Opcode::Store => {
+                    let key = self.pop()?;
+                    let value = self.pop()?;
+                    self.storage.insert(key, value);
+                }
+                Opcode::Arg => {
+                    let i = self.pop()?;
+                    let arg = usize::try_from(i)
+                        .ok()
+                        .and_then(|i| context.args.get(i))
+                        .ok_or_else(|| format!("Call has no argument {}", i))?;
+                    self.push(*arg)?;
+                }
+                Opcode::Balance => self.push(context.balance)?,
+                Opcode::Staked => self.push(context.staked)?,
+                Opcode::Height => self.push(context.height)?,
+                Opcode::Return => return self.pop().map(Some),
+                Opcode::Revert => return Err("Reverted".to_string()),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Assembles the `source` into bytecode.
+///
+/// The source is a list of words, separated by whitespace: opcode names, the operand of each `PUSH`,
+/// and `label:` definitions. A `PUSH @label` operand is the position of the label.
+///
+/// # Errors
+///
+/// Returns an error naming the word at fault.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let words: Vec<&str> = source.split_whitespace().collect();
+    // Find the position of every label first, so labels can be used before they are defined
+    let mut labels = BTreeMap::new();
+    let mut position = 0u64;
+    let mut i = 0;
+    while i < words.len() {
+        if let Some(label) = words[i].strip_suffix(':') {
+            if labels.insert(label, position).is_some() {
+                return Err(format!("Label '{}' is defined twice", label));
+            }
+        } else if Opcode::from_name(words[i]) == Some(Opcode::Push) {
+            position += 9;
+            i += 1;
+        } else {
+            position += 1;
+        }
+        i += 1;
+    }
+
+    let mut code = vec![];
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        if word.ends_with(':') {
+            continue;
+        }
+        let op = Opcode::from_name(word).ok_or_else(|| format!("Unknown opcode '{}'", word))?;
+        code.push(op as u8);
+        if op == Opcode::Push {
+            let operand = words.next().ok_or("PUSH has no operand")?;
+            let value = match operand.strip_prefix('@') {
+                Some(label) => *labels
+                    .get(label)
+                    .ok_or_else(|| format!("Label '{}' is not defined", label))?,
+                None => operand
+                    .parse()
+                    .map_err(|_| format!("Operand '{}' is not a number", operand))?,
+            };
+            code.extend(u64::to_be_bytes(value));
+        }
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn arithmetic_and_storage() {
+        // Adds the argument to the counter at key 7, and returns the new count
+        let code = assemble("PUSH 7 LOAD PUSH 0 ARG ADD DUP PUSH 7 STORE RETURN").unwrap();
+        let mut storage = Storage::new();
+        let context = _fixture_context(vec![5]);
+        assert_eq!(
+            execute(&code, &mut storage, &context, 1000).result,
+            Ok(Some(5))
+        );
+        assert_eq!(
+            execute(&code, &mut storage, &context, 1000).result,
+            Ok(Some(10))
+        );
+        assert_eq!(storage.get(&7), Some(&10));
+    }
+    #[test]
+    fn failures_leave_storage_unchanged() {
+        let mut storage = Storage::new();
+        let context = _fixture_context(vec![]);
+        let store_then_fail =
+            |tail: &str| assemble(&format!("PUSH 1 PUSH 2 STORE {}", tail)).unwrap();
+        for (tail, error) in [
+            ("REVERT", "Reverted"),
+            ("PUSH 1 PUSH 0 DIV", "Division by zero"),
+            ("PUSH 0 PUSH 1 SUB", "Arithmetic overflow"),
+            ("POP", "Stack underflow"),
+            ("PUSH 0 ARG", "Call has no argument 0"),
+            ("PUSH 1 JUMP", "Invalid jump to 1"),
+            ("PUSH 1000 JUMP", "Invalid jump to 1000"),
+            (
+                "PUSH 1 PUSH 18446744073709551615 JUMPIF",
+                "Invalid jump to 18446744073709551615",
+            ),
+        ] {
+            let execution = execute(&store_then_fail(tail), &mut storage, &context, 1000);
+            assert_eq!(execution.result, Err(error.to_string()), "{}", tail);
+        }
+        assert!(storage.is_empty());
+    }
+    #[test]
+    fn gas_is_metered() {
+        let code = assemble("loop: PUSH @loop JUMP").unwrap();
+        let execution = execute(&code, &mut Storage::new(), &_fixture_context(vec![]), 100);
+        assert_eq!(execution.result, Err("Out of gas".to_string()));
+        assert_eq!(execution.gas_used, 100);
+
+        let code = assemble("PUSH 1 PUSH 2 ADD RETURN").unwrap();
+        let execution = execute(&code, &mut Storage::new(), &_fixture_context(vec![]), 100);
+        assert_eq!(execution.gas_used, 4);
+    }
+    #[test]
+    fn rack_price_check_reads_caller_balance() {
+        // Returns 1 if the caller can afford the price in argument 0, jumping over the failure
+        let code = assemble(
+            "BALANCE STAKED SUB PUSH 0 ARG LT PUSH @poor JUMPIF PUSH 1 RETURN poor: REVERT",
+        )
+        .unwrap();
+        let mut context = _fixture_context(vec![10]);
+        context.balance = 30;
+        context.staked = 15;
+        assert_eq!(
+            execute(&code, &mut Storage::new(), &context, 1000).result,
+            Ok(Some(1))
+        );
+        context.staked = 25;
+        assert_eq!(
+            execute(&code, &mut Storage::new(), &context, 1000).result,
+            Err("Reverted".to_string())
+        );
+    }
+    #[test]
+    fn invalid_code_is_rejected() {
+        assert!(validate_code(&[]).is_err());
+        assert_eq!(
+            validate_code(&[0xff]),
+            Err("Unknown opcode 0xff at 0".to_string())
+        );
+        assert_eq!(
+            validate_code(&[0x01, 0]),
+            Err("PUSH at 0 has no operand".to_string())
+        );
+        assert!(assemble("PUSH").is_err());
+        assert!(assemble("PUSH @nowhere").is_err());
+        assert!(assemble("JUMPY").is_err());
+    }
+
+    fn _fixture_context(args: Vec<u64>) -> Context {
+        Context {
+            args,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,808 @@
+//! # Wallet
+//!
+//! A wallet holds the Ed25519 keypair of an `Account`, whose address is derived from the public key.
+//! It signs the `Transaction`s of the Account, and is stored as a `Keystore`: the secret key, encrypted with a password.
+//!
+//! The keystore key is derived from the password with PBKDF2-HMAC-SHA256, and the secret key is encrypted with ChaCha20-Poly1305,
+//! authenticating the address of the wallet with it.
+//!
+//! From the protocol version with transaction signatures, every transaction of an address derived from a key is mined with
+//! its signature, as a `TransactionSignature` of the `BlockBody`, and `validate_signatures` checks each against the key.
+//! Transactions of legacy names, which have no key, and of multisig accounts, which are witnessed, are not signed.
+//!
+//! **Note:** The same `Wallet` is used natively, where a `Keystore` is saved to, and loaded from, a file, and from JavaScript.
+
+use crate::{
+    address::{validate_address, Address},
+    body::FullBlock,
+    genesis::GenesisConfig,
+    hash::BlockHash,
+    sequence::{check_sequence, transaction_id, Sequences},
+    timelock::{ScheduledTransaction, TimeLock, TransactionTimeLocks},
+    Transaction,
+};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use wasm_bindgen::prelude::*;
+
+/// The version of the keystore format.
+pub const KEYSTORE_VERSION: u8 = 1;
+
+/// The number of PBKDF2 iterations of new keystores.
+pub const KEYSTORE_ITERATIONS: u32 = 100_000;
+
+/// The number of bytes of the keystore salt.
+const SALT_LENGTH: usize = 16;
+
+/// The number of bytes of the ChaCha20-Poly1305 nonce.
+const NONCE_LENGTH: usize = 12;
+
+/// The Ed25519 keypair of an Account.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct Wallet {
+    signing_key: SigningKey,
+}
+
+/// A password encrypted secret key, as stored on disk, or in the browser.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    /// The version of the keystore format.
+    pub version: u8,
+    /// The address of the wallet.
+    pub address: String,
+    /// The number of PBKDF2 iterations deriving the encryption key from the password.
+    pub iterations: u32,
+    /// The hex encoded PBKDF2 salt.
+    pub salt: String,
+    /// The hex encoded ChaCha20-Poly1305 nonce.
+    pub nonce: String,
+    /// The hex encoded encrypted secret key, followed by its authentication tag.
+    pub ciphertext: String,
+}
+
+/// A `Transaction`, signed by the wallet of its `address`.
+///
+/// The signature is of the ID of the transaction, so it is only valid on one chain, at one `sequence` of the address,
+/// with the time locks it was signed with. See `sequence::transaction_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SignedTransaction {
+    /// The signed transaction.
+    pub transaction: Transaction,
+    /// The sequence of the transaction among the transactions of its address.
+    pub sequence: u64,
+    /// The hex encoded Ed25519 public key of the signer.
+    pub public_key: String,
+    /// The hex encoded Ed25519 signature of the transaction ID.
+    pub signature: String,
+    /// The `valid_after` time lock the transaction was signed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_after: Option<TimeLock>,
+    /// The `valid_until` time lock the transaction was signed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<TimeLock>,
+}
+
+/// The signature of a mined transaction, as kept in the `BlockBody`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TransactionSignature {
+    /// The index of the transaction in the `transactions` of the block.
+    pub index: usize,
+    /// The hex encoded Ed25519 public key of the signer.
+    pub public_key: String,
+    /// The hex encoded Ed25519 signature of the transaction ID.
+    pub signature: String,
+}
+
+impl Wallet {
+    /// Generates a wallet with a random keypair.
+    pub fn generate() -> Self {
+        let mut secret_key = [0u8; 32];
+        OsRng.fill_bytes(&mut secret_key);
+        Self::from_secret_key(&secret_key)
+    }
+
+    /// Creates the wallet of the Ed25519 `secret_key`.
+    pub fn from_secret_key(secret_key: &[u8; 32]) -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(secret_key),
+        }
+    }
+
+    /// Creates the wallet of the hex encoded Ed25519 `secret_key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret key is not 32 hex encoded bytes.
+    pub fn from_hex(secret_key: &str) -> Result<Self, String> {
+        let secret_key: [u8; 32] = hex::decode(secret_key)
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or("Invalid secret key")?;
+        Ok(Self::from_secret_key(&secret_key))
+    }
+
+    /// Returns the address of the wallet.
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&self.public_key())
+    }
+
+    /// Returns the public key of the wallet.
+    pub fn public_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Returns the signing key of the wallet, such as to produce blocks with.
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.signing_key
+    }
+
+    /// Returns the hex encoded secret key of the wallet.
+    ///
+    /// **Note:** Prefer storing the wallet as a `Keystore`.
+    pub fn secret_key_hex(&self) -> String {
+        hex::encode(self.signing_key.to_bytes())
+    }
+
+    /// Signs the `scheduled` transaction, which must be from the address of the wallet, with its time locks, as the
+    /// transaction with the given `sequence` of the address, in the chain configured by the `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction is from another address, or has no canonical encoding.
+    pub fn sign_transaction(
+        &self,
+        scheduled: ScheduledTransaction,
+        sequence: u64,
+        config: &GenesisConfig,
+    ) -> Result<SignedTransaction, String> {
+        let address = self.address();
+        if scheduled.transaction.address != address.as_str() {
+            return Err(format!("Transaction is not from '{}'", address));
+        }
+        let id = transaction_id(
+            &config.hash(),
+            sequence,
+            &scheduled.transaction,
+            &scheduled.time_locks(0),
+        )?;
+        Ok(SignedTransaction {
+            transaction: scheduled.transaction,
+            sequence,
+            public_key: hex::encode(self.public_key().as_bytes()),
+            signature: hex::encode(self.signing_key.sign(&id.0).to_bytes()),
+            valid_after: scheduled.valid_after,
+            valid_until: scheduled.valid_until,
+        })
+    }
+
+    /// Encrypts the secret key of the wallet with the `password`, deriving the encryption key with the given number of `iterations`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret key cannot be encrypted.
+    pub fn encrypt(&self, password: &str, iterations: u32) -> Result<Keystore, String> {
+        let mut salt = [0u8; SALT_LENGTH];
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let address = self.address().to_string();
+        let ciphertext = keystore_cipher(password, &salt, iterations)
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: self.signing_key.as_bytes(),
+                    aad: address.as_bytes(),
+                },
+            )
+            .map_err(|_| "Secret key cannot be encrypted")?;
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            address,
+            iterations,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+}
+
+impl Keystore {
+    /// Decrypts the wallet with the `password`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keystore is malformed, the password is not correct, or the keystore was tampered with.
+    pub fn decrypt(&self, password: &str) -> Result<Wallet, String> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(format!(
+                "Keystore version {} is not supported",
+                self.version
+            ));
+        }
+        validate_address(&self.address)?;
+        let salt = hex::decode(&self.salt).map_err(|_| "Keystore salt is not hex")?;
+        let nonce: [u8; NONCE_LENGTH] = hex::decode(&self.nonce)
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or("Keystore nonce is not valid")?;
+        let ciphertext =
+            hex::decode(&self.ciphertext).map_err(|_| "Keystore ciphertext is not hex")?;
+        let secret_key: [u8; 32] = keystore_cipher(password, &salt, self.iterations)
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: self.address.as_bytes(),
+                },
+            )
+            .ok()
+            .and_then(|secret_key| secret_key.try_into().ok())
+            .ok_or("Keystore password is not correct")?;
+        let wallet = Wallet::from_secret_key(&secret_key);
+        if wallet.address().as_str() != self.address {
+            return Err("Keystore address does not match its key".to_string());
+        }
+        Ok(wallet)
+    }
+
+    /// Saves the keystore as JSON to the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Keystore cannot be saved: {}", e))
+    }
+
+    /// Loads a keystore from the JSON file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, or is not a keystore.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Keystore cannot be loaded: {}", e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Invalid keystore: {}", e))
+    }
+}
+
+impl SignedTransaction {
+    /// Checks the signature is of the transaction, by the key of the address the transaction is from, as the next transaction
+    /// of the address in the chain of the `sequences`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence is already used or skips a sequence, the public key or signature is malformed,
+    /// the address is not of the public key, or the signature is not valid.
+    pub fn verify(&self, sequences: &Sequences) -> Result<(), String> {
+        let address = &self.transaction.address;
+        check_sequence(address, self.sequence, sequences.next(address))?;
+        let time_locks = TransactionTimeLocks {
+            index: 0,
+            valid_after: self.valid_after,
+            valid_until: self.valid_until,
+        };
+        let id = transaction_id(
+            sequences.genesis_hash(),
+            self.sequence,
+            &self.transaction,
+            &time_locks,
+        )?;
+        verify_signature(&self.transaction, &self.public_key, &self.signature, &id)
+    }
+}
+
+impl From<SignedTransaction> for ScheduledTransaction {
+    fn from(signed: SignedTransaction) -> Self {
+        Self {
+            signature: Some(TransactionSignature {
+                index: 0,
+                public_key: signed.public_key,
+                signature: signed.signature,
+            }),
+            valid_after: signed.valid_after,
+            valid_until: signed.valid_until,
+            ..signed.transaction.into()
+        }
+    }
+}
+
+/// Checks the hex encoded `signature` is of the `transaction_id`, by the hex encoded `public_key` the address of the
+/// `transaction` is derived from.
+fn verify_signature(
+    transaction: &Transaction,
+    public_key: &str,
+    signature: &str,
+    transaction_id: &BlockHash,
+) -> Result<(), String> {
+    let public_key: [u8; 32] = hex::decode(public_key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or("Transaction public key is not valid")?;
+    let public_key =
+        VerifyingKey::from_bytes(&public_key).map_err(|_| "Transaction public key is not valid")?;
+    let address = Address::parse_with(&transaction.address, true)?;
+    if !address.is_owned_by(&public_key) {
+        return Err(format!(
+            "'{}' is not the address of the signing key",
+            address
+        ));
+    }
+    let signature: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|signature| signature.try_into().ok())
+        .ok_or("Transaction signature is not valid")?;
+    public_key
+        .verify(&transaction_id.0, &Signature::from_bytes(&signature))
+        .map_err(|_| "Transaction signature is not valid".to_string())
+}
+
+/// Returns `true` if transactions of the `address` must be signed by its key: unless it is a legacy name, or the address
+/// of a multisig account.
+///
+/// **Note:** Whether the chain accepts legacy names at all is checked by `address::validate_block_addresses`.
+pub fn needs_signature(address: &str) -> bool {
+    Address::parse_with(address, true).map_or(true, |address| {
+        !address.is_legacy() && !address.is_multisig()
+    })
+}
+
+/// Checks the `signature` of the `transaction` with the ID `transaction_id`, if its address needs one.
+///
+/// # Errors
+///
+/// Returns an error if the transaction needs a signature, and has none, or its signature is not valid.
+pub fn check_signature(
+    transaction: &Transaction,
+    signature: Option<&TransactionSignature>,
+    transaction_id: &BlockHash,
+) -> Result<(), String> {
+    match signature {
+        Some(signature) => verify_signature(
+            transaction,
+            &signature.public_key,
+            &signature.signature,
+            transaction_id,
+        ),
+        None if needs_signature(&transaction.address) => Err(format!(
+            "Transaction of '{}' is not signed",
+            transaction.address
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks the `transaction_signatures` of the `full_block`, whose transactions have the IDs `transaction_ids`.
+///
+/// # Errors
+///
+/// Returns an error naming the block, if the signatures are not in ascending order of transaction, or name no transaction,
+/// or the transaction, if it fails `check_signature`.
+pub fn validate_signatures(
+    full_block: &FullBlock,
+    transaction_ids: &[BlockHash],
+) -> Result<(), String> {
+    let id = full_block.block.id;
+    let transactions = &full_block.body.transactions;
+    let mut signatures: Vec<Option<&TransactionSignature>> = vec![None; transactions.len()];
+    let mut previous: Option<usize> = None;
+    for signature in &full_block.body.transaction_signatures {
+        let index = signature.index;
+        if previous.is_some_and(|previous| index <= previous) || index >= transactions.len() {
+            return Err(format!(
+                "Block {} has an invalid signature for transaction {}",
+                id, index
+            ));
+        }
+        signatures[index] = Some(signature);
+        previous = Some(index);
+    }
+    for (i, (transaction, signature)) in transactions.iter().zip(signatures).enumerate() {
+        let transaction_id = transaction_ids
+            .get(i)
+            .ok_or_else(|| format!("Block {} has no ID for transaction {}", id, i))?;
+        check_signature(transaction, signature, transaction_id)
+            .map_err(|e| format!("Transaction {} of block {}: {}", i, id, e))?;
+    }
+    Ok(())
+}
+
+/// Returns the keystore cipher, with the key derived from the `password`.
+fn keystore_cipher(password: &str, salt: &[u8], iterations: u32) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+#[wasm_bindgen]
+impl Wallet {
+    /// Generates a wallet with a random keypair.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const wallet = new Wallet();
+    /// localStorage.setItem("keystore", JSON.stringify(wallet.export_keystore(password)));
+    /// const sequence = get_next_sequence(chain, wallet.address(), config);
+    /// const transaction = wallet.sign_transaction({ event: "Stake", address: wallet.address() }, sequence, config);
+    /// ```
+    #[wasm_bindgen(constructor)]
+    pub fn from_js() -> Wallet {
+        Self::generate()
+    }
+
+    /// Decrypts the wallet of the `keystore` argument with the `password` argument.
+    ///
+    /// # Errors
+    ///
+    /// If `keystore` argument is not deserialisable into type `Keystore`, or the password is not correct, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = from_keystore)]
+    pub fn from_keystore_js(keystore: JsValue, password: String) -> Result<Wallet, JsError> {
+        let keystore: Keystore = keystore.into_serde()?;
+        keystore.decrypt(&password).map_err(|e| JsError::new(&e))
+    }
+
+    /// Returns the address of the wallet.
+    #[wasm_bindgen(js_name = address)]
+    pub fn address_js(&self) -> String {
+        self.address().into()
+    }
+
+    /// Returns the hex encoded public key of the wallet.
+    #[wasm_bindgen(js_name = public_key)]
+    pub fn public_key_js(&self) -> String {
+        hex::encode(self.public_key().as_bytes())
+    }
+
+    /// Returns the hex encoded secret key of the wallet, as taken by `produce_block_by_stake`.
+    #[wasm_bindgen(js_name = secret_key)]
+    pub fn secret_key_js(&self) -> String {
+        self.secret_key_hex()
+    }
+
+    /// Encrypts the secret key of the wallet with the `password` argument.
+    ///
+    /// # Errors
+    ///
+    /// If the secret key cannot be encrypted, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = export_keystore)]
+    pub fn export_keystore_js(&self, password: String) -> Result<JsValue, JsError> {
+        let keystore = self
+            .encrypt(&password, KEYSTORE_ITERATIONS)
+            .map_err(|e| JsError::new(&e))?;
+        Ok(JsValue::from_serde(&keystore)?)
+    }
+
+    /// Signs the `transaction` argument, which must be from the address of the wallet, with its time locks, as the transaction
+    /// with the `sequence` argument of the address, in the chain configured by the `config` argument.
+    ///
+    /// # Examples
+    ///
+    /// ```js
+    /// const { transaction, public_key, signature, ...timeLocks } = wallet.sign_transaction(
+    ///   { ...stake, valid_after: { Block: 10 } },
+    ///   sequence,
+    ///   config
+    /// );
+    /// // Offered for the next block, the signature goes alongside the transaction, with the time locks it signs
+    /// nodeState.transactions.push({ ...transaction, ...timeLocks, signature: { index: 0, public_key, signature } });
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If an argument is not deserialisable, or the transaction is from another address, a `JsError` is thrown.
+    #[wasm_bindgen(js_name = sign_transaction)]
+    pub fn sign_transaction_js(
+        &self,
+        transaction: JsValue,
+        sequence: u64,
+        config: JsValue,
+    ) -> Result<JsValue, JsError> {
+        let transaction: ScheduledTransaction = transaction.into_serde()?;
+        let config: GenesisConfig = config.into_serde()?;
+        let signed = self
+            .sign_transaction(transaction, sequence, &config)
+            .map_err(|e| JsError::new(&e))?;
+        Ok(JsValue::from_serde(&signed)?)
+    }
+}
+
+/// Checks the `transaction` argument is signed by the key of the address it is from, as the next transaction of the address
+/// after the last block of the `chain` argument.
+///
+/// # Examples
+///
+/// ```js
+/// const signed = wallet.sign_transaction({ event: "Stake", address: wallet.address() }, sequence, config);
+/// verify_transaction(signed, chain, config); // true
+/// ```
+///
+/// # Errors
+///
+/// If an argument is not deserialisable, a `JsError` is thrown.
+#[wasm_bindgen]
+pub fn verify_transaction(
+    transaction: JsValue,
+    chain: JsValue,
+    config: JsValue,
+) -> Result<bool, JsError> {
+    let transaction: SignedTransaction = transaction.into_serde()?;
+    let chain: Vec<FullBlock> = chain.into_serde()?;
+    let config: GenesisConfig = config.into_serde()?;
+    let sequences = Sequences::from_chain(&chain, &config);
+    Ok(transaction.verify(&sequences).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::{Account, AccountTrait},
+        assembly::assemble_block,
+        block::Block,
+        state::ChainState,
+        Events,
+    };
+    #[test]
+    fn keystore_round_trips_wallet() {
+        let wallet = Wallet::generate();
+        let keystore = wallet.encrypt("correct horse", 1).unwrap();
+        assert_eq!(keystore.address, wallet.address().as_str());
+        assert!(!keystore.ciphertext.contains(&wallet.secret_key_hex()));
+
+        let decrypted = keystore.decrypt("correct horse").unwrap();
+        assert_eq!(decrypted.secret_key_hex(), wallet.secret_key_hex());
+        assert_eq!(
+            keystore.decrypt("battery staple").err(),
+            Some("Keystore password is not correct".to_string())
+        );
+    }
+    #[test]
+    fn tampered_keystore_is_rejected() {
+        let keystore = _fixture_wallet().encrypt("password", 1).unwrap();
+        let other = Wallet::from_secret_key(&[2; 32]).address().to_string();
+        let tampered = Keystore {
+            address: other,
+            ..keystore.clone()
+        };
+        assert_eq!(
+            tampered.decrypt("password").err(),
+            Some("Keystore password is not correct".to_string())
+        );
+        let tampered = Keystore {
+            version: 9,
+            ..keystore
+        };
+        assert_eq!(
+            tampered.decrypt("password").err(),
+            Some("Keystore version 9 is not supported".to_string())
+        );
+    }
+    #[test]
+    fn keystore_is_saved_to_file() {
+        let wallet = _fixture_wallet();
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", wallet.address()));
+        wallet.encrypt("password", 1).unwrap().save(&path).unwrap();
+        let keystore = Keystore::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            keystore.decrypt("password").unwrap().address(),
+            wallet.address()
+        );
+    }
+    #[test]
+    fn signed_transactions_verify() {
+        let config = GenesisConfig::default();
+        let sequences = Sequences::new(&config);
+        let wallet = _fixture_wallet();
+        let mut signed = wallet
+            .sign_transaction(
+                _fixture_transaction(&wallet, Events::Stake).into(),
+                0,
+                &config,
+            )
+            .unwrap();
+        assert_eq!(signed.verify(&sequences), Ok(()));
+
+        signed.transaction.event = Events::Unstake;
+        assert_eq!(
+            signed.verify(&sequences),
+            Err("Transaction signature is not valid".to_string())
+        );
+
+        let other = Wallet::from_secret_key(&[2; 32]);
+        let mut forged = other
+            .sign_transaction(
+                _fixture_transaction(&other, Events::Stake).into(),
+                0,
+                &config,
+            )
+            .unwrap();
+        forged.transaction.address = wallet.address().into();
+        assert_eq!(
+            forged.verify(&sequences),
+            Err(format!(
+                "'{}' is not the address of the signing key",
+                wallet.address()
+            ))
+        );
+        assert_eq!(
+            wallet
+                .sign_transaction(
+                    _fixture_transaction(&other, Events::Stake).into(),
+                    0,
+                    &config
+                )
+                .err(),
+            Some(format!("Transaction is not from '{}'", wallet.address()))
+        );
+    }
+    #[test]
+    fn signed_transactions_cannot_be_replayed() {
+        let config = GenesisConfig::default();
+        let wallet = _fixture_wallet();
+        let stake = _fixture_transaction(&wallet, Events::Stake);
+        let signed = wallet
+            .sign_transaction(stake.clone().into(), 0, &config)
+            .unwrap();
+        let mut block = FullBlock::from(Block {
+            id: 1,
+            hash: String::new(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![],
+            nonce: 0,
+            next_miner: String::new(),
+            next_validators: vec![],
+        });
+        block.body.transactions = vec![stake.clone()];
+        let sequences = Sequences::from_chain(&[block], &config);
+        // Once mined, the signature cannot be used again
+        assert_eq!(
+            signed.verify(&sequences),
+            Err(format!(
+                "Sequence 0 of '{}' is already used",
+                wallet.address()
+            ))
+        );
+        let skipping = wallet
+            .sign_transaction(stake.clone().into(), 2, &config)
+            .unwrap();
+        assert_eq!(
+            skipping.verify(&sequences),
+            Err(format!(
+                "Sequence 2 of '{}' skips sequence 1",
+                wallet.address()
+            ))
+        );
+        // A signed sequence cannot be changed
+        let moved = SignedTransaction {
+            sequence: 1,
+            ..signed
+        };
+        assert_eq!(
+            moved.verify(&sequences),
+            Err("Transaction signature is not valid".to_string())
+        );
+        // Nor used on another chain
+        let other = GenesisConfig {
+            starting_tokens: 1,
+            ..Default::default()
+        };
+        let signed = wallet.sign_transaction(stake.into(), 0, &other).unwrap();
+        assert_eq!(
+            signed.verify(&Sequences::new(&config)),
+            Err("Transaction signature is not valid".to_string())
+        );
+    }
+    #[test]
+    fn signed_time_locks_cannot_be_changed() {
+        let config = GenesisConfig::default();
+        let sequences = Sequences::new(&config);
+        let wallet = _fixture_wallet();
+        let unstake = ScheduledTransaction {
+            valid_after: Some(TimeLock::Block(10)),
+            .._fixture_transaction(&wallet, Events::Unstake).into()
+        };
+        let signed = wallet.sign_transaction(unstake, 0, &config).unwrap();
+        assert_eq!(signed.verify(&sequences), Ok(()));
+        // Nor dropped, nor moved, once signed
+        for valid_after in [None, Some(TimeLock::Block(5))] {
+            let changed = SignedTransaction {
+                valid_after,
+                ..signed.clone()
+            };
+            assert_eq!(
+                changed.verify(&sequences),
+                Err("Transaction signature is not valid".to_string())
+            );
+        }
+        let scheduled = ScheduledTransaction::from(signed);
+        assert_eq!(scheduled.valid_after, Some(TimeLock::Block(10)));
+    }
+    #[test]
+    fn mined_transactions_need_signatures() {
+        let config = GenesisConfig::default();
+        let wallet = _fixture_wallet();
+        let address = String::from(wallet.address());
+        let genesis = Block {
+            id: 0,
+            hash: BlockHash::default().to_hex(),
+            previous_hash: String::new(),
+            timestamp: 1000,
+            data: vec![Account::new(&address), Account::new("Tom")],
+            nonce: 0,
+            next_miner: "Tom".to_string(),
+            next_validators: vec![],
+        };
+        let chain = vec![
+            assemble_block(&[], genesis.clone(), vec![], &config)
+                .unwrap()
+                .full_block,
+        ];
+        let stake = _fixture_transaction(&wallet, Events::Stake);
+        let signed = wallet
+            .sign_transaction(stake.clone().into(), 0, &config)
+            .unwrap();
+        let update = Transaction {
+            event: Events::UpdateChain,
+            address: "Tom".to_string(),
+        };
+        // The signature of sequence 0 is not valid for the next transaction
+        let transactions = vec![
+            stake.into(),
+            signed.clone().into(),
+            update.into(),
+            signed.into(),
+        ];
+        let block = Block { id: 1, ..genesis };
+        let assembled = assemble_block(&chain, block, transactions, &config).unwrap();
+        let errors: Vec<_> = assembled
+            .rejected
+            .iter()
+            .map(|rejection| rejection.error.clone())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                format!("Transaction of '{}' is not signed", address),
+                "Transaction signature is not valid".to_string()
+            ]
+        );
+        let mut full_block = assembled.full_block;
+        assert_eq!(full_block.body.transactions.len(), 2);
+        assert_eq!(full_block.body.transaction_signatures.len(), 1);
+        assert_eq!(full_block.body.transaction_signatures[0].index, 0);
+        let signed_chain = [chain.clone(), vec![full_block.clone()]].concat();
+        assert!(ChainState::from_chain(&signed_chain, &config).is_ok());
+
+        full_block.body.transaction_signatures.clear();
+        assert_eq!(
+            ChainState::from_chain(&[chain, vec![full_block]].concat(), &config),
+            Err(format!(
+                "Transaction 0 of block 1: Transaction of '{}' is not signed",
+                address
+            ))
+        );
+    }
+
+    fn _fixture_wallet() -> Wallet {
+        Wallet::from_secret_key(&[1; 32])
+    }
+    fn _fixture_transaction(wallet: &Wallet, event: Events) -> Transaction {
+        Transaction {
+            event,
+            address: wallet.address().into(),
+        }
+    }
+}
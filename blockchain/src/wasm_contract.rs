@@ -0,0 +1,527 @@
+//! # WASM Contract
+//!
+//! Contracts can also be WebAssembly modules, deployed by the same `DeployContract` transaction as `vm` bytecode:
+//! code starting with the WASM magic bytes is a module.
+//!
+//! A module exports its `memory`, and a `call` function taking no parameters and returning an `i64`.
+//! It may import these host functions from the `env` module:
+//! - `arg(i: i32) -> i64`, `balance() -> i64`, `staked() -> i64`, and `height() -> i64` read the `vm::Context` of the call
+//! - `storage_read(key: i64) -> i64` and `storage_write(key: i64, value: i64)` read and write the contract storage
+//! - `transfer(address: i32, len: i32, amount: i64)` moves spendable tokens of the caller, neither staked nor locked by vesting,
+//!   to the UTF-8 address in memory
+//! - `emit(event: i32, len: i32)` emits the UTF-8 string in memory
+//!
+//! Execution is metered by fuel, with the gas limit of a `vm` call. Host functions cost the gas of their `vm` counterpart.
+//! Floating point instructions are rejected, so every execution is deterministic. A module has at most one memory of
+//! `MAX_MEMORY_SIZE` bytes, and one table of `MAX_TABLE_ELEMENTS` elements: a module declaring more fails to instantiate.
+//!
+//! **Note:** Modules are run by the `wasmi` interpreter, which is only embedded in native builds. WASM builds can neither deploy,
+//! nor call, WASM contracts, so they cannot replay chains which do. So, WASM contracts are only enabled from protocol version `2`,
+//! which chains activate by choice. Before it, deploying a module fails in every build alike.
+//!
+//! A WASM build validating a block which runs a WASM contract returns an error starting with `UNSUPPORTED_ERROR`. This is a
+//! node capability error, not an error of the chain: the blocks before it are valid, and the block may be too. Check errors
+//! with `is_unsupported_error`, and leave such chains to native nodes, instead of refusing them.
+
+use crate::vm::{Context, Execution, Storage};
+use wasm_bindgen::prelude::*;
+
+/// The bytes every WebAssembly module starts with.
+pub const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// The maximum number of bytes of a contract module.
+pub const MAX_MODULE_SIZE: usize = 65_536;
+
+/// The maximum number of bytes of the memory of a contract.
+pub const MAX_MEMORY_SIZE: usize = 1 << 20;
+
+/// The maximum number of elements of a table of a contract.
+pub const MAX_TABLE_ELEMENTS: u32 = 1_024;
+
+/// The gas a `transfer` costs.
+pub const TRANSFER_GAS: u64 = 500;
+
+/// The gas an `emit` costs, besides 1 per byte of the event.
+pub const EMIT_GAS: u64 = 100;
+
+/// Whether this build can run WASM contracts.
+pub const IS_SUPPORTED: bool = cfg!(not(target_arch = "wasm32"));
+
+/// The start of the error of a build which cannot run WASM contracts, for a chain running one.
+pub const UNSUPPORTED_ERROR: &str = "WASM contracts are not supported by this build";
+
+/// Returns `true` if this build can run WASM contracts, so validate chains activating protocol version `2`.
+///
+/// # Examples
+///
+/// ```js
+/// const canValidate = supports_wasm_contracts();
+/// ```
+#[wasm_bindgen]
+pub fn supports_wasm_contracts() -> bool {
+    IS_SUPPORTED
+}
+
+/// Returns `true` if the `error` argument is a node capability error: the chain runs a WASM contract, which this build
+/// cannot run. The chain is not invalid, it is only left unvalidated from the block running the contract.
+///
+/// # Examples
+///
+/// ```js
+/// try {
+///   validate_chain_with_config(chain, config);
+/// } catch (e) {
+///   if (!is_unsupported_error(e.message)) throw e;
+///   // Defer to a native node
+/// }
+/// ```
+#[wasm_bindgen]
+pub fn is_unsupported_error(error: &str) -> bool {
+    error.starts_with(UNSUPPORTED_ERROR)
+}
+
+/// Returns `true` if the contract `code` is a WebAssembly module.
+pub fn is_wasm(code: &[u8]) -> bool {
+    code.starts_with(WASM_MAGIC)
+}
+
+/// Checks the `code` is a valid module without floating point instructions, exporting its `memory` and a `call` function.
+///
+/// # Errors
+///
+/// Returns an error if the module is invalid, or this is a WASM build.
+#[cfg(target_arch = "wasm32")]
+pub fn validate_module(_code: &[u8]) -> Result<(), String> {
+    Err(UNSUPPORTED_ERROR.to_string())
+}
+
+/// Runs the `call` function of the module `code` with the `context`, until it returns, traps, or uses the `gas_limit`.
+///
+/// **Note:** The `storage` is only changed if the execution succeeds.
+#[cfg(target_arch = "wasm32")]
+pub fn execute_module(
+    _code: &[u8],
+    _storage: &mut Storage,
+    _context: &Context,
+    _gas_limit: u64,
+) -> Execution {
+    Execution {
+        gas_used: 0,
+        result: Err(UNSUPPORTED_ERROR.to_string()),
+        transfers: vec![],
+        events: vec![],
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{execute_module, validate_module};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use super::*;
+    use crate::{
+        address::Address,
+        vm::{Opcode, TokenTransfer, OUT_OF_GAS},
+    };
+    use wasmi::{
+        core::TrapCode, Caller, Config, Engine, Error, Extern, ExternType, Linker, Module, Store,
+        StoreLimits, StoreLimitsBuilder,
+    };
+
+    /// The state of a call, available to host functions.
+    struct Host {
+        context: Context,
+        storage: Storage,
+        transfers: Vec<TokenTransfer>,
+        events: Vec<String>,
+        limits: StoreLimits,
+    }
+
+    fn engine() -> Engine {
+        let mut config = Config::default();
+        config.consume_fuel(true).floats(false);
+        Engine::new(&config)
+    }
+
+    fn compile(engine: &Engine, code: &[u8]) -> Result<Module, String> {
+        if code.len() > MAX_MODULE_SIZE {
+            return Err(format!(
+                "Contract module is larger than {} bytes",
+                MAX_MODULE_SIZE
+            ));
+        }
+        let module =
+            Module::new(engine, code).map_err(|e| format!("Invalid contract module: {}", e))?;
+        if !matches!(module.get_export("memory"), Some(ExternType::Memory(_))) {
+            return Err("Contract module does not export its memory".to_string());
+        }
+        if !matches!(module.get_export("call"), Some(ExternType::Func(_))) {
+            return Err("Contract module does not export a call function".to_string());
+        }
+        Ok(module)
+    }
+
+    /// Checks the `code` is a valid module without floating point instructions, exporting its `memory` and a `call` function.
+    pub fn validate_module(code: &[u8]) -> Result<(), String> {
+        compile(&engine(), code).map(|_| ())
+    }
+
+    /// Consumes `gas` from the remaining fuel of the call.
+    fn charge(caller: &mut Caller<'_, Host>, gas: u64) -> Result<(), Error> {
+        let fuel = caller.get_fuel()?;
+        if fuel < gas {
+            caller.set_fuel(0)?;
+            return Err(Error::from(TrapCode::OutOfFuel));
+        }
+        caller.set_fuel(fuel - gas)?;
+        Ok(())
+    }
+
+    /// Reads the UTF-8 string of `len` bytes at `ptr` in the exported memory.
+    fn read_string(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<String, Error> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| Error::new("Contract module does not export its memory"))?;
+        let start = ptr as u32 as usize;
+        let bytes = memory
+            .data(caller)
+            .get(start..start.saturating_add(len as u32 as usize))
+            .ok_or_else(|| Error::new("Out of bounds memory access"))?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::new("String is not UTF-8"))
+    }
+
+    fn link(engine: &Engine) -> Result<Linker<Host>, Error> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap("env", "arg", |mut caller: Caller<'_, Host>, i: i32| {
+            charge(&mut caller, Opcode::Arg.gas())?;
+            let args = &caller.data().context.args;
+            args.get(i as u32 as usize)
+                .map(|arg| *arg as i64)
+                .ok_or_else(|| Error::new(format!("Call has no argument {}", i)))
+        })?;
+        linker.func_wrap("env", "balance", |mut caller: Caller<'_, Host>| {
+            charge(&mut caller, Opcode::Balance.gas())?;
+            Ok(caller.data().context.balance as i64)
+        })?;
+        linker.func_wrap("env", "staked", |mut caller: Caller<'_, Host>| {
+            charge(&mut caller, Opcode::Staked.gas())?;
+            Ok(caller.data().context.staked as i64)
+        })?;
+        linker.func_wrap("env", "height", |mut caller: Caller<'_, Host>| {
+            charge(&mut caller, Opcode::Height.gas())?;
+            Ok(caller.data().context.height as i64)
+        })?;
+        linker.func_wrap(
+            "env",
+            "storage_read",
+            |mut caller: Caller<'_, Host>, key: i64| {
+                charge(&mut caller, Opcode::Load.gas())?;
+                let storage = &caller.data().storage;
+                Ok(storage.get(&(key as u64)).copied().unwrap_or_default() as i64)
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "storage_write",
+            |mut caller: Caller<'_, Host>, key: i64, value: i64| {
+                charge(&mut caller, Opcode::Store.gas())?;
+                caller.data_mut().storage.insert(key as u64, value as u64);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "transfer",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32, amount: i64| {
+                charge(&mut caller, TRANSFER_GAS)?;
+                let to = read_string(&caller, ptr, len)?;
+                let legacy_names = caller.data().context.legacy_addresses;
+                Address::parse_with(&to, legacy_names).map_err(Error::new)?;
+                let host = caller.data_mut();
+                let context = &host.context;
+                let spendable = context
+                    .balance
+                    .saturating_sub(context.staked)
+                    .saturating_sub(context.locked);
+                let transferred = host.transfers.iter().fold(0u64, |total, transfer| {
+                    total.saturating_add(transfer.amount)
+                });
+                let amount = amount as u64;
+                if transferred.saturating_add(amount) > spendable {
+                    return Err(Error::new(
+                        "Transfer is above the spendable tokens of the caller",
+                    ));
+                }
+                host.transfers.push(TokenTransfer { to, amount });
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "emit",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                charge(&mut caller, EMIT_GAS.saturating_add(len as u32 as u64))?;
+                let event = read_string(&caller, ptr, len)?;
+                caller.data_mut().events.push(event);
+                Ok(())
+            },
+        )?;
+        Ok(linker)
+    }
+
+    /// Instantiates the module `code`, and runs its `call` function in the `store`.
+    fn run(engine: &Engine, store: &mut Store<Host>, code: &[u8]) -> Result<Option<u64>, String> {
+        let module = compile(engine, code)?;
+        let to_string = |e: Error| match e.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => OUT_OF_GAS.to_string(),
+            _ => e.to_string(),
+        };
+        let linker = link(engine).map_err(to_string)?;
+        let instance = linker
+            .instantiate(&mut *store, &module)
+            .and_then(|instance| instance.start(&mut *store))
+            .map_err(to_string)?;
+        let call = instance
+            .get_typed_func::<(), i64>(&*store, "call")
+            .map_err(to_string)?;
+        let result = call.call(&mut *store, ()).map_err(to_string)?;
+        Ok(Some(result as u64))
+    }
+
+    /// Runs the `call` function of the module `code` with the `context`, until it returns, traps, or uses the `gas_limit`.
+    ///
+    /// **Note:** The `storage` is only changed if the execution succeeds.
+    pub fn execute_module(
+        code: &[u8],
+        storage: &mut Storage,
+        context: &Context,
+        gas_limit: u64,
+    ) -> Execution {
+        let engine = engine();
+        let host = Host {
+            context: context.clone(),
+            storage: storage.clone(),
+            transfers: vec![],
+            events: vec![],
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY_SIZE)
+                .table_elements(MAX_TABLE_ELEMENTS)
+                .memories(1)
+                .tables(1)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.limits);
+        store
+            .set_fuel(gas_limit)
+            .expect("fuel metering to be enabled");
+        let result = run(&engine, &mut store, code);
+        let gas_used = gas_limit - store.get_fuel().expect("fuel metering to be enabled");
+        let host = store.into_data();
+        match result {
+            Ok(result) => {
+                *storage = host.storage;
+                Execution {
+                    gas_used,
+                    result: Ok(result),
+                    transfers: host.transfers,
+                    events: host.events,
+                }
+            }
+            Err(error) => Execution {
+                gas_used,
+                result: Err(error),
+                transfers: vec![],
+                events: vec![],
+            },
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::vm::{Opcode, TokenTransfer};
+    #[test]
+    fn unsupported_errors_are_capability_errors() {
+        assert!(supports_wasm_contracts());
+        assert!(is_unsupported_error(&format!(
+            "{}: block 2 runs a WASM contract",
+            UNSUPPORTED_ERROR
+        )));
+        assert!(!is_unsupported_error("Block 2 is invalid"));
+    }
+    #[test]
+    fn module_reads_and_writes_storage() {
+        // Adds the argument to the counter at key 7, and returns the new count
+        let code = _fixture_module(
+            r#"(func (export "call") (result i64) (local i64)
+                (local.set 0 (i64.add (call $storage_read (i64.const 7)) (call $arg (i32.const 0))))
+                (call $storage_write (i64.const 7) (local.get 0))
+                (local.get 0))"#,
+        );
+        assert!(is_wasm(&code));
+        assert_eq!(validate_module(&code), Ok(()));
+        let mut storage = Storage::new();
+        let context = _fixture_context(vec![5]);
+        let execution = execute_module(&code, &mut storage, &context, 10_000);
+        assert_eq!(execution.result, Ok(Some(5)));
+        assert_eq!(
+            execute_module(&code, &mut storage, &context, 10_000).result,
+            Ok(Some(10))
+        );
+        assert_eq!(storage.get(&7), Some(&10));
+    }
+    #[test]
+    fn module_transfers_tokens_and_emits_events() {
+        let code = _fixture_module(
+            r#"(data (i32.const 0) "Camperbought")
+               (func (export "call") (result i64)
+                (call $transfer (i32.const 0) (i32.const 6) (call $arg (i32.const 0)))
+                (call $emit (i32.const 6) (i32.const 6))
+                (i64.const 0))"#,
+        );
+        let mut context = _fixture_context(vec![15]);
+        context.balance = 30;
+        context.staked = 10;
+        context.legacy_addresses = true;
+        let execution = execute_module(&code, &mut Storage::new(), &context, 10_000);
+        assert_eq!(execution.result, Ok(Some(0)));
+        assert_eq!(
+            execution.transfers,
+            vec![TokenTransfer {
+                to: "Camper".to_string(),
+                amount: 15
+            }]
+        );
+        assert_eq!(execution.events, vec!["bought".to_string()]);
+
+        // Only 20 tokens are unstaked
+        context.args = vec![25];
+        let execution = execute_module(&code, &mut Storage::new(), &context, 10_000);
+        assert!(execution.result.is_err());
+        assert!(execution.transfers.is_empty() && execution.events.is_empty());
+        // Of which 10 are locked by vesting
+        context.args = vec![15];
+        context.locked = 10;
+        let execution = execute_module(&code, &mut Storage::new(), &context, 10_000);
+        assert!(execution.result.is_err());
+        // "Camper" is only an address on chains with legacy addresses
+        context.locked = 0;
+        context.legacy_addresses = false;
+        let execution = execute_module(&code, &mut Storage::new(), &context, 10_000);
+        assert!(execution.result.is_err());
+        assert!(execution.transfers.is_empty());
+    }
+    #[test]
+    fn fuel_is_limited() {
+        let code = _fixture_module(
+            r#"(func (export "call") (result i64)
+                (call $storage_write (i64.const 1) (i64.const 1))
+                (loop $forever (br $forever))
+                (i64.const 0))"#,
+        );
+        let mut storage = Storage::new();
+        let execution = execute_module(&code, &mut storage, &_fixture_context(vec![]), 1000);
+        assert_eq!(execution.result, Err("Out of gas".to_string()));
+        assert_eq!(execution.gas_used, 1000);
+        assert!(storage.is_empty());
+    }
+    #[test]
+    fn host_reads_cost_their_opcode_gas() {
+        let gas = |read: &str, reads: usize| {
+            let body = format!("(drop (call ${}))", read).repeat(reads);
+            let code = _fixture_module(&format!(
+                r#"(func (export "call") (result i64) {} (i64.const 0))"#,
+                body
+            ));
+            execute_module(
+                &code,
+                &mut Storage::new(),
+                &_fixture_context(vec![]),
+                10_000,
+            )
+            .gas_used
+        };
+        // Each read costs the fuel of its call, plus the gas of its opcode
+        let height = gas("height", 2) - gas("height", 1);
+        let staked = gas("staked", 2) - gas("staked", 1);
+        assert_eq!(staked - height, Opcode::Staked.gas() - Opcode::Height.gas());
+        assert!(height > Opcode::Height.gas());
+    }
+    #[test]
+    fn memory_and_tables_are_limited() {
+        let call = r#"(func (export "call") (result i64) (i64.const 0))"#;
+        let huge_memory = wat::parse_str(format!(
+            r#"(module (memory (export "memory") 65536) {})"#,
+            call
+        ))
+        .unwrap();
+        assert_eq!(validate_module(&huge_memory), Ok(()));
+        let execution = execute_module(
+            &huge_memory,
+            &mut Storage::new(),
+            &_fixture_context(vec![]),
+            1000,
+        );
+        assert!(execution.result.is_err());
+        let huge_table = wat::parse_str(format!(
+            r#"(module (memory (export "memory") 1) (table 100000 funcref) {})"#,
+            call
+        ))
+        .unwrap();
+        let execution = execute_module(
+            &huge_table,
+            &mut Storage::new(),
+            &_fixture_context(vec![]),
+            1000,
+        );
+        assert!(execution.result.is_err());
+        // Growing the memory past the limit fails
+        let grow = _fixture_module(
+            r#"(func (export "call") (result i64) (i64.extend_i32_s (memory.grow (i32.const 16))))"#,
+        );
+        let execution = execute_module(&grow, &mut Storage::new(), &_fixture_context(vec![]), 1000);
+        assert_eq!(execution.result, Ok(Some(u64::MAX)));
+    }
+    #[test]
+    fn invalid_modules_are_rejected() {
+        let floats = _fixture_module(
+            r#"(func (export "call") (result i64) (i64.trunc_f64_s (f64.const 1.5)))"#,
+        );
+        assert!(validate_module(&floats).is_err());
+        let no_call = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert_eq!(
+            validate_module(&no_call),
+            Err("Contract module does not export a call function".to_string())
+        );
+        assert!(validate_module(b"\0asm").is_err());
+    }
+
+    fn _fixture_context(args: Vec<u64>) -> Context {
+        Context {
+            args,
+            ..Default::default()
+        }
+    }
+
+    /// A module importing every host function, and exporting its memory, around the given `body`.
+    fn _fixture_module(body: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module
+                (import "env" "arg" (func $arg (param i32) (result i64)))
+                (import "env" "staked" (func $staked (result i64)))
+                (import "env" "height" (func $height (result i64)))
+                (import "env" "storage_read" (func $storage_read (param i64) (result i64)))
+                (import "env" "storage_write" (func $storage_write (param i64 i64)))
+                (import "env" "transfer" (func $transfer (param i32 i32 i64)))
+                (import "env" "emit" (func $emit (param i32 i32)))
+                (memory (export "memory") 1)
+                {})"#,
+            body
+        ))
+        .unwrap()
+    }
+}